@@ -0,0 +1,146 @@
+// Copyright 2018 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Finding the bracket that matches the one at a caret.
+
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use xi_rope::interval::Interval;
+use xi_rope::rope::Rope;
+
+use layers::Layers;
+
+/// The pairs of brackets that are matched, opening bracket first.
+const BRACKET_PAIRS: [(u8, u8); 3] = [(b'(', b')'), (b'[', b']'), (b'{', b'}')];
+
+/// How far from a bracket its match is looked for, in bytes.
+const MAX_BRACKET_DISTANCE: usize = 100_000;
+
+/// A bracket next to a caret, and the bracket that matches it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BracketMatch {
+    /// The offset of the caret the bracket is next to.
+    pub caret: usize,
+    pub bracket: usize,
+    pub matching: usize,
+}
+
+/// Finds the bracket matching the one next to the caret at `offset`,
+/// looking at the bracket after the caret first. Brackets in strings and
+/// comments, as scoped by plugins in `layers`, are skipped.
+pub fn find_matching_bracket(text: &Rope, layers: &Layers, offset: usize)
+    -> Option<BracketMatch>
+{
+    let is_bracket_byte = |offset: usize| offset < text.len()
+        && bracket_pair(text.byte_at(offset)).is_some();
+    // most carets aren't next to a bracket; don't look at the scopes then
+    if !is_bracket_byte(offset) && (offset == 0 || !is_bracket_byte(offset - 1)) {
+        return None;
+    }
+    let start = offset.saturating_sub(MAX_BRACKET_DISTANCE + 1);
+    let end = offset.saturating_add(MAX_BRACKET_DISTANCE + 1).min(text.len());
+    let ignored = layers.string_and_comment_ranges(Interval::new_closed_open(start, end));
+    let is_bracket = |offset: usize| is_bracket_byte(offset) && !is_ignored(&ignored, offset);
+
+    let bracket = if is_bracket(offset) {
+        offset
+    } else if offset > 0 && is_bracket(offset - 1) {
+        offset - 1
+    } else {
+        return None;
+    };
+    let byte = text.byte_at(bracket);
+    let (open, close) = bracket_pair(byte).unwrap();
+    let mut depth = 0;
+    let mut scan = |pos: usize, b: u8| {
+        if b != open && b != close || is_ignored(&ignored, pos) {
+            return false;
+        }
+        if b == byte {
+            depth += 1;
+        } else {
+            depth -= 1;
+        }
+        depth == 0
+    };
+
+    let matching = if byte == open {
+        let mut pos = bracket;
+        text.iter_chunks(bracket, end)
+            .flat_map(|chunk| chunk.bytes())
+            .find(|&b| { pos += 1; scan(pos - 1, b) })
+            .map(|_| pos - 1)
+    } else {
+        let chunks = text.iter_chunks(start, bracket + 1).collect::<Vec<_>>();
+        let mut pos = bracket + 1;
+        chunks.iter().rev()
+            .flat_map(|chunk| chunk.bytes().rev())
+            .find(|&b| { pos -= 1; scan(pos, b) })
+            .map(|_| pos)
+    };
+    matching.map(|matching| BracketMatch { caret: offset, bracket, matching })
+}
+
+/// Returns the pair of brackets that `byte` is part of, if any.
+fn bracket_pair(byte: u8) -> Option<(u8, u8)> {
+    BRACKET_PAIRS.iter().find(|&&(open, close)| byte == open || byte == close).cloned()
+}
+
+/// Returns whether `offset` is in one of `ranges`, which are sorted.
+fn is_ignored(ranges: &[Range<usize>], offset: usize) -> bool {
+    ranges.binary_search_by(|range| {
+        if range.end <= offset {
+            Ordering::Less
+        } else if range.start > offset {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    }).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plugins::PluginPid;
+    use styles::ThemeStyleMap;
+    use xi_rope::spans::SpansBuilder;
+
+    #[test]
+    fn match_brackets() {
+        let text = Rope::from("f(a[0], \")\") { b }");
+        let mut layers = Layers::new(text.len());
+        let matching = |layers: &Layers, offset| find_matching_bracket(&text, layers, offset)
+            .map(|m| (m.bracket, m.matching));
+        assert_eq!(matching(&layers, 1), Some((1, 9)));
+        assert_eq!(matching(&layers, 3), Some((3, 5)));
+        assert_eq!(matching(&layers, 6), Some((5, 3)));
+        assert_eq!(matching(&layers, text.len()), Some((17, 13)));
+        assert_eq!(matching(&layers, 16), None);
+
+        // the bracket in the string is skipped once it is scoped
+        let plugin = PluginPid(1);
+        let scopes = vec![vec!["source.rust".to_owned()],
+                          vec!["source.rust".to_owned(), "string.quoted.double.rust".to_owned()]];
+        layers.add_scopes(plugin, scopes, &ThemeStyleMap::new());
+        let mut spans = SpansBuilder::new(text.len());
+        spans.add_span(Interval::new_closed_open(0, 8), 0);
+        spans.add_span(Interval::new_closed_open(8, 11), 1);
+        spans.add_span(Interval::new_closed_open(11, text.len()), 0);
+        layers.update_layer(plugin, Interval::new_closed_open(0, text.len()), spans.build());
+        assert_eq!(matching(&layers, 1), Some((1, 11)));
+        assert_eq!(matching(&layers, 10), None);
+    }
+}
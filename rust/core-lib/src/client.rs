@@ -14,6 +14,8 @@
 
 //! Requests and notifications from the core to front-ends.
 
+use std::ops::Range;
+use std::path::Path;
 use std::time::Instant;
 
 use serde_json::{self, Value};
@@ -24,8 +26,12 @@ use config::Table;
 use styles::ThemeSettings;
 use plugins::rpc::ClientPluginInfo;
 use plugins::Command;
+use project_search::{SearchId, SearchResult};
+use diff::{DiffId, Hunk};
+use file::LineEnding;
 
 /// An interface to the frontend.
+#[derive(Clone)]
 pub struct Client(RpcPeer);
 
 #[derive(Serialize, Deserialize)]
@@ -66,6 +72,62 @@ impl Client {
                                      }));
     }
 
+    /// Notifies the client that the file open in `view_id` has both kinds
+    /// of line endings; `line_ending` is the most common one.
+    pub fn mixed_line_endings(&self, view_id: ViewId, line_ending: LineEnding) {
+        self.0.send_rpc_notification("mixed_line_endings",
+                                     &json!({
+                                         "view_id": view_id,
+                                         "line_ending": line_ending,
+                                     }));
+    }
+
+    /// Notifies the client that changes to the file on disk were merged
+    /// into the dirty buffer of `view_id`. `conflicts` are the lines of
+    /// the conflicts the merge left in the buffer.
+    pub fn file_merged(&self, view_id: ViewId, conflicts: &[Range<usize>]) {
+        let conflicts = conflicts.iter()
+            .map(|c| json!({"start": c.start, "end": c.end}))
+            .collect::<Vec<_>>();
+        self.0.send_rpc_notification("file_merged",
+                                     &json!({
+                                         "view_id": view_id,
+                                         "conflicts": conflicts,
+                                     }));
+    }
+
+    /// Notifies the client that the texts compared by the side-by-side
+    /// diff `diff_id` changed, and are now aligned by `hunks`.
+    pub fn diff_changed(&self, diff_id: DiffId, hunks: &[Hunk]) {
+        self.0.send_rpc_notification("diff_changed",
+                                     &json!({
+                                         "diff_id": diff_id,
+                                         "hunks": hunks,
+                                     }));
+    }
+
+    /// Notifies the client that unsaved changes to the file open in
+    /// `view_id` were left in the swap file at `swap_path`, and can be
+    /// recovered or discarded.
+    pub fn swap_file_found(&self, view_id: ViewId, swap_path: &Path) {
+        self.0.send_rpc_notification("swap_file_found",
+                                     &json!({
+                                         "view_id": view_id,
+                                         "swap_path": swap_path,
+                                     }));
+    }
+
+    /// Reports how many of the `total` bytes of a large file have been
+    /// loaded into `view_id`.
+    pub fn load_progress(&self, view_id: ViewId, loaded: u64, total: u64) {
+        self.0.send_rpc_notification("load_progress",
+                                     &json!({
+                                         "view_id": view_id,
+                                         "loaded": loaded,
+                                         "total": total,
+                                     }));
+    }
+
     pub fn available_themes(&self, theme_names: Vec<String>) {
         self.0.send_rpc_notification("available_themes",
                                      &json!({"themes": theme_names}))
@@ -120,6 +182,22 @@ impl Client {
                                      }));
     }
 
+    /// Send the client a batch of results of a project search.
+    pub fn project_search_results(&self, search_id: SearchId,
+                                  results: &[SearchResult]) {
+        self.0.send_rpc_notification("project_search_results",
+                                     &json!({
+                                         "search_id": search_id,
+                                         "results": results,
+                                     }));
+    }
+
+    /// Notify the client that a project search has finished.
+    pub fn project_search_finished(&self, search_id: SearchId) {
+        self.0.send_rpc_notification("project_search_finished",
+                                     &json!({"search_id": search_id}));
+    }
+
     pub fn def_style(&self, style: &Value) {
         self.0.send_rpc_notification("def_style", &style)
     }
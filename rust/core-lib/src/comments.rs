@@ -0,0 +1,134 @@
+// Copyright 2018 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recognizing and adding the comment tokens of a syntax.
+
+use config::BufferItems;
+
+/// The tokens that start and end comments in a buffer's syntax.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommentTokens {
+    /// Comments out the rest of a line.
+    pub line: Option<String>,
+    /// Comments out the text between them.
+    pub block: Option<(String, String)>,
+}
+
+impl CommentTokens {
+    /// Returns the tokens configured in `items`, which are empty strings
+    /// for syntaxes without them.
+    pub fn from_config(items: &BufferItems) -> CommentTokens {
+        let line = Some(items.line_comment.clone()).filter(|s| !s.is_empty());
+        let block = match (items.block_comment_start.as_str(), items.block_comment_end.as_str()) {
+            ("", _) | (_, "") => None,
+            (start, end) => Some((start.to_owned(), end.to_owned())),
+        };
+        CommentTokens { line, block }
+    }
+
+    /// The tokens that comment out a whole line: the line token, or
+    /// without one, the block tokens.
+    fn line_tokens(&self) -> Option<(&str, &str)> {
+        match (self.line.as_ref(), self.block.as_ref()) {
+            (Some(line), _) => Some((line, "")),
+            (None, Some(&(ref start, ref end))) => Some((start, end)),
+            (None, None) => None,
+        }
+    }
+
+    /// Returns the text to insert before and after a line's content to
+    /// comment it out.
+    pub fn line_comment(&self) -> Option<(String, String)> {
+        self.line_tokens().map(|(start, end)| {
+            let end = if end.is_empty() { String::new() } else { format!(" {}", end) };
+            (format!("{} ", start), end)
+        })
+    }
+
+    /// If the content of a line, without its indentation and line ending,
+    /// is commented out, returns the lengths of the text to remove from its
+    /// start and end to uncomment it: the tokens and a space next to each.
+    pub fn line_commented(&self, content: &str) -> Option<(usize, usize)> {
+        let (start, end) = self.line_tokens()?;
+        let content = content.trim_end();
+        enclosed(content, start, end)
+    }
+
+    /// Returns `text` enclosed in the block tokens.
+    pub fn wrap(&self, text: &str) -> Option<String> {
+        self.block.as_ref().map(|&(ref start, ref end)| format!("{} {} {}", start, text, end))
+    }
+
+    /// If `text` is enclosed in the block tokens, returns the lengths of
+    /// the text to remove from its start and end to unwrap it.
+    pub fn wrapped(&self, text: &str) -> Option<(usize, usize)> {
+        let &(ref start, ref end) = self.block.as_ref()?;
+        enclosed(text, start, end)
+    }
+
+    /// If the text `before` and `after` a selection ends and starts with
+    /// the block tokens, returns the lengths of the text to remove before
+    /// and after it to unwrap it.
+    pub fn wrapped_around(&self, before: &str, after: &str) -> Option<(usize, usize)> {
+        let &(ref start, ref end) = self.block.as_ref()?;
+        let before_token = before.trim_end_matches(' ');
+        let after_token = after.trim_start_matches(' ');
+        if !before_token.ends_with(start.as_str()) || !after_token.starts_with(end.as_str()) {
+            return None;
+        }
+        Some((before.len() - before_token.len() + start.len(),
+              after.len() - after_token.len() + end.len()))
+    }
+}
+
+/// If `text` starts with `start` and ends with `end`, returns their
+/// lengths, each with a space between it and the rest of the text.
+fn enclosed(text: &str, start: &str, end: &str) -> Option<(usize, usize)> {
+    if !text.starts_with(start) || !text.ends_with(end) || text.len() < start.len() + end.len() {
+        return None;
+    }
+    let inner = &text[start.len()..text.len() - end.len()];
+    let prefix = start.len() + if inner.starts_with(' ') { 1 } else { 0 };
+    let inner = &inner[prefix - start.len()..];
+    let suffix = end.len() + if !end.is_empty() && inner.ends_with(' ') { 1 } else { 0 };
+    Some((prefix, suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comment_tokens() {
+        let c = CommentTokens {
+            line: Some("//".into()),
+            block: Some(("/*".into(), "*/".into())),
+        };
+        assert_eq!(c.line_comment(), Some(("// ".into(), "".into())));
+        assert_eq!(c.line_commented("// a  "), Some((3, 0)));
+        assert_eq!(c.line_commented("//a"), Some((2, 0)));
+        assert_eq!(c.line_commented("a // b"), None);
+        assert_eq!(c.wrap("a"), Some("/* a */".into()));
+        assert_eq!(c.wrapped("/* a */"), Some((3, 3)));
+        assert_eq!(c.wrapped("/**/"), Some((2, 2)));
+        assert_eq!(c.wrapped("/*/"), None);
+        assert_eq!(c.wrapped_around("x /* ", " */"), Some((3, 3)));
+        assert_eq!(c.wrapped_around("x /*", "*/ y"), Some((2, 2)));
+        assert_eq!(c.wrapped_around("x ", " */"), None);
+
+        let html = CommentTokens { line: None, block: Some(("<!--".into(), "-->".into())) };
+        assert_eq!(html.line_comment(), Some(("<!-- ".into(), " -->".into())));
+        assert_eq!(html.line_commented("<!-- a -->"), Some((5, 4)));
+    }
+}
@@ -21,6 +21,7 @@ use std::path::{PathBuf, Path};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
+use regex::{Regex, Error as RegexError};
 use serde::de::Deserialize;
 use serde_json::{self, Value};
 use toml;
@@ -34,6 +35,38 @@ mod defaults {
     use super::*;
     pub const BASE: &str = include_str!("../assets/defaults.toml");
     pub const WINDOWS: &str = include_str!("../assets/windows.toml");
+    pub const MAKEFILE: &str = include_str!("../assets/makefile.toml");
+    pub const YAML: &str = include_str!("../assets/yaml.toml");
+    pub const PYTHON: &str = include_str!("../assets/python.toml");
+    pub const JAVASCRIPT: &str = include_str!("../assets/javascript.toml");
+    pub const MARKDOWN: &str = include_str!("../assets/markdown.toml");
+    pub const SHELL: &str = include_str!("../assets/shell.toml");
+    pub const RUBY: &str = include_str!("../assets/ruby.toml");
+    pub const PERL: &str = include_str!("../assets/perl.toml");
+    pub const TOML: &str = include_str!("../assets/toml.toml");
+    pub const C_FAMILY: &str = include_str!("../assets/c_family.toml");
+
+    /// The syntaxes that have defaults of their own.
+    pub const SYNTAXES: [SyntaxDefinition; 18] = [
+        SyntaxDefinition::Makefile,
+        SyntaxDefinition::Yaml,
+        SyntaxDefinition::Python,
+        SyntaxDefinition::Javascript,
+        SyntaxDefinition::Markdown,
+        SyntaxDefinition::Shell,
+        SyntaxDefinition::Ruby,
+        SyntaxDefinition::Perl,
+        SyntaxDefinition::Toml,
+        SyntaxDefinition::Rust,
+        SyntaxDefinition::C,
+        SyntaxDefinition::Cpp,
+        SyntaxDefinition::Objc,
+        SyntaxDefinition::Go,
+        SyntaxDefinition::Dart,
+        SyntaxDefinition::Swift,
+        SyntaxDefinition::Java,
+        SyntaxDefinition::Php,
+    ];
 
     /// A cache of loaded defaults.
     lazy_static! {
@@ -64,6 +97,23 @@ mod defaults {
                 }
                 Some(base)
             }
+            ConfigDomain::Syntax(syntax) => match syntax {
+                SyntaxDefinition::Makefile => Some(load(MAKEFILE)),
+                SyntaxDefinition::Yaml => Some(load(YAML)),
+                SyntaxDefinition::Python => Some(load(PYTHON)),
+                SyntaxDefinition::Javascript => Some(load(JAVASCRIPT)),
+                SyntaxDefinition::Markdown => Some(load(MARKDOWN)),
+                SyntaxDefinition::Shell => Some(load(SHELL)),
+                SyntaxDefinition::Ruby => Some(load(RUBY)),
+                SyntaxDefinition::Perl => Some(load(PERL)),
+                SyntaxDefinition::Toml => Some(load(TOML)),
+                SyntaxDefinition::Rust | SyntaxDefinition::C | SyntaxDefinition::Cpp
+                    | SyntaxDefinition::Objc | SyntaxDefinition::Go
+                    | SyntaxDefinition::Dart | SyntaxDefinition::Swift
+                    | SyntaxDefinition::Java | SyntaxDefinition::Php =>
+                    Some(load(C_FAMILY)),
+                _ => None,
+            },
             _ => None,
         }
     }
@@ -117,6 +167,8 @@ pub enum ConfigError {
     Parse(PathBuf, toml::de::Error),
     /// The config table contained unexpected values
     UnexpectedItem(serde_json::Error),
+    /// An indent pattern in the config table was not a valid regex.
+    InvalidPattern(String, RegexError),
     /// An Io Error
     Io(io::Error),
 }
@@ -176,6 +228,18 @@ pub struct BufferItems {
     pub auto_indent: bool,
     pub scroll_past_end: bool,
     pub wrap_width: usize,
+    pub persist_undo_history: bool,
+    pub large_file_threshold: u64,
+    pub backup_files: bool,
+    pub backup_directory: String,
+    pub swap_file_interval: u64,
+    pub auto_close_pairs: bool,
+    pub auto_pairs: Vec<(String, String)>,
+    pub increase_indent_pattern: String,
+    pub decrease_indent_pattern: String,
+    pub line_comment: String,
+    pub block_comment_start: String,
+    pub block_comment_end: String,
 }
 
 pub type BufferConfig = Config<BufferItems>;
@@ -228,6 +292,9 @@ impl ConfigManager {
         let mut defaults = HashMap::new();
         defaults.insert(ConfigDomain::General,
                         ConfigPair::for_domain(ConfigDomain::General));
+        for &syntax in &defaults::SYNTAXES {
+            defaults.insert(syntax.into(), ConfigPair::for_domain(syntax));
+        }
         ConfigManager {
             configs: defaults,
             sources: HashMap::new(),
@@ -297,7 +364,13 @@ impl ConfigManager {
             if v.is_null() { continue }
             defaults.insert(k.to_owned(), v.to_owned());
         }
-        let _: BufferItems = serde_json::from_value(defaults.into())?;
+        let items: BufferItems = serde_json::from_value(defaults.into())?;
+        for pattern in &[items.increase_indent_pattern, items.decrease_indent_pattern] {
+            if !pattern.is_empty() {
+                Regex::new(pattern)
+                    .map_err(|e| ConfigError::InvalidPattern(pattern.to_owned(), e))?;
+            }
+        }
         Ok(())
     }
 
@@ -442,6 +515,7 @@ impl fmt::Display for ConfigError {
             Parse(ref p, ref e) => write!(f, "{} ({:?}), {:?}", self.description(), p, e),
             Io(ref e) => write!(f, "error loading config: {:?}", e),
             UnexpectedItem( ref e ) => write!(f, "{}", e),
+            InvalidPattern(ref p, ref e) => write!(f, "{} {:?}: {}", self.description(), p, e),
         }
     }
 }
@@ -454,6 +528,7 @@ impl Error for ConfigError {
             Parse( _, ref e ) => e.description(),
             Io( ref e ) => e.description(),
             UnexpectedItem( ref e ) => e.description(),
+            InvalidPattern( .. ) => "invalid indent pattern",
         }
     }
 }
@@ -566,6 +641,49 @@ mod tests {
         assert_eq!(config.items.tab_size, 85);
     }
 
+    #[test]
+    fn test_syntax_defaults() {
+        let mut manager = ConfigManager::new(None, None);
+        let config = manager.get_buffer_config(SyntaxDefinition::Python, None);
+        assert!(config.items.auto_pairs.contains(&("'".to_owned(), "'".to_owned())));
+        let config = manager.get_buffer_config(SyntaxDefinition::Rust, None);
+        assert!(!config.items.auto_pairs.contains(&("'".to_owned(), "'".to_owned())));
+        assert_eq!(config.items.line_comment, "//");
+        assert_eq!(config.items.block_comment_start, "/*");
+        let config = manager.get_buffer_config(SyntaxDefinition::Json, None);
+        assert_eq!(config.items.line_comment, "");
+        assert_eq!(config.items.block_comment_start, "");
+        let config = manager.get_buffer_config(SyntaxDefinition::Shell, None);
+        assert_eq!(config.items.line_comment, "#");
+        let config = manager.get_buffer_config(SyntaxDefinition::Makefile, None);
+        assert!(!config.items.translate_tabs_to_spaces);
+
+        // user settings for the syntax take precedence
+        let changes = json!({"translate_tabs_to_spaces": true}).as_object().unwrap().to_owned();
+        manager.update_user_config(SyntaxDefinition::Makefile.into(), changes).unwrap();
+        let config = manager.get_buffer_config(SyntaxDefinition::Makefile, None);
+        assert!(config.items.translate_tabs_to_spaces);
+    }
+
+    #[test]
+    fn test_invalid_indent_pattern() {
+        let mut manager = ConfigManager::new(None, None);
+        let changes = json!({"increase_indent_pattern": "\\{\\s*$"})
+            .as_object().unwrap().to_owned();
+        assert!(manager.update_user_config(ConfigDomain::General, changes).is_ok());
+        let changes = json!({"decrease_indent_pattern": "^\\s*[}"})
+            .as_object().unwrap().to_owned();
+        match manager.update_user_config(ConfigDomain::General, changes) {
+            Err(ConfigError::InvalidPattern(ref p, _)) => assert_eq!(p, "^\\s*[}"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        // the invalid pattern isn't used
+        let defaults = ConfigManager::new(None, None).default_buffer_config();
+        let config = manager.default_buffer_config();
+        assert_eq!(config.items.decrease_indent_pattern, defaults.items.decrease_indent_pattern);
+        assert_eq!(config.items.increase_indent_pattern, "\\{\\s*$");
+    }
+
     #[test]
     fn test_config_domain_serde() {
         assert!(ConfigDomain::try_from_path(Path::new("hi/python.xiconfig")).is_ok());
@@ -102,10 +102,10 @@ impl Handler for XiCore {
         }
 
         // wait for client_started before setting up inner
-        if let &ClientStarted { ref config_dir, ref client_extras_dir } = &rpc {
+        if let &ClientStarted { ref config_dir, ref client_extras_dir, ref cache_dir } = &rpc {
             assert!(self.is_waiting(), "client_started can only be sent once");
             let state = CoreState::new(ctx.get_peer(), config_dir.clone(),
-                                      client_extras_dir.clone());
+                                      client_extras_dir.clone(), cache_dir.clone());
             let state = Arc::new(Mutex::new(state));
             *self = XiCore::Running(state);
             let weak_self = self.weak_self().unwrap();
@@ -154,6 +154,14 @@ impl WeakXiCore {
             core.inner().plugin_update(plugin, view, response);
         }
     }
+
+    /// Handles a plugin's answer to the format request `id`.
+    pub fn handle_plugin_format(&self, view: ViewId, id: usize,
+                                response: Result<Value, RpcError>) {
+        if let Some(core) = self.upgrade() {
+            core.inner().plugin_format(view, id, response);
+        }
+    }
 }
 
 /// Handler for messages originating from plugins.
@@ -185,7 +193,7 @@ pub fn dummy_weak_core() -> WeakXiCore {
     use xi_rpc::test_utils::DummyPeer;
     use xi_rpc::Peer;
     let peer = Box::new(DummyPeer);
-    let state = CoreState::new(&peer.box_clone(), None, None);
+    let state = CoreState::new(&peer.box_clone(), None, None, None);
     let core = Arc::new(Mutex::new(state));
     WeakXiCore(Arc::downgrade(&core))
 }
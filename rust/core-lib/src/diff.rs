@@ -0,0 +1,783 @@
+// Copyright 2018 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Line-based diffs, side-by-side comparisons and three-way merges of
+//! texts.
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::ops::Range;
+
+use xi_rope::delta::{Builder, Delta};
+use xi_rope::interval::Interval;
+use xi_rope::rope::{LinesMetric, Rope, RopeInfo};
+use xi_rope::spans::{Spans, SpansBuilder};
+
+use file::fnv_hash;
+use styles::Style;
+use tabs::BufferId;
+
+/// The most edits the diff searches for, to bound the time and memory it
+/// takes. Texts that differ by more are treated as one big change.
+const MAX_EDIT_COST: usize = 2000;
+
+/// Hunks of a side-by-side diff longer than this many bytes on either side
+/// are highlighted as a whole, rather than word by word.
+const MAX_INTRALINE_LEN: usize = 10_000;
+
+/// The lines that start, separate and end a conflict in merged text.
+pub const CONFLICT_START: &str = "<<<<<<< buffer";
+pub const CONFLICT_SEPARATOR: &str = "=======";
+pub const CONFLICT_END: &str = ">>>>>>> disk";
+
+/// A run of lines that differ between two texts: the `old` lines of one
+/// were replaced by the `new` lines of the other. Either range may be
+/// empty.
+///
+/// It is sent to the client as the `left` and `right` lines of a
+/// side-by-side diff.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Hunk {
+    #[serde(rename = "left")]
+    pub old: Range<usize>,
+    #[serde(rename = "right")]
+    pub new: Range<usize>,
+}
+
+/// The result of a three-way merge.
+#[derive(Debug)]
+pub struct Merge {
+    pub text: Rope,
+    /// The lines of `text` that hold conflicts, including their markers.
+    pub conflicts: Vec<Range<usize>>,
+}
+
+/// Which version of a conflict to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictSide {
+    /// The changes made in the buffer.
+    Buffer,
+    /// The changes made to the file on disk.
+    Disk,
+    /// Both, the buffer's first.
+    Both,
+}
+
+/// A conflict found in text, as byte offsets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// The whole conflict, including its markers.
+    pub range: Range<usize>,
+    /// The buffer's side of the conflict.
+    pub buffer: Range<usize>,
+    /// The disk's side of the conflict.
+    pub disk: Range<usize>,
+}
+
+/// How a line differs from the base text it is compared with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineChange {
+    Added,
+    Modified,
+    /// Lines of the base were removed just before this line.
+    RemovedAbove,
+    /// Lines of the base were removed just after this line, which is the
+    /// last one.
+    RemovedBelow,
+}
+
+/// Tracks which lines of a buffer differ from a base text, such as the
+/// text that was last saved, for markers in the gutter.
+///
+/// Lines are compared by hash, and only the hashes of edited lines are
+/// recomputed after an edit. Edits only move the markers of the lines
+/// around them; comparing the whole text again is left to `update`, so
+/// that it can be done once a burst of edits is over.
+pub struct DiffGutter {
+    base: Vec<u64>,
+    lines: Vec<u64>,
+    changes: BTreeMap<usize, LineChange>,
+    stale: bool,
+}
+
+impl DiffGutter {
+    pub fn new(base: &Rope, text: &Rope) -> DiffGutter {
+        let mut gutter = DiffGutter {
+            base: line_hashes(base, 0, line_count(base)),
+            lines: line_hashes(text, 0, line_count(text)),
+            changes: BTreeMap::new(),
+            stale: false,
+        };
+        gutter.compare();
+        gutter
+    }
+
+    /// Returns how `line` differs from the base, if it does.
+    pub fn line_change(&self, line: usize) -> Option<LineChange> {
+        self.changes.get(&line).cloned()
+    }
+
+    pub fn changes(&self) -> &BTreeMap<usize, LineChange> {
+        &self.changes
+    }
+
+    /// Returns `true` if the text was edited since it was last compared.
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Compares the text with a new base. Returns the lines whose change
+    /// is different now.
+    pub fn set_base(&mut self, base: &Rope) -> Vec<usize> {
+        self.base = line_hashes(base, 0, line_count(base));
+        let old = self.compare();
+        changed_lines(&old, &self.changes)
+    }
+
+    /// Compares the text with the base again if it was edited. Returns the
+    /// lines whose change is different now.
+    pub fn update(&mut self) -> Vec<usize> {
+        if !self.stale {
+            return Vec::new();
+        }
+        let old = self.compare();
+        changed_lines(&old, &self.changes)
+    }
+
+    /// Updates the lines after `delta` turned `old_text` into `text`.
+    ///
+    /// The markers of the lines after the edit move with them, and the
+    /// edited lines are marked as modified, or added if there are more of
+    /// them, until the next `update`.
+    pub fn apply_delta(&mut self, delta: &Delta<RopeInfo>, old_text: &Rope, text: &Rope) {
+        self.stale = true;
+        if self.lines.len() != line_count(old_text) {
+            // we missed an edit; start over
+            self.lines = line_hashes(text, 0, line_count(text));
+            return;
+        }
+        let (iv, new_len) = delta.summary();
+        let start = old_text.line_of_offset(iv.start());
+        let old_end = old_text.line_of_offset(iv.end()) + 1;
+        let new_end = text.line_of_offset(iv.start() + new_len) + 1;
+        let hashes = line_hashes(text, start, new_end);
+        self.lines.splice(start..old_end, hashes);
+
+        let moved = self.changes.split_off(&old_end);
+        let edited = self.changes.split_off(&start);
+        for line in start..new_end {
+            let change = match edited.get(&line) {
+                Some(&LineChange::Added) => LineChange::Added,
+                _ if line >= old_end => LineChange::Added,
+                _ => LineChange::Modified,
+            };
+            self.changes.insert(line, change);
+        }
+        self.changes.extend(moved.into_iter()
+            .map(|(line, change)| (line + new_end - old_end, change)));
+    }
+
+    /// Recomputes the changes, returning the previous ones.
+    fn compare(&mut self) -> BTreeMap<usize, LineChange> {
+        self.stale = false;
+        let mut changes = BTreeMap::new();
+        let last = self.lines.len() - 1;
+        for hunk in diff_lines(&self.base, &self.lines) {
+            if hunk.new.start == hunk.new.end {
+                match hunk.new.start {
+                    l if l > last => changes.insert(last, LineChange::RemovedBelow),
+                    l => changes.insert(l, LineChange::RemovedAbove),
+                };
+                continue;
+            }
+            let change = if hunk.old.start == hunk.old.end {
+                LineChange::Added
+            } else {
+                LineChange::Modified
+            };
+            for line in hunk.new {
+                changes.insert(line, change);
+            }
+        }
+        mem::replace(&mut self.changes, changes)
+    }
+}
+
+/// Returns the number of lines in `text`, counting the empty line after a
+/// final line break, as views do.
+fn line_count(text: &Rope) -> usize {
+    text.measure::<LinesMetric>() + 1
+}
+
+/// Returns the hashes of the lines from `start` to `end` of `text`.
+fn line_hashes(text: &Rope, start: usize, end: usize) -> Vec<u64> {
+    let offset = text.offset_of_line(start);
+    let mut hashes = text.lines_raw(offset, text.len())
+        .take(end - start)
+        .map(|line| fnv_hash(line.as_bytes()))
+        .collect::<Vec<_>>();
+    // the empty last line
+    if hashes.len() < end - start {
+        hashes.push(fnv_hash(b""));
+    }
+    hashes
+}
+
+/// Returns the lines that have different changes in `old` and `new`.
+fn changed_lines(old: &BTreeMap<usize, LineChange>, new: &BTreeMap<usize, LineChange>)
+    -> Vec<usize>
+{
+    let mut lines = old.iter()
+        .filter(|&(line, change)| new.get(line) != Some(change))
+        .map(|(line, _)| *line)
+        .chain(new.keys().filter(|line| !old.contains_key(line)).cloned())
+        .collect::<Vec<_>>();
+    lines.sort();
+    lines
+}
+
+/// Splits `text` into lines, each including its line ending.
+pub fn lines(text: &Rope) -> Vec<String> {
+    text.lines_raw_all().map(|l| l.into_owned()).collect()
+}
+
+/// Returns the hunks that turn `old` into `new`, in order.
+pub fn diff_lines<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Hunk> {
+    let prefix = old.iter().zip(new.iter()).take_while(|&(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev())
+        .take_while(|&(a, b)| a == b).count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+    myers(old_mid, new_mid).into_iter()
+        .map(|h| Hunk {
+            old: h.old.start + prefix..h.old.end + prefix,
+            new: h.new.start + prefix..h.new.end + prefix,
+        })
+        .collect()
+}
+
+/// Finds the shortest edit script with Myers' algorithm, keeping the
+/// frontier of each round so that the path can be traced back.
+fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Hunk> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    if n == 0 && m == 0 {
+        return Vec::new();
+    }
+    let whole = vec![Hunk { old: 0..a.len(), new: 0..b.len() }];
+    if n == 0 || m == 0 {
+        return whole;
+    }
+
+    let max = (n + m) as usize;
+    // v[k + max] is the furthest x reached on diagonal k = x - y
+    let mut v = vec![0isize; 2 * max + 2];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    for d in 0..=max as isize {
+        if d as usize > MAX_EDIT_COST {
+            return whole;
+        }
+        let window = (max as isize - d) as usize..(max as isize + d + 1) as usize;
+        trace.push(v[window].to_vec());
+        let mut k = -d;
+        while k <= d {
+            let ix = (k + max as isize) as usize;
+            let mut x = if k == -d || (k != d && v[ix - 1] < v[ix + 1]) {
+                v[ix + 1]
+            } else {
+                v[ix - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[ix] = x;
+            if x >= n && y >= m {
+                return backtrack(&trace, n, m);
+            }
+            k += 2;
+        }
+    }
+    unreachable!("the edit script is at most n + m long")
+}
+
+/// Traces the path found by `myers` back from the end, collecting the
+/// steps that aren't matches into hunks.
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (1..trace.len() as isize).rev() {
+        // the frontier before round d, indexed from diagonal -d
+        let v = |k: isize| trace[d as usize][(k + d) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v(k - 1) < v(k + 1)) { k + 1 } else { k - 1 };
+        let prev_x = v(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+        }
+        let (old_end, new_end) = (x as usize, y as usize);
+        x = prev_x;
+        y = prev_y;
+        // extend the hunk that follows, if this step is adjacent to it
+        match hunks.last_mut() {
+            Some(ref mut h) if h.old.start == old_end && h.new.start == new_end => {
+                h.old.start = x as usize;
+                h.new.start = y as usize;
+                continue;
+            }
+            _ => (),
+        }
+        hunks.push(Hunk { old: x as usize..old_end, new: y as usize..new_end });
+    }
+    hunks.reverse();
+    hunks
+}
+
+/// Returns a delta that turns `old` into `new`. The lines that differ are
+/// found first, and then only the characters that differ within them are
+/// replaced, so that selections and spans outside them are kept.
+pub fn line_delta(old: &Rope, new: &Rope) -> Delta<RopeInfo> {
+    let old_lines = lines(old);
+    let new_lines = lines(new);
+    let mut offsets = Vec::with_capacity(old_lines.len() + 1);
+    offsets.push(0);
+    for line in &old_lines {
+        let last = *offsets.last().unwrap();
+        offsets.push(last + line.len());
+    }
+    let mut builder = Builder::new(old.len());
+    for hunk in diff_lines(&old_lines, &new_lines) {
+        let old_text = old_lines[hunk.old.clone()].concat();
+        let new_text = new_lines[hunk.new].concat();
+        let prefix = common_prefix_len(&old_text, &new_text);
+        let suffix = common_suffix_len(&old_text[prefix..], &new_text[prefix..]);
+        let iv = Interval::new_closed_open(offsets[hunk.old.start] + prefix,
+                                           offsets[hunk.old.end] - suffix);
+        let inserted = &new_text[prefix..new_text.len() - suffix];
+        if inserted.is_empty() {
+            builder.delete(iv);
+        } else {
+            builder.replace(iv, Rope::from(inserted));
+        }
+    }
+    builder.build()
+}
+
+/// Returns the length in bytes of the longest common prefix of `a` and `b`.
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.char_indices().zip(b.chars())
+        .find(|&((_, ca), cb)| ca != cb)
+        .map(|((i, _), _)| i)
+        .unwrap_or_else(|| a.len().min(b.len()))
+}
+
+/// Returns the length in bytes of the longest common suffix of `a` and `b`.
+fn common_suffix_len(a: &str, b: &str) -> usize {
+    a.chars().rev().zip(b.chars().rev())
+        .take_while(|&(ca, cb)| ca == cb)
+        .map(|(c, _)| c.len_utf8())
+        .sum()
+}
+
+/// Merges the changes that turned `base` into `buffer` with those that
+/// turned it into `disk`. Where both changed the same lines differently,
+/// both versions are kept, between conflict markers.
+pub fn merge3(base: &Rope, buffer: &Rope, disk: &Rope) -> Merge {
+    let base_lines = lines(base);
+    let buffer_lines = lines(buffer);
+    let disk_lines = lines(disk);
+    let buffer_hunks = diff_lines(&base_lines, &buffer_lines);
+    let disk_hunks = diff_lines(&base_lines, &disk_lines);
+
+    let mut out: Vec<String> = Vec::new();
+    let mut conflicts = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut base_pos = 0;
+    loop {
+        let lo = match (buffer_hunks.get(i), disk_hunks.get(j)) {
+            (Some(a), Some(b)) => a.old.start.min(b.old.start),
+            (Some(a), None) => a.old.start,
+            (None, Some(b)) => b.old.start,
+            (None, None) => break,
+        };
+        out.extend_from_slice(&base_lines[base_pos..lo]);
+
+        // grow the region until no hunk of either side overlaps its end
+        let (first_i, first_j) = (i, j);
+        let mut hi = lo;
+        loop {
+            if let Some(h) = buffer_hunks.get(i).filter(|h| h.old.start <= hi) {
+                hi = hi.max(h.old.end);
+                i += 1;
+            } else if let Some(h) = disk_hunks.get(j).filter(|h| h.old.start <= hi) {
+                hi = hi.max(h.old.end);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        let buffer_side = side(&buffer_hunks[first_i..i], &buffer_lines, &base_lines, lo, hi);
+        let disk_side = side(&disk_hunks[first_j..j], &disk_lines, &base_lines, lo, hi);
+        if first_j == j || buffer_side == disk_side {
+            out.extend_from_slice(buffer_side);
+        } else if first_i == i {
+            out.extend_from_slice(disk_side);
+        } else {
+            let start = out.len();
+            out.push(format!("{}\n", CONFLICT_START));
+            push_terminated(&mut out, buffer_side);
+            out.push(format!("{}\n", CONFLICT_SEPARATOR));
+            push_terminated(&mut out, disk_side);
+            out.push(format!("{}\n", CONFLICT_END));
+            conflicts.push(start..out.len());
+        }
+        base_pos = hi;
+    }
+    out.extend_from_slice(&base_lines[base_pos..]);
+    Merge { text: Rope::from(out.concat()), conflicts }
+}
+
+/// Returns the lines one side has in place of the base lines `lo..hi`,
+/// given its hunks that fall in that range.
+fn side<'a>(hunks: &[Hunk], lines: &'a [String], base: &'a [String], lo: usize, hi: usize)
+    -> &'a [String]
+{
+    match (hunks.first(), hunks.last()) {
+        (Some(first), Some(last)) => {
+            let start = first.new.start - (first.old.start - lo);
+            let end = last.new.end + (hi - last.old.end);
+            &lines[start..end]
+        }
+        _ => &base[lo..hi],
+    }
+}
+
+/// Appends `lines`, making sure the last one ends with a line break, so
+/// that a conflict marker can follow it.
+fn push_terminated(out: &mut Vec<String>, lines: &[String]) {
+    out.extend_from_slice(lines);
+    if let Some(last) = out.last_mut() {
+        if !last.ends_with('\n') {
+            last.push('\n');
+        }
+    }
+}
+
+/// Finds the conflicts left in `text` by `merge3`, in order. Conflicts
+/// whose markers were edited away are not found.
+pub fn find_conflicts(text: &Rope) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    let mut offset = 0;
+    // the offsets of the current conflict's start and separator lines
+    let mut start = None;
+    let mut separator = None;
+    for line in text.lines_raw_all() {
+        let line_start = offset;
+        offset += line.len();
+        let content = line.trim_right_matches(|c| c == '\n' || c == '\r');
+        match (content, start, separator) {
+            (CONFLICT_START, _, _) => {
+                start = Some((line_start, offset));
+                separator = None;
+            }
+            (CONFLICT_SEPARATOR, Some(_), None) => separator = Some((line_start, offset)),
+            (CONFLICT_END, Some((s_start, s_end)), Some((sep_start, sep_end))) => {
+                conflicts.push(Conflict {
+                    range: s_start..offset,
+                    buffer: s_end..sep_start,
+                    disk: sep_end..line_start,
+                });
+                start = None;
+                separator = None;
+            }
+            _ => (),
+        }
+    }
+    conflicts
+}
+
+/// Identifies a side-by-side diff session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+         Serialize, Deserialize, Hash)]
+pub struct DiffId(pub(crate) usize);
+
+/// The text on the right side of a diff session.
+pub(crate) enum DiffSide {
+    Buffer(BufferId),
+    /// The contents of a file that isn't open, which don't change.
+    File(Rope),
+}
+
+/// Two texts compared side by side, which are compared again whenever
+/// one of them is edited.
+pub(crate) struct DiffSession {
+    pub(crate) left: BufferId,
+    pub(crate) right: DiffSide,
+    /// Whether either buffer was edited since the texts were compared.
+    pub(crate) dirty: bool,
+}
+
+impl DiffSession {
+    pub(crate) fn has_buffer(&self, buffer_id: BufferId) -> bool {
+        match self.right {
+            DiffSide::Buffer(right) => self.left == buffer_id || right == buffer_id,
+            DiffSide::File(_) => self.left == buffer_id,
+        }
+    }
+}
+
+/// The result of comparing two texts side by side.
+#[derive(Debug)]
+pub struct SideBySide {
+    /// The aligned lines: the `old` lines of each hunk, in the left text,
+    /// correspond to its `new` lines, in the right text.
+    pub hunks: Vec<Hunk>,
+    /// The byte ranges of the left text that were removed or changed.
+    pub left_changes: Vec<Range<usize>>,
+    /// The byte ranges of the right text that were added or changed.
+    pub right_changes: Vec<Range<usize>>,
+}
+
+impl SideBySide {
+    /// Returns the styles highlighting the changes of one side.
+    pub fn styles(&self, text: &Rope, right: bool, style: &Style) -> Spans<Style> {
+        let changes = if right { &self.right_changes } else { &self.left_changes };
+        let mut builder = SpansBuilder::new(text.len());
+        for range in changes {
+            builder.add_span(Interval::new_closed_open(range.start, range.end),
+                             style.clone());
+        }
+        builder.build()
+    }
+}
+
+/// Compares `left` with `right` line by line, and the lines of each hunk
+/// word by word.
+pub fn side_by_side(left: &Rope, right: &Rope) -> SideBySide {
+    let hunks = diff_lines(&lines(left), &lines(right));
+    let mut left_changes = Vec::new();
+    let mut right_changes = Vec::new();
+    for hunk in &hunks {
+        let old = left.offset_of_line(hunk.old.start)..left.offset_of_line(hunk.old.end);
+        let new = right.offset_of_line(hunk.new.start)..right.offset_of_line(hunk.new.end);
+        if old.start == old.end || new.start == new.end
+            || old.len() > MAX_INTRALINE_LEN || new.len() > MAX_INTRALINE_LEN
+        {
+            push_change(&mut left_changes, old);
+            push_change(&mut right_changes, new);
+            continue;
+        }
+        let old_text = left.slice_to_string(old.start, old.end);
+        let new_text = right.slice_to_string(new.start, new.end);
+        let old_words = words(&old_text);
+        let new_words = words(&new_text);
+        let old_offsets = word_offsets(&old_words, old.start);
+        let new_offsets = word_offsets(&new_words, new.start);
+        for change in diff_lines(&old_words, &new_words) {
+            push_change(&mut left_changes,
+                        old_offsets[change.old.start]..old_offsets[change.old.end]);
+            push_change(&mut right_changes,
+                        new_offsets[change.new.start]..new_offsets[change.new.end]);
+        }
+    }
+    SideBySide { hunks, left_changes, right_changes }
+}
+
+/// Splits `s` into words, runs of whitespace, and single other characters.
+fn words(s: &str) -> Vec<&str> {
+    fn class(c: char) -> u8 {
+        if c.is_alphanumeric() || c == '_' { 1 } else if c.is_whitespace() { 2 } else { 0 }
+    }
+    let mut words = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let mut end = start + c.len_utf8();
+        if class(c) != 0 {
+            while let Some(&(i, next)) = chars.peek() {
+                if class(next) != class(c) {
+                    break;
+                }
+                end = i + next.len_utf8();
+                chars.next();
+            }
+        }
+        words.push(&s[start..end]);
+    }
+    words
+}
+
+/// Returns the offsets of the starts of `words`, and of their end, given
+/// the offset of the first.
+fn word_offsets(words: &[&str], start: usize) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(words.len() + 1);
+    offsets.push(start);
+    for word in words {
+        let last = offsets[offsets.len() - 1];
+        offsets.push(last + word.len());
+    }
+    offsets
+}
+
+/// Adds a range to `changes`, joining it with the previous one if they
+/// touch.
+fn push_change(changes: &mut Vec<Range<usize>>, range: Range<usize>) {
+    if range.start == range.end {
+        return;
+    }
+    if let Some(last) = changes.last_mut() {
+        if last.end == range.start {
+            last.end = range.end;
+            return;
+        }
+    }
+    changes.push(range);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(s: &str) -> Vec<&str> {
+        s.split_terminator('\n').collect()
+    }
+
+    #[test]
+    fn diff() {
+        let hunks = diff_lines(&split("a\nb\nc\nd\n"), &split("a\nx\nc\nd\ne\n"));
+        assert_eq!(hunks, vec![Hunk { old: 1..2, new: 1..2 }, Hunk { old: 4..4, new: 4..5 }]);
+        assert_eq!(diff_lines(&split("a\nb\n"), &split("a\nb\n")), vec![]);
+        assert_eq!(diff_lines(&split(""), &split("a\n")), vec![Hunk { old: 0..0, new: 0..1 }]);
+        let hunks = diff_lines(&split("a\nb\nc\na\nb\nb\na\n"), &split("c\nb\na\nb\na\nc\n"));
+        let changed: usize = hunks.iter().map(|h| h.old.len() + h.new.len()).sum();
+        assert_eq!(changed, 5);
+    }
+
+    #[test]
+    fn apply_line_delta() {
+        let old = Rope::from("one\ntwo\nthree\nfour");
+        let new = Rope::from("one\n2\nthree\nfour\nfive\n");
+        let delta = line_delta(&old, &new);
+        assert_eq!(String::from(delta.apply(&old)), String::from(&new));
+        assert!(line_delta(&old, &old).is_identity());
+
+        // only the characters that differ are replaced
+        let new = Rope::from("one\ntwó\nthree\nfour");
+        let delta = line_delta(&old, &new);
+        assert_eq!(String::from(delta.apply(&old)), String::from(&new));
+        assert_eq!(delta.inserts_len(), "ó".len());
+        assert_eq!(delta.summary().0, Interval::new_closed_open(6, 7));
+    }
+
+    #[test]
+    fn gutter() {
+        use self::LineChange::*;
+        let base = Rope::from("a\nb\nc\nd\n");
+        let mut text = base.clone();
+        let mut gutter = DiffGutter::new(&base, &text);
+        assert!(gutter.changes().is_empty());
+
+        let edit = |gutter: &mut DiffGutter, text: &mut Rope, start, end, s: &str| {
+            let mut builder = Builder::new(text.len());
+            builder.replace(Interval::new_closed_open(start, end), Rope::from(s));
+            let delta = builder.build();
+            let new_text = delta.apply(text);
+            gutter.apply_delta(&delta, text, &new_text);
+            *text = new_text;
+        };
+        let changes = |gutter: &DiffGutter| gutter.changes().iter()
+            .map(|(l, c)| (*l, *c)).collect::<Vec<_>>();
+        // modify b, add a line before d
+        edit(&mut gutter, &mut text, 2, 3, "B");
+        edit(&mut gutter, &mut text, 6, 6, "new\n");
+        assert_eq!(String::from(&text), "a\nB\nc\nnew\nd\n");
+        // edited lines are only guessed until the next update
+        assert!(gutter.is_stale());
+        assert_eq!(changes(&gutter), vec![(1, Modified), (3, Modified), (4, Added)]);
+        assert_eq!(gutter.update(), vec![3, 4]);
+        assert!(!gutter.is_stale());
+        assert_eq!(changes(&gutter), vec![(1, Modified), (3, Added)]);
+        assert_eq!(gutter.update(), Vec::<usize>::new());
+
+        // joining the first lines moves the other markers
+        edit(&mut gutter, &mut text, 0, 2, "");
+        assert_eq!(changes(&gutter), vec![(0, Modified), (2, Added)]);
+        assert_eq!(gutter.update(), Vec::<usize>::new());
+        assert_eq!(gutter.line_change(0), Some(Modified));
+
+        // changing the base changes lines that weren't edited
+        assert_eq!(gutter.set_base(&text), vec![0, 2]);
+        assert!(gutter.changes().is_empty());
+        edit(&mut gutter, &mut text, 8, 10, "");
+        assert_eq!(gutter.update(), vec![3]);
+        assert_eq!(gutter.line_change(3), Some(RemovedAbove));
+        let mut gutter = DiffGutter::new(&Rope::from("x\ny"), &Rope::from("x\n"));
+        assert_eq!(gutter.set_base(&Rope::from("x\n")), vec![1]);
+        let gutter = DiffGutter::new(&Rope::from("x\ny"), &Rope::from("x"));
+        assert_eq!(gutter.line_change(0), Some(Modified));
+    }
+
+    #[test]
+    fn compare_side_by_side() {
+        let left = Rope::from("same\nlet x = old_value;\ngone\nend\n");
+        let right = Rope::from("same\nlet x = new_value;\nend\nadded\n");
+        let diff = side_by_side(&left, &right);
+        assert_eq!(diff.hunks, vec![Hunk { old: 1..3, new: 1..2 },
+                                    Hunk { old: 4..4, new: 3..4 }]);
+        let changed = |text: &Rope, changes: &[Range<usize>]| changes.iter()
+            .map(|r| text.slice_to_string(r.start, r.end))
+            .collect::<Vec<_>>();
+        assert_eq!(changed(&left, &diff.left_changes), vec!["old_value", "\ngone"]);
+        assert_eq!(changed(&right, &diff.right_changes), vec!["new_value", "added\n"]);
+        assert_eq!(words("a_b(c)  d"), vec!["a_b", "(", "c", ")", "  ", "d"]);
+    }
+
+    #[test]
+    fn merge_without_conflicts() {
+        let base = Rope::from("a\nb\nc\nd\ne\n");
+        let buffer = Rope::from("a\nB\nc\nd\ne\n");
+        let disk = Rope::from("a\nb\nc\nd\nE\nf\n");
+        let merge = merge3(&base, &buffer, &disk);
+        assert_eq!(String::from(merge.text), "a\nB\nc\nd\nE\nf\n");
+        assert!(merge.conflicts.is_empty());
+
+        // the same change on both sides is not a conflict
+        let merge = merge3(&base, &disk, &disk);
+        assert_eq!(String::from(merge.text), String::from(&disk));
+        assert!(merge.conflicts.is_empty());
+    }
+
+    #[test]
+    fn merge_with_conflicts() {
+        let base = Rope::from("a\nb\nc");
+        let buffer = Rope::from("a\nmine\nc");
+        let disk = Rope::from("a\ntheirs\nc");
+        let merge = merge3(&base, &buffer, &disk);
+        assert_eq!(String::from(&merge.text),
+                   "a\n<<<<<<< buffer\nmine\n=======\ntheirs\n>>>>>>> disk\nc");
+        assert_eq!(merge.conflicts, vec![1..6]);
+
+        let conflicts = find_conflicts(&merge.text);
+        assert_eq!(conflicts.len(), 1);
+        let text = String::from(&merge.text);
+        assert_eq!(&text[conflicts[0].buffer.clone()], "mine\n");
+        assert_eq!(&text[conflicts[0].disk.clone()], "theirs\n");
+        assert_eq!(&text[conflicts[0].range.clone()],
+                   "<<<<<<< buffer\nmine\n=======\ntheirs\n>>>>>>> disk\n");
+    }
+}
@@ -18,7 +18,10 @@
 //! This simplifies code elsewhere, and makes it easier to route events to
 //! the editor or view as appropriate.
 
+use diff::ConflictSide;
+use line_ops::SortOptions;
 use movement::Movement;
+use undo_tree::TimeStep;
 use ::rpc::{GestureType, LineRange, EditNotification, MouseAction};
 
 
@@ -34,8 +37,8 @@ pub(crate) enum ViewEvent {
     Drag(MouseAction),
     Gesture { line: u64, col: u64, ty: GestureType },
     GotoLine { line: u64 },
-    FindNext { wrap_around: Option<bool>, allow_same: Option<bool> },
-    FindPrevious { wrap_around: Option<bool> },
+    FindNext { wrap_around: Option<bool>, allow_same: Option<bool>, query: Option<String> },
+    FindPrevious { wrap_around: Option<bool>, query: Option<String> },
     Cancel,
 }
 
@@ -46,14 +49,24 @@ pub(crate) enum BufferEvent {
     Transpose,
     Undo,
     Redo,
+    GotoUndoState(usize),
+    UndoEarlier(TimeStep),
+    UndoLater(TimeStep),
     Uppercase,
     Lowercase,
     Indent,
     Outdent,
+    DuplicateLine,
+    MoveLines { up: bool },
+    JoinLines,
+    SortLines(SortOptions),
+    DeleteLine,
+    ToggleComment,
     Insert(String),
     InsertNewline,
     InsertTab,
     Yank,
+    ResolveConflict { index: Option<usize>, side: ConflictSide },
 }
 
 /// An event that needs special handling
@@ -62,6 +75,9 @@ pub(crate) enum SpecialEvent {
     DebugWrapWidth,
     DebugPrintSpans,
     RequestLines(LineRange),
+    Fold,
+    Unfold { all: bool },
+    Format { selection: bool },
 }
 
 pub(crate) enum EventDomain {
@@ -169,6 +185,10 @@ impl From<EditNotification> for EventDomain {
                 ViewEvent::Move(Movement::EndOfDocument).into(),
             MoveToEndOfDocumentAndModifySelection =>
                 ViewEvent::ModifySelection(Movement::EndOfDocument).into(),
+            MoveToMatchingBracket =>
+                ViewEvent::Move(Movement::MatchingBracket).into(),
+            SelectToMatchingBracket =>
+                ViewEvent::ModifySelection(Movement::MatchingBracket).into(),
             ScrollPageUp =>
                 ViewEvent::Move(Movement::UpPage).into(),
             PageUpAndModifySelection =>
@@ -191,19 +211,50 @@ impl From<EditNotification> for EventDomain {
                 ViewEvent::Gesture { line, col, ty }.into(),
             Undo => BufferEvent::Undo.into(),
             Redo => BufferEvent::Redo.into(),
-            FindNext { wrap_around, allow_same } =>
-                ViewEvent::FindNext { wrap_around, allow_same }.into(),
-            FindPrevious { wrap_around } =>
-                ViewEvent::FindPrevious { wrap_around }.into(),
+            GotoUndoState { id } => BufferEvent::GotoUndoState(id).into(),
+            UndoEarlier { count, seconds } =>
+                BufferEvent::UndoEarlier(time_step(count, seconds)).into(),
+            UndoLater { count, seconds } =>
+                BufferEvent::UndoLater(time_step(count, seconds)).into(),
+            FindNext { wrap_around, allow_same, query } =>
+                ViewEvent::FindNext { wrap_around, allow_same, query }.into(),
+            FindPrevious { wrap_around, query } =>
+                ViewEvent::FindPrevious { wrap_around, query }.into(),
             DebugRewrap => SpecialEvent::DebugRewrap.into(),
             DebugWrapWidth => SpecialEvent::DebugWrapWidth.into(),
             DebugPrintSpans => SpecialEvent::DebugPrintSpans.into(),
+            Fold => SpecialEvent::Fold.into(),
+            Unfold => SpecialEvent::Unfold { all: false }.into(),
+            UnfoldAll => SpecialEvent::Unfold { all: true }.into(),
+            FormatDocument => SpecialEvent::Format { selection: false }.into(),
+            FormatSelection => SpecialEvent::Format { selection: true }.into(),
             CancelOperation => ViewEvent::Cancel.into(),
             Uppercase => BufferEvent::Uppercase.into(),
             Lowercase => BufferEvent::Lowercase.into(),
             Indent => BufferEvent::Indent.into(),
             Outdent => BufferEvent::Outdent.into(),
+            DuplicateLine => BufferEvent::DuplicateLine.into(),
+            MoveLinesUp => BufferEvent::MoveLines { up: true }.into(),
+            MoveLinesDown => BufferEvent::MoveLines { up: false }.into(),
+            JoinLines => BufferEvent::JoinLines.into(),
+            SortLines { numeric, ignore_case, reverse, unique } =>
+                BufferEvent::SortLines(SortOptions {
+                    numeric: numeric.unwrap_or(false),
+                    ignore_case: ignore_case.unwrap_or(false),
+                    reverse: reverse.unwrap_or(false),
+                    unique: unique.unwrap_or(false),
+                }).into(),
+            DeleteLine => BufferEvent::DeleteLine.into(),
+            ToggleComment => BufferEvent::ToggleComment.into(),
+            ResolveConflict { index, side } =>
+                BufferEvent::ResolveConflict { index, side }.into(),
         }
     }
 }
 
+fn time_step(count: Option<usize>, seconds: Option<u64>) -> TimeStep {
+    match seconds {
+        Some(seconds) => TimeStep::Seconds(seconds),
+        None => TimeStep::Count(count.unwrap_or(1)),
+    }
+}
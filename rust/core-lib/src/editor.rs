@@ -14,43 +14,68 @@
 
 use std::borrow::{Borrow, Cow};
 use std::cmp::min;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
+use std::mem;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
 
-use serde_json::Value;
+use serde_json::{self, Value};
 
 use xi_rope::rope::{Rope, RopeInfo, LinesMetric};
+use xi_rope::tree::Cursor;
 use xi_rope::interval::Interval;
 use xi_rope::delta::{self, Delta, Transformer};
-use xi_rope::engine::{Engine, RevId, RevToken};
+use xi_rope::engine::{Engine, RevId, RevToken, Revisions, SessionId};
 use xi_rope::spans::SpansBuilder;
 use xi_trace::trace_block;
 
+use comments::CommentTokens;
 use config::{BufferConfig, Table};
+use diff::{self, ConflictSide, DiffGutter};
 use event_context::MAX_SIZE_LIMIT;
 use edit_types::BufferEvent;
+use fold::{self, IndentFold, IndentLevels};
+use indent::{self, IndentRules};
+use line_ops::{self, SortOptions};
+use file::{LineEnding, fnv_hash, fnv_hash_update};
 use layers::Layers;
 use movement::{Movement, region_movement};
 use plugins::PluginId;
-use plugins::rpc::{PluginEdit, ScopeSpan, TextUnit, GetDataResponse};
+use plugins::rpc::{FoldRange, PluginEdit, ScopeSpan, TextUnit, GetDataResponse};
 use selection::{Selection, SelRegion};
 use styles::ThemeStyleMap;
+use sync::{MAX_SESSIONS, SessionSelection, SyncStore};
 use syntax::SyntaxDefinition;
+use undo_tree::{TimeStep, UndoTree};
 use view::View;
 
-#[cfg(not(feature = "ledger"))]
-pub struct SyncStore;
-#[cfg(feature = "ledger")]
-use fuchsia::sync::SyncStore;
-
 // TODO This could go much higher without issue but while developing it is
 // better to keep it low to expose bugs in the GC during casual testing.
 const MAX_UNDOS: usize = 20;
+/// The maximum number of states in the undo tree, including those on
+/// branches that were undone.
+const MAX_UNDO_STATES: usize = 100;
 
 enum IndentDirection {
     In,
     Out
 }
 
+/// The persisted form of a buffer's undo history. The engine is generic so
+/// that it can be serialized by reference.
+#[derive(Serialize, Deserialize)]
+struct UndoHistory<E> {
+    /// A hash of the text the history was saved with.
+    text_hash: u64,
+    engine: E,
+    undo_group_id: usize,
+    undo_tree: UndoTree,
+    undos: BTreeSet<usize>,
+    gc_undos: BTreeSet<usize>,
+}
+
 pub struct Editor {
     /// The contents of the buffer.
     text: Rope,
@@ -61,12 +86,11 @@ pub struct Editor {
     last_rev_id: RevId,
     /// The revision of the last save.
     pristine_rev_id: RevId,
+    /// The text of the file as it was last loaded or saved.
+    saved_text: Rope,
     undo_group_id: usize,
-    /// Undo groups that may still be toggled
-    live_undos: Vec<usize>,
-    /// The index of the current undo; subsequent undos are currently 'undone'
-    /// (but may be redone)
-    cur_undo: usize,
+    /// Undo groups that may still be toggled, and which of them are applied
+    undo_tree: UndoTree,
     /// undo groups that are undone
     undos: BTreeSet<usize>,
     /// undo groups that are no longer live and should be gc'ed
@@ -77,15 +101,72 @@ pub struct Editor {
 
     revs_in_flight: usize,
 
-    /// Used only on Fuchsia for syncing
-    #[allow(dead_code)]
-    sync_store: Option<SyncStore>,
-    #[allow(dead_code)]
+    /// Where the state of this buffer is shared with other sessions.
+    sync_store: Option<Box<SyncStore>>,
     last_synced_rev: RevId,
+    last_synced_selections: Vec<SessionSelection>,
+    /// The session this buffer is synced as, if it was started with
+    /// `start_sync`.
+    sync_session: Option<SessionId>,
+    /// While syncing, the groups of the edits that were made to undo or
+    /// redo other groups.
+    sync_undos: BTreeSet<usize>,
+    /// While syncing, the groups every session agreed to collect, which
+    /// are collected once no plugin holds on to a revision.
+    sync_collect: Option<BTreeSet<usize>>,
 
     syntax: SyntaxDefinition,
     layers: Layers,
     config: BufferConfig,
+    /// Whether the buffer holds a file above the `large_file_threshold`,
+    /// for which expensive features are turned off.
+    large_file: bool,
+    /// Whether the file is still being loaded into this buffer, or failed
+    /// to load. The buffer can't be edited or saved until it is loaded.
+    loading: bool,
+    /// The lines that differ from `diff_base`, or from `saved_text` if
+    /// the client hasn't set a base. `None` for large files.
+    diff_gutter: Option<DiffGutter>,
+    diff_base: Option<Rope>,
+    /// Whether the base changed since the gutter was last updated.
+    diff_base_changed: bool,
+    /// The ranges each plugin reports as foldable.
+    fold_ranges: BTreeMap<PluginId, Vec<Range<usize>>>,
+    /// The indentation of each line, for folding text no plugin reports
+    /// fold ranges for. `None` for large files.
+    indent_levels: Option<IndentLevels>,
+    /// The offsets of the closing characters of pairs that were inserted
+    /// along with the opening ones, which typing over skips.
+    auto_closed: Vec<usize>,
+    /// The closing characters inserted by the edit being committed, which
+    /// are already in the coordinates of the new text.
+    new_auto_closed: Vec<usize>,
+    /// The selection that the edit being committed leaves, when it isn't
+    /// the one the edit would transform the old selection into.
+    selection_for_edit: Option<Selection>,
+    /// The rules for indenting lines, when auto indent is on.
+    indent_rules: Option<IndentRules>,
+    /// The request for plugins to format the text, while it is unanswered.
+    format_request: Option<FormatRequest>,
+    next_format_id: usize,
+}
+
+/// A replacement of a range of lines, which moves the selections in the
+/// range by `moved` bytes.
+struct LineEdit {
+    range: Range<usize>,
+    text: String,
+    moved: isize,
+}
+
+/// A request for plugins to format the text. The first edit a plugin
+/// answers with is applied; the request is dropped when every plugin has
+/// answered, or at its deadline.
+struct FormatRequest {
+    id: usize,
+    rev: u64,
+    unanswered: usize,
+    deadline: Instant,
 }
 
 impl Editor {
@@ -98,12 +179,43 @@ impl Editor {
     pub fn with_text<T>(text: T, config: BufferConfig) -> Editor
         where T: Into<Rope>,
     {
+        Editor::with_rope(text.into(), config, false)
+    }
 
-        let engine = Engine::new(text.into());
+    /// Creates a new `Editor` for a large file, without the features that
+    /// take too long on large files: the diff gutter and indentation folds.
+    pub(crate) fn with_large_text<T>(text: T, config: BufferConfig) -> Editor
+        where T: Into<Rope>,
+    {
+        Editor::with_rope(text.into(), config, true)
+    }
+
+    fn with_rope(text: Rope, config: BufferConfig, large_file: bool) -> Editor {
+        let engine = Engine::new(text);
         let buffer = engine.get_head().clone();
         let last_rev_id = engine.get_head_rev_id();
+        let layers = Layers::new(buffer.len());
+        let (diff_gutter, indent_levels) = if large_file {
+            (None, None)
+        } else {
+            (Some(DiffGutter::new(&buffer, &buffer)),
+             Some(IndentLevels::new(&buffer, config.items.tab_size)))
+        };
+        let indent_rules = IndentRules::from_config(&config.items);
 
         Editor {
+            diff_gutter,
+            diff_base: None,
+            diff_base_changed: false,
+            fold_ranges: BTreeMap::new(),
+            indent_levels,
+            auto_closed: Vec::new(),
+            new_auto_closed: Vec::new(),
+            selection_for_edit: None,
+            indent_rules,
+            format_request: None,
+            next_format_id: 0,
+            saved_text: buffer.clone(),
             text: buffer,
             syntax: SyntaxDefinition::default(),
             engine,
@@ -113,20 +225,43 @@ impl Editor {
             // GC only works on undone edits or prefixes of the visible edits,
             // but initial file loading can create an edit with undo group 0,
             // so we want to collect that as part of the prefix.
-            live_undos: vec![0],
-            cur_undo: 1,
+            undo_tree: UndoTree::new(0),
             undos: BTreeSet::new(),
             gc_undos: BTreeSet::new(),
             last_edit_type: EditType::Other,
             this_edit_type: EditType::Other,
-            layers: Layers::default(),
+            layers,
             config,
             revs_in_flight: 0,
             sync_store: None,
             last_synced_rev: last_rev_id,
+            last_synced_selections: Vec::new(),
+            sync_session: None,
+            sync_undos: BTreeSet::new(),
+            sync_collect: None,
+            large_file,
+            loading: false,
         }
     }
 
+    pub(crate) fn is_large_file(&self) -> bool {
+        self.large_file
+    }
+
+    pub(crate) fn set_loading(&mut self, loading: bool) {
+        self.loading = loading;
+    }
+
+    pub(crate) fn is_loading(&self) -> bool {
+        self.loading
+    }
+
+    /// The column to wrap lines at, or `0` to not wrap them. Large files
+    /// aren't wrapped, as that requires measuring every line.
+    pub(crate) fn wrap_width(&self) -> usize {
+        if self.large_file { 0 } else { self.config.items.wrap_width }
+    }
+
     pub(crate) fn get_buffer(&self) -> &Rope {
         &self.text
     }
@@ -148,7 +283,7 @@ impl Editor {
     }
 
     pub(crate) fn get_active_undo_group(&self) -> usize {
-        *self.live_undos.last().unwrap_or(&0)
+        self.undo_tree.current()
     }
 
     pub(crate) fn update_edit_type(&mut self) {
@@ -158,6 +293,63 @@ impl Editor {
 
     pub(crate) fn set_pristine(&mut self) {
         self.pristine_rev_id = self.engine.get_head_rev_id();
+        self.saved_text = self.text.clone();
+        self.diff_base_changed |= self.diff_base.is_none();
+    }
+
+    /// Sets the text the diff gutter compares with, such as the version
+    /// in version control. With `None`, it compares with the saved text.
+    pub(crate) fn set_diff_base(&mut self, base: Option<Rope>) {
+        self.diff_base = base;
+        self.diff_base_changed = true;
+    }
+
+    pub(crate) fn get_diff_gutter(&self) -> Option<&DiffGutter> {
+        self.diff_gutter.as_ref()
+    }
+
+    /// Updates the diff gutter after `edit`, a delta and the text it was
+    /// applied to, and after any change of the base. Returns the lines
+    /// whose markers changed without being edited.
+    ///
+    /// Edits only move the markers; the text is compared again by
+    /// `compare_diff_gutter`, when idle.
+    pub(crate) fn update_diff_gutter(&mut self, edit: Option<(&Delta<RopeInfo>, &Rope)>)
+        -> Vec<usize>
+    {
+        let gutter = match self.diff_gutter {
+            Some(ref mut gutter) => gutter,
+            None => return Vec::new(),
+        };
+        if let Some((delta, last_text)) = edit {
+            gutter.apply_delta(delta, last_text, &self.text);
+        }
+        if !self.diff_base_changed {
+            return Vec::new();
+        }
+        self.diff_base_changed = false;
+        let base = self.diff_base.as_ref().unwrap_or(&self.saved_text);
+        gutter.set_base(base)
+    }
+
+    /// Returns `true` if the diff gutter needs to compare the text again
+    /// after edits.
+    pub(crate) fn diff_gutter_is_stale(&self) -> bool {
+        self.diff_gutter.as_ref().map(|g| g.is_stale()).unwrap_or(false)
+    }
+
+    /// Compares the text with the base of the diff gutter again, after
+    /// edits. Returns the lines whose markers changed.
+    pub(crate) fn compare_diff_gutter(&mut self) -> Vec<usize> {
+        match self.diff_gutter {
+            Some(ref mut gutter) => gutter.update(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The text of the file as it was last loaded or saved.
+    pub(crate) fn get_saved_text(&self) -> &Rope {
+        &self.saved_text
     }
 
     pub(crate) fn is_pristine(&self) -> bool {
@@ -165,21 +357,209 @@ impl Editor {
                                            self.engine.get_head_rev_id())
     }
 
-    /// Sets this Editor's contents to `text`, preserving undo state and cursor
-    /// position when possible.
+    /// Sets this Editor's contents to `text`, as it was loaded from the
+    /// file, with an edit that can be undone.
     pub fn reload(&mut self, text: Rope) {
-        let mut builder = delta::Builder::new(self.text.len());
-        let all_iv = Interval::new_closed_open(0, self.text.len());
-        builder.replace(all_iv, text);
-        self.add_delta(builder.build());
+        self.replace_text(text);
         self.set_pristine();
     }
 
+    /// Changes this Editor's contents to `text`, with an edit that only
+    /// replaces the parts that differ, so that selections and spans
+    /// elsewhere are kept. The buffer is left dirty.
+    pub(crate) fn replace_text(&mut self, text: Rope) {
+        let delta = diff::line_delta(&self.text, &text);
+        if !delta.is_identity() {
+            self.this_edit_type = EditType::Other;
+            self.add_delta(delta);
+        }
+    }
+
+    /// Starts a request for `plugins` plugins to format the current text,
+    /// replacing any request still unanswered. Returns the request's id.
+    pub(crate) fn start_format(&mut self, plugins: usize, deadline: Instant) -> usize {
+        let id = self.next_format_id;
+        self.next_format_id += 1;
+        let rev = self.get_head_rev_token();
+        self.format_request = Some(FormatRequest { id, rev, unanswered: plugins, deadline });
+        id
+    }
+
+    /// Handles a plugin's answer to the format request `id`, which is
+    /// `None` if the plugin doesn't format the text. The first edit is
+    /// applied, replacing only the lines it changes so that selections
+    /// are kept. Returns an error if the request ends without an edit.
+    pub(crate) fn apply_format_edit(&mut self, id: usize, edit: Option<PluginEdit>)
+        -> Result<(), String>
+    {
+        let rev = match self.format_request {
+            Some(ref mut request) if request.id == id => {
+                // a plugin may answer more than once
+                request.unanswered = request.unanswered.saturating_sub(1);
+                if edit.is_none() && request.unanswered > 0 {
+                    return Ok(());
+                }
+                request.rev
+            }
+            _ => return Ok(()),
+        };
+        self.format_request = None;
+        let edit = edit.ok_or_else(|| "no plugin formatted the text".to_owned())?;
+        if edit.rev != rev || rev != self.get_head_rev_token() {
+            return Err("the text changed while it was being formatted".into());
+        }
+        if edit.delta.base_len != self.text.len() {
+            return Err("a plugin sent an invalid format edit".into());
+        }
+        let text = edit.delta.apply(&self.text);
+        self.replace_text(text);
+        Ok(())
+    }
+
+    /// Drops the format request if its deadline has passed by `now`,
+    /// returning whether it did.
+    pub(crate) fn format_timed_out(&mut self, now: Instant) -> bool {
+        if self.format_request.as_ref().map_or(false, |r| r.deadline <= now) {
+            self.format_request = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Merges the changes made to the file on disk, which now holds
+    /// `disk_text`, into the buffer, as a single edit. Returns the lines
+    /// of the conflicts between them, which are marked in the buffer.
+    pub(crate) fn merge_from_disk(&mut self, disk_text: Rope) -> Vec<Range<usize>> {
+        let merge = diff::merge3(&self.saved_text, &self.text, &disk_text);
+        let delta = diff::line_delta(&self.text, &merge.text);
+        if !delta.is_identity() {
+            self.this_edit_type = EditType::Other;
+            self.add_delta(delta);
+        }
+        if String::from(&self.text) == String::from(&disk_text) {
+            self.set_pristine();
+        } else {
+            self.saved_text = disk_text;
+            self.diff_base_changed |= self.diff_base.is_none();
+        }
+        merge.conflicts
+    }
+
+    /// Replaces the conflict numbered `index`, or every conflict if it is
+    /// `None`, with the given side of it.
+    fn resolve_conflicts(&mut self, index: Option<usize>, side: ConflictSide) {
+        let conflicts = diff::find_conflicts(&self.text);
+        let mut builder = delta::Builder::new(self.text.len());
+        for (i, conflict) in conflicts.into_iter().enumerate() {
+            if index.map(|index| index != i).unwrap_or(false) {
+                continue;
+            }
+            let kept = match side {
+                ConflictSide::Buffer => self.text.slice(conflict.buffer.start, conflict.buffer.end),
+                ConflictSide::Disk => self.text.slice(conflict.disk.start, conflict.disk.end),
+                ConflictSide::Both => {
+                    let mut both = String::from(
+                        self.text.slice(conflict.buffer.start, conflict.buffer.end));
+                    both.push_str(&String::from(
+                        self.text.slice(conflict.disk.start, conflict.disk.end)));
+                    Rope::from(both)
+                }
+            };
+            let iv = Interval::new_closed_open(conflict.range.start, conflict.range.end);
+            builder.replace(iv, kept);
+        }
+        if !builder.is_empty() {
+            self.this_edit_type = EditType::Other;
+            self.add_delta(builder.build());
+        }
+    }
+
+    /// Converts every line ending in the buffer to `line_ending`, as a
+    /// single edit. Returns `false` if there was nothing to convert.
+    pub(crate) fn convert_line_endings(&mut self, line_ending: LineEnding) -> bool {
+        let mut builder = delta::Builder::new(self.text.len());
+        let mut changed = false;
+        let mut offset = 0;
+        let mut prev = 0;
+        for chunk in self.text.iter_chunks(0, self.text.len()) {
+            for (i, &b) in chunk.as_bytes().iter().enumerate() {
+                let after_cr = prev == b'\r';
+                prev = b;
+                if b != b'\n' {
+                    continue;
+                }
+                let pos = offset + i;
+                match (line_ending, after_cr) {
+                    (LineEnding::Lf, true) =>
+                        builder.delete(Interval::new_closed_open(pos - 1, pos)),
+                    (LineEnding::CrLf, false) =>
+                        builder.replace(Interval::new_closed_open(pos, pos), Rope::from("\r")),
+                    _ => continue,
+                }
+                changed = true;
+            }
+            offset += chunk.len();
+        }
+        if changed {
+            self.this_edit_type = EditType::Other;
+            self.add_delta(builder.build());
+        }
+        changed
+    }
+
+    /// Writes the undo history of this buffer, so that it can be restored
+    /// with `restore_undo_history` when the same text is loaded again.
+    pub(crate) fn save_undo_history<W: Write>(&self, writer: W)
+        -> serde_json::Result<()>
+    {
+        let history = UndoHistory {
+            text_hash: text_hash(&self.text),
+            engine: &self.engine,
+            undo_group_id: self.undo_group_id,
+            undo_tree: self.undo_tree.clone(),
+            undos: self.undos.clone(),
+            gc_undos: self.gc_undos.clone(),
+        };
+        serde_json::to_writer(writer, &history)
+    }
+
+    /// Replaces the history of this buffer with one written by
+    /// `save_undo_history`. Returns `false`, leaving the buffer untouched,
+    /// if the history was saved for different text.
+    pub(crate) fn restore_undo_history<R: Read>(&mut self, reader: R)
+        -> serde_json::Result<bool>
+    {
+        let history: UndoHistory<Engine> = serde_json::from_reader(reader)?;
+        let hash = text_hash(&self.text);
+        if history.text_hash != hash
+            || text_hash(history.engine.get_head()) != hash {
+            return Ok(false);
+        }
+
+        self.engine = history.engine;
+        self.engine.continue_session();
+        self.text = self.engine.get_head().clone();
+        self.last_rev_id = self.engine.get_head_rev_id();
+        self.pristine_rev_id = self.last_rev_id;
+        self.last_synced_rev = self.last_rev_id;
+        self.undo_group_id = history.undo_group_id;
+        self.undo_tree = history.undo_tree;
+        self.undos = history.undos;
+        self.gc_undos = history.gc_undos;
+        Ok(true)
+    }
+
     /// Sets the config for this buffer. If the new config differs
     /// from the existing config, returns the modified items.
     pub fn set_config(&mut self, conf: BufferConfig) -> Option<Table> {
         if let Some(changes) = conf.changes_from(Some(&self.config)) {
             self.config = conf;
+            let tab_size = self.config.items.tab_size;
+            if self.indent_levels.as_ref().map_or(false, |l| l.tab_size() != tab_size) {
+                self.indent_levels = Some(IndentLevels::new(&self.text, tab_size));
+            }
+            self.indent_rules = IndentRules::from_config(&self.config.items);
             Some(changes)
         } else {
             None
@@ -219,6 +599,142 @@ impl Editor {
         self.add_delta(builder.build());
     }
 
+    /// Returns the selection that the last committed edit leaves, if it
+    /// isn't the one the edit transforms the old selection into.
+    pub(crate) fn take_selection_for_edit(&mut self) -> Option<Selection> {
+        self.selection_for_edit.take()
+    }
+
+    /// Returns the pairs of characters closed as they are typed, or none
+    /// if that is turned off.
+    fn auto_pairs(&self) -> Vec<(char, char)> {
+        if !self.config.items.auto_close_pairs {
+            return Vec::new();
+        }
+        self.config.items.auto_pairs.iter()
+            .filter_map(|&(ref open, ref close)| {
+                let mut open_chars = open.chars();
+                let mut close_chars = close.chars();
+                match (open_chars.next(), open_chars.next(),
+                       close_chars.next(), close_chars.next()) {
+                    (Some(open), None, Some(close), None) => Some((open, close)),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Inserts `c` at each selection, closing pairs: an opening character
+    /// wraps a selection in the pair, or is inserted with the closing one
+    /// after a caret, and a closing character inserted that way is typed
+    /// over. A line that `c` makes match the decrease indent pattern is
+    /// dedented.
+    fn insert_char(&mut self, view: &mut View, c: char, pairs: &[(char, char)]) {
+        let opening = pairs.iter().find(|pair| pair.0 == c).cloned();
+        let closing = pairs.iter().any(|pair| pair.1 == c);
+        let caret_lines = view.sel_regions().iter()
+            .map(|region| self.text.line_of_offset(region.end))
+            .collect::<Vec<_>>();
+        // typing over only applies on the lines being edited
+        let text = &self.text;
+        self.auto_closed.retain(|&offset| offset < text.len()
+                                && caret_lines.contains(&text.line_of_offset(offset)));
+
+        let mut builder = delta::Builder::new(self.text.len());
+        let mut selection = Selection::new();
+        let mut auto_closed = Vec::new();
+        // how much longer the text before the current region has become
+        let mut shift = 0isize;
+        // where the last replaced text ends, which reindenting can't precede
+        let mut last_end = 0;
+        let moved = |offset: usize, shift: isize| (offset as isize + shift) as usize;
+        for region in view.sel_regions() {
+            let next = char_after(&self.text, region.max());
+            let prev = char_before(&self.text, region.min());
+            let iv = Interval::new_closed_open(region.min(), region.max());
+            let typeover = region.is_caret() && closing && next == Some(c)
+                && self.auto_closed.contains(&region.end);
+            if typeover {
+                self.auto_closed.retain(|&offset| offset != region.end);
+                let offset = moved(region.end + c.len_utf8(), shift);
+                selection.add_region(SelRegion::caret(offset));
+            } else if let Some((open, close)) = opening.filter(|_| !region.is_caret()) {
+                // the selected text is left as it is, keeping its styles
+                let (min, max) = (region.min(), region.max());
+                builder.replace(Interval::new_closed_open(min, min), Rope::from(open.to_string()));
+                builder.replace(Interval::new_closed_open(max, max), Rope::from(close.to_string()));
+                let start = moved(region.start + open.len_utf8(), shift);
+                let end = moved(region.end + open.len_utf8(), shift);
+                selection.add_region(SelRegion::new(start, end));
+                shift += (open.len_utf8() + close.len_utf8()) as isize;
+            } else if let Some((open, close)) = opening.filter(|&pair| can_close(pair, prev, next, pairs)) {
+                builder.replace(iv, Rope::from(format!("{}{}", open, close)));
+                let offset = moved(region.end + open.len_utf8(), shift);
+                selection.add_region(SelRegion::caret(offset));
+                auto_closed.push(offset);
+                shift += (open.len_utf8() + close.len_utf8()) as isize;
+            } else if let Some((start, line)) = self.dedent_for_char(region, c, last_end) {
+                builder.replace(Interval::new_closed_open(start, region.max()),
+                                Rope::from(line.as_str()));
+                let offset = moved(start + line.len(), shift);
+                selection.add_region(SelRegion::caret(offset));
+                shift += line.len() as isize - (region.max() - start) as isize;
+            } else {
+                builder.replace(iv, Rope::from(c.to_string()));
+                let offset = moved(region.min() + c.len_utf8(), shift);
+                selection.add_region(SelRegion::caret(offset));
+                shift += c.len_utf8() as isize - (region.max() - region.min()) as isize;
+            }
+            last_end = region.max();
+        }
+
+        if builder.is_empty() {
+            view.set_selection(&self.text, selection);
+            return;
+        }
+        self.this_edit_type = EditType::InsertChars;
+        self.add_delta(builder.build());
+        self.selection_for_edit = Some(selection);
+        self.new_auto_closed = auto_closed;
+    }
+
+    /// If typing `c` over `region` makes its line match the decrease indent
+    /// pattern, returns where the line starts and the dedented text up to
+    /// and including `c`. Lines indented other than the line before them
+    /// suggests are left alone, as are lines starting before `last_end`.
+    fn dedent_for_char(&self, region: &SelRegion, c: char, last_end: usize)
+        -> Option<(usize, String)>
+    {
+        let rules = self.indent_rules.as_ref()?;
+        let line = self.text.line_of_offset(region.min());
+        let start = self.text.offset_of_line(line);
+        let ws_end = start + indent::leading_whitespace_len(&self.text, start);
+        if start < last_end || region.min() < ws_end {
+            return None;
+        }
+        let prefix = self.text.slice_to_string(ws_end, region.min());
+        let end = self.text.offset_of_line(self.text.line_of_offset(region.max()) + 1);
+        let rest = self.text.slice_to_string(region.max(), end);
+        let rest = rest.trim_end_matches(|c| c == '\n' || c == '\r');
+        if rules.decreases(&format!("{}{}", prefix, rest))
+            || !rules.decreases(&format!("{}{}{}", prefix, c, rest)) {
+            return None;
+        }
+        let width = rules.width(&self.text.slice_to_string(start, ws_end));
+        let prev = indent::prev_nonblank_line(&self.text, line);
+        if width == 0 || width != rules.indent_after(prev.as_ref().map(String::as_str)) {
+            return None;
+        }
+        let indent = self.indent_text(width - rules.unit().min(width));
+        Some((start, format!("{}{}{}", indent, prefix, c)))
+    }
+
+    /// Returns the whitespace for an indentation `width` columns wide.
+    fn indent_text(&self, width: usize) -> String {
+        indent::indent_text(width, self.config.items.tab_size,
+                            self.config.items.translate_tabs_to_spaces)
+    }
+
     /// Applies a delta to the text, and updates undo state.
     ///
     /// Records the delta into the CRDT engine so that it can be undone. Also
@@ -237,27 +753,34 @@ impl Editor {
         if self.this_edit_type == self.last_edit_type
             && self.this_edit_type != EditType::Other
             && self.this_edit_type != EditType::Transpose
-            && !self.live_undos.is_empty()
         {
-            undo_group = *self.live_undos.last().unwrap();
+            undo_group = self.undo_tree.current();
         } else {
-            undo_group = self.undo_group_id;
-            self.gc_undos.extend(&self.live_undos[self.cur_undo..]);
-            self.live_undos.truncate(self.cur_undo);
-            self.live_undos.push(undo_group);
-            if self.live_undos.len() <= MAX_UNDOS {
-                self.cur_undo += 1;
-            } else {
-                self.gc_undos.insert(self.live_undos.remove(0));
-            }
-            self.undo_group_id += 1;
+            undo_group = self.new_undo_group();
+            self.undo_tree.push(undo_group);
+            let pruned = self.undo_tree.prune(MAX_UNDOS, MAX_UNDO_STATES);
+            self.gc_undos.extend(pruned);
         }
+        self.undo_tree.touch();
         self.last_edit_type = self.this_edit_type;
         let priority = 0x10000;
         self.engine.edit_rev(priority, undo_group, head_rev_id.token(), delta);
         self.text = self.engine.get_head().clone();
     }
 
+    /// Allocates a new undo group. While syncing, the group is congruent
+    /// to our session slot, so that other sessions can't allocate it
+    /// concurrently.
+    fn new_undo_group(&mut self) -> usize {
+        let mut group = self.undo_group_id;
+        if let Some((_, slot)) = self.sync_session {
+            let slot = slot as usize % MAX_SESSIONS;
+            group += (slot + MAX_SESSIONS - group % MAX_SESSIONS) % MAX_SESSIONS;
+        }
+        self.undo_group_id = group + 1;
+        group
+    }
+
     /// generates a delta from a plugin's response and applies it to the buffer.
     pub fn apply_plugin_edit(&mut self, edit: PluginEdit) {
         let _t = trace_block("Editor::apply_plugin_edit", &["core"]);
@@ -295,15 +818,42 @@ impl Editor {
 
         let keep_selections = self.this_edit_type == EditType::Transpose;
         self.layers.update_all(&delta);
+        for ranges in self.fold_ranges.values_mut() {
+            fold::transform_ranges(ranges, &delta);
+        }
+        if let Some(ref mut levels) = self.indent_levels {
+            levels.apply_delta(&delta, &self.text);
+        }
+        if !self.auto_closed.is_empty() {
+            let mut transformer = Transformer::new(&delta);
+            for offset in &mut self.auto_closed {
+                *offset = transformer.transform(*offset, true);
+            }
+        }
+        self.auto_closed.append(&mut self.new_auto_closed);
 
         self.last_rev_id = self.engine.get_head_rev_id();
-        self.sync_state_changed();
+        // the ledger doesn't share selections, and merges new states
+        // without an `EventContext` to sync them
+        #[cfg(target_os = "fuchsia")]
+        self.sync_state_changed(&[]);
         Some((delta, last_text, keep_selections))
     }
 
     #[cfg(not(target_os = "fuchsia"))]
     fn gc_undos(&mut self) {
-        if self.revs_in_flight == 0 && !self.gc_undos.is_empty() {
+        if self.revs_in_flight != 0 {
+            return;
+        }
+        if let Some(sync_store) = self.sync_store.as_mut() {
+            // other sessions merge against our revisions, so only what
+            // they agreed to collect can be
+            if let Some(groups) = self.sync_collect.take() {
+                self.engine.gc(&groups);
+                self.gc_undos = &self.gc_undos - &groups;
+                sync_store.collected(&self.engine, &self.last_synced_selections);
+            }
+        } else if !self.gc_undos.is_empty() {
             self.engine.gc(&self.gc_undos);
             self.undos = &self.undos - &self.gc_undos;
             self.gc_undos.clear();
@@ -316,16 +866,51 @@ impl Editor {
         // last_rev_id and so that merge will work.
     }
 
+    /// Merges the state of another session into this buffer. The change
+    /// is not committed; see `EventContext::merge_sync_state`.
     pub fn merge_new_state(&mut self, new_engine: Engine) {
-        self.engine.merge(&new_engine);
+        self.merge_engine(&new_engine);
+    }
+
+    /// Merges revisions another session sent into this buffer, and returns
+    /// the delta from that session's text to the merged text. The change
+    /// is not committed; see `EventContext::merge_sync_state`.
+    ///
+    /// The revisions come from another process, so if they can't be merged
+    /// the buffer is left unchanged and an error is returned, even if the
+    /// merge panics.
+    pub(crate) fn merge_sync_revisions(&mut self, revisions: &Revisions)
+        -> Result<Delta<RopeInfo>, String>
+    {
+        let engine = &mut self.engine;
+        let merged = panic::catch_unwind(AssertUnwindSafe(|| engine.merge_revisions(revisions)));
+        let delta = match merged {
+            Ok(Ok(delta)) => delta,
+            Ok(Err(e)) => return Err(e.to_string()),
+            Err(_) => return Err("merging the revisions failed".to_owned()),
+        };
+        self.text = self.engine.get_head().clone();
+        self.undo_group_id = self.engine.max_undo_group_id() + 1;
+        Ok(delta)
+    }
+
+    /// Garbage collects `groups`, which every session sharing this buffer
+    /// agreed to collect, as soon as no plugin holds on to a revision.
+    /// `SyncStore::collected` is called once they are.
+    pub(crate) fn collect_synced(&mut self, groups: BTreeSet<usize>) {
+        if self.sync_store.is_some() {
+            self.sync_collect = Some(groups);
+            self.gc_undos();
+        }
+    }
+
+    fn merge_engine(&mut self, new_engine: &Engine) {
+        self.engine.merge(new_engine);
         self.text = self.engine.get_head().clone();
         // TODO: better undo semantics. This only implements separate undo
         // histories for low concurrency.
         self.undo_group_id = self.engine.max_undo_group_id() + 1;
         self.last_synced_rev = self.engine.get_head_rev_id();
-        self.commit_delta();
-        //self.render();
-        //FIXME: render after fuchsia sync
     }
 
     /// See `Engine::set_session_id`. Only useful for Fuchsia sync.
@@ -333,27 +918,84 @@ impl Editor {
         self.engine.set_session_id(session);
     }
 
-    #[cfg(feature = "ledger")]
-    pub fn set_sync_store(&mut self, sync_store: SyncStore) {
+    pub fn set_sync_store(&mut self, sync_store: Box<SyncStore>) {
+        self.sync_store = Some(sync_store);
+    }
+
+    /// Starts sharing this buffer through `sync_store`, as `session`.
+    ///
+    /// Engines can only be merged if they have the same first revision,
+    /// and don't contain undo revisions, so the engine is replaced by a
+    /// new one that holds the current text as undo group `0`; the undo
+    /// history is discarded. A session that joins a shared buffer should
+    /// start from an empty buffer, and merge the host's state.
+    pub fn start_sync(&mut self, session: SessionId, sync_store: Box<SyncStore>) {
+        let was_pristine = self.is_pristine();
+        let mut engine = Engine::empty();
+        engine.set_session_id(session);
+        let first_rev = engine.get_head_rev_id();
+        if self.text.len() > 0 {
+            let iv = Interval::new_closed_open(0, 0);
+            let delta = Delta::simple_edit(iv, self.text.clone(), 0);
+            engine.edit_rev(0, 0, first_rev.token(), delta);
+        }
+
+        self.engine = engine;
+        self.last_rev_id = self.engine.get_head_rev_id();
+        self.last_synced_rev = self.last_rev_id;
+        self.last_synced_selections.clear();
+        self.pristine_rev_id = if was_pristine { self.last_rev_id } else { first_rev };
+        self.undo_group_id = 1;
+        self.undo_tree = UndoTree::new(0);
+        self.undos.clear();
+        self.gc_undos.clear();
+        self.sync_undos.clear();
+        self.sync_collect = None;
+        self.last_edit_type = EditType::Other;
+        self.sync_session = Some(session);
         self.sync_store = Some(sync_store);
     }
 
-    #[cfg(not(feature = "ledger"))]
-    pub fn sync_state_changed(&mut self) {
+    /// Stops sharing this buffer. The undo history is kept.
+    pub fn stop_sync(&mut self) {
+        self.sync_store = None;
+        self.sync_collect = None;
+        if self.sync_session.take().is_some() {
+            // Undo for real what was undone with edits. The text is the same
+            // either way, and the edits that did the undoing can be collected.
+            let sync_undos = mem::replace(&mut self.sync_undos, BTreeSet::new());
+            self.undos.extend(&sync_undos);
+            self.gc_undos.extend(sync_undos);
+            self.engine.undo(self.undos.clone());
+            self.gc_undos();
+        }
     }
 
-    #[cfg(feature = "ledger")]
-    pub fn sync_state_changed(&mut self) {
+    /// Returns the engine, for sending to other sessions.
+    pub(crate) fn get_engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Returns the session this buffer is synced as, if any.
+    pub(crate) fn sync_session(&self) -> Option<SessionId> {
+        self.sync_session
+    }
+
+    /// Shares the state of the buffer and `selections`, if either has
+    /// changed since it was last shared.
+    pub fn sync_state_changed(&mut self, selections: &[SessionSelection]) {
         if let Some(sync_store) = self.sync_store.as_mut() {
             // we don't want to sync right after recieving a new merge
-            if self.last_synced_rev != self.engine.get_head_rev_id() {
+            if self.last_synced_rev != self.engine.get_head_rev_id()
+                || self.last_synced_selections[..] != *selections {
                 self.last_synced_rev = self.engine.get_head_rev_id();
-                sync_store.state_changed();
+                self.last_synced_selections = selections.to_owned();
+                sync_store.collectible(&(&self.gc_undos - &self.undos));
+                sync_store.state_changed(&self.engine, selections);
             }
         }
     }
 
-    #[cfg(feature = "ledger")]
     pub fn transaction_ready(&mut self) {
         if let Some(sync_store) = self.sync_store.as_mut() {
             sync_store.commit_transaction(&self.engine);
@@ -363,8 +1005,23 @@ impl Editor {
     fn delete_backward(&mut self, view: &View) {
         // TODO: this function is workable but probably overall code complexity
         // could be improved by implementing a "backspace" movement instead.
+        let pairs = self.auto_pairs();
         let mut builder = delta::Builder::new(self.text.len());
         for region in view.sel_regions() {
+            // backspace in an empty pair deletes both of its characters
+            let empty_pair = if region.is_caret() && !pairs.is_empty() {
+                let prev = char_before(&self.text, region.end);
+                let next = char_after(&self.text, region.end);
+                let around = prev.and_then(|prev| next.map(|next| (prev, next)));
+                pairs.iter().find(|&&pair| Some(pair) == around).cloned()
+            } else {
+                None
+            };
+            if let Some((open, close)) = empty_pair {
+                builder.delete(Interval::new_closed_open(region.end - open.len_utf8(),
+                                                         region.end + close.len_utf8()));
+                continue;
+            }
             let start = if !region.is_caret() {
                 region.min()
             } else {
@@ -465,7 +1122,56 @@ impl Editor {
     fn insert_newline(&mut self, view: &View) {
         self.this_edit_type = EditType::InsertChars;
         let text = self.config.items.line_ending.clone();
-        self.insert(view, &text);
+        if self.indent_rules.is_none() {
+            return self.insert(view, &text);
+        }
+
+        let mut builder = delta::Builder::new(self.text.len());
+        let mut selection = Selection::new();
+        let mut shift = 0isize;
+        for region in view.sel_regions() {
+            let (end, inserted, caret) = self.indented_newline(region, &text);
+            builder.replace(Interval::new_closed_open(region.min(), end),
+                            Rope::from(inserted.as_str()));
+            let offset = region.min() as isize + shift + caret as isize;
+            selection.add_region(SelRegion::caret(offset as usize));
+            shift += inserted.len() as isize - (end - region.min()) as isize;
+        }
+        self.add_delta(builder.build());
+        self.selection_for_edit = Some(selection);
+    }
+
+    /// Returns the end of the text a newline typed over `region` replaces,
+    /// which includes whitespace after the region, the text replacing it,
+    /// and how far into that text the caret goes. The new line is indented
+    /// like the line before it, or one level more or less according to the
+    /// indent patterns; a caret between an opening token and a closing one
+    /// gets a line of its own.
+    fn indented_newline(&self, region: &SelRegion, line_ending: &str) -> (usize, String, usize) {
+        let rules = self.indent_rules.as_ref().unwrap();
+        let start = self.text.offset_of_line(self.text.line_of_offset(region.min()));
+        let before = self.text.slice_to_string(start, region.min());
+        let line_end = self.text.offset_of_line(self.text.line_of_offset(region.max()) + 1);
+        let rest = self.text.slice_to_string(region.max(), line_end);
+        let rest = rest.trim_end_matches(|c| c == '\n' || c == '\r');
+        let rest_ws = rest.len() - rest.trim_start_matches(|c| c == ' ' || c == '\t').len();
+        let rest = &rest[rest_ws..];
+
+        let width = rules.indent_after(Some(&before));
+        let closing = !rest.is_empty() && rules.decreases(rest);
+        let dedented = width - rules.unit().min(width);
+        let end = region.max() + rest_ws;
+        if closing && rules.increases(&before) {
+            let inserted = format!("{}{}", line_ending, self.indent_text(width));
+            let caret = inserted.len();
+            let inserted = format!("{}{}{}", inserted, line_ending, self.indent_text(dedented));
+            (end, inserted, caret)
+        } else {
+            let width = if closing { dedented } else { width };
+            let inserted = format!("{}{}", line_ending, self.indent_text(width));
+            let caret = inserted.len();
+            (end, inserted, caret)
+        }
     }
 
     fn insert_tab(&mut self, view: &View) {
@@ -577,7 +1283,14 @@ impl Editor {
 
     // TODO: insert from keyboard or input method shouldn't break undo group,
     // but paste should.
-    fn do_insert(&mut self, view: &View, chars: &str) {
+    fn do_insert(&mut self, view: &mut View, chars: &str) {
+        let pairs = self.auto_pairs();
+        let mut chars_iter = chars.chars();
+        if let (Some(c), None) = (chars_iter.next(), chars_iter.next()) {
+            if !pairs.is_empty() || self.indent_rules.is_some() {
+                return self.insert_char(view, c, &pairs);
+            }
+        }
         self.this_edit_type = EditType::InsertChars;
         self.insert(view, chars);
     }
@@ -598,29 +1311,122 @@ impl Editor {
         }
     }
 
+    /// Replaces find occurrences in `view` with `template` (see
+    /// `View::find_replacements`), as a single undo group. Returns the number
+    /// of occurrences replaced.
+    pub(crate) fn do_replace(&mut self, view: &View, template: &str,
+                             all: bool, query: Option<&str>) -> usize {
+        let replacements = view.find_replacements(&self.text, template,
+                                                  all, query);
+        if replacements.is_empty() {
+            return 0;
+        }
+        let mut builder = delta::Builder::new(self.text.len());
+        for &(iv, ref replacement) in replacements.iter() {
+            builder.replace(iv, Rope::from(replacement.as_str()));
+        }
+        self.this_edit_type = EditType::Other;
+        self.add_delta(builder.build());
+        replacements.len()
+    }
+
     fn do_undo(&mut self) {
-        if self.cur_undo > 1 {
-            self.cur_undo -= 1;
-            assert!(self.undos.insert(self.live_undos[self.cur_undo]));
+        if let Some(group) = self.undo_tree.undo() {
+            assert!(self.undos.insert(group));
             self.this_edit_type = EditType::Undo;
             self.update_undos();
         }
     }
 
     fn do_redo(&mut self) {
-        if self.cur_undo < self.live_undos.len() {
-            assert!(self.undos.remove(&self.live_undos[self.cur_undo]));
-            self.cur_undo += 1;
+        if let Some(group) = self.undo_tree.redo() {
+            assert!(self.undos.remove(&group));
             self.this_edit_type = EditType::Redo;
             self.update_undos();
         }
     }
 
+    /// Moves to the undo state `id`, which may be on a different branch
+    /// of the undo tree.
+    fn goto_undo_state(&mut self, id: usize) {
+        if id == self.undo_tree.current() || !self.undo_tree.jump(id) {
+            return;
+        }
+        self.undos = self.undos_for_current_state(&self.undo_tree);
+        self.this_edit_type = EditType::Undo;
+        self.update_undos();
+    }
+
+    /// Returns the undo groups to undo for the current state of `tree`.
+    /// Groups that are pending gc are no longer in the tree, and keep their
+    /// current state.
+    fn undos_for_current_state(&self, tree: &UndoTree) -> BTreeSet<usize> {
+        let pending = self.undos.intersection(&self.gc_undos).cloned();
+        tree.undone_groups().into_iter().chain(pending).collect()
+    }
+
+    fn undo_earlier(&mut self, step: TimeStep) {
+        let id = self.undo_tree.earlier(step);
+        self.goto_undo_state(id);
+    }
+
+    fn undo_later(&mut self, step: TimeStep) {
+        let id = self.undo_tree.later(step);
+        self.goto_undo_state(id);
+    }
+
+    /// Returns the states of the undo tree, and which of them is current.
+    pub(crate) fn get_undo_tree(&self) -> Value {
+        let states = self.undo_tree.states()
+            .map(|state| json!({
+                "id": state.id,
+                "parent": state.parent,
+                "timestamp": state.timestamp,
+            }))
+            .collect::<Vec<_>>();
+        json!({
+            "current": self.undo_tree.current(),
+            "states": states,
+        })
+    }
+
+    /// Returns a delta that transforms the text of undo state `id` into the
+    /// current text, or `None` if there is no such state.
+    pub(crate) fn undo_state_diff(&self, id: usize) -> Option<Delta<RopeInfo>> {
+        let mut tree = self.undo_tree.clone();
+        if !tree.jump(id) {
+            return None;
+        }
+        let undos = self.undos_for_current_state(&tree);
+        Some(self.engine.delta_undo_head(&undos))
+    }
+
     fn update_undos(&mut self) {
-        self.engine.undo(self.undos.clone());
+        if self.sync_session.is_some() {
+            self.update_undos_with_edit();
+        } else {
+            self.engine.undo(self.undos.clone());
+        }
         self.text = self.engine.get_head().clone();
     }
 
+    /// Undo revisions can't be merged, so while syncing the text is brought
+    /// to the undo state by an ordinary edit instead. The edits made this
+    /// way are themselves undone when computing each new state, so the
+    /// original groups can still be redone.
+    fn update_undos_with_edit(&mut self) {
+        let mut groups = self.undos.clone();
+        groups.extend(&self.sync_undos);
+        let delta = self.engine.delta_head_undo(&groups);
+        if delta.is_identity() {
+            return;
+        }
+        let undo_group = self.new_undo_group();
+        let head_rev_id = self.engine.get_head_rev_id();
+        self.engine.edit_rev(0x10000, undo_group, head_rev_id.token(), delta);
+        self.sync_undos.insert(undo_group);
+    }
+
     fn sel_region_to_interval_and_rope(&self, region: SelRegion) -> (Interval, Rope) {
         let as_interval = Interval::new_closed_open(region.min(), region.max());
         let interval_rope = Rope::from(self.text.slice_to_string(
@@ -685,6 +1491,351 @@ impl Editor {
         }
     }
 
+    /// Returns the ranges of the lines spanned by the selections, from the
+    /// start of each one's first line to the start of the line after its
+    /// last. Wrapped lines and lines hidden by folds are included whole.
+    /// Ranges that overlap, or touch if `merge_adjacent` is set, are merged.
+    fn line_blocks(&self, view: &View, merge_adjacent: bool) -> Vec<Range<usize>> {
+        self.line_blocks_of(view, view.sel_regions(), merge_adjacent)
+    }
+
+    /// Returns the ranges of the lines spanned by `regions`, as with
+    /// `line_blocks`.
+    fn line_blocks_of(&self, view: &View, regions: &[SelRegion], merge_adjacent: bool)
+        -> Vec<Range<usize>>
+    {
+        let mut blocks: Vec<Range<usize>> = Vec::new();
+        for region in regions {
+            let lines = view.get_line_range(&self.text, region);
+            let start = view.offset_of_line(&self.text, lines.start);
+            let end = view.offset_of_line(&self.text, lines.end);
+            let start = self.text.offset_of_line(self.text.line_of_offset(start));
+            let end_line = self.text.line_of_offset(end);
+            let end = if self.text.offset_of_line(end_line) == end {
+                end
+            } else {
+                self.text.offset_of_line(end_line + 1)
+            };
+            match blocks.last_mut() {
+                Some(ref mut last) if start < last.end || merge_adjacent && start == last.end =>
+                    last.end = last.end.max(end),
+                _ => blocks.push(start..end),
+            }
+        }
+        blocks
+    }
+
+    /// Returns the line ending used by the text of `range`, or if it has
+    /// none, the configured one.
+    fn line_ending_of(&self, range: &Range<usize>) -> String {
+        let text = self.text.slice_to_string(range.start, range.end);
+        match text.find('\n') {
+            Some(i) if i > 0 && text.as_bytes()[i - 1] == b'\r' => "\r\n".into(),
+            Some(_) => "\n".into(),
+            None => self.config.items.line_ending.clone(),
+        }
+    }
+
+    /// Applies `edits`, which are sorted and disjoint, as one undoable edit.
+    /// A selection inside an edit's range is moved by its `moved` bytes,
+    /// staying within the new text; other selections keep their place.
+    fn apply_line_edits(&mut self, view: &View, edits: &[LineEdit]) {
+        if edits.is_empty() {
+            return;
+        }
+        let mut builder = delta::Builder::new(self.text.len());
+        for edit in edits {
+            let iv = Interval::new_closed_open(edit.range.start, edit.range.end);
+            builder.replace(iv, Rope::from(edit.text.as_str()));
+        }
+
+        let new_starts = edits.iter()
+            .scan(0isize, |shift, edit| {
+                let start = (edit.range.start as isize + *shift) as usize;
+                *shift += edit.text.len() as isize - edit.range.len() as isize;
+                Some(start)
+            })
+            .collect::<Vec<_>>();
+        let moved = |i: usize, offset: usize| {
+            let edit = &edits[i];
+            let offset = offset as isize - edit.range.start as isize + edit.moved;
+            new_starts[i] + (offset.max(0) as usize).min(edit.text.len())
+        };
+        let shifted = |offset: usize| {
+            match edits.iter().rposition(|edit| edit.range.start < offset) {
+                Some(i) if offset < edits[i].range.end => moved(i, offset),
+                Some(i) => new_starts[i] + edits[i].text.len() + offset - edits[i].range.end,
+                None => offset,
+            }
+        };
+        let mut selection = Selection::new();
+        for region in view.sel_regions() {
+            let region = match edits.iter().rposition(
+                |edit| edit.range.start <= region.min() && region.max() <= edit.range.end) {
+                Some(i) => SelRegion::new(moved(i, region.start), moved(i, region.end)),
+                None => SelRegion::new(shifted(region.start), shifted(region.end)),
+            };
+            selection.add_region(region);
+        }
+        self.this_edit_type = EditType::Other;
+        self.add_delta(builder.build());
+        self.selection_for_edit = Some(selection);
+    }
+
+    /// Duplicates each selection after itself, and the lines of each caret
+    /// below them, moving the selections to the copies.
+    fn duplicate_lines(&mut self, view: &View) {
+        let blocks = self.line_blocks(view, false);
+        let mut edits: Vec<LineEdit> = Vec::new();
+        for region in view.sel_regions() {
+            let range = if region.is_caret() {
+                blocks.iter().rev().find(|b| b.start <= region.start && region.start <= b.end)
+                    .cloned().unwrap()
+            } else {
+                region.min()..region.max()
+            };
+            if edits.last().map_or(false, |last| range.start < last.range.end) {
+                continue;
+            }
+            let text = self.text.slice_to_string(range.start, range.end);
+            let separator = if region.is_caret() && !text.ends_with('\n') {
+                self.line_ending_of(&range)
+            } else {
+                String::new()
+            };
+            let moved = (text.len() + separator.len()) as isize;
+            let text = format!("{}{}{}", text, separator, text);
+            edits.push(LineEdit { range, text, moved });
+        }
+        self.apply_line_edits(view, &edits);
+    }
+
+    /// Swaps the lines of each selection with the line above or below them.
+    fn move_lines(&mut self, view: &View, up: bool) {
+        let mut edits = Vec::new();
+        for block in self.line_blocks(view, true) {
+            let line = self.text.line_of_offset(block.start);
+            let other = if up && block.start > 0 {
+                self.text.offset_of_line(line - 1)..block.start
+            } else if !up && block.end < self.text.len() {
+                let next = self.text.line_of_offset(block.end);
+                block.end..self.text.offset_of_line(next + 1)
+            } else {
+                continue;
+            };
+            let (first, second) = if up { (&block, &other) } else { (&other, &block) };
+            let first_text = self.text.slice_to_string(first.start, first.end);
+            let second_text = self.text.slice_to_string(second.start, second.end);
+            // the last line has no line ending to move with it
+            let ending = self.line_ending_of(&second);
+            let (first_text, second_text) = if first_text.ends_with('\n') {
+                (first_text, second_text)
+            } else {
+                let second_text = second_text[..second_text.len() - ending.len()].to_owned();
+                (first_text + &ending, second_text)
+            };
+            let moved = if up {
+                -(other.len() as isize)
+            } else {
+                first_text.len() as isize
+            };
+            let range = other.start.min(block.start)..other.end.max(block.end);
+            edits.push(LineEdit { range, text: first_text + &second_text, moved });
+        }
+        self.apply_line_edits(view, &edits);
+    }
+
+    /// Joins the lines of each selection, or a caret's line and the next,
+    /// replacing the whitespace around each line break with a space.
+    fn join_lines(&mut self, view: &View) {
+        let mut edits = Vec::new();
+        for block in self.line_blocks(view, false) {
+            let first = self.text.line_of_offset(block.start);
+            let mut last = self.text.line_of_offset(block.end);
+            if self.text.offset_of_line(last) == block.end && last > first {
+                last -= 1;
+            }
+            if last == first {
+                last += 1;
+            }
+            let max_line = self.text.line_of_offset(self.text.len());
+            // the text the next line is joined to, which blank lines don't end
+            let mut joined = String::new();
+            for line in first..last.min(max_line) {
+                let is_space = |c| c == ' ' || c == '\t';
+                let line_text = self.line_content(line);
+                let next_text = self.line_content(line + 1);
+                let before = line_text.trim_end_matches(is_space);
+                let after = next_text.trim_start_matches(is_space);
+                if !before.is_empty() {
+                    joined = before.to_owned();
+                }
+                // a blank line's whitespace went with the previous join
+                let start = (self.text.offset_of_line(line) + before.len())
+                    .max(edits.last().map_or(0, |e: &LineEdit| e.range.end));
+                let end = self.text.offset_of_line(line + 1) + next_text.len() - after.len();
+                let text = line_ops::join_separator(&joined, after).to_owned();
+                edits.push(LineEdit { range: start..end, text, moved: 0 });
+            }
+        }
+        self.apply_line_edits(view, &edits);
+    }
+
+    /// Sorts the lines of each selection, or with a single caret, of the
+    /// paragraph it is in, between blank lines.
+    fn sort_lines(&mut self, view: &View, options: SortOptions) {
+        let single_caret = view.sel_regions().len() == 1 && view.sel_regions()[0].is_caret();
+        let blocks = if single_caret {
+            self.paragraph_of(view.sel_regions()[0].end).into_iter().collect()
+        } else {
+            self.line_blocks(view, false)
+        };
+        let edits = blocks.into_iter()
+            .map(|range| {
+                let ending = self.line_ending_of(&range);
+                let text = self.text.slice_to_string(range.start, range.end);
+                let text = line_ops::sort_lines(&text, &ending, options);
+                LineEdit { range, text, moved: 0 }
+            })
+            .collect::<Vec<_>>();
+        self.apply_line_edits(view, &edits);
+    }
+
+    /// Returns the range of the lines around `offset` up to the blank lines
+    /// before and after them, or `None` if `offset` is on a blank line.
+    fn paragraph_of(&self, offset: usize) -> Option<Range<usize>> {
+        let is_blank = |line: usize| self.line_content(line).trim().is_empty();
+        let line = self.text.line_of_offset(offset);
+        if is_blank(line) {
+            return None;
+        }
+        let last_line = self.text.line_of_offset(self.text.len());
+        let first = (0..line).rev().take_while(|&l| !is_blank(l)).last().unwrap_or(line);
+        let last = (line + 1..=last_line).take_while(|&l| !is_blank(l)).last().unwrap_or(line);
+        Some(self.text.offset_of_line(first)..self.text.offset_of_line(last + 1))
+    }
+
+    /// Deletes the lines of each selection.
+    fn delete_lines(&mut self, view: &View) {
+        let mut edits: Vec<LineEdit> = Vec::new();
+        for mut block in self.line_blocks(view, true) {
+            // the last line takes the line ending before it along
+            let ends_line = block.start < block.end && self.text.byte_at(block.end - 1) == b'\n';
+            if block.end == self.text.len() && !ends_line && block.start > 0 {
+                let crlf = block.start >= 2 && self.text.byte_at(block.start - 2) == b'\r';
+                block.start -= if crlf { 2 } else { 1 };
+            }
+            if block.start < block.end {
+                edits.push(LineEdit { range: block, text: String::new(), moved: 0 });
+            }
+        }
+        self.apply_line_edits(view, &edits);
+    }
+
+    /// Comments out or uncomments the lines of each caret and multi-line
+    /// selection, and wraps or unwraps each selection within a line in the
+    /// block comment tokens. The lines are uncommented if all of them that
+    /// aren't blank are commented, and otherwise commented at the least
+    /// indentation of each selection's lines, so that the tokens line up.
+    fn toggle_comment(&mut self, view: &View) {
+        let tokens = CommentTokens::from_config(&self.config.items);
+        let (prefix, suffix) = match tokens.line_comment() {
+            Some(line_comment) => line_comment,
+            None => return,
+        };
+        let mut edits = Vec::new();
+        let mut line_regions = Vec::new();
+        for region in view.sel_regions() {
+            let text = self.text.slice_to_string(region.min(), region.max());
+            let line = self.text.line_of_offset(region.min());
+            let line_start = self.text.offset_of_line(line);
+            let in_line = !region.is_caret() && line == self.text.line_of_offset(region.max())
+                && text.trim() != self.line_content(line).trim();
+            let around = if in_line {
+                let before = self.text.slice_to_string(line_start, region.min());
+                let line_end = line_start + self.line_content(line).len();
+                let after = self.text.slice_to_string(region.max(), line_end);
+                tokens.wrapped_around(&before, &after)
+            } else {
+                None
+            };
+            if let Some((start, end)) = around {
+                edits.push(LineEdit { range: region.min() - start..region.max() + end,
+                                      text, moved: -(start as isize) });
+            } else if let Some((start, end)) = tokens.wrapped(&text).filter(|_| !region.is_caret()) {
+                let inner = text[start..text.len() - end].to_owned();
+                edits.push(LineEdit { range: region.min()..region.max(), text: inner,
+                                      moved: -(start as isize) });
+            } else if let Some(wrapped) = tokens.wrap(&text).filter(|_| in_line) {
+                let moved = tokens.block.as_ref().map_or(0, |block| block.0.len() + 1) as isize;
+                edits.push(LineEdit { range: region.min()..region.max(), text: wrapped, moved });
+            } else {
+                line_regions.push(*region);
+            }
+        }
+
+        let blocks = self.line_blocks_of(view, &line_regions, false);
+        let lines = blocks.iter()
+            .map(|block| {
+                let first = self.text.line_of_offset(block.start);
+                let last = self.text.line_of_offset(block.end);
+                let last = if last > first && self.text.offset_of_line(last) == block.end {
+                    last
+                } else {
+                    last + 1
+                };
+                (first..last).filter(|&line| !self.line_content(line).trim().is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let uncomment = lines.iter().flat_map(|lines| lines.iter())
+            .all(|&line| tokens.line_commented(self.line_content(line).trim_start()).is_some());
+        for lines in &lines {
+            let indent = lines.iter()
+                .map(|&line| indent::leading_whitespace_len(&self.text, self.text.offset_of_line(line)))
+                .min().unwrap_or(0);
+            for &line in lines {
+                let start = self.text.offset_of_line(line);
+                let content = self.line_content(line);
+                let content_end = start + content.trim_end().len();
+                if uncomment {
+                    let indent = content.len() - content.trim_start().len();
+                    let (remove_start, remove_end) =
+                        tokens.line_commented(content.trim_start()).unwrap();
+                    edits.push(LineEdit { range: start + indent..start + indent + remove_start,
+                                          text: String::new(), moved: 0 });
+                    if remove_end > 0 {
+                        edits.push(LineEdit { range: content_end - remove_end..content_end,
+                                              text: String::new(), moved: 0 });
+                    }
+                } else {
+                    edits.push(LineEdit { range: start + indent..start + indent,
+                                          text: prefix.clone(), moved: prefix.len() as isize });
+                    if !suffix.is_empty() {
+                        edits.push(LineEdit { range: content_end..content_end,
+                                              text: suffix.clone(), moved: 0 });
+                    }
+                }
+            }
+        }
+
+        edits.sort_by_key(|edit| (edit.range.start, edit.range.end));
+        let mut disjoint: Vec<LineEdit> = Vec::new();
+        for edit in edits {
+            if disjoint.last().map_or(true, |last| edit.range.start >= last.range.end) {
+                disjoint.push(edit);
+            }
+        }
+        self.apply_line_edits(view, &disjoint);
+    }
+
+    /// Returns the text of `line`, without its line ending.
+    fn line_content(&self, line: usize) -> String {
+        let text = self.text.slice_to_string(self.text.offset_of_line(line),
+                                             self.text.offset_of_line(line + 1));
+        text.trim_end_matches(|c| c == '\n' || c == '\r').to_owned()
+    }
+
     pub(crate) fn do_edit(&mut self, view: &mut View, kill_ring: &mut Rope,
                           cmd: BufferEvent) {
         use self::BufferEvent::*;
@@ -695,14 +1846,24 @@ impl Editor {
             Transpose => self.do_transpose(view),
             Undo => self.do_undo(),
             Redo => self.do_redo(),
+            GotoUndoState(id) => self.goto_undo_state(id),
+            UndoEarlier(step) => self.undo_earlier(step),
+            UndoLater(step) => self.undo_later(step),
             Uppercase => self.transform_text(view, |s| s.to_uppercase()),
             Lowercase => self.transform_text(view, |s| s.to_lowercase()),
             Indent => self.modify_indent(view, IndentDirection::In),
             Outdent => self.modify_indent(view, IndentDirection::Out),
+            DuplicateLine => self.duplicate_lines(view),
+            MoveLines { up } => self.move_lines(view, up),
+            JoinLines => self.join_lines(view),
+            SortLines(options) => self.sort_lines(view, options),
+            DeleteLine => self.delete_lines(view),
+            ToggleComment => self.toggle_comment(view),
             InsertNewline => self.insert_newline(view),
             InsertTab => self.insert_tab(view),
             Insert(chars) => self.do_insert(view, &chars),
             Yank => self.yank(view, kill_ring),
+            ResolveConflict { index, side } => self.resolve_conflicts(index, side),
         }
     }
 
@@ -743,6 +1904,78 @@ impl Editor {
         view.invalidate_styles(&self.text, start, end_offset);
     }
 
+    /// Replaces the foldable ranges reported by `plugin`, which were
+    /// computed against the revision `rev`.
+    pub fn update_fold_ranges(&mut self, plugin: PluginId, ranges: Vec<FoldRange>,
+                              rev: RevToken) {
+        let delta = if rev != self.engine.get_head_rev_id().token() {
+            Some(self.engine.delta_rev_head(rev))
+        } else {
+            None
+        };
+        // TODO: more protection against invalid input
+        let len = delta.as_ref().map_or(self.text.len(), |delta| delta.base_len);
+        let mut ranges = ranges.into_iter()
+            .filter(|range| range.start < range.end)
+            .map(|range| range.start.min(len)..range.end.min(len))
+            .collect::<Vec<_>>();
+        if let Some(delta) = delta {
+            fold::transform_ranges(&mut ranges, &delta);
+        }
+        self.fold_ranges.insert(plugin, ranges);
+    }
+
+    /// Sets the indentation of a line, as of the revision `rev`, to `indent`
+    /// columns, overriding the indentation computed by the core.
+    pub fn set_line_indent(&mut self, line: usize, indent: usize, rev: RevToken) {
+        let text = match self.engine.get_rev(rev) {
+            Some(text) => text,
+            None => return,
+        };
+        if line > text.measure::<LinesMetric>() {
+            return;
+        }
+        let mut start = text.offset_of_line(line);
+        if rev != self.engine.get_head_rev_id().token() {
+            let delta = self.engine.delta_rev_head(rev);
+            start = Transformer::new(&delta).transform(start, false);
+            // the line may have been joined to the one before it since
+            if start != self.text.offset_of_line(self.text.line_of_offset(start)) {
+                return;
+            }
+        }
+        let end = start + indent::leading_whitespace_len(&self.text, start);
+        let indent = self.indent_text(indent);
+        if self.text.slice_to_string(start, end) == indent {
+            return;
+        }
+        let mut builder = delta::Builder::new(self.text.len());
+        builder.replace(Interval::new_closed_open(start, end), Rope::from(indent));
+        self.this_edit_type = EditType::InsertChars;
+        self.add_delta(builder.build());
+    }
+
+    pub(crate) fn remove_fold_ranges(&mut self, plugin: PluginId) {
+        self.fold_ranges.remove(&plugin);
+    }
+
+    /// Returns the foldable ranges reported by every plugin, or if none
+    /// reported any, the indented blocks.
+    pub(crate) fn fold_ranges(&self) -> Vec<Range<usize>> {
+        if self.fold_ranges.values().all(Vec::is_empty) {
+            return IndentLevels::flatten(&self.indent_folds());
+        }
+        self.fold_ranges.values().flat_map(|ranges| ranges.iter().cloned()).collect()
+    }
+
+    /// Returns the blocks of lines that are indented more than the line
+    /// before them, nested. Empty for large files.
+    pub(crate) fn indent_folds(&self) -> Vec<IndentFold> {
+        self.indent_levels.as_ref()
+            .map(|levels| levels.folds(&self.text))
+            .unwrap_or_default()
+    }
+
     pub fn plugin_get_data(&self, start: usize,
                            unit: TextUnit,
                            max_size: usize,
@@ -800,6 +2033,11 @@ impl EditType {
     }
 }
 
+fn text_hash(text: &Rope) -> u64 {
+    text.iter_chunks_all()
+        .fold(fnv_hash(&[]), |hash, chunk| fnv_hash_update(hash, chunk.as_bytes()))
+}
+
 fn last_selection_region(regions: &[SelRegion]) -> Option<&SelRegion> {
     for region in regions.iter().rev() {
         if !region.is_caret() {
@@ -809,6 +2047,35 @@ fn last_selection_region(regions: &[SelRegion]) -> Option<&SelRegion> {
     None
 }
 
+/// Returns whether typing the opening character of `pair` between `prev`
+/// and `next` should insert the closing one too: only before whitespace,
+/// the end of the text or a closing character, and for quotes, not after
+/// a word or another quote.
+fn can_close(pair: (char, char), prev: Option<char>, next: Option<char>,
+             pairs: &[(char, char)]) -> bool {
+    let before_ok = match next {
+        None => true,
+        Some(next) => next.is_whitespace()
+            || (next != pair.1 || pair.0 != pair.1) && pairs.iter().any(|p| p.1 == next),
+    };
+    let after_ok = pair.0 != pair.1
+        || prev.map_or(true, |prev| !prev.is_alphanumeric() && prev != pair.0);
+    before_ok && after_ok
+}
+
+/// Returns the character before `offset` in `text`, if any.
+fn char_before(text: &Rope, offset: usize) -> Option<char> {
+    if offset == 0 {
+        return None;
+    }
+    Cursor::new(text, offset).prev_codepoint()
+}
+
+/// Returns the character after `offset` in `text`, if any.
+fn char_after(text: &Rope, offset: usize) -> Option<char> {
+    Cursor::new(text, offset).next_codepoint()
+}
+
 fn n_spaces(n: usize) -> &'static str {
     let spaces = "                                ";
     assert!(n <= spaces.len());
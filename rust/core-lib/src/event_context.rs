@@ -16,32 +16,39 @@
 
 use std::cell::RefCell;
 use std::iter;
+use std::ops::Range;
 use std::path::Path;
 use std::time::{Duration, Instant};
 
 use serde_json::{self, Value};
 
 use xi_rope::Rope;
+use xi_rope::engine::{Revisions, SessionId};
 use xi_rope::interval::Interval;
 use xi_rope::rope::LinesMetric;
+use xi_rope::spans::Spans;
 use xi_rpc::{RemoteError, Error as RpcError};
 use xi_trace::trace_block;
 
 use rpc::{EditNotification, EditRequest, LineRange};
-use plugins::rpc::{ClientPluginInfo, PluginBufferInfo, PluginNotification,
+use plugins::rpc::{ClientPluginInfo, PluginBufferInfo, PluginEdit, PluginNotification,
                    PluginRequest, PluginUpdate};
 
-use styles::ThemeStyleMap;
+use styles::{Style, ThemeStyleMap};
 use config::{BufferConfig, ConfigManager};
 
 use WeakXiCore;
-use tabs::{ViewId, PluginId, RENDER_VIEW_IDLE_MASK};
+use tabs::{ViewId, PluginId, FORMAT_TIMEOUT_MASK, RENDER_VIEW_IDLE_MASK};
 use editor::Editor;
-use file::FileInfo;
-use edit_types::{EventDomain, SpecialEvent};
+use file::{FileInfo, LineEnding};
+use edit_types::{EventDomain, SpecialEvent, ViewEvent};
 use client::Client;
+use brackets::find_matching_bracket;
+use diff::DiffId;
+use movement::Movement;
 use plugins::Plugin;
-use selection::SelRegion;
+use selection::{Selection, SelRegion};
+use sync::{HOST_SLOT, SessionSelection};
 use view::View;
 use width_cache::WidthCache;
 
@@ -54,6 +61,9 @@ pub const MAX_SIZE_LIMIT: usize = 1024 * 1024;
 /// window will be sent to the view along with the edit.
 const RENDER_DELAY: Duration = Duration::from_millis(2);
 
+/// How long plugins have to answer a request to format a buffer.
+const FORMAT_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// A collection of all the state relevant for handling a particular event.
 ///
 /// This is created dynamically for each event that arrives to the core,
@@ -99,14 +109,20 @@ impl<'a> EventContext<'a> {
         let event: EventDomain = cmd.into();
         match event {
             E::View(cmd) => {
+                    if let ViewEvent::Move(Movement::MatchingBracket)
+                        | ViewEvent::ModifySelection(Movement::MatchingBracket) = cmd {
+                        self.update_brackets();
+                    }
                     self.with_view(|view, text| view.do_edit(text, cmd));
                     self.editor.borrow_mut().update_edit_type();
                 },
+            E::Buffer(_) if self.editor.borrow().is_loading() => (),
             E::Buffer(cmd) => self.with_editor(
                 |ed, view, kill_ring| ed.do_edit(view, kill_ring, cmd)),
             E::Special(cmd) => self.do_special(cmd),
         }
         self.after_edit("core");
+        self.sync_state();
         self.render_if_needed();
     }
 
@@ -126,6 +142,11 @@ impl<'a> EventContext<'a> {
                 }),
             SpecialEvent::RequestLines(LineRange { first, last }) =>
                 self.do_request_lines(first as usize, last as usize),
+            SpecialEvent::Fold => self.with_editor(
+                |ed, view, _| view.fold(ed.get_buffer(), &ed.fold_ranges())),
+            SpecialEvent::Unfold { all } => self.with_view(
+                |view, text| view.unfold(text, all)),
+            SpecialEvent::Format { selection } => self.do_format(selection),
         }
     }
 
@@ -135,11 +156,22 @@ impl<'a> EventContext<'a> {
         let result = match cmd {
             Cut => Ok(self.with_editor(|ed, view, _| ed.do_cut(view))),
             Copy => Ok(self.with_editor(|ed, view, _| ed.do_copy(view))),
-            Find { chars, case_sensitive } => Ok(self.with_view(
-                |view, text| view.do_find(text, chars, case_sensitive))),
-            // Replace
+            Find { chars, case_sensitive, regex, whole_words, ignore_accents, query } =>
+                self.with_view(|view, text| view.do_find(
+                    text, query.as_ref().map(String::as_str), chars,
+                    case_sensitive, regex, whole_words, ignore_accents))
+                .map_err(|e| RemoteError::custom(400, e.to_string(), None)),
+            Replace { chars, all, query } =>
+                Ok(self.do_replace(&chars, all, query.as_ref().map(String::as_str))),
+            UndoTree => Ok(self.editor.borrow().get_undo_tree()),
+            UndoStateDiff { id } => self.editor.borrow().undo_state_diff(id)
+                .map(|delta| json!(delta))
+                .ok_or_else(|| RemoteError::custom(
+                    404, format!("no undo state with id {}", id), None)),
+            IndentFoldRanges => Ok(json!(self.editor.borrow().indent_folds())),
         };
         self.after_edit("core");
+        self.sync_state();
         self.render_if_needed();
         result
     }
@@ -160,11 +192,96 @@ impl<'a> EventContext<'a> {
             Edit { edit } => self.with_editor(
                 |ed, _, _| ed.apply_plugin_edit(edit)),
             Alert { msg } => self.client.alert(&msg),
+            UpdateFoldRanges { ranges, rev } => self.with_editor(
+                |ed, _, _| ed.update_fold_ranges(plugin, ranges, rev)),
+            SetIndent { line, indent, rev } => self.with_editor(
+                |ed, _, _| ed.set_line_indent(line, indent, rev)),
         };
         self.after_edit(&plugin.to_string());
+        self.sync_state();
+        self.render_if_needed();
+    }
+
+    /// Merges the revisions another session editing this buffer sent,
+    /// along with its selections. If `replace_all` is set, the selections
+    /// of sessions that aren't in `selections` are removed. Fails, leaving
+    /// the buffer unchanged, if the revisions can't be merged.
+    pub(crate) fn merge_sync_state(&mut self, revisions: &Revisions,
+                                   selections: &[SessionSelection], replace_all: bool)
+        -> Result<(), String>
+    {
+        let delta = self.editor.borrow_mut().merge_sync_revisions(revisions)?;
+        self.after_edit("sync");
+        {
+            let ed = self.editor.borrow();
+            let own_session = ed.sync_session();
+            let mut view = self.view.borrow_mut();
+            if replace_all {
+                let gone = view.remote_sessions()
+                    .filter(|s| !selections.iter().any(|&(session, _)| session == *s))
+                    .collect::<Vec<_>>();
+                for session in gone {
+                    view.set_remote_selection(ed.get_buffer(), session, None);
+                }
+            }
+            for &(session, ref regions) in selections {
+                if Some(session) == own_session {
+                    continue;
+                }
+                let mut selection = Selection::new();
+                for &(start, end) in regions {
+                    let len = delta.base_len;
+                    selection.add_region(SelRegion::new(start.min(len), end.min(len)));
+                }
+                let selection = selection.apply_delta(&delta, true, false);
+                view.set_remote_selection(ed.get_buffer(), session, Some(selection));
+            }
+        }
+        self.sync_state();
+        self.render_if_needed();
+        Ok(())
+    }
+
+    /// Removes the selection of another session that stopped editing this
+    /// buffer.
+    pub(crate) fn remove_remote_selection(&mut self, session: SessionId) {
+        self.with_view(|view, text| view.set_remote_selection(text, session, None));
+        self.sync_state();
+        self.render_if_needed();
+    }
+
+    /// Stops sharing this buffer with other sessions.
+    pub(crate) fn stop_sync(&mut self) {
+        self.editor.borrow_mut().stop_sync();
+        self.with_view(|view, text| view.clear_remote_selections(text));
         self.render_if_needed();
     }
 
+    /// Returns the selections to share with the other sessions editing
+    /// this buffer: our own, and if we are the host, those of the guests.
+    pub(crate) fn sync_selections(&self) -> Vec<SessionSelection> {
+        let session = match self.editor.borrow().sync_session() {
+            Some(session) => session,
+            None => return Vec::new(),
+        };
+        let to_ranges = |regions: &[SelRegion]| regions.iter()
+            .map(|r| (r.start, r.end))
+            .collect::<Vec<_>>();
+        let view = self.view.borrow();
+        let mut result = vec![(session, to_ranges(view.sel_regions()))];
+        if session.1 == HOST_SLOT {
+            result.extend(view.remote_selections()
+                          .map(|(session, regions)| (session, to_ranges(regions))));
+        }
+        result
+    }
+
+    /// Shares the state of the buffer with other sessions, if it changed.
+    fn sync_state(&mut self) {
+        let selections = self.sync_selections();
+        self.editor.borrow_mut().sync_state_changed(&selections);
+    }
+
     pub(crate) fn do_plugin_cmd_sync(&mut self, _plugin: PluginId,
                                       cmd: PluginRequest) -> Value {
         use self::PluginRequest::*;
@@ -193,6 +310,11 @@ impl<'a> EventContext<'a> {
         iter_views.for_each(|view| view.borrow_mut()
                             .after_edit(ed.get_buffer(), &last_text, &delta,
                                         self.client, &mut width_cache, keep_sels));
+        if let Some(selection) = ed.take_selection_for_edit() {
+            self.view.borrow_mut().set_selection(ed.get_buffer(), selection);
+        }
+        let changed_lines = ed.update_diff_gutter(Some((&delta, &last_text)));
+        self.invalidate_lines(ed.get_buffer(), &changed_lines);
 
         let new_len = delta.new_document_len();
         let nb_lines = ed.get_buffer().measure::<LinesMetric>() + 1;
@@ -257,11 +379,35 @@ impl<'a> EventContext<'a> {
     /// Flushes any changes in the views out to the frontend.
     fn render(&mut self) {
         let _t = trace_block("EventContext::render", &["core"]);
+        let changed_lines = self.editor.borrow_mut().update_diff_gutter(None);
+        self.update_brackets();
         let ed = self.editor.borrow();
+        self.invalidate_lines(ed.get_buffer(), &changed_lines);
         //TODO: render other views
         self.view.borrow_mut()
             .render_if_dirty(ed.get_buffer(), self.client, self.style_map,
-                             ed.get_layers().get_merged(), ed.is_pristine())
+                             ed.get_layers().get_merged(), ed.get_diff_gutter(),
+                             ed.is_pristine())
+    }
+
+    /// Finds the brackets matching the ones next to the carets.
+    fn update_brackets(&mut self) {
+        let ed = self.editor.borrow();
+        let mut view = self.view.borrow_mut();
+        let brackets = view.sel_regions().iter()
+            .filter_map(|region| find_matching_bracket(
+                    ed.get_buffer(), ed.get_layers(), region.end))
+            .collect();
+        view.set_brackets(ed.get_buffer(), brackets);
+    }
+
+    /// Invalidates `lines` in every view of the buffer.
+    fn invalidate_lines(&self, text: &Rope, lines: &[usize]) {
+        if lines.is_empty() {
+            return;
+        }
+        iter::once(&self.view).chain(self.siblings.iter())
+            .for_each(|view| view.borrow_mut().invalidate_lines(text, lines));
     }
 }
 
@@ -284,10 +430,32 @@ impl<'a> EventContext<'a> {
         self.client.available_plugins(self.view.borrow().view_id,
                                       &available_plugins);
 
+        self.send_file_state();
+        self.render()
+    }
+
+    /// Updates the view once the file it shows has been loaded, after the
+    /// view was set up.
+    pub(crate) fn finish_loading(&mut self) {
+        self.with_view(|view, text| view.set_dirty(text));
+        {
+            let ed = self.editor.borrow();
+            self.view.borrow_mut().rewrap(ed.get_buffer(), ed.wrap_width());
+        }
+        self.send_file_state();
+        self.render()
+    }
+
+    /// Sends the client the config of the buffer, and flags files with
+    /// mixed line endings.
+    fn send_file_state(&self) {
         let ed = self.editor.borrow();
         let config = ed.get_config().to_table();
         self.client.config_changed(self.view.borrow().view_id, &config);
-        self.render()
+        if let Some(info) = self.info.filter(|info| info.mixed_line_endings) {
+            let line_ending = info.line_ending.unwrap_or(LineEnding::Lf);
+            self.client.mixed_line_endings(self.view.borrow().view_id, line_ending);
+        }
     }
 
     pub(crate) fn after_save(&mut self, path: &Path, new_config: BufferConfig) {
@@ -322,7 +490,7 @@ impl<'a> EventContext<'a> {
                                                               view.buffer_id);
             if let Some(changes) = ed.set_config(new_config) {
                 if changes.contains_key("wrap_width") {
-                    let wrap_width = ed.get_config().items.wrap_width;
+                    let wrap_width = ed.wrap_width();
                     view.rewrap(&ed.get_buffer(), wrap_width);
                     view.set_dirty(&ed.get_buffer());
                 }
@@ -335,23 +503,84 @@ impl<'a> EventContext<'a> {
     }
 
     pub(crate) fn reload(&mut self, text: Rope) {
-        self.with_editor(|ed, view, _| {
-            let new_len = text.len();
-            view.collapse_selections(ed.get_buffer());
-            view.unset_find();
-            let prev_sel = view.sel_regions().first().map(|s| s.clone());
-            ed.reload(text);
-            if let Some(prev_sel) = prev_sel {
-                let offset = prev_sel.start.min(new_len);
-                let sel = SelRegion::caret(offset);
-                view.set_selection(ed.get_buffer(), sel);
+        self.set_text(text, true)
+    }
+
+    /// Replaces the buffer with unsaved `text`, such as changes recovered
+    /// from a swap file.
+    pub(crate) fn recover(&mut self, text: Rope) {
+        self.set_text(text, false)
+    }
+
+    /// Replaces the text of the buffer with only the edits needed to turn
+    /// it into `text`, which move selections, find results and spans along.
+    fn set_text(&mut self, text: Rope, pristine: bool) {
+        {
+            let mut ed = self.editor.borrow_mut();
+            if pristine {
+                ed.reload(text);
+            } else {
+                ed.replace_text(text);
             }
-        });
+        }
 
         self.after_edit("core");
+        self.sync_state();
         self.render();
     }
 
+    /// Merges the changes made to the file on disk, which now holds
+    /// `text`, with the unsaved changes in the buffer, and tells the client
+    /// where they conflict.
+    pub(crate) fn merge_from_disk(&mut self, text: Rope) {
+        let conflicts = self.editor.borrow_mut().merge_from_disk(text);
+        self.after_edit("core");
+        self.sync_state();
+        self.render_if_needed();
+        let view_id = self.view.borrow().view_id;
+        self.client.file_merged(view_id, &conflicts);
+    }
+
+    pub(crate) fn set_diff_base(&mut self, base: Option<Rope>) {
+        self.editor.borrow_mut().set_diff_base(base);
+        self.render_if_needed();
+    }
+
+    /// Compares the buffer with the base of the diff gutter again, after
+    /// edits have only moved its markers.
+    pub(crate) fn compare_diff_gutter(&mut self) {
+        let changed_lines = self.editor.borrow_mut().compare_diff_gutter();
+        if changed_lines.is_empty() {
+            return;
+        }
+        self.invalidate_lines(self.editor.borrow().get_buffer(), &changed_lines);
+        self.render_if_needed();
+    }
+
+    /// Sets the styles highlighting the changes of the side-by-side diff
+    /// `diff_id` in the buffer, or removes them with `None`.
+    pub(crate) fn set_diff_styles(&mut self, diff_id: DiffId,
+                                  styles: Option<Spans<Style>>) {
+        {
+            let mut ed = self.editor.borrow_mut();
+            ed.get_layers_mut().set_diff_styles(diff_id, styles);
+            let text = ed.get_buffer();
+            iter::once(&self.view).chain(self.siblings.iter())
+                .for_each(|view| view.borrow_mut().set_dirty(text));
+        }
+        self.render();
+    }
+
+    /// Converts the line endings of the buffer to `line_ending`, as a
+    /// single edit that can be undone.
+    pub(crate) fn convert_line_endings(&mut self, line_ending: LineEnding) {
+        if self.editor.borrow_mut().convert_line_endings(line_ending) {
+            self.after_edit("core");
+            self.sync_state();
+            self.render_if_needed();
+        }
+    }
+
     pub(crate) fn plugin_info(&mut self) -> PluginBufferInfo {
         let ed = self.editor.borrow();
         let nb_lines = ed.get_buffer().measure::<LinesMetric>() + 1;
@@ -380,6 +609,7 @@ impl<'a> EventContext<'a> {
         self.client.plugin_stopped(self.view.borrow().view_id, &plugin.name, 0);
         self.with_editor(|ed, view, _| {
             ed.get_layers_mut().remove_layer(plugin.id);
+            ed.remove_fold_ranges(plugin.id);
             view.set_dirty(ed.get_buffer());
         });
         self.render();
@@ -394,6 +624,94 @@ impl<'a> EventContext<'a> {
         self.editor.borrow_mut().dec_revs_in_flight();
     }
 
+    /// Asks the plugins activated for the buffer's syntax to format it, or
+    /// the lines spanned by the selections, giving up after `FORMAT_TIMEOUT`.
+    fn do_format(&mut self, selection: bool) {
+        let plugins = {
+            let ed = self.editor.borrow();
+            self.plugins.iter()
+                .filter(|plugin| plugin.handles_syntax(ed.get_syntax()))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        if plugins.is_empty() {
+            self.client.alert("no plugin formatted the text");
+            return;
+        }
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        {
+            let ed = self.editor.borrow();
+            let text = ed.get_buffer();
+            if selection {
+                for region in self.view.borrow().sel_regions() {
+                    let start = text.offset_of_line(text.line_of_offset(region.min()));
+                    let end = text.offset_of_line(text.line_of_offset(region.max()) + 1);
+                    match ranges.last_mut() {
+                        Some(ref mut last) if last.end >= start => last.end = end,
+                        _ => ranges.push(start..end),
+                    }
+                }
+            } else {
+                ranges.push(0..text.len());
+            }
+        }
+
+        let deadline = Instant::now() + FORMAT_TIMEOUT;
+        let (id, rev) = {
+            let mut ed = self.editor.borrow_mut();
+            (ed.start_format(plugins.len(), deadline), ed.get_head_rev_token())
+        };
+        let view_id = self.view.borrow().view_id;
+        plugins.iter().for_each(|plugin| {
+            let weak_core = self.weak_core.clone();
+            plugin.format(view_id, rev, &ranges, move |resp| {
+                weak_core.handle_plugin_format(view_id, id, resp);
+            });
+        });
+        let view_id: usize = view_id.into();
+        self.client.schedule_timer(deadline, FORMAT_TIMEOUT_MASK | view_id);
+    }
+
+    /// Handles a plugin's answer to the format request `id`.
+    pub(crate) fn do_plugin_format(&mut self, id: usize, response: Result<Value, RpcError>) {
+        let edit = match response.map(serde_json::from_value::<Option<PluginEdit>>) {
+            Ok(Ok(edit)) => edit,
+            Ok(Err(err)) => {
+                eprintln!("plugin format response json err: {:?}", err);
+                None
+            }
+            Err(err) => {
+                eprintln!("plugin format request failed: {:?}", err);
+                None
+            }
+        };
+        let result = self.editor.borrow_mut().apply_format_edit(id, edit);
+        if let Err(msg) = result {
+            self.client.alert(msg);
+        }
+        self.after_edit("core");
+        self.sync_state();
+        self.render_if_needed();
+    }
+
+    /// Gives up on the format request, if its deadline has passed.
+    pub(crate) fn format_timed_out(&mut self) {
+        if self.editor.borrow_mut().format_timed_out(Instant::now()) {
+            self.client.alert("formatting timed out");
+        }
+    }
+
+    fn do_replace(&mut self, chars: &str, all: bool, query: Option<&str>) -> Value {
+        let n_replaced = self.with_editor(
+            |ed, view, _| ed.do_replace(view, chars, all, query));
+        if !all {
+            // occurrences are only updated once the edit is committed
+            self.after_edit("core");
+            self.with_view(|view, text| view.find_next(text, false, true, query));
+        }
+        json!(n_replaced)
+    }
+
     fn debug_wrap_width(&mut self) {
         {
             let mut view = self.view.borrow_mut();
@@ -410,8 +728,8 @@ impl<'a> EventContext<'a> {
         let mut view = self.view.borrow_mut();
         let ed = self.editor.borrow();
         view.request_lines(ed.get_buffer(), self.client, self.style_map,
-                           ed.get_layers().get_merged(), first, last,
-                           ed.is_pristine())
+                           ed.get_layers().get_merged(), ed.get_diff_gutter(),
+                           first, last, ed.is_pristine())
     }
 }
 
@@ -421,7 +739,15 @@ mod tests {
     use super::*;
     use core::dummy_weak_core;
     use tabs::BufferId;
+    use rpc::GestureType;
+    use diff::ConflictSide;
+    use config::ConfigDomain;
     use xi_rpc::test_utils::DummyPeer;
+    use std::sync::{Arc, Mutex};
+    use xi_rope::delta::Delta;
+    use xi_rope::rope::RopeInfo;
+    use sync::{SessionSelection, SyncStore};
+    use xi_rope::engine::{Engine, Versions};
 
     struct ContextHarness {
         view: RefCell<View>,
@@ -794,4 +1120,858 @@ mod tests {
         that has three\n\
         |lines." );
     }
+
+    #[test]
+    fn regex_find_and_replace() {
+        let initial_text = "\
+        let foo = bar(1);\n\
+        let baz = qux(22);\n\
+        foo(bar);";
+        let harness = ContextHarness::new(initial_text);
+        let mut ctx = harness.make_context();
+
+        let find = EditRequest::Find {
+            chars: Some(r"^let (\w+) = (\w+)\((\d+)\);$".into()),
+            case_sensitive: true,
+            regex: true,
+            whole_words: false,
+            ignore_accents: false,
+            query: None,
+        };
+        assert!(ctx.do_edit_sync(find).is_ok());
+        let replace = EditRequest::Replace {
+            chars: "let $1 = $2.call($3);".into(), all: true, query: None };
+        assert_eq!(ctx.do_edit_sync(replace), Ok(json!(2)));
+        assert_eq!(harness.debug_render(),"\
+        let foo = bar.call(1);|\n\
+        let baz = qux.call(22);\n\
+        foo(bar);" );
+
+        // replace-all is a single undo group
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(),"\
+        let foo = bar(1);|\n\
+        let baz = qux(22);\n\
+        foo(bar);" );
+
+        // replacing one occurrence selects the next
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        let find = EditRequest::Find {
+            chars: Some("(b)(a)".into()), case_sensitive: true, regex: true,
+            whole_words: false, ignore_accents: false, query: None };
+        assert!(ctx.do_edit_sync(find).is_ok());
+        let replace = EditRequest::Replace {
+            chars: "$2$1".into(), all: false, query: None };
+        assert_eq!(ctx.do_edit_sync(replace), Ok(json!(0)));
+        assert_eq!(harness.debug_render(),"\
+        let foo = [ba|]r(1);\n\
+        let baz = qux(22);\n\
+        foo(bar);" );
+        let replace = EditRequest::Replace {
+            chars: "$2$1".into(), all: false, query: None };
+        assert_eq!(ctx.do_edit_sync(replace), Ok(json!(1)));
+        assert_eq!(harness.debug_render(),"\
+        let foo = abr(1);\n\
+        let [ba|]z = qux(22);\n\
+        foo(bar);" );
+
+        let bad = EditRequest::Find {
+            chars: Some("(".into()), case_sensitive: true, regex: true,
+            whole_words: false, ignore_accents: false, query: None };
+        assert!(ctx.do_edit_sync(bad).is_err());
+    }
+
+    #[test]
+    fn multiple_find_queries() {
+        let initial_text = "foo bar foo bar";
+        let harness = ContextHarness::new(initial_text);
+        let mut ctx = harness.make_context();
+
+        let find = EditRequest::Find {
+            chars: Some("foo".into()), case_sensitive: true, regex: false,
+            whole_words: false, ignore_accents: false, query: None };
+        assert!(ctx.do_edit_sync(find).is_ok());
+        let find = EditRequest::Find {
+            chars: Some("bar".into()), case_sensitive: true, regex: false,
+            whole_words: false, ignore_accents: false, query: Some("second".into()) };
+        assert!(ctx.do_edit_sync(find).is_ok());
+
+        // each query is navigated on its own
+        ctx.do_edit(EditNotification::FindNext {
+            wrap_around: Some(true), allow_same: None, query: Some("second".into()) });
+        assert_eq!(harness.debug_render(), "foo [bar|] foo bar");
+        ctx.do_edit(EditNotification::FindNext {
+            wrap_around: Some(true), allow_same: None, query: None });
+        assert_eq!(harness.debug_render(), "foo bar [foo|] bar");
+
+        // and replaced on its own
+        let replace = EditRequest::Replace {
+            chars: "baz".into(), all: true, query: Some("second".into()) };
+        assert_eq!(ctx.do_edit_sync(replace), Ok(json!(2)));
+        assert_eq!(harness.debug_render(), "foo baz [foo|] baz");
+
+        // clearing the named query leaves the default one alone
+        let find = EditRequest::Find {
+            chars: Some("".into()), case_sensitive: true, regex: false,
+            whole_words: false, ignore_accents: false, query: Some("second".into()) };
+        assert!(ctx.do_edit_sync(find).is_ok());
+        ctx.do_edit(EditNotification::FindNext {
+            wrap_around: Some(true), allow_same: None, query: None });
+        assert_eq!(harness.debug_render(), "[foo|] baz foo baz");
+    }
+
+    #[test]
+    fn find_whole_words() {
+        let initial_text = "view_id view views view\nRésumé resume";
+        let harness = ContextHarness::new(initial_text);
+        let mut ctx = harness.make_context();
+
+        let find = EditRequest::Find {
+            chars: Some("view".into()), case_sensitive: true, regex: false,
+            whole_words: true, ignore_accents: false, query: None };
+        assert!(ctx.do_edit_sync(find).is_ok());
+        let replace = EditRequest::Replace {
+            chars: "pane".into(), all: true, query: None };
+        assert_eq!(ctx.do_edit_sync(replace), Ok(json!(2)));
+        assert_eq!(harness.debug_render(), "|view_id pane views pane\nRésumé resume");
+
+        // a whole word occurrence stops being one when the word grows
+        ctx.do_edit(EditNotification::Gesture {
+            line: 0, col: 12, ty: GestureType::PointSelect });
+        ctx.do_edit(EditNotification::Insert { chars: "s".into() });
+        let find = EditRequest::Find {
+            chars: Some("pane".into()), case_sensitive: true, regex: false,
+            whole_words: true, ignore_accents: false, query: None };
+        assert!(ctx.do_edit_sync(find).is_ok());
+        let replace = EditRequest::Replace {
+            chars: "view".into(), all: true, query: None };
+        assert_eq!(ctx.do_edit_sync(replace), Ok(json!(1)));
+        assert_eq!(harness.debug_render(), "view_id panes| views view\nRésumé resume");
+
+        let find = EditRequest::Find {
+            chars: Some("RESUME".into()), case_sensitive: false, regex: false,
+            whole_words: true, ignore_accents: true, query: None };
+        assert!(ctx.do_edit_sync(find).is_ok());
+        let replace = EditRequest::Replace {
+            chars: "CV".into(), all: true, query: None };
+        assert_eq!(ctx.do_edit_sync(replace), Ok(json!(2)));
+        assert_eq!(harness.debug_render(), "view_id panes| views view\nCV CV");
+    }
+
+    #[test]
+    fn restore_undo_history() {
+        let harness = ContextHarness::new("one");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Insert { chars: "a".into() });
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::InsertNewline);
+        assert_eq!(harness.debug_render(), "aone\n|");
+
+        let mut history = Vec::new();
+        harness.editor.borrow().save_undo_history(&mut history).unwrap();
+
+        let other = ContextHarness::new("one\n");
+        let restored = other.editor.borrow_mut()
+            .restore_undo_history(history.as_slice());
+        assert_eq!(restored.ok(), Some(false));
+
+        let harness = ContextHarness::new("aone\n");
+        let restored = harness.editor.borrow_mut()
+            .restore_undo_history(history.as_slice());
+        assert_eq!(restored.ok(), Some(true));
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "|aone");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "|one");
+        ctx.do_edit(EditNotification::Redo);
+        assert_eq!(harness.debug_render(), "a|one");
+
+        // new edits don't disturb the restored history
+        ctx.do_edit(EditNotification::Insert { chars: "b".into() });
+        assert_eq!(harness.debug_render(), "ab|one");
+        ctx.do_edit(EditNotification::Undo);
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "|one");
+    }
+
+    #[test]
+    fn undo_tree() {
+        let harness = ContextHarness::new("");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Insert { chars: "ab".into() });
+        ctx.do_edit(EditNotification::DeleteBackward);
+        ctx.do_edit(EditNotification::Undo);
+        // a new edit after undo starts a new branch
+        ctx.do_edit(EditNotification::Insert { chars: "c".into() });
+        assert_eq!(harness.debug_render(), "abc|");
+
+        let tree = ctx.do_edit_sync(EditRequest::UndoTree).unwrap();
+        assert_eq!(tree["current"], json!(3));
+        let parents = tree["states"].as_array().unwrap().iter()
+            .map(|state| (state["id"].clone(), state["parent"].clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(parents, vec![(json!(0), json!(null)), (json!(1), json!(0)),
+                                 (json!(2), json!(1)), (json!(3), json!(1))]);
+
+        ctx.do_edit(EditNotification::GotoUndoState { id: 2 });
+        assert_eq!(harness.debug_render(), "a|");
+        let diff = ctx.do_edit_sync(EditRequest::UndoStateDiff { id: 3 }).unwrap();
+        let diff: Delta<RopeInfo> = serde_json::from_value(diff).unwrap();
+        assert_eq!(String::from(diff.apply(&Rope::from("abc"))), "a");
+        assert!(ctx.do_edit_sync(EditRequest::UndoStateDiff { id: 9 }).is_err());
+
+        ctx.do_edit(EditNotification::UndoEarlier { count: None, seconds: None });
+        assert_eq!(harness.debug_render(), "ab|");
+        ctx.do_edit(EditNotification::UndoLater { count: Some(2), seconds: None });
+        assert_eq!(harness.debug_render(), "abc|");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "ab|");
+        ctx.do_edit(EditNotification::Redo);
+        assert_eq!(harness.debug_render(), "abc|");
+        ctx.do_edit(EditNotification::UndoEarlier { count: None, seconds: Some(3600) });
+        assert_eq!(harness.debug_render(), "|");
+
+        // old states are dropped once the tree gets too large
+        for _ in 0..150 {
+            ctx.do_edit(EditNotification::Insert { chars: "x".into() });
+            ctx.do_edit(EditNotification::Undo);
+        }
+        for _ in 0..30 {
+            ctx.do_edit(EditNotification::Insert { chars: "y".into() });
+            ctx.do_edit(EditNotification::DeleteBackward);
+        }
+        assert_eq!(harness.debug_render(), "|");
+        let tree = ctx.do_edit_sync(EditRequest::UndoTree).unwrap();
+        let states = tree["states"].as_array().unwrap();
+        assert!(states.len() <= 100);
+        let root = states[0]["id"].as_u64().unwrap() as usize;
+        ctx.do_edit(EditNotification::GotoUndoState { id: root });
+        assert_eq!(harness.debug_render(), "|");
+        ctx.do_edit(EditNotification::UndoLater { count: None, seconds: None });
+        assert_eq!(harness.debug_render(), "y|");
+        ctx.do_edit(EditNotification::UndoLater { count: Some(1000), seconds: None });
+        assert_eq!(harness.debug_render(), "|");
+    }
+
+    #[test]
+    fn convert_line_endings() {
+        let harness = ContextHarness::new("a\r\nb\nc\r\n\n");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.convert_line_endings(LineEnding::CrLf);
+        assert_eq!(harness.debug_render(), "a\r\nb\r\nc\r\n\r\n|");
+        ctx.convert_line_endings(LineEnding::Lf);
+        assert_eq!(harness.debug_render(), "a\nb\nc\n\n|");
+
+        // each conversion is undone at once
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "a\r\nb\r\nc\r\n\r\n|");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "a\r\nb\nc\r\n\n|");
+
+        // converting to the endings already used is not an edit
+        ctx.do_edit(EditNotification::Redo);
+        ctx.convert_line_endings(LineEnding::CrLf);
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "a\r\nb\nc\r\n\n|");
+    }
+
+    #[test]
+    fn reload_keeps_selections() {
+        let harness = ContextHarness::new("fn main() {\n    one();\n    two();\n}\n");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 2, col: 4, ty: GestureType::PointSelect });
+        ctx.do_edit(EditNotification::MoveRightAndModifySelection);
+        ctx.do_edit(EditNotification::MoveRightAndModifySelection);
+        ctx.reload(Rope::from("// main\nfn main() {\n  one();\n  two();\n}\n"));
+        assert_eq!(harness.debug_render(), "// main\nfn main() {\n  one();\n  [tw|]o();\n}\n");
+        assert!(ctx.editor.borrow().is_pristine());
+
+        // reloading can be undone
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "fn main() {\n    one();\n    [tw|]o();\n}\n");
+        assert!(!ctx.editor.borrow().is_pristine());
+    }
+
+    #[test]
+    fn merge_from_disk() {
+        let harness = ContextHarness::new("one\ntwo\nthree\n");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Insert { chars: "1 ".into() });
+        ctx.merge_from_disk(Rope::from("one\ntwo\n3\n"));
+        assert_eq!(harness.debug_render(), "1 |one\ntwo\n3\n");
+        assert!(!ctx.editor.borrow().is_pristine());
+
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: "four\n".into() });
+        ctx.merge_from_disk(Rope::from("one\ntwo\n3\n4\n"));
+        assert_eq!(harness.debug_render(), "1 one\ntwo\n3\n\
+                   <<<<<<< buffer\nfour\n=======\n4\n>>>>>>> disk\n|");
+
+        ctx.do_edit(EditNotification::ResolveConflict { index: Some(0),
+                                                         side: ConflictSide::Both });
+        assert_eq!(harness.debug_render(), "1 one\ntwo\n3\nfour\n4\n|");
+        ctx.do_edit(EditNotification::Undo);
+        ctx.do_edit(EditNotification::ResolveConflict { index: None,
+                                                         side: ConflictSide::Disk });
+        assert_eq!(harness.debug_render(), "1 one\ntwo\n3\n4\n|");
+
+        // the merge is undone at once
+        ctx.do_edit(EditNotification::Undo);
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "1 one\ntwo\n3\nfour\n|");
+    }
+
+    #[test]
+    fn diff_gutter() {
+        use diff::LineChange;
+        let harness = ContextHarness::new("one\ntwo\nthree\n");
+        let mut ctx = harness.make_context();
+        let changes = |ctx: &EventContext| ctx.editor.borrow().get_diff_gutter().unwrap()
+            .changes().iter().map(|(l, c)| (*l, *c)).collect::<Vec<_>>();
+        ctx.do_edit(EditNotification::Insert { chars: "1 ".into() });
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: "four\n".into() });
+        // edits only move the markers until the buffer is compared again
+        assert_eq!(changes(&ctx), vec![(0, LineChange::Modified), (3, LineChange::Modified),
+                                       (4, LineChange::Added)]);
+        ctx.compare_diff_gutter();
+        assert_eq!(changes(&ctx), vec![(0, LineChange::Modified), (3, LineChange::Added)]);
+
+        // saving makes the buffer the base
+        ctx.editor.borrow_mut().set_pristine();
+        ctx.render_if_needed();
+        assert_eq!(changes(&ctx), vec![]);
+
+        ctx.set_diff_base(Some(Rope::from("one\ntwo\nthree\n")));
+        assert_eq!(changes(&ctx), vec![(0, LineChange::Modified), (3, LineChange::Added)]);
+        ctx.do_edit(EditNotification::MoveUp);
+        ctx.do_edit(EditNotification::DeleteToEndOfParagraph);
+        ctx.do_edit(EditNotification::DeleteForward);
+        assert_eq!(harness.debug_render(), "1 one\ntwo\nthree\n|");
+        ctx.compare_diff_gutter();
+        assert_eq!(changes(&ctx), vec![(0, LineChange::Modified)]);
+    }
+
+    #[test]
+    fn fold_lines() {
+        use plugins::PluginPid;
+        use plugins::rpc::FoldRange;
+        let harness = ContextHarness::new("fn a() {\n    b\n}\nc\n");
+        let mut ctx = harness.make_context();
+        let rev = ctx.editor.borrow().get_head_rev_token();
+        ctx.editor.borrow_mut().update_fold_ranges(
+            PluginPid(0), vec![FoldRange { start: 7, end: 15 }], rev);
+        ctx.do_edit(EditNotification::Fold);
+        let line_count = |ctx: &EventContext| {
+            let ed = ctx.editor.borrow();
+            ctx.view.borrow().line_of_offset(ed.get_buffer(), ed.get_buffer().len()) + 1
+        };
+        assert_eq!(line_count(&ctx), 3);
+        ctx.do_edit(EditNotification::MoveToRightEndOfLine);
+        assert_eq!(harness.debug_render(), "fn a() {|\n    b\n}\nc\n");
+        ctx.do_edit(EditNotification::MoveDown);
+        assert_eq!(harness.debug_render(), "fn a() {\n    b\n}\nc|\n");
+
+        // the fold moves with edits before it
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: "a\n".into() });
+        assert_eq!(line_count(&ctx), 4);
+        ctx.do_edit(EditNotification::MoveDown);
+        assert_eq!(harness.debug_render(), "a\nfn a() {\n    b\n}\n|c\n");
+
+        ctx.do_edit(EditNotification::MoveUp);
+        ctx.do_edit(EditNotification::Unfold);
+        assert_eq!(line_count(&ctx), 6);
+        ctx.do_edit(EditNotification::Fold);
+        assert_eq!(line_count(&ctx), 4);
+
+        // moving into the folded lines opens the fold
+        ctx.do_edit(EditNotification::MoveDown);
+        ctx.do_edit(EditNotification::MoveLeft);
+        assert_eq!(line_count(&ctx), 6);
+        assert_eq!(harness.debug_render(), "a\nfn a() {\n    b\n}|\nc\n");
+    }
+
+    #[test]
+    fn indent_fold_ranges() {
+        let harness = ContextHarness::new("if a:\n    b\nc:\n    d\n");
+        let mut ctx = harness.make_context();
+        let ranges = ctx.do_edit_sync(EditRequest::IndentFoldRanges).unwrap();
+        assert_eq!(ranges, json!([
+            {"start": 5, "end": 11, "start_line": 0, "end_line": 1, "children": []},
+            {"start": 14, "end": 20, "start_line": 2, "end_line": 3, "children": []},
+        ]));
+
+        ctx.do_edit(EditNotification::Insert { chars: "x\n".into() });
+        let ranges = ctx.do_edit_sync(EditRequest::IndentFoldRanges).unwrap();
+        assert_eq!(ranges[0]["start_line"], json!(1));
+
+        // without fold ranges from plugins, the indented blocks are folded
+        ctx.do_edit(EditNotification::Fold);
+        assert_eq!(harness.debug_render(), "x\n|if a:\n    b\nc:\n    d\n");
+        ctx.do_edit(EditNotification::MoveDown);
+        ctx.do_edit(EditNotification::MoveDown);
+        assert_eq!(harness.debug_render(), "x\nif a:\n    b\nc:\n|    d\n");
+        ctx.do_edit(EditNotification::MoveUp);
+        ctx.do_edit(EditNotification::Fold);
+        ctx.do_edit(EditNotification::MoveDown);
+        assert_eq!(harness.debug_render(), "x\nif a:\n    b\nc:\n    d\n|");
+    }
+
+    #[test]
+    fn matching_brackets() {
+        let harness = ContextHarness::new("f(a, [b]) {\n}");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveRight);
+        ctx.do_edit(EditNotification::MoveToMatchingBracket);
+        assert_eq!(harness.debug_render(), "f(a, [b]|) {\n}");
+        assert_eq!(harness.view.borrow().matching_bracket(8).map(|m| m.matching), Some(1));
+        ctx.do_edit(EditNotification::MoveToMatchingBracket);
+        assert_eq!(harness.debug_render(), "f|(a, [b]) {\n}");
+        ctx.do_edit(EditNotification::SelectToMatchingBracket);
+        assert_eq!(harness.debug_render(), "f[(a, [b])|] {\n}");
+
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::MoveToMatchingBracket);
+        assert_eq!(harness.debug_render(), "f(a, [b]) {|\n}");
+        ctx.do_edit(EditNotification::MoveToMatchingBracket);
+        assert_eq!(harness.debug_render(), "f(a, [b]) {\n}|");
+        ctx.do_edit(EditNotification::MoveLeft);
+        ctx.do_edit(EditNotification::MoveLeft);
+        ctx.do_edit(EditNotification::SelectToMatchingBracket);
+        assert_eq!(harness.debug_render(), "f(a, [b]) {[\n}|]");
+    }
+
+    #[test]
+    fn auto_close_pairs() {
+        let harness = ContextHarness::new("\n");
+        let mut ctx = harness.make_context();
+        let mut config_manager = ConfigManager::new(None, None);
+        let changes = json!({"auto_close_pairs": true}).as_object().unwrap().to_owned();
+        config_manager.update_user_config(ConfigDomain::General, changes).unwrap();
+        ctx.editor.borrow_mut().set_config(config_manager.default_buffer_config());
+
+        ctx.do_edit(EditNotification::AddSelectionBelow);
+        ctx.do_edit(EditNotification::Insert { chars: "(".into() });
+        assert_eq!(harness.debug_render(), "(|)\n(|)");
+        ctx.do_edit(EditNotification::Insert { chars: "\"".into() });
+        ctx.do_edit(EditNotification::Insert { chars: "x".into() });
+        assert_eq!(harness.debug_render(), "(\"x|\")\n(\"x|\")");
+        ctx.do_edit(EditNotification::Insert { chars: "\"".into() });
+        ctx.do_edit(EditNotification::Insert { chars: ")".into() });
+        assert_eq!(harness.debug_render(), "(\"x\")|\n(\"x\")|");
+
+        // a closing character that wasn't inserted with its pair is typed
+        ctx.do_edit(EditNotification::MoveLeft);
+        ctx.do_edit(EditNotification::Insert { chars: ")".into() });
+        assert_eq!(harness.debug_render(), "(\"x\")|)\n(\"x\")|)");
+
+        // a pair isn't closed before other text
+        ctx.do_edit(EditNotification::MoveToBeginningOfParagraph);
+        ctx.do_edit(EditNotification::Insert { chars: "[".into() });
+        assert_eq!(harness.debug_render(), "[|(\"x\"))\n[|(\"x\"))");
+        ctx.do_edit(EditNotification::MoveToRightEndOfLineAndModifySelection);
+        ctx.do_edit(EditNotification::Insert { chars: "{".into() });
+        assert_eq!(harness.debug_render(), "[{[(\"x\"))|]}\n[{[(\"x\"))|]}");
+
+        ctx.do_edit(EditNotification::MoveToRightEndOfLine);
+        ctx.do_edit(EditNotification::Insert { chars: "[".into() });
+        assert_eq!(harness.debug_render(), "[{(\"x\"))}[|]\n[{(\"x\"))}[|]");
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "[{(\"x\"))}|\n[{(\"x\"))}|");
+
+        // the characters around the carets can be longer than a byte
+        ctx.do_edit(EditNotification::SelectAll);
+        ctx.do_edit(EditNotification::Insert { chars: "a\u{e9}\u{1f600}x".into() });
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: "(".into() });
+        assert_eq!(harness.debug_render(), "(|a\u{e9}\u{1f600}x");
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::MoveLeft);
+        ctx.do_edit(EditNotification::Insert { chars: "\"".into() });
+        assert_eq!(harness.debug_render(), "(a\u{e9}\u{1f600}\"|x");
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: " \u{1f600}".into() });
+        ctx.do_edit(EditNotification::Insert { chars: "[".into() });
+        assert_eq!(harness.debug_render(), "(a\u{e9}\u{1f600}\"x \u{1f600}[|]");
+    }
+
+    #[test]
+    fn backspace_after_multibyte() {
+        let harness = ContextHarness::new("\u{1f600}\u{e9}");
+        let mut ctx = harness.make_context();
+        let mut config_manager = ConfigManager::new(None, None);
+        let changes = json!({"auto_close_pairs": true}).as_object().unwrap().to_owned();
+        config_manager.update_user_config(ConfigDomain::General, changes).unwrap();
+        ctx.editor.borrow_mut().set_config(config_manager.default_buffer_config());
+
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "\u{1f600}|");
+        ctx.do_edit(EditNotification::Insert { chars: "(".into() });
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::MoveRight);
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "|()");
+        ctx.do_edit(EditNotification::MoveRight);
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "|");
+    }
+
+    #[test]
+    fn auto_indent() {
+        use plugins::PluginPid;
+        let harness = ContextHarness::new("");
+        let mut ctx = harness.make_context();
+        let mut config_manager = ConfigManager::new(None, None);
+        let changes = json!({"auto_indent": true, "auto_close_pairs": true})
+            .as_object().unwrap().to_owned();
+        config_manager.update_user_config(ConfigDomain::General, changes).unwrap();
+        ctx.editor.borrow_mut().set_config(config_manager.default_buffer_config());
+
+        ctx.do_edit(EditNotification::Insert { chars: "fn a() ".into() });
+        ctx.do_edit(EditNotification::Insert { chars: "{".into() });
+        ctx.do_edit(EditNotification::InsertNewline);
+        assert_eq!(harness.debug_render(), "fn a() {\n    |\n}");
+        ctx.do_edit(EditNotification::Insert { chars: "if b {".into() });
+        ctx.do_edit(EditNotification::InsertNewline);
+        ctx.do_edit(EditNotification::Insert { chars: "c;".into() });
+        ctx.do_edit(EditNotification::InsertNewline);
+        assert_eq!(harness.debug_render(), "fn a() {\n    if b {\n        c;\n        |\n}");
+
+        // a closing token typed at the expected indentation dedents the line
+        ctx.do_edit(EditNotification::Insert { chars: "]".into() });
+        assert_eq!(harness.debug_render(), "fn a() {\n    if b {\n        c;\n    ]|\n}");
+        // but not one already indented differently
+        ctx.do_edit(EditNotification::DeleteBackward);
+        ctx.do_edit(EditNotification::Insert { chars: "}".into() });
+        assert_eq!(harness.debug_render(), "fn a() {\n    if b {\n        c;\n    }|\n}");
+
+        // a plugin can override the indentation
+        let rev = ctx.editor.borrow().get_head_rev_token();
+        let plugin = PluginPid(1);
+        ctx.do_plugin_cmd(plugin, PluginNotification::SetIndent { line: 2, indent: 2, rev });
+        assert_eq!(harness.debug_render(), "fn a() {\n    if b {\n  c;\n    }|\n}");
+    }
+
+    #[test]
+    fn format_document() {
+        use xi_rope::interval::Interval;
+        let harness = ContextHarness::new("fn a(){\nb;\n}\n");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveDown);
+        ctx.do_edit(EditNotification::MoveRight);
+        // without plugins, nothing happens
+        ctx.do_edit(EditNotification::FormatDocument);
+        assert_eq!(harness.debug_render(), "fn a(){\nb|;\n}\n");
+
+        let format_edit = |ed: &Editor| {
+            let len = ed.get_buffer().len();
+            let iv = Interval::new_closed_open(0, len);
+            let delta = Delta::simple_edit(iv, Rope::from("fn a() {\n    b;\n}\n"), len);
+            json!(PluginEdit {
+                rev: ed.get_head_rev_token(),
+                delta,
+                priority: 0,
+                after_cursor: false,
+                undo_group: None,
+                author: "format".into(),
+            })
+        };
+        let deadline = Instant::now() + FORMAT_TIMEOUT;
+        let id = ctx.editor.borrow_mut().start_format(2, deadline);
+        ctx.do_plugin_format(id, Ok(Value::Null));
+        assert_eq!(harness.debug_render(), "fn a(){\nb|;\n}\n");
+        // the whole text is replaced, but the caret stays where it was
+        let edit = format_edit(&ctx.editor.borrow());
+        ctx.do_plugin_format(id, Ok(edit));
+        assert_eq!(harness.debug_render(), "fn a() {\n    b|;\n}\n");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "fn a(){\nb|;\n}\n");
+
+        // an edit to an older revision is dropped
+        let id = ctx.editor.borrow_mut().start_format(1, deadline);
+        let edit = format_edit(&ctx.editor.borrow());
+        ctx.do_edit(EditNotification::Insert { chars: "c".into() });
+        ctx.do_plugin_format(id, Ok(edit));
+        assert_eq!(harness.debug_render(), "fn a(){\nbc|;\n}\n");
+
+        // answers beyond the expected ones end the request
+        let id = ctx.editor.borrow_mut().start_format(0, deadline);
+        assert!(ctx.editor.borrow_mut().apply_format_edit(id, None).is_err());
+
+        ctx.editor.borrow_mut().start_format(1, deadline);
+        assert!(!ctx.editor.borrow_mut().format_timed_out(Instant::now()));
+        assert!(ctx.editor.borrow_mut().format_timed_out(deadline));
+    }
+
+    #[test]
+    fn line_operations() {
+        let harness = ContextHarness::new("a\nb c\nd");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveDown);
+        ctx.do_edit(EditNotification::MoveRight);
+        ctx.do_edit(EditNotification::DuplicateLine);
+        assert_eq!(harness.debug_render(), "a\nb c\nb| c\nd");
+        ctx.do_edit(EditNotification::MoveLinesUp);
+        ctx.do_edit(EditNotification::MoveLinesUp);
+        assert_eq!(harness.debug_render(), "b| c\na\nb c\nd");
+        // nothing moves past the first line
+        ctx.do_edit(EditNotification::MoveLinesUp);
+        assert_eq!(harness.debug_render(), "b| c\na\nb c\nd");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "a\nb c\n|b c\nd");
+        // the last line gets a line ending when it moves
+        ctx.do_edit(EditNotification::MoveUp);
+        ctx.do_edit(EditNotification::AddSelectionBelow);
+        ctx.do_edit(EditNotification::MoveLinesDown);
+        assert_eq!(harness.debug_render(), "a\nd\n|b c\n|b c");
+        ctx.do_edit(EditNotification::DeleteLine);
+        assert_eq!(harness.debug_render(), "a\nd|");
+
+        // a selection is duplicated after itself
+        ctx.do_edit(EditNotification::MoveToLeftEndOfLineAndModifySelection);
+        ctx.do_edit(EditNotification::DuplicateLine);
+        assert_eq!(harness.debug_render(), "a\nd[|d]");
+
+        ctx.do_edit(EditNotification::SelectAll);
+        ctx.do_edit(EditNotification::Insert { chars: "b  \n  a\nd\n\nc\n".into() });
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::JoinLines);
+        assert_eq!(harness.debug_render(), "|b a\nd\n\nc\n");
+        // a caret sorts its paragraph
+        ctx.do_edit(EditNotification::SortLines {
+            numeric: None, ignore_case: None, reverse: Some(true), unique: None });
+        assert_eq!(harness.debug_render(), "|d\nb a\n\nc\n");
+        ctx.do_edit(EditNotification::MoveDown);
+        ctx.do_edit(EditNotification::MoveDown);
+        ctx.do_edit(EditNotification::SortLines {
+            numeric: None, ignore_case: None, reverse: None, unique: None });
+        assert_eq!(harness.debug_render(), "d\nb a\n|\nc\n");
+        ctx.do_edit(EditNotification::SelectAll);
+        ctx.do_edit(EditNotification::SortLines {
+            numeric: None, ignore_case: None, reverse: Some(true), unique: None });
+        assert_eq!(harness.debug_render(), "[d\nc\nb a\n\n|]");
+        // the blank line is joined, but not the empty one after the last
+        // line break
+        ctx.do_edit(EditNotification::SelectAll);
+        ctx.do_edit(EditNotification::JoinLines);
+        assert_eq!(harness.debug_render(), "[d c b a\n|]");
+
+        // blank lines within the joined lines don't take the lines after them
+        ctx.do_edit(EditNotification::Insert { chars: "a\n\nb".into() });
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::JoinLines);
+        assert_eq!(harness.debug_render(), "|a\nb");
+        ctx.do_edit(EditNotification::SelectAll);
+        ctx.do_edit(EditNotification::Insert { chars: "a\n\n  \n\t b\n\nc".into() });
+        ctx.do_edit(EditNotification::SelectAll);
+        ctx.do_edit(EditNotification::JoinLines);
+        assert_eq!(harness.debug_render(), "[a b c|]");
+    }
+
+    #[test]
+    fn toggle_comment() {
+        use syntax::SyntaxDefinition;
+        let harness = ContextHarness::new("a {\n    b;\n\n  c;\n}");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveDown);
+        ctx.do_edit(EditNotification::AddSelectionBelow);
+        // plain text has no comments
+        ctx.do_edit(EditNotification::ToggleComment);
+        assert_eq!(harness.debug_render(), "a {\n|    b;\n|\n  c;\n}");
+        assert!(ctx.editor.borrow().is_pristine());
+
+        let config_manager = ConfigManager::new(None, None);
+        let config = config_manager.get_buffer_config(SyntaxDefinition::Rust, None);
+        ctx.editor.borrow_mut().set_config(config);
+        ctx.do_edit(EditNotification::ToggleComment);
+        assert_eq!(harness.debug_render(), "a {\n|    // b;\n|\n  c;\n}");
+
+        // lines are commented at their least indentation, unless all are
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::MoveDown);
+        ctx.do_edit(EditNotification::MoveDownAndModifySelection);
+        ctx.do_edit(EditNotification::MoveDownAndModifySelection);
+        ctx.do_edit(EditNotification::MoveDownAndModifySelection);
+        ctx.do_edit(EditNotification::ToggleComment);
+        assert_eq!(harness.debug_render(), "a {\n[  //   // b;\n\n  // c;\n|]}");
+        ctx.do_edit(EditNotification::ToggleComment);
+        assert_eq!(harness.debug_render(), "a {\n[    // b;\n\n  c;\n|]}");
+
+        // a selection within a line is wrapped in a block comment
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::MoveRightAndModifySelection);
+        ctx.do_edit(EditNotification::ToggleComment);
+        assert_eq!(harness.debug_render(), "/* [a|] */ {\n    // b;\n\n  c;\n}");
+        ctx.do_edit(EditNotification::ToggleComment);
+        assert_eq!(harness.debug_render(), "[a|] {\n    // b;\n\n  c;\n}");
+    }
+
+    /// A `SyncStore` that keeps the last state it was sent.
+    #[derive(Clone, Default)]
+    struct TestStore(Arc<Mutex<Option<(String, Vec<SessionSelection>)>>>);
+
+    impl SyncStore for TestStore {
+        fn state_changed(&mut self, engine: &Engine, selections: &[SessionSelection]) {
+            let engine = serde_json::to_string(engine).unwrap();
+            *self.0.lock().unwrap() = Some((engine, selections.to_owned()));
+        }
+    }
+
+    impl TestStore {
+        /// Takes the last state sent, as the revisions `to` doesn't have.
+        fn take(&self, to: &ContextHarness) -> Revisions {
+            self.take_with_selections(to).0
+        }
+
+        fn take_with_selections(&self, to: &ContextHarness)
+            -> (Revisions, Vec<SessionSelection>)
+        {
+            let (state, selections) = self.0.lock().unwrap().take().expect("no state sent");
+            let engine: Engine = serde_json::from_str(&state).unwrap();
+            let known = to.editor.borrow().get_engine().versions();
+            (engine.revisions_since(&known), selections)
+        }
+
+        fn is_empty(&self) -> bool {
+            self.0.lock().unwrap().is_none()
+        }
+    }
+
+    fn text(harness: &ContextHarness) -> String {
+        String::from(harness.editor.borrow().get_buffer())
+    }
+
+    #[test]
+    fn sync_merge() {
+        let host = ContextHarness::new("hello");
+        let host_store = TestStore::default();
+        host.editor.borrow_mut().start_sync((1, 0), Box::new(host_store.clone()));
+        let guest = ContextHarness::new("");
+        let guest_store = TestStore::default();
+        guest.editor.borrow_mut().start_sync((2, 1), Box::new(guest_store.clone()));
+
+        let welcome = host.editor.borrow().get_engine().revisions_since(&Versions::default());
+        let mut guest_ctx = guest.make_context();
+        guest_ctx.merge_sync_state(&welcome, &[], true).unwrap();
+        assert_eq!(text(&guest), "hello");
+
+        // concurrent edits
+        guest_ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        guest_ctx.do_edit(EditNotification::Insert { chars: " world".into() });
+        let mut host_ctx = host.make_context();
+        host_ctx.do_edit(EditNotification::Insert { chars: "> ".into() });
+        host_ctx.merge_sync_state(&guest_store.take(&host), &[], false).unwrap();
+        guest_ctx.merge_sync_state(&host_store.take(&guest), &[], false).unwrap();
+        assert_eq!(text(&host), "> hello world");
+        assert_eq!(text(&guest), "> hello world");
+        assert_eq!(host.debug_render(), "> |hello world");
+
+        // undo is an edit that other sessions can merge
+        guest_ctx.do_edit(EditNotification::Undo);
+        host_ctx.merge_sync_state(&guest_store.take(&host), &[], false).unwrap();
+        assert_eq!(text(&host), "> hello");
+        guest_ctx.do_edit(EditNotification::Redo);
+        host_ctx.merge_sync_state(&guest_store.take(&host), &[], false).unwrap();
+        assert_eq!(text(&host), "> hello world");
+        host_ctx.do_edit(EditNotification::Undo);
+        guest_ctx.merge_sync_state(&host_store.take(&guest), &[], false).unwrap();
+        assert_eq!(text(&guest), "hello world");
+
+        // history keeps working after syncing stops
+        guest.editor.borrow_mut().stop_sync();
+        guest_ctx.do_edit(EditNotification::Undo);
+        assert_eq!(text(&guest), "hello");
+        guest_ctx.do_edit(EditNotification::Redo);
+        assert_eq!(text(&guest), "hello world");
+    }
+
+    #[test]
+    fn sync_format() {
+        let host = ContextHarness::new("a(){}");
+        let host_store = TestStore::default();
+        host.editor.borrow_mut().start_sync((1, 0), Box::new(host_store.clone()));
+        let guest = ContextHarness::new("");
+        guest.editor.borrow_mut().start_sync((2, 1), Box::new(TestStore::default()));
+        let welcome = host.editor.borrow().get_engine().revisions_since(&Versions::default());
+        let mut guest_ctx = guest.make_context();
+        guest_ctx.merge_sync_state(&welcome, &[], true).unwrap();
+        host_store.0.lock().unwrap().take();
+
+        // a format edit is shared as soon as it is applied
+        let mut host_ctx = host.make_context();
+        let deadline = Instant::now() + FORMAT_TIMEOUT;
+        let id = host.editor.borrow_mut().start_format(1, deadline);
+        let edit = json!(PluginEdit {
+            rev: host.editor.borrow().get_head_rev_token(),
+            delta: Delta::simple_edit(Interval::new_closed_open(3, 3), Rope::from(" "), 5),
+            priority: 0,
+            after_cursor: false,
+            undo_group: None,
+            author: "format".into(),
+        });
+        host_ctx.do_plugin_format(id, Ok(edit));
+        guest_ctx.merge_sync_state(&host_store.take(&guest), &[], false).unwrap();
+        assert_eq!(text(&guest), "a() {}");
+    }
+
+    fn remote_ranges(harness: &ContextHarness) -> Vec<SessionSelection> {
+        harness.view.borrow().remote_selections()
+            .map(|(session, regions)| {
+                (session, regions.iter().map(|r| (r.start, r.end)).collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn remote_selections() {
+        let host = ContextHarness::new("hello world");
+        let host_store = TestStore::default();
+        host.editor.borrow_mut().start_sync((1, 0), Box::new(host_store.clone()));
+        let guest = ContextHarness::new("");
+        let guest_store = TestStore::default();
+        guest.editor.borrow_mut().start_sync((2, 1), Box::new(guest_store.clone()));
+
+        let mut host_ctx = host.make_context();
+        let mut guest_ctx = guest.make_context();
+        let welcome = host.editor.borrow().get_engine().revisions_since(&Versions::default());
+        let selections = host_ctx.sync_selections();
+        guest_ctx.merge_sync_state(&welcome, &selections, true).unwrap();
+        assert_eq!(remote_ranges(&guest), vec![((1, 0), vec![(0, 0)])]);
+
+        // a selection made concurrently with an edit is moved by the edit
+        guest_ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        guest_ctx.do_edit(EditNotification::MoveWordLeftAndModifySelection);
+        host_ctx.do_edit(EditNotification::Insert { chars: ">> ".into() });
+        let (revisions, selections) = guest_store.take_with_selections(&host);
+        assert_eq!(selections, vec![((2, 1), vec![(11, 6)])]);
+        host_ctx.merge_sync_state(&revisions, &selections, false).unwrap();
+        assert_eq!(remote_ranges(&host), vec![((2, 1), vec![(14, 9)])]);
+
+        // the host relays the selections of every session
+        let (revisions, selections) = host_store.take_with_selections(&guest);
+        assert_eq!(selections, vec![((1, 0), vec![(3, 3)]), ((2, 1), vec![(14, 9)])]);
+        guest_ctx.merge_sync_state(&revisions, &selections, true).unwrap();
+        assert_eq!(text(&guest), ">> hello world");
+        assert_eq!(remote_ranges(&guest), vec![((1, 0), vec![(3, 3)])]);
+        // the guest's own selection moved with the merge, so it is shared
+        // again, but the host has nothing new to relay
+        let (revisions, selections) = guest_store.take_with_selections(&host);
+        assert_eq!(selections, vec![((2, 1), vec![(14, 9)])]);
+        host_ctx.merge_sync_state(&revisions, &selections, false).unwrap();
+        assert!(host_store.is_empty());
+
+        // moving the cursor is shared without any edit
+        host_ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        let (_, selections) = host_store.take_with_selections(&guest);
+        assert_eq!(selections[0], ((1, 0), vec![(14, 14)]));
+
+        host_ctx.remove_remote_selection((2, 1));
+        assert!(remote_ranges(&host).is_empty());
+        let (_, selections) = host_store.take_with_selections(&guest);
+        assert_eq!(selections, vec![((1, 0), vec![(14, 14)])]);
+    }
 }
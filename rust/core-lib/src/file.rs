@@ -15,15 +15,21 @@
 //! Interactions with the file system.
 
 use std::collections::HashMap;
-use std::io::{self, Read, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::fmt;
-use std::fs::File;
-use std::path::{Path, PathBuf};
-use std::str;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::process;
+use std::str::{self, FromStr};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::SystemTime;
 
+use encoding_rs::{Decoder, DecoderResult, Encoding,
+                  SHIFT_JIS, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
 use xi_rpc::RemoteError;
 use xi_rope::Rope;
+use xi_rope::rope::RopeInfo;
+use xi_rope::tree::TreeBuilder;
 
 use tabs::BufferId;
 
@@ -33,6 +39,23 @@ use tabs::OPEN_FILE_EVENT_TOKEN;
 use watcher::FileWatcher;
 
 const UTF8_BOM: &str = "\u{feff}";
+const UTF16LE_BOM: &[u8] = b"\xff\xfe";
+const UTF16BE_BOM: &[u8] = b"\xfe\xff";
+
+/// How many bytes at the start of a file are examined when guessing
+/// whether it is UTF-16 without a byte order mark.
+const UTF16_SNIFF_LEN: usize = 4096;
+
+/// How many bytes at the start of a file are examined when guessing the
+/// encoding of a file that is loaded a chunk at a time.
+const LOADER_SNIFF_LEN: usize = 64 * 1024;
+
+/// How many times saving tries another name for its temporary file, when
+/// one is taken.
+const MAX_TEMP_FILE_ATTEMPTS: usize = 100;
+
+/// Makes the names of temporary files unique within this process.
+static TEMP_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 /// Tracks all state related to open files.
 pub struct FileManager {
@@ -46,6 +69,10 @@ pub struct FileManager {
 #[derive(Debug)]
 pub struct FileInfo {
     pub encoding: CharacterEncoding,
+    /// The most common line ending in the file, if it has any.
+    pub line_ending: Option<LineEnding>,
+    /// Whether the file uses both kinds of line endings.
+    pub mixed_line_endings: bool,
     pub path: PathBuf,
     pub mod_time: Option<SystemTime>,
     pub has_changed: bool,
@@ -54,13 +81,45 @@ pub struct FileInfo {
 pub enum FileError {
     Io(io::Error),
     UnknownEncoding,
+    /// The text contains characters that can't be represented in the
+    /// encoding it is being saved in.
+    Unencodable(CharacterEncoding),
     HasChanged,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// The encodings files can be read and written in. A file is written in
+/// the encoding it was read in, including its byte order mark, if any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CharacterEncoding {
     Utf8,
-    Utf8WithBom
+    Utf8WithBom,
+    Utf16Le,
+    Utf16LeWithBom,
+    Utf16Be,
+    Utf16BeWithBom,
+    /// ISO-8859-1, where each byte is the code point of the same value.
+    Latin1,
+    Windows1252,
+    ShiftJis,
+}
+
+/// Where a copy of a file's previous contents is kept when it is saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Backup {
+    /// Next to the file, as `file~`.
+    Adjacent,
+    /// In the given directory, named after the full path of the file, with
+    /// path separators replaced by `!`. A relative directory is relative to
+    /// the file's directory.
+    Directory(PathBuf),
+}
+
+/// The kinds of line endings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineEnding {
+    Lf,
+    CrLf,
 }
 
 impl FileManager {
@@ -94,6 +153,11 @@ impl FileManager {
         self.open_files.get(path).map(|id| *id)
     }
 
+    /// Returns the paths of all open files, with the buffers they are open in.
+    pub fn open_files<'a>(&'a self) -> impl Iterator<Item=(&'a Path, BufferId)> + 'a {
+        self.open_files.iter().map(|(path, id)| (path.as_path(), *id))
+    }
+
     /// Returns `true` if this file is open and has changed on disk.
     /// This state is stashed.
     pub fn check_file(&mut self, path: &Path, id: BufferId) -> bool {
@@ -109,19 +173,48 @@ impl FileManager {
 
     pub fn open(&mut self, path: &Path, id: BufferId)
         -> Result<Rope, FileError>
+    {
+        self.open_with_encoding(path, id, None)
+    }
+
+    /// Opens the file at `path` like `open`, decoding it with `encoding`
+    /// instead of the one it is guessed to have.
+    pub fn open_with_encoding(&mut self, path: &Path, id: BufferId,
+                              encoding: Option<CharacterEncoding>)
+        -> Result<Rope, FileError>
     {
         if !path.exists() {
             let _ = File::create(path)?;
         }
 
-        let (rope, info) = try_load_file(path)?;
+        let (rope, info) = try_load_file(path, encoding)?;
+        self.add_file(id, info);
+        Ok(rope)
+    }
 
-        self.open_files.insert(path.to_owned(), id);
+    /// Starts loading the file at `path` a chunk at a time; see
+    /// `FileLoader`. The file is opened once it is passed to
+    /// `finish_loading`.
+    pub fn start_loading(&self, path: &Path) -> Result<FileLoader, FileError> {
+        FileLoader::new(path)
+    }
+
+    /// Opens a file that `loader` has finished reading, returning its
+    /// contents.
+    pub fn finish_loading(&mut self, id: BufferId, loader: FileLoader) -> Rope {
+        let (rope, info) = loader.finish();
+        self.add_file(id, info);
+        rope
+    }
+
+    fn add_file(&mut self, id: BufferId, info: FileInfo) {
+        self.open_files.insert(info.path.clone(), id);
+        #[cfg(feature = "notify")]
+        let path = info.path.clone();
         if self.file_info.insert(id, info).is_none() {
             #[cfg(feature = "notify")]
-            self.watcher.watch(path, false, OPEN_FILE_EVENT_TOKEN);
+            self.watcher.watch(&path, false, OPEN_FILE_EVENT_TOKEN);
         }
-        Ok(rope)
     }
 
     pub fn close(&mut self, id: BufferId) {
@@ -132,23 +225,38 @@ impl FileManager {
         }
     }
 
-    pub fn save(&mut self, path: &Path, text: &Rope, id: BufferId)
+    /// Saves `text` to `path`. The file is written in `encoding` if it is
+    /// given, and otherwise in the encoding it was opened or last saved
+    /// in, or in UTF-8 for a new file. If `line_ending` is given, the line
+    /// endings written are converted to it; `text` itself is unchanged.
+    /// If `backup` is given, the previous contents of the file are kept
+    /// there.
+    pub fn save(&mut self, path: &Path, text: &Rope, id: BufferId,
+                encoding: Option<CharacterEncoding>, line_ending: Option<LineEnding>,
+                backup: Option<&Backup>)
         -> Result<(), FileError>
     {
+        let converted = line_ending.and_then(|line_ending| line_ending.convert(text));
+        let text = converted.as_ref().unwrap_or(text);
         let is_existing = self.file_info.contains_key(&id);
         if is_existing {
-            self.save_existing(path, text, id)
+            self.save_existing(path, text, id, encoding, backup)
         } else {
-            self.save_new(path, text, id)
+            let encoding = encoding.unwrap_or(CharacterEncoding::Utf8);
+            self.save_new(path, text, id, encoding, backup)
         }
     }
 
-    fn save_new(&mut self, path: &Path, text: &Rope, id: BufferId)
+    fn save_new(&mut self, path: &Path, text: &Rope, id: BufferId,
+                encoding: CharacterEncoding, backup: Option<&Backup>)
         -> Result<(), FileError>
     {
-        try_save(path, text, CharacterEncoding::Utf8)?;
+        try_save(path, text, encoding, backup)?;
+        let (line_ending, mixed_line_endings) = LineEnding::detect(text);
         let info = FileInfo {
-            encoding: CharacterEncoding::Utf8,
+            encoding,
+            line_ending,
+            mixed_line_endings,
             path: path.to_owned(),
             mod_time: get_mod_time(path),
             has_changed: false,
@@ -160,41 +268,49 @@ impl FileManager {
         Ok(())
     }
 
-    fn save_existing(&mut self, path: &Path, text: &Rope, id: BufferId)
+    fn save_existing(&mut self, path: &Path, text: &Rope, id: BufferId,
+                     encoding: Option<CharacterEncoding>, backup: Option<&Backup>)
         -> Result<(), FileError>
     {
         let prev_path = self.file_info.get(&id).unwrap().path.clone();
+        let encoding = encoding.unwrap_or(self.file_info.get(&id).unwrap().encoding);
         if prev_path != path {
-            self.save_new(path, text, id)?;
+            self.save_new(path, text, id, encoding, backup)?;
             self.open_files.remove(&prev_path);
             #[cfg(feature = "notify")]
             self.watcher.unwatch(&prev_path, OPEN_FILE_EVENT_TOKEN);
         } else if self.file_info.get(&id).unwrap().has_changed {
             return Err(FileError::HasChanged);
         } else {
-            let encoding = self.file_info.get(&id).unwrap().encoding;
-            try_save(path, text, encoding)?;
-            self.file_info.get_mut(&id).unwrap()
-                .mod_time = get_mod_time(path);
+            try_save(path, text, encoding, backup)?;
+            let info = self.file_info.get_mut(&id).unwrap();
+            info.mod_time = get_mod_time(path);
+            info.encoding = encoding;
+            let (line_ending, mixed) = LineEnding::detect(text);
+            info.line_ending = line_ending;
+            info.mixed_line_endings = mixed;
         }
         Ok(())
     }
 }
 
-fn try_load_file<P>(path: P) -> Result<(Rope, FileInfo), FileError>
+fn try_load_file<P>(path: P, encoding: Option<CharacterEncoding>)
+    -> Result<(Rope, FileInfo), FileError>
 where P: AsRef<Path>
 {
-    // TODO: support for non-utf8
     // it's arguable that the rope crate should have file loading functionality
     let mut f = File::open(path.as_ref())?;
     let mod_time = f.metadata()?.modified().ok();
     let mut bytes = Vec::new();
     f.read_to_end(&mut bytes)?;
 
-    let encoding = CharacterEncoding::guess(&bytes);
+    let encoding = encoding.unwrap_or_else(|| CharacterEncoding::guess(&bytes));
     let rope = try_decode(bytes, encoding)?;
+    let (line_ending, mixed_line_endings) = LineEnding::detect(&rope);
     let info = FileInfo {
         encoding,
+        line_ending,
+        mixed_line_endings,
         mod_time,
         path: path.as_ref().to_owned(),
         has_changed: false,
@@ -202,43 +318,626 @@ where P: AsRef<Path>
     Ok((rope, info))
 }
 
-fn try_save(path: &Path, text: &Rope, encoding: CharacterEncoding)
-    -> io::Result<()>
+/// Saves `text` to `path` without ever leaving a partly written file,
+/// using `replace_file`.
+fn try_save(path: &Path, text: &Rope, encoding: CharacterEncoding,
+            backup: Option<&Backup>)
+    -> Result<(), FileError>
 {
-    let mut f = File::create(path)?;
-        match encoding {
-            CharacterEncoding::Utf8WithBom => f.write_all(UTF8_BOM.as_bytes())?,
-            CharacterEncoding::Utf8 => (),
+    // encode everything first, so that the file is left alone if
+    // the text can't be encoded
+    let bytes = match encoding {
+        CharacterEncoding::Utf8 | CharacterEncoding::Utf8WithBom => None,
+        _ => Some(try_encode(text, encoding)?),
+    };
+    replace_file(path, backup, |f| match bytes {
+        Some(ref bytes) => f.write_all(bytes),
+        None => {
+            f.write_all(encoding.bom())?;
+            for chunk in text.iter_chunks(0, text.len()) {
+                f.write_all(chunk.as_bytes())?;
+            }
+            Ok(())
         }
+    })
+}
 
-        for chunk in text.iter_chunks(0, text.len()) {
-            f.write_all(chunk.as_bytes())?;
+/// Replaces the file at `path` with what `write` writes, without ever
+/// leaving a partly written file: the contents are written to a temporary
+/// file in the same directory, which reaches the disk before it replaces
+/// the file. If `path` is a symlink, the file it points to is replaced,
+/// and the permissions of a replaced file are kept. Missing parent
+/// directories are created.
+pub(crate) fn replace_file<F>(path: &Path, backup: Option<&Backup>, write: F)
+    -> Result<(), FileError>
+    where F: FnOnce(&mut File) -> io::Result<()>
+{
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let permissions = fs::metadata(&target).ok().map(|m| m.permissions());
+    let (mut file, tmp_path) = create_temp_file(&target)?;
+    let written = write(&mut file).and_then(|_| file.sync_all());
+    drop(file);
+    let result = written
+        .and_then(|_| match permissions {
+            Some(ref permissions) => fs::set_permissions(&tmp_path, permissions.clone()),
+            None => Ok(()),
+        })
+        .and_then(|_| match (backup, permissions.is_some()) {
+            (Some(backup), true) => backup.copy(&target),
+            _ => Ok(()),
+        })
+        .and_then(|_| fs::rename(&tmp_path, &target));
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e.into());
+    }
+
+    // make the rename itself durable; not every platform can sync a directory
+    if let Some(dir) = target.parent() {
+        let _ = File::open(dir).and_then(|d| d.sync_all());
+    }
+    Ok(())
+}
+
+/// Creates a new file next to `path`, for a file that will replace it, and
+/// returns it along with its path. Each call uses a different name, and
+/// names taken by files left behind by crashes are skipped.
+fn create_temp_file(path: &Path) -> io::Result<(File, PathBuf)> {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut attempts = 0;
+    loop {
+        let count = TEMP_FILE_COUNT.fetch_add(1, Ordering::Relaxed);
+        let tmp_path = path.with_file_name(
+            format!(".{}.{}-{}.xi-tmp", name, process::id(), count));
+        match OpenOptions::new().write(true).create_new(true).open(&tmp_path) {
+            Ok(file) => return Ok((file, tmp_path)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists
+                && attempts < MAX_TEMP_FILE_ATTEMPTS => attempts += 1,
+            Err(e) => return Err(e),
         }
-        Ok(())
+    }
+}
+
+impl Backup {
+    /// Copies the file at `path`, which is absolute, to its backup.
+    fn copy(&self, path: &Path) -> io::Result<()> {
+        let backup_path = self.path_for(path);
+        if let Backup::Directory(_) = *self {
+            fs::create_dir_all(self.directory_for(path))?;
+        }
+        fs::copy(path, backup_path).map(|_| ())
+    }
+
+    /// The directory the backup of the file at `path` goes in. A relative
+    /// backup directory is relative to the file's directory.
+    fn directory_for(&self, path: &Path) -> PathBuf {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        match *self {
+            Backup::Adjacent => dir.to_owned(),
+            Backup::Directory(ref backups) => dir.join(backups),
+        }
+    }
+
+    fn path_for(&self, path: &Path) -> PathBuf {
+        match *self {
+            Backup::Adjacent => {
+                let mut name = path.file_name().unwrap_or_default().to_owned();
+                name.push("~");
+                path.with_file_name(name)
+            }
+            Backup::Directory(_) => {
+                let name = path.to_string_lossy().replace(MAIN_SEPARATOR, "!");
+                self.directory_for(path).join(name + "~")
+            }
+        }
+    }
+}
+
+/// Encodes `text`, including the encoding's byte order mark, if any.
+fn try_encode(text: &Rope, encoding: CharacterEncoding) -> Result<Vec<u8>, FileError> {
+    use self::CharacterEncoding::*;
+    let mut bytes = Vec::with_capacity(text.len());
+    bytes.extend_from_slice(encoding.bom());
+    for chunk in text.iter_chunks(0, text.len()) {
+        match encoding {
+            Utf8 | Utf8WithBom => bytes.extend_from_slice(chunk.as_bytes()),
+            Utf16Le | Utf16LeWithBom => for unit in chunk.encode_utf16() {
+                bytes.extend_from_slice(&unit.to_le_bytes());
+            },
+            Utf16Be | Utf16BeWithBom => for unit in chunk.encode_utf16() {
+                bytes.extend_from_slice(&unit.to_be_bytes());
+            },
+            Latin1 => for c in chunk.chars() {
+                if c as u32 > 0xff {
+                    return Err(FileError::Unencodable(encoding));
+                }
+                bytes.push(c as u8);
+            },
+            Windows1252 | ShiftJis => {
+                let (encoded, _, had_errors) = encoding.legacy_encoding().encode(chunk);
+                if had_errors {
+                    return Err(FileError::Unencodable(encoding));
+                }
+                bytes.extend_from_slice(&encoded);
+            }
+        }
+    }
+    Ok(bytes)
 }
 
 fn try_decode(bytes: Vec<u8>,
               encoding: CharacterEncoding) -> Result<Rope, FileError> {
+    use self::CharacterEncoding::*;
+    let decode = |encoding: &'static Encoding, bytes: &[u8]| {
+        encoding.decode_without_bom_handling_and_without_replacement(bytes)
+            .map(|s| Rope::from(s.as_ref()))
+            .ok_or(FileError::UnknownEncoding)
+    };
     match encoding {
-        CharacterEncoding::Utf8 =>
+        Utf8 =>
             Ok(Rope::from(str::from_utf8(&bytes)?)),
-        CharacterEncoding::Utf8WithBom => {
+        Utf8WithBom => {
             let s = String::from_utf8(bytes).map_err(|e| e.utf8_error())?;
             Ok(Rope::from(&s[UTF8_BOM.len()..]))
         }
+        Utf16Le => decode(UTF_16LE, &bytes),
+        Utf16LeWithBom => decode(UTF_16LE, &bytes[UTF16LE_BOM.len()..]),
+        Utf16Be => decode(UTF_16BE, &bytes),
+        Utf16BeWithBom => decode(UTF_16BE, &bytes[UTF16BE_BOM.len()..]),
+        Latin1 => Ok(Rope::from(bytes.iter().map(|&b| b as char).collect::<String>())),
+        Windows1252 | ShiftJis => decode(encoding.legacy_encoding(), &bytes),
+    }
+}
+
+/// Returns whether `s` is valid Shift_JIS, and looks more like it than
+/// like Latin-1: more of its non-ASCII characters are double-byte
+/// characters with a non-ASCII second byte than not.
+fn is_plausible_shift_jis(s: &[u8]) -> bool {
+    if SHIFT_JIS.decode_without_bom_handling_and_without_replacement(s).is_none() {
+        return false;
+    }
+    let (mut paired, mut single) = (0, 0);
+    let mut i = 0;
+    while i < s.len() {
+        match s[i] {
+            0x81..=0x9f | 0xe0..=0xfc => {
+                if s[i + 1] >= 0x80 { paired += 1 } else { single += 1 }
+                i += 2;
+            }
+            0x80..=0xff => {
+                single += 1;
+                i += 1;
+            }
+            _ => i += 1,
+        }
     }
+    paired > single
 }
 
 impl CharacterEncoding {
+    /// Guesses the encoding of a file's contents. Byte order marks are
+    /// trusted; otherwise the first encoding that the contents are valid
+    /// and plausible in is picked, from UTF-16, UTF-8, Shift_JIS, and
+    /// finally Windows-1252 or Latin-1, which can decode anything.
+    ///
+    /// Most Latin-1 text is also valid Shift_JIS, so Shift_JIS is only
+    /// picked if most of its non-ASCII characters are double-byte ones
+    /// made of two non-ASCII bytes, which is rare in Latin-1 text.
     fn guess(s: &[u8]) -> Self {
+        CharacterEncoding::guess_start(s, true)
+    }
+
+    /// Guesses the encoding of a file from `s`, which is the whole file if
+    /// `complete` is set, and otherwise its start, where the last character
+    /// may be cut off.
+    fn guess_start(s: &[u8], complete: bool) -> Self {
+        use self::CharacterEncoding::*;
+        let (even_len, line_len) = if complete {
+            (s.len(), s.len())
+        } else {
+            // a line feed can't be part of a multi-byte character
+            let line_len = s.iter().rposition(|&b| b == b'\n').map(|i| i + 1);
+            (s.len() & !1, line_len.unwrap_or(s.len()))
+        };
+        let is_utf8 = match str::from_utf8(s) {
+            Ok(_) => true,
+            Err(e) => !complete && e.error_len().is_none(),
+        };
         if s.starts_with(UTF8_BOM.as_bytes()) {
-            CharacterEncoding::Utf8WithBom
+            Utf8WithBom
+        } else if s.starts_with(UTF16LE_BOM) {
+            Utf16LeWithBom
+        } else if s.starts_with(UTF16BE_BOM) {
+            Utf16BeWithBom
+        } else if let Some(encoding) = guess_utf16(&s[..even_len]) {
+            // ASCII text in UTF-16 is also valid UTF-8
+            encoding
+        } else if is_utf8 {
+            Utf8
+        } else if is_plausible_shift_jis(&s[..line_len]) {
+            ShiftJis
+        } else if s.iter().any(|&b| b >= 0x80 && b < 0xa0) {
+            // C1 controls are unlikely in Latin-1 text
+            Windows1252
         } else {
-            CharacterEncoding::Utf8
+            Latin1
+        }
+    }
+
+    /// Returns the encoding called `name`, which is one of the names
+    /// returned by `name`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        use self::CharacterEncoding::*;
+        let encoding = match name.to_lowercase().as_str() {
+            "utf-8" => Utf8,
+            "utf-8-bom" => Utf8WithBom,
+            "utf-16le" => Utf16Le,
+            "utf-16le-bom" => Utf16LeWithBom,
+            "utf-16be" => Utf16Be,
+            "utf-16be-bom" => Utf16BeWithBom,
+            "iso-8859-1" => Latin1,
+            "windows-1252" => Windows1252,
+            "shift_jis" => ShiftJis,
+            _ => return None,
+        };
+        Some(encoding)
+    }
+
+    pub fn name(self) -> &'static str {
+        use self::CharacterEncoding::*;
+        match self {
+            Utf8 => "utf-8",
+            Utf8WithBom => "utf-8-bom",
+            Utf16Le => "utf-16le",
+            Utf16LeWithBom => "utf-16le-bom",
+            Utf16Be => "utf-16be",
+            Utf16BeWithBom => "utf-16be-bom",
+            Latin1 => "iso-8859-1",
+            Windows1252 => "windows-1252",
+            ShiftJis => "shift_jis",
+        }
+    }
+
+    /// The `encoding_rs` encoding used for the legacy multi-byte and
+    /// single-byte encodings.
+    fn legacy_encoding(self) -> &'static Encoding {
+        match self {
+            CharacterEncoding::ShiftJis => SHIFT_JIS,
+            _ => WINDOWS_1252,
+        }
+    }
+
+    /// The `encoding_rs` encoding that decodes this encoding, or `None`
+    /// for Latin-1, which `encoding_rs` treats as Windows-1252.
+    fn encoding_rs(self) -> Option<&'static Encoding> {
+        use self::CharacterEncoding::*;
+        match self {
+            Utf8 | Utf8WithBom => Some(UTF_8),
+            Utf16Le | Utf16LeWithBom => Some(UTF_16LE),
+            Utf16Be | Utf16BeWithBom => Some(UTF_16BE),
+            Latin1 => None,
+            Windows1252 | ShiftJis => Some(self.legacy_encoding()),
+        }
+    }
+
+    /// The byte order mark that files in this encoding start with.
+    fn bom(self) -> &'static [u8] {
+        use self::CharacterEncoding::*;
+        match self {
+            Utf8WithBom => UTF8_BOM.as_bytes(),
+            Utf16LeWithBom => UTF16LE_BOM,
+            Utf16BeWithBom => UTF16BE_BOM,
+            _ => &[],
+        }
+    }
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+
+    /// Returns `text` with every line ending converted to this one, or
+    /// `None` if it already only uses this one.
+    pub fn convert(self, text: &Rope) -> Option<Rope> {
+        match LineEnding::detect(text) {
+            (None, _) => return None,
+            (Some(ending), false) if ending == self => return None,
+            _ => (),
+        }
+        let text = String::from(text).replace("\r\n", "\n");
+        match self {
+            LineEnding::Lf => Some(Rope::from(text)),
+            LineEnding::CrLf => Some(Rope::from(text.replace('\n', "\r\n"))),
+        }
+    }
+
+    /// Finds the line endings used in `text`. Returns the most common one,
+    /// or `None` if there are no line breaks, and whether both are used.
+    pub fn detect(text: &Rope) -> (Option<Self>, bool) {
+        let mut counter = LineEndingCounter::default();
+        for chunk in text.iter_chunks(0, text.len()) {
+            counter.add(chunk);
+        }
+        counter.result()
+    }
+}
+
+impl FromStr for LineEnding {
+    type Err = ();
+
+    /// Parses the line ending `s` consists of, as found in the
+    /// `line_ending` setting.
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "\n" => Ok(LineEnding::Lf),
+            "\r\n" => Ok(LineEnding::CrLf),
+            _ => Err(()),
         }
     }
 }
 
+/// Counts the line endings of a text that is seen a piece at a time.
+#[derive(Default)]
+struct LineEndingCounter {
+    lf: usize,
+    crlf: usize,
+    prev: u8,
+}
+
+impl LineEndingCounter {
+    fn add(&mut self, s: &str) {
+        for &b in s.as_bytes() {
+            if b == b'\n' {
+                if self.prev == b'\r' { self.crlf += 1 } else { self.lf += 1 }
+            }
+            self.prev = b;
+        }
+    }
+
+    /// See `LineEnding::detect`.
+    fn result(&self) -> (Option<LineEnding>, bool) {
+        let ending = match (self.lf, self.crlf) {
+            (0, 0) => None,
+            (lf, crlf) if crlf > lf => Some(LineEnding::CrLf),
+            _ => Some(LineEnding::Lf),
+        };
+        (ending, self.lf > 0 && self.crlf > 0)
+    }
+}
+
+/// Reads a file a chunk at a time, so that loading a large file can be
+/// interleaved with handling other events, and its progress reported.
+///
+/// The encoding is guessed from the start of the file, and each chunk is
+/// decoded as it is read, so the file is never held in memory as bytes.
+/// The decoded text is still held in memory as a whole, in the rope: files
+/// are not memory-mapped, as the rope needs its text as UTF-8, and a
+/// mapped file could change under it.
+pub struct FileLoader {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    read: u64,
+    mod_time: Option<SystemTime>,
+    encoding: CharacterEncoding,
+    /// The decoder for the encoding, or `None` for Latin-1, where each
+    /// byte is a character.
+    decoder: Option<Decoder>,
+    /// The length of the byte order mark that is still to be skipped.
+    bom_len: usize,
+    line_endings: LineEndingCounter,
+    builder: TreeBuilder<RopeInfo>,
+}
+
+impl FileLoader {
+    fn new(path: &Path) -> Result<Self, FileError> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let mut sample = Vec::new();
+        (&mut file).take(LOADER_SNIFF_LEN as u64).read_to_end(&mut sample)?;
+        file.seek(SeekFrom::Start(0))?;
+        let complete = sample.len() as u64 == metadata.len();
+        let encoding = CharacterEncoding::guess_start(&sample, complete);
+        Ok(FileLoader {
+            path: path.to_owned(),
+            file,
+            len: metadata.len(),
+            read: 0,
+            mod_time: metadata.modified().ok(),
+            encoding,
+            decoder: encoding.encoding_rs().map(Encoding::new_decoder_without_bom_handling),
+            bom_len: encoding.bom().len(),
+            line_endings: LineEndingCounter::default(),
+            builder: TreeBuilder::new(),
+        })
+    }
+
+    /// Reads and decodes up to `max_bytes` more of the file. Returns
+    /// `true` once the whole file has been read.
+    pub fn step(&mut self, max_bytes: usize) -> Result<bool, FileError> {
+        let mut bytes = Vec::with_capacity(max_bytes);
+        (&mut self.file).take(max_bytes as u64).read_to_end(&mut bytes)?;
+        self.read += bytes.len() as u64;
+        let last = bytes.len() < max_bytes;
+
+        let skip = self.bom_len.min(bytes.len());
+        self.bom_len -= skip;
+        let bytes = &bytes[skip..];
+        let mut text = String::new();
+        let decoded = match self.decoder {
+            None => Ok(text.extend(bytes.iter().map(|&b| b as char))),
+            Some(ref mut decoder) => decode_chunk(decoder, bytes, last, &mut text),
+        };
+        if decoded.is_err() && self.encoding == CharacterEncoding::Utf8 {
+            // the start of the file looked like UTF-8, but the rest isn't;
+            // load it again in a single-byte encoding, as smaller files are
+            let encoding = if bytes.iter().any(|&b| b >= 0x80 && b < 0xa0) {
+                CharacterEncoding::Windows1252
+            } else {
+                CharacterEncoding::Latin1
+            };
+            self.restart(encoding)?;
+            return Ok(false);
+        }
+        decoded?;
+        self.line_endings.add(&text);
+        self.builder.push_str(&text);
+        Ok(last)
+    }
+
+    /// Starts reading the file from the beginning again, in `encoding`.
+    fn restart(&mut self, encoding: CharacterEncoding) -> Result<(), FileError> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.read = 0;
+        self.encoding = encoding;
+        self.decoder = encoding.encoding_rs().map(Encoding::new_decoder_without_bom_handling);
+        self.bom_len = encoding.bom().len();
+        self.line_endings = LineEndingCounter::default();
+        self.builder = TreeBuilder::new();
+        Ok(())
+    }
+
+    /// Returns the number of bytes read so far, and the length of the file.
+    pub fn progress(&self) -> (u64, u64) {
+        (self.read, self.len)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn finish(self) -> (Rope, FileInfo) {
+        let (line_ending, mixed_line_endings) = self.line_endings.result();
+        let info = FileInfo {
+            encoding: self.encoding,
+            line_ending,
+            mixed_line_endings,
+            path: self.path,
+            mod_time: self.mod_time,
+            has_changed: false,
+        };
+        (self.builder.build(), info)
+    }
+}
+
+/// Decodes `bytes` with `decoder`, appending the text to `out`. A character
+/// cut off at the end of `bytes` is kept by the decoder for the next chunk,
+/// unless this is the `last` chunk.
+fn decode_chunk(decoder: &mut Decoder, mut bytes: &[u8], last: bool, out: &mut String)
+    -> Result<(), FileError>
+{
+    loop {
+        let needed = decoder.max_utf8_buffer_length_without_replacement(bytes.len())
+            .unwrap_or(bytes.len() * 3 + 16);
+        out.reserve(needed);
+        let (result, read) = decoder.decode_to_string_without_replacement(bytes, out, last);
+        bytes = &bytes[read..];
+        match result {
+            DecoderResult::InputEmpty => return Ok(()),
+            DecoderResult::OutputFull => continue,
+            DecoderResult::Malformed(..) => return Err(FileError::UnknownEncoding),
+        }
+    }
+}
+
+/// Guesses whether `s` is UTF-16 without a byte order mark, from how often
+/// the high byte of a code unit is zero, as it is for ASCII characters.
+fn guess_utf16(s: &[u8]) -> Option<CharacterEncoding> {
+    if s.is_empty() || s.len() % 2 != 0 {
+        return None;
+    }
+    let sample = &s[..s.len().min(UTF16_SNIFF_LEN)];
+    let zeros_at = |parity| sample.iter().skip(parity).step_by(2)
+        .filter(|&&b| b == 0)
+        .count();
+    let (even_zeros, odd_zeros) = (zeros_at(0), zeros_at(1));
+    let units = sample.len() / 2;
+    let encoding = if odd_zeros * 2 > units && even_zeros == 0 {
+        CharacterEncoding::Utf16Le
+    } else if even_zeros * 2 > units && odd_zeros == 0 {
+        CharacterEncoding::Utf16Be
+    } else {
+        return None;
+    };
+    // only guess encodings the file can actually be decoded in
+    try_decode(s.to_owned(), encoding).ok().map(|_| encoding)
+}
+
+/// Returns the location where the undo history of the file at `path` is
+/// persisted. With a `cache_dir` the history is kept there, keyed by a hash
+/// of the canonical path; otherwise it is kept in a hidden file next to
+/// the file itself.
+pub fn undo_history_path(path: &Path, cache_dir: Option<&Path>)
+    -> Option<PathBuf>
+{
+    sidecar_path(path, cache_dir, "undo_history", "json", "xi-undo")
+}
+
+/// Returns the location of the swap file that holds unsaved changes to
+/// the file at `path`. Like the undo history, it is kept in `cache_dir` if
+/// there is one, and otherwise next to the file.
+pub fn swap_file_path(path: &Path, cache_dir: Option<&Path>) -> Option<PathBuf> {
+    sidecar_path(path, cache_dir, "swap", "swp", "xi-swap")
+}
+
+/// Returns the location of a file that holds state about the file at
+/// `path`: either in the `subdir` directory of `cache_dir`, named by a hash
+/// of the canonical path with the extension `ext`, or in a hidden file next
+/// to the file, with the extension `hidden_ext`.
+fn sidecar_path(path: &Path, cache_dir: Option<&Path>, subdir: &str, ext: &str,
+                hidden_ext: &str) -> Option<PathBuf>
+{
+    let path = path.canonicalize().ok()?;
+    match cache_dir {
+        Some(dir) => {
+            let hash = fnv_hash(path.to_string_lossy().as_bytes());
+            Some(dir.join(subdir).join(format!("{:016x}.{}", hash, ext)))
+        }
+        None => {
+            let name = format!(".{}.{}", path.file_name()?.to_string_lossy(), hidden_ext);
+            Some(path.with_file_name(name))
+        }
+    }
+}
+
+/// Writes `text` to the swap file at `swap_path`, replacing it atomically.
+pub fn write_swap_file(swap_path: &Path, text: &Rope) -> Result<(), FileError> {
+    try_save(swap_path, text, CharacterEncoding::Utf8, None)
+}
+
+/// Reads the text saved in the swap file at `swap_path`.
+pub fn read_swap_file(swap_path: &Path) -> Result<Rope, FileError> {
+    let bytes = fs::read(swap_path)?;
+    try_decode(bytes, CharacterEncoding::Utf8)
+}
+
+/// Reads the file at `path` without opening it in a buffer, decoding it
+/// with the encoding it is guessed to have.
+pub fn read_file(path: &Path) -> Result<Rope, FileError> {
+    try_load_file(path, None).map(|(rope, _)| rope)
+}
+
+/// Computes the 64-bit FNV-1a hash of `bytes`. The result is stable across
+/// runs, which makes it suitable for naming files on disk.
+pub(crate) fn fnv_hash(bytes: &[u8]) -> u64 {
+    fnv_hash_update(0xcbf2_9ce4_8422_2325, bytes)
+}
+
+/// Continues an FNV-1a hash from `hash` over additional `bytes`.
+pub(crate) fn fnv_hash_update(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
 /// Returns the modification timestamp for the file at a given path,
 /// if present.
 fn get_mod_time<P>(path: P) -> Option<SystemTime>
@@ -275,9 +974,231 @@ impl fmt::Display for FileError {
         match self {
             &FileError::Io(ref e) => write!(f, "{}", e),
             &FileError::UnknownEncoding => write!(f, "Error decoding file"),
+            &FileError::Unencodable(encoding) => write!(
+                f, "The text can't be saved as {}", encoding.name()),
             &FileError::HasChanged => write!(f, "File has changed on disk. \
             Please save elsewhere and reload the file."),
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+    use super::CharacterEncoding::*;
+    use std::fs;
+    use self::tempdir::TempDir;
+
+    fn round_trip(bytes: &[u8], text: &str) -> CharacterEncoding {
+        let encoding = CharacterEncoding::guess(bytes);
+        let rope = try_decode(bytes.to_owned(), encoding).ok().expect("decoding failed");
+        assert_eq!(String::from(&rope), text);
+        assert_eq!(try_encode(&rope, encoding).ok().unwrap(), bytes);
+        encoding
+    }
+
+    #[test]
+    fn guess_encodings() {
+        assert_eq!(round_trip(b"caf\xc3\xa9\n", "café\n"), Utf8);
+        assert_eq!(round_trip(b"\xef\xbb\xbfcaf\xc3\xa9", "café"), Utf8WithBom);
+        assert_eq!(round_trip(b"h\0i\0\n\0", "hi\n"), Utf16Le);
+        assert_eq!(round_trip(b"\0h\0i\0\n", "hi\n"), Utf16Be);
+        assert_eq!(round_trip(b"\xff\xfeh\0=\xd8\0\xde", "h\u{1f600}"), Utf16LeWithBom);
+        assert_eq!(round_trip(b"\xfe\xff\0h\x30\x42", "hあ"), Utf16BeWithBom);
+        assert_eq!(round_trip(b"\x82\xa0\x82\xa2\n", "あい\n"), ShiftJis);
+        assert_eq!(round_trip(b"\x93quoted\x94 caf\xe9\n", "\u{201c}quoted\u{201d} café\n"),
+                   Windows1252);
+        assert_eq!(round_trip(b"caf\xe9 cr\xe8me\n", "café crème\n"), Latin1);
+        // also valid Shift_JIS, but with ASCII second bytes or half-width katakana
+        assert_eq!(round_trip(b"Caf\xe9s and cr\xe8pes", "Cafés and crèpes"), Latin1);
+        assert_eq!(round_trip(b"Stra\xdfe", "Straße"), Latin1);
+        assert_eq!(round_trip(b"\x93Caf\xe9s\x94", "\u{201c}Cafés\u{201d}"), Windows1252);
+        assert_eq!(round_trip(b"", ""), Utf8);
+    }
+
+    #[test]
+    fn detect_line_endings() {
+        let detect = |s: &str| LineEnding::detect(&Rope::from(s));
+        assert_eq!(detect("no breaks"), (None, false));
+        assert_eq!(detect("a\nb\n"), (Some(LineEnding::Lf), false));
+        assert_eq!(detect("a\r\nb\r\n"), (Some(LineEnding::CrLf), false));
+        assert_eq!(detect("a\r\nb\r\nc\n"), (Some(LineEnding::CrLf), true));
+        assert_eq!(detect("a\r\nb\nc\n\r"), (Some(LineEnding::Lf), true));
+    }
+
+    #[test]
+    fn convert_line_endings() {
+        let convert = |s: &str, line_ending: LineEnding| {
+            line_ending.convert(&Rope::from(s)).map(String::from)
+        };
+        assert_eq!(convert("a\r\nb\nc", LineEnding::Lf), Some("a\nb\nc".to_owned()));
+        assert_eq!(convert("a\r\nb\nc", LineEnding::CrLf), Some("a\r\nb\r\nc".to_owned()));
+        assert_eq!(convert("a\r\nb\r\n", LineEnding::CrLf), None);
+        assert_eq!(convert("no breaks", LineEnding::CrLf), None);
+        assert_eq!("\r\n".parse(), Ok(LineEnding::CrLf));
+        assert_eq!("\r".parse::<LineEnding>(), Err(()));
+    }
+
+    #[test]
+    fn names() {
+        for &encoding in &[Utf8, Utf8WithBom, Utf16Le, Utf16LeWithBom, Utf16Be,
+                           Utf16BeWithBom, Latin1, Windows1252, ShiftJis] {
+            assert_eq!(CharacterEncoding::from_name(encoding.name()), Some(encoding));
+        }
+        assert_eq!(CharacterEncoding::from_name("Shift_JIS"), Some(ShiftJis));
+        assert_eq!(CharacterEncoding::from_name("ebcdic"), None);
+    }
+
+    #[test]
+    fn unencodable_text_is_not_saved() {
+        let dir = TempDir::new("xi-file").unwrap();
+        let path = dir.path().join("latin1.txt");
+        try_save(&path, &Rope::from("café"), Latin1, None).ok().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xe9");
+
+        match try_save(&path, &Rope::from("日本"), Latin1, None) {
+            Err(FileError::Unencodable(Latin1)) => (),
+            _ => panic!("expected an encoding error"),
+        }
+        assert!(try_save(&path, &Rope::from("日本"), Windows1252, None).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xe9");
+
+        let (rope, info) = try_load_file(&path, Some(Windows1252)).ok().unwrap();
+        assert_eq!(String::from(&rope), "café");
+        assert_eq!(info.encoding, Windows1252);
+    }
+
+    #[test]
+    fn save_replaces_file() {
+        let dir = TempDir::new("xi-file").unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "old").unwrap();
+        try_save(&path, &Rope::from("new"), Utf8, Some(&Backup::Adjacent)).ok().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_to_string(dir.path().join("file.txt~")).unwrap(), "old");
+
+        let backups = dir.path().join("backups");
+        try_save(&path, &Rope::from("newer"), Utf8, Some(&Backup::Directory(backups.clone())))
+            .ok().unwrap();
+        let target = fs::canonicalize(&path).unwrap();
+        let name = target.to_string_lossy().replace(MAIN_SEPARATOR, "!") + "~";
+        assert_eq!(fs::read_to_string(backups.join(&name)).unwrap(), "new");
+
+        // a relative backup directory is next to the file
+        try_save(&path, &Rope::from("newest"), Utf8,
+                 Some(&Backup::Directory(PathBuf::from("backups")))).ok().unwrap();
+        assert_eq!(fs::read_to_string(backups.join(&name)).unwrap(), "newer");
+
+        // no temporary files are left behind
+        let mut names = fs::read_dir(dir.path()).unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["backups", "file.txt", "file.txt~"]);
+    }
+
+    #[test]
+    fn temp_files_are_unique() {
+        let dir = TempDir::new("xi-file").unwrap();
+        let path = dir.path().join("file.txt");
+        let (_, first) = create_temp_file(&path).unwrap();
+        let (_, second) = create_temp_file(&path).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), Some(dir.path()));
+    }
+
+    #[test]
+    fn failed_write_keeps_file() {
+        let dir = TempDir::new("xi-file").unwrap();
+        let path = dir.path().join("history").join("undo.json");
+        replace_file(&path, None, |f| f.write_all(b"old")).ok().unwrap();
+        let result = replace_file(&path, None, |f| {
+            f.write_all(b"partial")?;
+            Err(io::Error::new(io::ErrorKind::Other, "crashed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_keeps_permissions_and_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+        let dir = TempDir::new("xi-file").unwrap();
+        let path = dir.path().join("script.sh");
+        let link = dir.path().join("link.sh");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o751)).unwrap();
+        symlink(&path, &link).unwrap();
+
+        try_save(&link, &Rope::from("new"), Utf8, None).ok().unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o751);
+    }
+
+    #[test]
+    fn load_in_chunks() {
+        let dir = TempDir::new("xi-file").unwrap();
+        let path = dir.path().join("large.txt");
+        let cases: &[(&[u8], &str, CharacterEncoding)] = &[
+            (b"caf\xc3\xa9\r\n\xf0\x9f\x98\x80\r\n", "café\r\n\u{1f600}\r\n", Utf8),
+            (b"\xff\xfeh\0=\xd8\0\xde\r\0\n\0", "h\u{1f600}\r\n", Utf16LeWithBom),
+            (b"\x82\xa0\x82\xa2\r\n\x82\xa4", "あい\r\nう", ShiftJis),
+        ];
+        for &(bytes, text, encoding) in cases {
+            fs::write(&path, bytes).unwrap();
+            let mut loader = FileLoader::new(&path).ok().unwrap();
+            let mut steps = 0;
+            while !loader.step(3).ok().unwrap() {
+                steps += 1;
+                assert_eq!(loader.progress(), (3 * steps, bytes.len() as u64));
+            }
+            assert_eq!(loader.progress().0, bytes.len() as u64);
+
+            let (rope, info) = loader.finish();
+            assert_eq!(String::from(&rope), text);
+            assert_eq!(info.encoding, encoding);
+            assert_eq!(info.line_ending, Some(LineEnding::CrLf));
+            assert!(!info.mixed_line_endings);
+        }
+    }
+
+    #[test]
+    fn load_in_chunks_after_ascii_start() {
+        let dir = TempDir::new("xi-file").unwrap();
+        let path = dir.path().join("large.log");
+        let start = "ok\n".repeat(LOADER_SNIFF_LEN / 3 + 1);
+        let cases: &[(&[u8], &str, CharacterEncoding)] = &[
+            (b"caf\xe9\n", "caf\u{e9}\n", Latin1),
+            (b"\x93quoted\x94\n", "\u{201c}quoted\u{201d}\n", Windows1252),
+        ];
+        for &(end, text, encoding) in cases {
+            let mut bytes = start.clone().into_bytes();
+            bytes.extend_from_slice(end);
+            fs::write(&path, &bytes).unwrap();
+            let mut loader = FileLoader::new(&path).ok().unwrap();
+            assert_eq!(loader.encoding, Utf8);
+            while !loader.step(4096).ok().unwrap() {}
+            assert_eq!(loader.progress().0, bytes.len() as u64);
+
+            let (rope, info) = loader.finish();
+            assert_eq!(String::from(&rope), format!("{}{}", start, text));
+            assert_eq!(info.encoding, encoding);
+            assert_eq!(info.line_ending, Some(LineEnding::Lf));
+        }
+    }
+
+    #[test]
+    fn guess_truncated_sample() {
+        assert_eq!(CharacterEncoding::guess_start(b"caf\xc3", false), Utf8);
+        assert_eq!(CharacterEncoding::guess_start(b"caf\xc3", true), Latin1);
+        assert_eq!(CharacterEncoding::guess_start(b"h\0i\0\n", false), Utf16Le);
+        assert_eq!(CharacterEncoding::guess_start(b"\x82\xa0\n\x82", false), ShiftJis);
+    }
+}
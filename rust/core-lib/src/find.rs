@@ -16,27 +16,39 @@
 
 use std::cmp::{min,max};
 
+use regex::{Captures, Regex, RegexBuilder, Error as RegexError};
 use serde_json::Value;
 
 use index_set::IndexSet;
 use xi_rope::delta::{Delta, DeltaRegion};
-use xi_rope::find::{find, CaseMatching};
+use xi_rope::find::{find, is_multiline_regex, CaseMatching, RegexSearcher};
 use xi_rope::rope::{Rope, LinesMetric, RopeInfo};
 use xi_rope::tree::Cursor;
 use xi_rope::interval::Interval;
 use selection::{Selection, SelRegion};
+use word_boundaries::WordCursor;
 use xi_rope::tree::Metric;
 
+/// The number of lines around an edit that are searched again for a regex
+/// that can match across line breaks. Matches that span more lines than this
+/// aren't updated until the next search.
+const MULTILINE_MARGIN: usize = 16;
+
 /// Contains logic to search text
 pub struct Find {
-    // todo: link to search query so that search results can be correlated back to query
-
+    /// The name of the query this instance represents, or `None` for the
+    /// default query.
+    name: Option<String>,
     /// The occurrences, which determine the highlights, have been updated.
     hls_dirty: bool,
     /// The currently active search string
     search_string: Option<String>,
     /// The case matching setting for the currently active search
     case_matching: CaseMatching,
+    /// The compiled pattern, if the currently active search is a regex search
+    regex: Option<Regex>,
+    /// Only match occurrences that start and end at word boundaries
+    whole_words: bool,
     /// The set of all known find occurrences (highlights)
     occurrences: Selection,
     /// Set of ranges that have already been searched for the currently active search string
@@ -44,16 +56,28 @@ pub struct Find {
 }
 
 impl Find {
-    pub fn new() -> Find {
+    pub fn new(name: Option<String>) -> Find {
         Find {
+            name,
             hls_dirty: true,
             search_string: None,
             case_matching: CaseMatching::CaseInsensitive,
+            regex: None,
+            whole_words: false,
             occurrences: Selection::new(),
             valid_search: IndexSet::new(),
         }
     }
 
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(String::as_str)
+    }
+
+    /// Returns `true` if this query has an active search string.
+    pub fn is_active(&self) -> bool {
+        self.search_string.is_some()
+    }
+
     pub fn occurrences(&self) -> &Selection {
         &self.occurrences
     }
@@ -86,59 +110,101 @@ impl Find {
             }
 
             // update find for the whole delta (is going to only update invalid regions)
-            let (iv, _) = delta.summary();
-            self.update_find(text, iv.start(), iv.end(), true);
+            let (iv, new_len) = delta.summary();
+            // a regex match depends on the whole line it is on (or, for
+            // multi-line patterns, on the lines around it), so invalidate that
+            // too; so does whether a match is a whole word, conservatively
+            let (start, end) = if self.regex.is_some() || self.whole_words {
+                let margin = self.multiline_margin();
+                let start_line = text.line_of_offset(iv.start()).saturating_sub(margin);
+                let start = text.offset_of_line(start_line);
+                let end = end_of_lines(text, iv.start() + new_len, margin);
+                self.valid_search.delete_range(start, end);
+                self.occurrences.delete_range(start, end, false);
+                (start, end)
+            } else {
+                (iv.start(), iv.end())
+            };
+            self.update_find(text, start, end, true);
+        }
+    }
+
+    /// The number of lines around an edit or searched region that a match
+    /// of the current search may extend into.
+    fn multiline_margin(&self) -> usize {
+        match self.regex {
+            Some(ref regex) if is_multiline_regex(regex.as_str()) => MULTILINE_MARGIN,
+            _ => 0,
         }
     }
 
     /// Set search parameters and executes the search.
+    ///
+    /// If `is_regex` is set, `search_string` is interpreted as a regular
+    /// expression; an error is returned if it fails to compile. Otherwise,
+    /// case insensitive searches use full Unicode case folding, and if
+    /// `ignore_accents` is set they also ignore diacritics. If `whole_words`
+    /// is set, only occurrences that start and end at word boundaries are
+    /// found.
     pub fn do_find(&mut self, text: &Rope, search_string: Option<String>,
-                   case_sensitive: bool) -> Value {
+                   case_sensitive: bool, is_regex: bool, whole_words: bool,
+                   ignore_accents: bool) -> Result<Value, RegexError> {
         if search_string.is_none() {
             self.unset();
-            return Value::Null;
+            return Ok(Value::Null);
         }
 
         let search_string = search_string.unwrap();
         if search_string.len() == 0 {
             self.unset();
-            return Value::Null;
+            return Ok(Value::Null);
         }
 
-        self.set_find(&search_string, case_sensitive);
+        self.set_find(&search_string, case_sensitive, is_regex, whole_words,
+                      ignore_accents)?;
         self.update_find(text, 0, text.len(), false);
 
-        Value::String(search_string.to_string())
+        Ok(Value::String(search_string.to_string()))
     }
 
     /// Unsets the search and removes all highlights from the view.
     pub fn unset(&mut self) {
         self.search_string = None;
+        self.regex = None;
         self.occurrences = Selection::new();
         self.hls_dirty = true;
         self.valid_search.clear();
     }
 
     /// Sets find parameters and search query.
-    fn set_find(&mut self, search_string: &str,
-                case_sensitive: bool) {
-        let case_matching = if case_sensitive {
-            CaseMatching::Exact
-        } else {
-            CaseMatching::CaseInsensitive
-        };
+    fn set_find(&mut self, search_string: &str, case_sensitive: bool,
+                is_regex: bool, whole_words: bool, ignore_accents: bool)
+                -> Result<(), RegexError> {
+        let case_matching = case_matching(case_sensitive, ignore_accents);
 
         if let Some(ref s) = self.search_string {
-            if s == search_string && case_matching == self.case_matching {
+            if s == search_string && case_matching == self.case_matching
+                && is_regex == self.regex.is_some() && whole_words == self.whole_words {
                 // search parameters did not change
-                return;
+                return Ok(());
             }
         }
 
+        // compile before touching any state, so that a bad pattern leaves
+        // the previous search intact
+        let regex = if is_regex {
+            Some(build_regex(search_string, case_sensitive)?)
+        } else {
+            None
+        };
+
         self.unset();
 
         self.search_string = Some(search_string.to_string());
         self.case_matching = case_matching;
+        self.regex = regex;
+        self.whole_words = whole_words;
+        Ok(())
     }
 
     /// Execute the search on the provided text in the range provided by `start` and `end`.
@@ -154,20 +220,36 @@ impl Find {
         let slop = if include_slop { self.search_string.as_ref().unwrap().len() * 2 } else { 0 };
         let mut invalidate_from = None;
 
-        for (_start, end) in self.valid_search.minus_one_range(start, end) {
+        for (range_start, end) in self.valid_search.minus_one_range(start, end) {
             let search_string = self.search_string.as_ref().unwrap();
 
             // expand region to be able to find occurrences around the region's edges
-            let from = max(0, slop) - slop;
-            let to = min(end + slop, text.len());
+            let from = max(range_start, slop) - slop;
+            // regex matches have no fixed length, but are bounded by the line,
+            // or for multi-line patterns by the margin around it
+            let to = match self.regex {
+                Some(_) => end_of_lines(text, end, self.multiline_margin()),
+                None => min(end + slop, text.len()),
+            };
 
             // TODO: this interval might cut a unicode codepoint, make sure it is
             // aligned to codepoint boundaries.
-            let text = text.subseq(Interval::new_closed_open(0, to));
-            let mut cursor = Cursor::new(&text, from);
-            while let Some(start) = find(&mut cursor, self.case_matching, &search_string) {
+            let sub_text = text.subseq(Interval::new_closed_open(0, to));
+            let mut cursor = Cursor::new(&sub_text, from);
+            let mut searcher = self.regex.as_ref().map(RegexSearcher::new);
+            while let Some(start) = match searcher {
+                Some(ref mut searcher) => searcher.find(&mut cursor),
+                None => find(&mut cursor, self.case_matching, &search_string),
+            } {
                 let end = cursor.pos();
 
+                if self.whole_words && !is_whole_word(text, start, end) {
+                    // an occurrence may still start inside this one
+                    cursor.set(start);
+                    cursor.next_codepoint();
+                    continue;
+                }
+
                 let region = SelRegion::new(start, end);
                 let prev_len = self.occurrences.len();
                 let (_, e) = self.occurrences.add_range_distinct(region);
@@ -196,7 +278,10 @@ impl Find {
             self.valid_search.union_one_range(start, invalidate_from);
 
             // invalidate all search results from the point of the ambiguous search result until ...
-            let is_multi_line = LinesMetric::next(self.search_string.as_ref().unwrap(), 0).is_some();
+            let is_multi_line = match self.regex {
+                Some(ref regex) => is_multiline_regex(regex.as_str()),
+                None => LinesMetric::next(self.search_string.as_ref().unwrap(), 0).is_some(),
+            };
             if is_multi_line {
                 // ... the end of the file
                 self.valid_search.delete_range(invalidate_from, text_len);
@@ -247,4 +332,74 @@ impl Find {
             next_occurrence.cloned()
         }
     }
-}
\ No newline at end of file
+
+    /// Returns the text that should replace `occurrence` given the replacement
+    /// `template`.
+    ///
+    /// For regex searches, `$1`, `${name}` and friends in the template are
+    /// expanded to the corresponding capture groups of the match (see
+    /// `regex::Captures::expand`); otherwise the template is used verbatim.
+    pub fn replacement(&self, text: &Rope, occurrence: &SelRegion,
+                       template: &str) -> String {
+        let regex = match self.regex {
+            Some(ref regex) => regex,
+            None => return template.to_owned(),
+        };
+
+        // re-run the match on the lines of the occurrence, which are all the
+        // context anchors and word boundaries need to resolve the same way
+        let start = text.offset_of_line(text.line_of_offset(occurrence.min()));
+        let end = end_of_line(text, occurrence.max());
+        let hay = text.slice_to_string(start, end);
+        let (match_start, match_end) = (occurrence.min() - start, occurrence.max() - start);
+        let mut result = String::new();
+        let is_occurrence = |caps: &Captures| {
+            let mat = caps.get(0).unwrap();
+            mat.start() == match_start && mat.end() == match_end
+        };
+        match regex.captures_iter(&hay).find(is_occurrence) {
+            Some(caps) => caps.expand(template, &mut result),
+            None => result.push_str(template),
+        }
+        result
+    }
+}
+
+/// Returns the case matching policy for a search with the given options.
+pub(crate) fn case_matching(case_sensitive: bool, ignore_accents: bool) -> CaseMatching {
+    if case_sensitive {
+        CaseMatching::Exact
+    } else if ignore_accents {
+        CaseMatching::AccentInsensitive
+    } else {
+        CaseMatching::CaseFolding
+    }
+}
+
+/// Compiles the pattern of a regex search, with `^` and `$` matching at
+/// line boundaries.
+pub(crate) fn build_regex(pattern: &str, case_sensitive: bool) -> Result<Regex, RegexError> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(!case_sensitive)
+        .multi_line(true)
+        .build()
+}
+
+/// Returns `true` if the range from `start` to `end` both starts and ends at
+/// a word boundary.
+pub(crate) fn is_whole_word(text: &Rope, start: usize, end: usize) -> bool {
+    WordCursor::new(text, start).is_boundary() && WordCursor::new(text, end).is_boundary()
+}
+
+/// Returns the offset of the end of the line containing `offset`, including
+/// the line break.
+fn end_of_line(text: &Rope, offset: usize) -> usize {
+    end_of_lines(text, offset, 0)
+}
+
+/// Returns the offset of the end of the line `extra` lines after the one
+/// containing `offset`, including the line break.
+fn end_of_lines(text: &Rope, offset: usize, extra: usize) -> usize {
+    let max_line = text.measure::<LinesMetric>() + 1;
+    text.offset_of_line(min(text.line_of_offset(offset) + 1 + extra, max_line))
+}
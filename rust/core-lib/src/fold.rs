@@ -0,0 +1,418 @@
+// Copyright 2018 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Folded regions of a view, which are shown as a single line.
+
+use std::ops::Range;
+
+use xi_rope::delta::{Delta, Transformer};
+use xi_rope::rope::{LinesMetric, Rope, RopeInfo};
+
+/// A run of text hidden by one or more folds. It starts at the start of a
+/// line, and ends at the start of a line or at the end of the text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hidden {
+    pub range: Range<usize>,
+    /// The number of visual lines that are hidden.
+    pub lines: usize,
+    /// Whether the last line of the text is hidden.
+    to_end: bool,
+}
+
+impl Hidden {
+    pub fn contains(&self, offset: usize) -> bool {
+        self.range.start <= offset && (offset < self.range.end || self.to_end)
+    }
+}
+
+/// The folds of a view.
+///
+/// A fold keeps the line containing its start visible, as a placeholder
+/// for the lines that follow it, up to the one containing its end, which
+/// are hidden. Folds may be nested.
+#[derive(Debug, Default)]
+pub struct Folds {
+    /// The folded ranges, ordered by their start.
+    folds: Vec<Range<usize>>,
+    /// The text they hide, in order and without overlaps.
+    hidden: Vec<Hidden>,
+}
+
+impl Folds {
+    pub fn is_empty(&self) -> bool {
+        self.folds.is_empty()
+    }
+
+    pub fn hidden(&self) -> &[Hidden] {
+        &self.hidden
+    }
+
+    /// Returns the hidden text containing `offset`, if it is hidden.
+    pub fn hidden_containing(&self, offset: usize) -> Option<&Hidden> {
+        self.hidden.iter()
+            .take_while(|hidden| hidden.range.start <= offset)
+            .find(|hidden| hidden.contains(offset))
+    }
+
+    /// Returns the hidden text starting at `offset`, if any.
+    pub fn hidden_at(&self, offset: usize) -> Option<&Hidden> {
+        self.hidden.iter().find(|hidden| hidden.range.start == offset)
+    }
+
+    /// Folds `range`. Returns `false` if it was already folded, or if it
+    /// doesn't span more than one line.
+    pub fn add(&mut self, text: &Rope, range: Range<usize>) -> bool {
+        if text.line_of_offset(range.start) >= text.line_of_offset(range.end)
+            || self.folds.contains(&range)
+        {
+            return false;
+        }
+        let ix = self.folds.iter().position(|fold| fold.start > range.start)
+            .unwrap_or_else(|| self.folds.len());
+        self.folds.insert(ix, range);
+        true
+    }
+
+    /// Removes the folds whose lines include `line`, returning whether
+    /// there were any.
+    pub fn remove_at_line(&mut self, text: &Rope, line: usize) -> bool {
+        let len = self.folds.len();
+        self.folds.retain(|fold| line < text.line_of_offset(fold.start)
+                          || line > text.line_of_offset(fold.end));
+        self.folds.len() != len
+    }
+
+    /// Removes the folds that hide `offset`, returning whether there were
+    /// any.
+    pub fn remove_hiding(&mut self, text: &Rope, offset: usize) -> bool {
+        let len = self.folds.len();
+        self.folds.retain(|fold| !hidden_range(text, fold).contains(offset));
+        self.folds.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.folds.clear();
+        self.hidden.clear();
+    }
+
+    /// Moves the folds along with the edit `delta`, which produced `text`.
+    /// Folds that no longer span more than one line are removed.
+    pub fn apply_delta(&mut self, delta: &Delta<RopeInfo>, text: &Rope) {
+        let mut transformer = Transformer::new(delta);
+        let mut folds = self.folds.iter()
+            .map(|fold| transformer.transform(fold.start, true)
+                 ..transformer.transform(fold.end, false))
+            .filter(|fold| text.line_of_offset(fold.start) < text.line_of_offset(fold.end))
+            .collect::<Vec<_>>();
+        folds.dedup();
+        self.folds = folds;
+    }
+
+    /// Recomputes the hidden text, after the folds, the text or the way it
+    /// is broken into visual lines changed. `line_of_offset` returns the
+    /// visual line of an offset, ignoring folds.
+    pub fn update_hidden<F>(&mut self, text: &Rope, line_of_offset: F)
+        where F: Fn(usize) -> usize
+    {
+        let mut ranges = self.folds.iter()
+            .map(|fold| hidden_range(text, fold))
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|hidden| hidden.range.start);
+
+        let mut merged: Vec<Hidden> = Vec::new();
+        for hidden in ranges {
+            if let Some(last) = merged.last_mut() {
+                // a fold starting on a hidden line is hidden itself
+                if hidden.range.start <= last.range.end {
+                    last.range.end = last.range.end.max(hidden.range.end);
+                    last.to_end |= hidden.to_end;
+                    continue;
+                }
+            }
+            merged.push(hidden);
+        }
+        for hidden in &mut merged {
+            let end_line = line_of_offset(hidden.range.end);
+            let start_line = line_of_offset(hidden.range.start);
+            hidden.lines = end_line - start_line + if hidden.to_end { 1 } else { 0 };
+        }
+        self.hidden = merged;
+    }
+}
+
+/// Returns the text hidden by `fold`, without counting its lines.
+fn hidden_range(text: &Rope, fold: &Range<usize>) -> Hidden {
+    let first_line = text.line_of_offset(fold.start) + 1;
+    let last_line = text.line_of_offset(fold.end);
+    let start = text.offset_of_line(first_line);
+    if last_line >= text.measure::<LinesMetric>() {
+        Hidden { range: start..text.len(), lines: 0, to_end: true }
+    } else {
+        Hidden { range: start..text.offset_of_line(last_line + 1), lines: 0, to_end: false }
+    }
+}
+
+/// A block of lines that are indented more than the line before them,
+/// which can be folded into that line.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct IndentFold {
+    /// The end of the line before the block.
+    pub start: usize,
+    /// The end of the last line of the block, not counting trailing
+    /// blank lines.
+    pub end: usize,
+    pub start_line: usize,
+    pub end_line: usize,
+    /// The blocks nested in this one.
+    pub children: Vec<IndentFold>,
+}
+
+impl IndentFold {
+    fn flatten_into(&self, ranges: &mut Vec<Range<usize>>) {
+        ranges.push(self.start..self.end);
+        for child in &self.children {
+            child.flatten_into(ranges);
+        }
+    }
+}
+
+/// The indentation of each line of a buffer, kept up to date as it is
+/// edited, from which fold ranges are computed when no plugin provides
+/// them.
+#[derive(Debug)]
+pub struct IndentLevels {
+    /// The width of the indentation of each line, or `None` for lines
+    /// that are blank.
+    levels: Vec<Option<usize>>,
+    tab_size: usize,
+}
+
+impl IndentLevels {
+    pub fn new(text: &Rope, tab_size: usize) -> IndentLevels {
+        let n_lines = text.measure::<LinesMetric>() + 1;
+        let levels = line_levels(text, 0, n_lines, tab_size);
+        IndentLevels { levels, tab_size }
+    }
+
+    pub fn tab_size(&self) -> usize {
+        self.tab_size
+    }
+
+    /// Updates the levels of the lines touched by `delta`, which produced
+    /// `text`.
+    pub fn apply_delta(&mut self, delta: &Delta<RopeInfo>, text: &Rope) {
+        let (iv, new_len) = delta.summary();
+        let n_lines = text.measure::<LinesMetric>() + 1;
+        let start_line = text.line_of_offset(iv.start());
+        let new_end_line = text.line_of_offset(iv.start() + new_len) + 1;
+        // the lines after the edit are the same in the old and new text
+        let old_end_line = (self.levels.len() + new_end_line).saturating_sub(n_lines)
+            .max(start_line)
+            .min(self.levels.len());
+        let levels = line_levels(text, start_line, new_end_line, self.tab_size);
+        self.levels.splice(start_line..old_end_line, levels);
+    }
+
+    /// Returns the outermost indented blocks of `text`, with the blocks
+    /// nested in them.
+    pub fn folds(&self, text: &Rope) -> Vec<IndentFold> {
+        // the headers of the enclosing blocks, with their levels, and the
+        // blocks found in them so far
+        let mut stack: Vec<(usize, usize, Vec<IndentFold>)> = vec![(0, 0, Vec::new())];
+        let mut last_line = 0;
+        let lines = self.levels.iter().enumerate()
+            .filter_map(|(line, level)| level.map(|level| (line, level + 1)));
+        for (line, level) in lines {
+            while stack.len() > 1 && stack.last().unwrap().0 >= level {
+                close_fold(text, &mut stack, last_line);
+            }
+            stack.push((level, line, Vec::new()));
+            last_line = line;
+        }
+        while stack.len() > 1 {
+            close_fold(text, &mut stack, last_line);
+        }
+        stack.pop().unwrap().2
+    }
+
+    /// Returns the ranges of `folds`, and of the blocks nested in them,
+    /// ordered by their start.
+    pub fn flatten(folds: &[IndentFold]) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        for fold in folds {
+            fold.flatten_into(&mut ranges);
+        }
+        ranges
+    }
+}
+
+/// Pops the innermost header off `stack`, adding the block after it to
+/// the enclosing one if it isn't empty. `last_line` is the last line that
+/// isn't blank.
+fn close_fold(text: &Rope, stack: &mut Vec<(usize, usize, Vec<IndentFold>)>,
+              last_line: usize) {
+    let (_, start_line, children) = stack.pop().unwrap();
+    let parent = &mut stack.last_mut().unwrap().2;
+    if last_line > start_line {
+        parent.push(IndentFold {
+            start: end_of_line(text, start_line),
+            end: end_of_line(text, last_line),
+            start_line,
+            end_line: last_line,
+            children,
+        });
+    } else {
+        parent.extend(children);
+    }
+}
+
+/// Returns the offset of the end of `line`, before its line break.
+fn end_of_line(text: &Rope, line: usize) -> usize {
+    let mut end = text.offset_of_line(line + 1);
+    if end > text.offset_of_line(line) && text.byte_at(end - 1) == b'\n' {
+        end -= 1;
+        if end > 0 && text.byte_at(end - 1) == b'\r' {
+            end -= 1;
+        }
+    }
+    end
+}
+
+/// Returns the indentation levels of the lines from `start` up to `end`.
+fn line_levels(text: &Rope, start: usize, end: usize, tab_size: usize) -> Vec<Option<usize>> {
+    let tab_size = tab_size.max(1);
+    let mut levels = text.lines_raw(text.offset_of_line(start), text.offset_of_line(end))
+        .map(|line| {
+            let mut width = 0;
+            for c in line.chars() {
+                match c {
+                    ' ' => width += 1,
+                    '\t' => width += tab_size - width % tab_size,
+                    '\n' | '\r' => return None,
+                    c if c.is_whitespace() => width += 1,
+                    _ => return Some(width),
+                }
+            }
+            None
+        })
+        .collect::<Vec<_>>();
+    levels.resize(end - start, None);
+    levels
+}
+
+/// Moves `ranges` along with the edit `delta`.
+pub fn transform_ranges(ranges: &mut Vec<Range<usize>>, delta: &Delta<RopeInfo>) {
+    let mut transformer = Transformer::new(delta);
+    for range in ranges.iter_mut() {
+        *range = transformer.transform(range.start, true)..transformer.transform(range.end, false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xi_rope::delta::Builder;
+    use xi_rope::interval::Interval;
+
+    fn hidden(folds: &Folds) -> Vec<(Range<usize>, usize)> {
+        folds.hidden().iter().map(|h| (h.range.clone(), h.lines)).collect()
+    }
+
+    #[test]
+    fn hide_lines() {
+        let text = Rope::from("fn a() {\n    b\n    c\n}\nfn d() {\n    e\n}");
+        let mut folds = Folds::default();
+        let line_of_offset = |offset| text.line_of_offset(offset);
+        assert!(!folds.add(&text, 0..8));
+        assert!(folds.add(&text, 0..20));
+        assert!(!folds.add(&text, 0..20));
+        folds.update_hidden(&text, line_of_offset);
+        assert_eq!(hidden(&folds), vec![(9..21, 2)]);
+        assert!(folds.hidden_containing(9).is_some());
+        assert!(folds.hidden_containing(21).is_none());
+        assert_eq!(folds.hidden_at(9).map(|h| h.lines), Some(2));
+
+        // nested folds merge, and folding the last line hides the end
+        assert!(folds.add(&text, 4..16));
+        assert!(folds.add(&text, 23..text.len()));
+        folds.update_hidden(&text, line_of_offset);
+        assert_eq!(hidden(&folds), vec![(9..21, 2), (32..text.len(), 2)]);
+        assert!(folds.hidden_containing(text.len()).is_some());
+
+        assert!(folds.remove_at_line(&text, 0));
+        assert!(!folds.remove_at_line(&text, 0));
+        assert!(folds.remove_hiding(&text, 33));
+        assert!(folds.is_empty());
+    }
+
+    #[test]
+    fn move_with_edits() {
+        let text = Rope::from("a\nb {\nc\n}\n");
+        let mut folds = Folds::default();
+        folds.add(&text, 4..7);
+        let edit = |text: &Rope, start, end, s: &str| {
+            let mut builder = Builder::new(text.len());
+            builder.replace(Interval::new_closed_open(start, end), Rope::from(s));
+            let delta = builder.build();
+            let new_text = delta.apply(text);
+            (delta, new_text)
+        };
+        let (delta, text) = edit(&text, 0, 0, "z\n");
+        folds.apply_delta(&delta, &text);
+        folds.update_hidden(&text, |offset| text.line_of_offset(offset));
+        assert_eq!(hidden(&folds), vec![(8..10, 1)]);
+
+        // joining the lines of the fold removes it
+        let (delta, text) = edit(&text, 7, 8, "");
+        folds.apply_delta(&delta, &text);
+        assert!(folds.is_empty());
+
+        let mut ranges = vec![0..1, 2..4];
+        transform_ranges(&mut ranges, &edit(&text, 1, 1, "xx").0);
+        assert_eq!(ranges, vec![0..1, 4..6]);
+    }
+
+    #[test]
+    fn indent_folds() {
+        let text = Rope::from("def a():\n    if b:\n\tc\n\n    d\ne\n");
+        let mut levels = IndentLevels::new(&text, 8);
+        let lines = |folds: &[IndentFold]| folds.iter()
+            .map(|f| (f.start_line, f.end_line, f.children.len()))
+            .collect::<Vec<_>>();
+        let folds = levels.folds(&text);
+        assert_eq!(lines(&folds), vec![(0, 4, 1)]);
+        assert_eq!(lines(&folds[0].children), vec![(1, 2, 0)]);
+        assert_eq!(IndentLevels::flatten(&folds), vec![8..28, 18..21]);
+
+        // with the tab as wide as the indentation of the `if`, the block ends
+        let folds = IndentLevels::new(&text, 4).folds(&text);
+        assert_eq!(lines(&folds), vec![(0, 4, 0)]);
+
+        let mut builder = Builder::new(text.len());
+        builder.replace(Interval::new_closed_open(20, 20), Rope::from("x\ny\n    "));
+        let delta = builder.build();
+        let text = delta.apply(&text);
+        levels.apply_delta(&delta, &text);
+        assert_eq!(levels.levels, IndentLevels::new(&text, 8).levels);
+        let folds = levels.folds(&text);
+        assert_eq!(lines(&folds), vec![(0, 2, 1), (3, 6, 0)]);
+
+        let mut builder = Builder::new(text.len());
+        builder.delete(Interval::new_closed_open(18, 29));
+        let delta = builder.build();
+        let text = delta.apply(&text);
+        levels.apply_delta(&delta, &text);
+        assert_eq!(levels.levels, IndentLevels::new(&text, 8).levels);
+    }
+}
@@ -25,6 +25,7 @@ use magenta::{Channel, ChannelOpts, HandleBase};
 use serde_json;
 
 use super::ledger::{ledger_crash_callback, self};
+use sync::{SessionSelection, SyncStore};
 use tabs::{BufferIdentifier, BufferContainerRef};
 use xi_rope::engine::Engine;
 
@@ -38,7 +39,7 @@ fn buf_to_state(buf: &[u8]) -> Result<Engine, serde_json::Error> {
 }
 
 /// Stores state needed by the container to perform synchronization.
-pub struct SyncStore {
+pub struct LedgerSyncStore {
     page: Page_Proxy,
     key: Vec<u8>,
     updates: Sender<SyncMsg>,
@@ -46,7 +47,7 @@ pub struct SyncStore {
     buffer: BufferIdentifier,
 }
 
-impl SyncStore {
+impl LedgerSyncStore {
     /// - `page` is a reference to the Ledger page to store data under.
     /// - `key` is the key the `Syncable` managed by this `SyncStore` will be stored under.
     ///    This example only supports storing things under a single key per page.
@@ -55,7 +56,7 @@ impl SyncStore {
     /// Returns a sync store and schedules the loading of initial
     /// state and subscribes to state updates for this document.
     pub fn new(mut page: Page_Proxy, key: Vec<u8>, updates: Sender<SyncMsg>,
-            buffer: BufferIdentifier) -> SyncStore {
+            buffer: BufferIdentifier) -> LedgerSyncStore {
         let (s1, s2) = Channel::create(ChannelOpts::Normal).unwrap();
         let watcher_client = PageWatcher_Client::from_handle(s1.into_handle());
         let watcher_client_ptr = ::fidl::InterfacePtr {
@@ -82,14 +83,16 @@ impl SyncStore {
             }
         });
 
-        SyncStore { page, key, updates, buffer, transaction_pending: false }
+        LedgerSyncStore { page, key, updates, buffer, transaction_pending: false }
     }
+}
 
+impl SyncStore for LedgerSyncStore {
     /// Called whenever this app changed its own state and would like to
     /// persist the changes to the ledger. Changes can't be committed
     /// immediately since we have to wait for PageWatcher changes that may not
     /// have arrived yet.
-    pub fn state_changed(&mut self) {
+    fn state_changed(&mut self, _engine: &Engine, _selections: &[SessionSelection]) {
         if !self.transaction_pending {
             self.transaction_pending = true;
             let ready_future = self.page.start_transaction();
@@ -108,7 +111,7 @@ impl SyncStore {
     }
 
     /// Should be called in SyncContainer::transaction_ready to persist the current state.
-    pub fn commit_transaction(&mut self, state: &Engine) {
+    fn commit_transaction(&mut self, state: &Engine) {
         assert!(self.transaction_pending, "must call state_changed (and wait) before commit");
         self.page.put(self.key.clone(), state_to_buf(state)).with(ledger_crash_callback);
         self.page.commit().with(ledger_crash_callback);
@@ -161,7 +164,10 @@ impl<W: Write + Send + 'static> SyncUpdater<W> {
                     let mut container = self.container_ref.lock();
                     match (container.editor_for_buffer_mut(&buffer), buf_to_state(&new_buf)) {
                         (Some(mut editor), Ok(new_state)) => {
+                            //FIXME: this doesn't update views or plugins;
+                            // see `EventContext::merge_sync_state`.
                             editor.merge_new_state(new_state);
+                            editor.commit_delta();
                             if let Some(promise) = done {
                                 promise.set_ok(None);
                             }
@@ -0,0 +1,167 @@
+// Copyright 2018 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Computing the indentation of new and edited lines from the
+//! per-syntax patterns in the buffer config.
+
+use regex::Regex;
+
+use xi_rope::rope::Rope;
+
+use config::BufferItems;
+
+/// The patterns deciding how lines are indented relative to the line
+/// before them.
+#[derive(Debug, Clone)]
+pub struct IndentRules {
+    /// Matches a line after which the indentation increases.
+    increase: Option<Regex>,
+    /// Matches a line which is indented one level less than the line
+    /// before it would suggest.
+    decrease: Option<Regex>,
+    tab_size: usize,
+}
+
+impl IndentRules {
+    /// Compiles the rules in `items`, returning `None` if auto indent is
+    /// turned off. Empty patterns never match; invalid ones are rejected
+    /// with an error when the config is loaded.
+    pub fn from_config(items: &BufferItems) -> Option<IndentRules> {
+        if !items.auto_indent {
+            return None;
+        }
+        Some(IndentRules {
+            increase: compile(&items.increase_indent_pattern),
+            decrease: compile(&items.decrease_indent_pattern),
+            tab_size: items.tab_size.max(1),
+        })
+    }
+
+    /// Returns whether the line after `line` is indented one more level.
+    pub fn increases(&self, line: &str) -> bool {
+        self.increase.as_ref().map_or(false, |re| re.is_match(line))
+    }
+
+    /// Returns whether `line` is indented one level less.
+    pub fn decreases(&self, line: &str) -> bool {
+        self.decrease.as_ref().map_or(false, |re| re.is_match(line))
+    }
+
+    /// The width of one level of indentation, in columns.
+    pub fn unit(&self) -> usize {
+        self.tab_size
+    }
+
+    /// Returns the width of the leading whitespace of `line`.
+    pub fn width(&self, line: &str) -> usize {
+        let mut width = 0;
+        for c in line.chars() {
+            match c {
+                '\t' => width += self.tab_size - width % self.tab_size,
+                '\n' | '\r' => break,
+                c if c.is_whitespace() => width += 1,
+                _ => break,
+            }
+        }
+        width
+    }
+
+    /// Returns the indentation of the line following `prev`, which is the
+    /// text of a previous line, or `None` if there is no such line.
+    pub fn indent_after(&self, prev: Option<&str>) -> usize {
+        match prev {
+            Some(prev) if self.increases(prev) => self.width(prev) + self.unit(),
+            Some(prev) => self.width(prev),
+            None => 0,
+        }
+    }
+}
+
+/// Returns the whitespace making up an indentation `width` columns wide.
+pub fn indent_text(width: usize, tab_size: usize, use_spaces: bool) -> String {
+    if use_spaces || tab_size == 0 {
+        " ".repeat(width)
+    } else {
+        let mut s = "\t".repeat(width / tab_size);
+        s.push_str(&" ".repeat(width % tab_size));
+        s
+    }
+}
+
+/// Returns the length in bytes of the leading whitespace of the line
+/// starting at `offset`.
+pub fn leading_whitespace_len(text: &Rope, offset: usize) -> usize {
+    let end = text.len().min(text.offset_of_line(text.line_of_offset(offset) + 1));
+    text.iter_chunks(offset, end)
+        .flat_map(|chunk| chunk.bytes())
+        .take_while(|&b| b == b' ' || b == b'\t')
+        .count()
+}
+
+/// Returns the text of the last line before `line` which isn't blank,
+/// without its line ending.
+pub fn prev_nonblank_line(text: &Rope, line: usize) -> Option<String> {
+    (0..line).rev()
+        .map(|line| text.lines_raw(text.offset_of_line(line), text.offset_of_line(line + 1))
+             .next().unwrap_or_default())
+        .find(|s| !s.trim().is_empty())
+        .map(|s| s.trim_end_matches(|c| c == '\n' || c == '\r').to_owned())
+}
+
+fn compile(pattern: &str) -> Option<Regex> {
+    if pattern.is_empty() {
+        return None;
+    }
+    match Regex::new(pattern) {
+        Ok(re) => Some(re),
+        Err(e) => {
+            eprintln!("invalid indent pattern {:?}: {}", pattern, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::ConfigManager;
+
+    fn rules(increase: &str, decrease: &str) -> IndentRules {
+        let mut items = ConfigManager::new(None, None).default_buffer_config().items;
+        items.auto_indent = true;
+        items.increase_indent_pattern = increase.to_owned();
+        items.decrease_indent_pattern = decrease.to_owned();
+        IndentRules::from_config(&items).unwrap()
+    }
+
+    #[test]
+    fn indent_rules() {
+        let rules = rules(r"\{\s*$", r"^\s*\}");
+        assert_eq!(rules.width("  \tfoo"), 4);
+        assert_eq!(rules.width("        "), 8);
+        assert_eq!(rules.indent_after(Some("    fn a() {")), 8);
+        assert_eq!(rules.indent_after(Some("    a();")), 4);
+        assert_eq!(rules.indent_after(None), 0);
+        assert!(rules.decreases("    }"));
+        assert!(!rules.decreases("    a }"));
+        assert_eq!(indent_text(6, 4, false), "\t  ");
+        assert_eq!(indent_text(6, 4, true), "      ");
+
+        let text = Rope::from("a {\n  \t b\n\n   \nc");
+        assert_eq!(leading_whitespace_len(&text, 4), 4);
+        assert_eq!(leading_whitespace_len(&text, 10), 0);
+        assert_eq!(prev_nonblank_line(&text, 4), Some("  \t b".to_owned()));
+        assert_eq!(prev_nonblank_line(&text, 0), None);
+    }
+}
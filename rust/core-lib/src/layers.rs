@@ -19,6 +19,7 @@
 //! into styles using a theme, augmented with additional style definitions.
 
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
 use syntect::parsing::Scope;
 use syntect::highlighting::StyleModifier;
 
@@ -28,14 +29,23 @@ use xi_rope::rope::RopeInfo;
 use xi_rope::spans::{Spans, SpansBuilder};
 use xi_trace::trace_block;
 
+use diff::DiffId;
 use styles::{Style, ThemeStyleMap};
 use plugins::PluginPid;
 
+lazy_static! {
+    static ref STRING_SCOPE: Scope = Scope::new("string").unwrap();
+    static ref COMMENT_SCOPE: Scope = Scope::new("comment").unwrap();
+}
+
 /// A collection of layers containing scope information.
 #[derive(Default)]
 pub struct Layers {
     layers: BTreeMap<PluginPid, ScopeLayer>,
     deleted: HashSet<PluginPid>,
+    /// The changes highlighted by each side-by-side diff of the buffer,
+    /// drawn over the styles of the other layers.
+    diffs: BTreeMap<DiffId, Spans<Style>>,
     merged: Spans<Style>,
 }
 
@@ -55,6 +65,14 @@ pub struct ScopeLayer {
 
 impl Layers {
 
+    /// Creates empty layers for a text of `len` bytes.
+    pub fn new(len: usize) -> Layers {
+        Layers {
+            merged: SpansBuilder::new(len).build(),
+            ..Layers::default()
+        }
+    }
+
     pub fn get_merged(&self) -> &Spans<Style> {
         &self.merged
     }
@@ -74,6 +92,9 @@ impl Layers {
     /// as edits occur.
     pub fn update_all(&mut self, delta: &Delta<RopeInfo>) {
         self.merged.apply_shape(delta);
+        for diff in self.diffs.values_mut() {
+            diff.apply_shape(delta);
+        }
 
         for layer in self.layers.values_mut() {
             layer.blank_scopes(delta);
@@ -103,6 +124,18 @@ impl Layers {
         layer
     }
 
+    /// Sets the styles highlighting the changes of the side-by-side diff
+    /// `diff_id`, or removes them with `None`.
+    pub fn set_diff_styles(&mut self, diff_id: DiffId, styles: Option<Spans<Style>>) {
+        match styles {
+            Some(styles) => self.diffs.insert(diff_id, styles),
+            None => self.diffs.remove(&diff_id),
+        };
+        self.merged = SpansBuilder::new(self.merged.len()).build();
+        let iv_all = Interval::new_closed_closed(0, self.merged.len());
+        self.resolve_styles(iv_all);
+    }
+
     pub fn theme_changed(&mut self, style_map: &ThemeStyleMap) {
         for layer in self.layers.values_mut() {
             layer.theme_changed(style_map);
@@ -115,14 +148,15 @@ impl Layers {
     /// Resolves styles from all layers for the given interval, updating
     /// the master style spans.
     fn resolve_styles(&mut self, iv: Interval) {
-        if self.layers.is_empty() {
-            return
-        }
-        let mut layer_iter = self.layers.values();
-        let mut resolved = layer_iter.next().unwrap().style_spans.subseq(iv);
+        let mut layer_iter = self.layers.values().map(|layer| &layer.style_spans)
+            .chain(self.diffs.values());
+        let mut resolved = match layer_iter.next() {
+            Some(first) => first.subseq(iv),
+            None => return,
+        };
 
         for other in layer_iter {
-            let spans = other.style_spans.subseq(iv);
+            let spans = other.subseq(iv);
             assert_eq!(resolved.len(), spans.len());
             resolved = resolved.merge(&spans, |a, b| {
                 match b {
@@ -134,6 +168,36 @@ impl Layers {
         self.merged.edit(iv, resolved);
     }
 
+    /// Returns the ranges in `iv` that some layer scopes as a string or
+    /// a comment, sorted and merged.
+    pub fn string_and_comment_ranges(&self, iv: Interval) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        for layer in self.layers.values() {
+            for (span_iv, &scope) in layer.scope_spans.subseq(iv).iter() {
+                let stack = match layer.stack_lookup.get(scope as usize) {
+                    Some(stack) => stack,
+                    None => continue,
+                };
+                if stack.iter().any(|s| STRING_SCOPE.is_prefix_of(*s)
+                                    || COMMENT_SCOPE.is_prefix_of(*s)) {
+                    ranges.push(iv.start() + span_iv.start()..iv.start() + span_iv.end());
+                }
+            }
+        }
+        ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<usize>> = Vec::new();
+        for range in ranges {
+            if let Some(last) = merged.last_mut() {
+                if range.start <= last.end {
+                    last.end = last.end.max(range.end);
+                    continue;
+                }
+            }
+            merged.push(range);
+        }
+        merged
+    }
+
     /// Prints scopes and style information for the given `Interval`.
     pub fn debug_print_spans(&self, iv: Interval) {
         for (id, layer) in &self.layers {
@@ -38,6 +38,8 @@ extern crate serde_json;
 extern crate serde_derive;
 #[macro_use]
 extern crate lazy_static;
+extern crate regex;
+extern crate encoding_rs;
 extern crate time;
 extern crate syntect;
 extern crate toml;
@@ -73,16 +75,25 @@ pub mod internal {
     pub mod core;
     pub mod tabs;
     pub mod editor;
+    pub mod undo_tree;
     pub mod edit_types;
     pub mod event_context;
+    pub mod brackets;
+    pub mod comments;
     pub mod file;
+    pub mod diff;
     pub mod find;
+    pub mod fold;
+    pub mod indent;
+    pub mod line_ops;
+    pub mod project_search;
     pub mod view;
     pub mod linewrap;
     pub mod plugins;
     #[cfg(feature = "ledger")]
     pub mod fuchsia;
     pub mod styles;
+    pub mod sync;
     pub mod word_boundaries;
     pub mod index_set;
     pub mod selection;
@@ -103,13 +114,22 @@ use internal::core;
 use internal::client;
 use internal::edit_types;
 use internal::editor;
+use internal::undo_tree;
 use internal::event_context;
+use internal::brackets;
+use internal::comments;
 use internal::file;
+use internal::diff;
 use internal::find;
+use internal::fold;
+use internal::indent;
+use internal::line_ops;
+use internal::project_search;
 use internal::view;
 use internal::linewrap;
 use internal::plugins;
 use internal::styles;
+use internal::sync;
 use internal::word_boundaries;
 use internal::index_set;
 use internal::selection;
@@ -0,0 +1,103 @@
+// Copyright 2018 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rearranging whole lines of text: sorting and joining them.
+
+use std::cmp::Ordering;
+
+/// How lines are compared and filtered when sorting them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SortOptions {
+    /// Compare the numbers the lines start with, lines without one first.
+    pub numeric: bool,
+    pub ignore_case: bool,
+    pub reverse: bool,
+    /// Keep only the first of lines that compare equal.
+    pub unique: bool,
+}
+
+/// Sorts the lines of `text`, which are separated by `line_ending`. A
+/// line ending at the end of `text` is kept there.
+pub fn sort_lines(text: &str, line_ending: &str, options: SortOptions) -> String {
+    let trailing = text.ends_with(line_ending);
+    let body = if trailing { &text[..text.len() - line_ending.len()] } else { text };
+    let mut lines = body.split(line_ending).collect::<Vec<_>>();
+    let compare = |a: &&str, b: &&str| {
+        let ordering = if options.numeric {
+            leading_number(a).partial_cmp(&leading_number(b)).unwrap_or(Ordering::Equal)
+        } else {
+            Ordering::Equal
+        };
+        ordering.then_with(|| if options.ignore_case {
+            a.to_lowercase().cmp(&b.to_lowercase())
+        } else {
+            a.cmp(b)
+        })
+    };
+    lines.sort_by(|a, b| {
+        let ordering = compare(a, b);
+        if options.reverse { ordering.reverse() } else { ordering }
+    });
+    if options.unique {
+        lines.dedup_by(|a, b| compare(a, b) == Ordering::Equal);
+    }
+
+    let mut result = lines.join(line_ending);
+    if trailing {
+        result.push_str(line_ending);
+    }
+    result
+}
+
+/// Returns the number at the start of `line`, after any whitespace.
+fn leading_number(line: &str) -> Option<f64> {
+    let line = line.trim_start();
+    let end = line.char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || (i == 0 && c == '-')))
+        .map_or(line.len(), |(i, _)| i);
+    line[..end].parse().ok()
+}
+
+/// Returns the text that joins a line ending in `before` to one starting
+/// with `after`, once the whitespace between them is removed: a space,
+/// unless either side is empty.
+pub fn join_separator(before: &str, after: &str) -> &'static str {
+    if before.is_empty() || after.is_empty() { "" } else { " " }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort() {
+        let text = "b\nB\n10 x\na\n9\nb\n";
+        let sort = |options| sort_lines(text, "\n", options);
+        assert_eq!(sort(SortOptions::default()), "10 x\n9\nB\na\nb\nb\n");
+        assert_eq!(sort(SortOptions { reverse: true, ..Default::default() }),
+                   "b\nb\na\nB\n9\n10 x\n");
+        assert_eq!(sort(SortOptions { numeric: true, ..Default::default() }),
+                   "B\na\nb\nb\n9\n10 x\n");
+        assert_eq!(sort(SortOptions { ignore_case: true, unique: true, ..Default::default() }),
+                   "10 x\n9\na\nb\n");
+        assert_eq!(sort_lines("b\r\na", "\r\n", SortOptions::default()), "a\r\nb");
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(leading_number("  -1.5 apples"), Some(-1.5));
+        assert_eq!(leading_number("42"), Some(42.0));
+        assert_eq!(leading_number("x1"), None);
+    }
+}
@@ -55,6 +55,9 @@ pub enum Movement {
     StartOfDocument,
     /// Move to the end of the document
     EndOfDocument,
+    /// Move to the bracket matching the one next to the caret. When
+    /// modifying the selection, it is extended past both brackets.
+    MatchingBracket,
 }
 
 /// Compute movement based on vertical motion by the given number of lines.
@@ -153,8 +156,7 @@ pub fn region_movement(m: Movement, r: SelRegion, view: &View, text: &Rope, modi
             let mut offset = text.len();
 
             // calculate end of line
-            let next_line_offset = view.offset_of_line(text, line + 1);
-            if line < view.line_of_offset(text, offset) {
+            if let Some(next_line_offset) = view.next_line_offset(text, line) {
                 if let Some(prev) = text.prev_grapheme_offset(next_line_offset) {
                     offset = prev;
                 }
@@ -204,6 +206,17 @@ pub fn region_movement(m: Movement, r: SelRegion, view: &View, text: &Rope, modi
         Movement::DownPage => vertical_motion(r, view, text, scroll_height(view), modify),
         Movement::StartOfDocument => (0, None),
         Movement::EndOfDocument => (text.len(), None),
+        Movement::MatchingBracket => {
+            match view.matching_bracket(r.end) {
+                // the caret stays on the same side of the bracket, so
+                // that moving again goes back
+                Some(m) if modify && m.matching > m.bracket => (m.matching + 1, None),
+                Some(m) if modify => (m.matching, None),
+                Some(m) if m.bracket == r.end => (m.matching, None),
+                Some(m) => (m.matching + 1, None),
+                None => (r.end, r.horiz),
+            }
+        }
     };
     SelRegion::new(
         if modify { r.start } else { offset },
@@ -48,7 +48,6 @@ pub enum PluginActivation {
     /// Always run this plugin, when available.
     Autorun,
     /// Run this plugin if the provided SyntaxDefinition is active.
-    #[allow(dead_code)]
     OnSyntax(SyntaxDefinition),
     /// Run this plugin in response to a given command.
     #[allow(dead_code)]
@@ -199,6 +198,16 @@ impl PluginDescription {
             _ => false,
         }
     }
+
+    /// Returns the syntaxes this plugin is activated for.
+    pub fn syntaxes(&self) -> Vec<SyntaxDefinition> {
+        self.activations.iter()
+            .filter_map(|activation| match *activation {
+                PluginActivation::OnSyntax(syntax) => Some(syntax),
+                _ => None,
+            })
+            .collect()
+    }
 }
 
 impl Default for PluginScope {
@@ -255,4 +264,19 @@ mod tests {
         assert_eq!(command.rpc_cmd.params_ref()["non_arg"], "plugin supplied value");
         assert_eq!(command.args[1].options.clone().unwrap()[1].value, json!(10));
     }
+
+    #[test]
+    fn test_syntaxes() {
+        let json = r#"
+    {
+        "name": "rustfmt",
+        "version": "0.1",
+        "exec_path": "bin/rustfmt-plugin",
+        "activations": ["autorun", {"on_syntax": "rust"}]
+    }
+        "#;
+
+        let desc: PluginDescription = serde_json::from_str(&json).unwrap();
+        assert_eq!(desc.syntaxes(), vec![SyntaxDefinition::Rust]);
+    }
 }
@@ -20,6 +20,7 @@ mod catalog;
 
 use std::fmt;
 use std::io::BufReader;
+use std::ops::Range;
 use std::path::Path;
 use std::process::{Child, Command as ProcCommand, Stdio};
 use std::thread;
@@ -31,6 +32,7 @@ use xi_trace;
 
 use WeakXiCore;
 use config::Table;
+use syntax::SyntaxDefinition;
 use tabs::ViewId;
 
 use self::rpc::{PluginUpdate, PluginBufferInfo};
@@ -60,6 +62,9 @@ pub struct Plugin {
     peer: RpcPeer,
     pub(crate) id: PluginId,
     pub(crate) name: String,
+    /// The syntaxes the plugin is activated for, whose buffers it is asked
+    /// to format.
+    syntaxes: Vec<SyntaxDefinition>,
     #[allow(dead_code)]
     process: Child,
 }
@@ -111,6 +116,24 @@ where F: FnOnce(Result<Value, xi_rpc::Error>) + Send + 'static
                                          Box::new(callback))
     }
 
+    /// Asks the plugin for an edit formatting `ranges` of the text at `rev`.
+    pub fn format<F>(&self, view_id: ViewId, rev: u64, ranges: &[Range<usize>], callback: F)
+        where F: FnOnce(Result<Value, xi_rpc::Error>) + Send + 'static
+    {
+        self.peer.send_rpc_request_async("format",
+                                         &json!({
+                                             "view_id": view_id,
+                                             "rev": rev,
+                                             "ranges": ranges,
+                                         }),
+                                         Box::new(callback))
+    }
+
+    /// Returns `true` if the plugin is activated for `syntax`, and so may
+    /// format buffers of that syntax.
+    pub fn handles_syntax(&self, syntax: &SyntaxDefinition) -> bool {
+        self.syntaxes.contains(syntax)
+    }
 
     pub fn toggle_tracing(&self, enabled: bool) {
         self.peer.send_rpc_notification("tracing_config",
@@ -144,8 +167,9 @@ pub(crate) fn start_plugin_process(plugin_desc: PluginDescription,
                 let mut looper = RpcLoop::new(child_stdin);
                 let peer: RpcPeer = Box::new(looper.get_raw_peer());
                 let name = plugin_desc.name.clone();
+                let syntaxes = plugin_desc.syntaxes();
 peer.send_rpc_notification("ping", &Value::Array(Vec::new()));
-                let plugin = Plugin { peer, process: child, name, id };
+                let plugin = Plugin { peer, process: child, name, id, syntaxes };
 
                 // set tracing immediately
                 if xi_trace::is_enabled() {
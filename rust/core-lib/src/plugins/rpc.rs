@@ -14,6 +14,7 @@
 
 //! RPC types, corresponding to protocol requests, notifications & responses.
 
+use std::ops::Range;
 use std::path::PathBuf;
 use std::borrow::Borrow;
 
@@ -95,6 +96,10 @@ pub struct EmptyStruct {}
 pub enum HostRequest {
     Update(PluginUpdate),
     CollectTrace(EmptyStruct),
+    /// Asks for an edit formatting the `ranges` of the text at `rev`. The
+    /// response is a `PluginEdit`, or null if the plugin doesn't format
+    /// the buffer.
+    Format { view_id: ViewIdentifier, rev: u64, ranges: Vec<Range<usize>> },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -140,6 +145,13 @@ pub struct ScopeSpan {
     pub scope_id: u32,
 }
 
+/// A range of the text that can be folded, such as a block.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct FoldRange {
+    pub start: usize,
+    pub end: usize,
+}
+
 /// The object returned by the `get_data` RPC.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GetDataResponse {
@@ -181,6 +193,11 @@ pub enum PluginNotification {
     UpdateSpans { start: usize, len: usize, spans: Vec<ScopeSpan>, rev: u64 },
     Edit { edit: PluginEdit },
     Alert { msg: String },
+    /// Replaces the ranges the plugin reports as foldable.
+    UpdateFoldRanges { ranges: Vec<FoldRange>, rev: u64 },
+    /// Sets the indentation of a line, in columns, overriding the
+    /// indentation computed from the syntax's indent patterns.
+    SetIndent { line: usize, indent: usize, rev: u64 },
 }
 
 /// Common wrapper for plugin-originating RPCs.
@@ -0,0 +1,519 @@
+// Copyright 2018 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Searching for text in all the files of a directory tree.
+//!
+//! A search runs on a worker thread, a few files at a time, so that it
+//! doesn't hold up editing and can be cancelled at any point.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::vec;
+
+use regex::{self, Regex, Error as RegexError};
+
+use xi_rope::find::{find, CaseMatching, RegexSearcher};
+use xi_rope::rope::Rope;
+use xi_rope::tree::Cursor;
+
+use client::Client;
+use find::{build_regex, case_matching, is_whole_word};
+
+/// The maximum length of the preview of a line, in bytes.
+const MAX_PREVIEW_LEN: usize = 256;
+
+/// Files on disk larger than this, in bytes, are not searched.
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The number of files searched between checks for cancellation, and
+/// whose results are sent to the client together.
+const FILES_PER_STEP: usize = 16;
+
+/// Identifies a project search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+         Serialize, Deserialize, Hash)]
+pub struct SearchId(pub(crate) usize);
+
+/// A single occurrence found by a project search.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    pub path: PathBuf,
+    /// The line of the occurrence, starting at 0.
+    pub line: usize,
+    /// The offset of the occurrence in its line, in utf-8 code units.
+    pub col: usize,
+    /// The line the occurrence is in, without its line ending, and
+    /// truncated if it is very long.
+    pub preview: String,
+}
+
+/// The state of a project search.
+pub struct ProjectSearch {
+    id: SearchId,
+    pattern: String,
+    case_matching: CaseMatching,
+    regex: Option<Regex>,
+    whole_words: bool,
+    walker: Walker,
+}
+
+impl ProjectSearch {
+    /// Creates a search for `pattern` in the files below `root`, which
+    /// should be an absolute path. The options are the same as for a search
+    /// in a single view; see `Find::do_find`.
+    ///
+    /// `excludes` are additional patterns, in `.gitignore` syntax, for files
+    /// to skip; they take precedence over any `.gitignore` file.
+    pub fn new(id: SearchId, root: PathBuf, pattern: &str, case_sensitive: bool,
+               is_regex: bool, whole_words: bool, ignore_accents: bool,
+               excludes: &[String]) -> Result<Self, RegexError> {
+        let regex = if is_regex {
+            Some(build_regex(pattern, case_sensitive)?)
+        } else {
+            None
+        };
+        let excludes = IgnoreFile::new(&root, excludes.iter().map(String::as_str));
+        Ok(ProjectSearch {
+            id,
+            pattern: pattern.to_owned(),
+            case_matching: case_matching(case_sensitive, ignore_accents),
+            regex,
+            whole_words,
+            walker: Walker::new(root, excludes),
+        })
+    }
+
+    pub fn id(&self) -> SearchId {
+        self.id
+    }
+
+    /// Runs the search to completion on a new thread, which sends the
+    /// results to `client` as they are found.
+    ///
+    /// `open_buffers` are the contents of the open files, by canonical path;
+    /// they are searched as they were when the search started.
+    pub fn spawn(mut self, open_buffers: HashMap<PathBuf, Rope>, client: Client)
+                 -> SearchHandle {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let handle = SearchHandle { cancelled: cancelled.clone() };
+        thread::Builder::new()
+            .name("project search".to_owned())
+            .spawn(move || {
+                let open_buffer = |path: &Path| open_buffers.get(path).cloned();
+                loop {
+                    let (results, done) = self.step(FILES_PER_STEP, &open_buffer);
+                    if cancelled.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    if !results.is_empty() {
+                        client.project_search_results(self.id, &results);
+                    }
+                    if done {
+                        client.project_search_finished(self.id);
+                        return;
+                    }
+                }
+            })
+            .expect("project search should spawn");
+        handle
+    }
+
+    /// Searches up to `max_files` more files, returning the occurrences found
+    /// and whether the search is finished.
+    ///
+    /// `open_buffer` returns the contents of the buffer for a path, if the
+    /// file is open; open files are searched as they are in the editor,
+    /// including unsaved changes. Other files are read from disk, and
+    /// skipped if they are very large, or binary or otherwise not valid
+    /// UTF-8.
+    pub fn step<F>(&mut self, max_files: usize, open_buffer: F)
+                   -> (Vec<SearchResult>, bool)
+        where F: Fn(&Path) -> Option<Rope>
+    {
+        let mut results = Vec::new();
+        for _ in 0..max_files {
+            let path = match self.walker.next_file() {
+                Some(path) => path,
+                None => return (results, true),
+            };
+            let text = match open_buffer(&path) {
+                Some(text) => text,
+                None => match read_file(&path) {
+                    Some(text) => text,
+                    None => continue,
+                },
+            };
+            self.search_text(&text, &path, &mut results);
+        }
+        (results, false)
+    }
+
+    fn search_text(&self, text: &Rope, path: &Path, results: &mut Vec<SearchResult>) {
+        let mut cursor = Cursor::new(text, 0);
+        let mut searcher = self.regex.as_ref().map(RegexSearcher::new);
+        while let Some(start) = match searcher {
+            Some(ref mut searcher) => searcher.find(&mut cursor),
+            None => find(&mut cursor, self.case_matching, &self.pattern),
+        } {
+            if self.whole_words && !is_whole_word(text, start, cursor.pos()) {
+                // an occurrence may still start inside this one
+                cursor.set(start);
+                cursor.next_codepoint();
+                continue;
+            }
+            let line = text.line_of_offset(start);
+            let line_start = text.offset_of_line(line);
+            let preview = text.lines(line_start, text.len()).next()
+                .map(|line| truncate(&line, MAX_PREVIEW_LEN).to_owned())
+                .unwrap_or_default();
+            results.push(SearchResult {
+                path: path.to_owned(),
+                line,
+                col: start - line_start,
+                preview,
+            });
+        }
+    }
+}
+
+/// A handle to a search running on a worker thread.
+pub struct SearchHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl SearchHandle {
+    /// Stops the search; no more results are sent after it returns.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the search has finished or was cancelled.
+    pub fn is_finished(&self) -> bool {
+        // the worker thread drops its reference when it exits
+        Arc::strong_count(&self.cancelled) == 1
+    }
+}
+
+/// Reads a file from disk, returning `None` if it can't be read, is larger
+/// than `MAX_FILE_SIZE`, or is binary: if it contains a NUL byte or is
+/// not UTF-8.
+fn read_file(path: &Path) -> Option<Rope> {
+    let mut f = File::open(path).ok()?;
+    if f.metadata().ok()?.len() > MAX_FILE_SIZE {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    f.read_to_end(&mut bytes).ok()?;
+    if bytes.contains(&0) {
+        return None;
+    }
+    String::from_utf8(bytes).ok().map(Rope::from)
+}
+
+/// Truncates `s` to at most `len` bytes, on a codepoint boundary.
+fn truncate(s: &str, len: usize) -> &str {
+    if s.len() <= len {
+        return s;
+    }
+    let mut end = len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// A depth-first traversal of the files below a directory, which skips
+/// files excluded by `.gitignore` files, and version control metadata.
+/// Symbolic links to directories are not followed.
+struct Walker {
+    stack: Vec<Dir>,
+    excludes: IgnoreFile,
+}
+
+/// The entries of a directory that remain to be visited.
+struct Dir {
+    entries: vec::IntoIter<PathBuf>,
+    ignore: Option<IgnoreFile>,
+}
+
+impl Walker {
+    fn new(root: PathBuf, excludes: IgnoreFile) -> Walker {
+        let root = Dir { entries: vec![root].into_iter(), ignore: None };
+        Walker { stack: vec![root], excludes }
+    }
+
+    fn next_file(&mut self) -> Option<PathBuf> {
+        loop {
+            let next = self.stack.last_mut()?.entries.next();
+            let path = match next {
+                Some(path) => path,
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            let meta = match fs::symlink_metadata(&path) {
+                Ok(meta) => meta,
+                Err(_) => continue,
+            };
+            let is_dir = meta.is_dir();
+            if self.is_ignored(&path, is_dir) {
+                continue;
+            }
+            if is_dir {
+                self.push_dir(path);
+            } else if meta.is_file() || fs::metadata(&path).map(|m| m.is_file()).unwrap_or(false) {
+                return Some(path);
+            }
+        }
+    }
+
+    fn push_dir(&mut self, path: PathBuf) {
+        let mut entries = match fs::read_dir(&path) {
+            Ok(entries) => entries.filter_map(|e| e.ok().map(|e| e.path()))
+                .collect::<Vec<_>>(),
+            Err(_) => return,
+        };
+        entries.sort();
+        let ignore = read_to_string(&path.join(".gitignore"))
+            .map(|s| IgnoreFile::new(&path, s.lines()));
+        self.stack.push(Dir { entries: entries.into_iter(), ignore });
+    }
+
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if path.file_name().map(|n| n == ".git").unwrap_or(false) {
+            return true;
+        }
+        // the innermost matching rule wins
+        let ignores = self.stack.iter().rev().filter_map(|dir| dir.ignore.as_ref());
+        ::std::iter::once(&self.excludes).chain(ignores)
+            .filter_map(|ignore| ignore.matched(path, is_dir))
+            .next()
+            .unwrap_or(false)
+    }
+}
+
+fn read_to_string(path: &Path) -> Option<String> {
+    let mut f = File::open(path).ok()?;
+    let mut s = String::new();
+    f.read_to_string(&mut s).ok()?;
+    Some(s)
+}
+
+/// The rules of a `.gitignore` file, which apply to the paths below `base`.
+struct IgnoreFile {
+    base: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+struct IgnoreRule {
+    regex: Regex,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl IgnoreFile {
+    fn new<'a, I>(base: &Path, lines: I) -> IgnoreFile
+        where I: Iterator<Item=&'a str>
+    {
+        let rules = lines.filter_map(IgnoreRule::parse).collect();
+        IgnoreFile { base: base.to_owned(), rules }
+    }
+
+    /// Returns `Some(true)` if `path` is ignored, `Some(false)` if it is
+    /// explicitly not ignored by a negated rule, and `None` if no rule
+    /// matches it.
+    fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let rel_path = path.strip_prefix(&self.base).ok()?;
+        let rel_path = rel_path.components()
+            .filter_map(|c| match c {
+                Component::Normal(s) => s.to_str(),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        // later rules take precedence
+        self.rules.iter().rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.regex.is_match(&rel_path))
+            .map(|rule| !rule.negated)
+    }
+}
+
+impl IgnoreRule {
+    fn parse(line: &str) -> Option<IgnoreRule> {
+        let line = line.trim_right();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.starts_with('!') {
+            true => (true, &line[1..]),
+            // a backslash escapes a leading `#` or `!`
+            false if line.starts_with("\\#") || line.starts_with("\\!") => (false, &line[1..]),
+            false => (false, line),
+        };
+        let (dir_only, line) = match line.ends_with('/') {
+            true => (true, &line[..line.len() - 1]),
+            false => (false, line),
+        };
+        // a pattern with a slash is relative to the .gitignore's directory,
+        // otherwise it can match at any depth
+        let anchored = line.contains('/');
+        let line = line.trim_left_matches('/');
+        if line.is_empty() {
+            return None;
+        }
+        let prefix = if anchored { "^" } else { "^(?:.*/)?" };
+        let pattern = format!("{}{}$", prefix, glob_to_regex(line));
+        Regex::new(&pattern).ok().map(|regex| IgnoreRule { regex, negated, dir_only })
+    }
+}
+
+/// Translates a glob, as found in `.gitignore` files, to a regular expression.
+fn glob_to_regex(glob: &str) -> String {
+    let mut result = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    // "**/" matches any number of directories, including none
+                    chars.next();
+                    result.push_str("(?:.*/)?");
+                } else {
+                    result.push_str(".*");
+                }
+            }
+            '*' => result.push_str("[^/]*"),
+            '?' => result.push_str("[^/]"),
+            '[' => {
+                result.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    result.push('^');
+                }
+                while let Some(c) = chars.next() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' || c == '^' {
+                        result.push('\\');
+                    }
+                    result.push(c);
+                }
+                result.push(']');
+            }
+            '\\' => if let Some(c) = chars.next() {
+                result.push_str(&regex::escape(&c.to_string()));
+            },
+            c => result.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+extern crate tempdir;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_file(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap().write_all(contents.as_bytes()).unwrap();
+    }
+
+    fn ignored(rules: &str, path: &str, is_dir: bool) -> Option<bool> {
+        let ignore = IgnoreFile::new(Path::new("/base"), rules.lines());
+        ignore.matched(&Path::new("/base").join(path), is_dir)
+    }
+
+    #[test]
+    fn ignore_rules() {
+        assert_eq!(ignored("*.o", "a.o", false), Some(true));
+        assert_eq!(ignored("*.o", "src/a.o", false), Some(true));
+        assert_eq!(ignored("*.o", "a.oo", false), None);
+        assert_eq!(ignored("/target", "target", true), Some(true));
+        assert_eq!(ignored("/target", "src/target", true), None);
+        assert_eq!(ignored("build/", "build", false), None);
+        assert_eq!(ignored("build/", "src/build", true), Some(true));
+        assert_eq!(ignored("doc/*.md", "doc/a.md", false), Some(true));
+        assert_eq!(ignored("doc/*.md", "doc/x/a.md", false), None);
+        assert_eq!(ignored("**/gen/*.rs", "a/b/gen/x.rs", false), Some(true));
+        assert_eq!(ignored("*.log\n!keep.log", "keep.log", false), Some(false));
+        assert_eq!(ignored("# comment\n\nfile[0-9]", "file7", false), Some(true));
+        assert_eq!(ignored("file[!0-9]", "file7", false), None);
+        assert_eq!(ignored("\\#a", "#a", false), Some(true));
+        assert_eq!(ignored("\\!a", "!a", false), Some(true));
+        assert_eq!(ignored("\\\\a", "\\a", false), Some(true));
+        assert_eq!(ignored("\\\\a", "a", false), None);
+    }
+
+    #[test]
+    fn search_files() {
+        let tmp = tempdir::TempDir::new("xi-test-project-search").unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        write_file(&root.join(".gitignore"), "target/\n*.log\n");
+        write_file(&root.join("a.rs"), "fn view() {}\nlet view_id = view;\n");
+        write_file(&root.join("src/b.rs"), "// view\n");
+        write_file(&root.join("src/c.log"), "view\n");
+        write_file(&root.join("target/d.rs"), "view\n");
+        write_file(&root.join("vendor/.gitignore"), "!*.log\n");
+        write_file(&root.join("vendor/e.log"), "a view\n");
+        write_file(&root.join("open.rs"), "nothing here\n");
+        write_file(&root.join("bin.dat"), "view\0\n");
+
+        let open_path = root.join("open.rs");
+        let open_buffer = |path: &Path| if path == open_path {
+            Some(Rope::from("unsaved view\n"))
+        } else {
+            None
+        };
+
+        let mut search = ProjectSearch::new(SearchId(1), root.clone(), "view", true,
+                                            false, true, false, &[]).unwrap();
+        let (results, done) = search.step(2, &open_buffer);
+        assert!(!done);
+        assert_eq!(results, vec![
+            SearchResult { path: root.join("a.rs"), line: 0, col: 3,
+                           preview: "fn view() {}".into() },
+            SearchResult { path: root.join("a.rs"), line: 1, col: 14,
+                           preview: "let view_id = view;".into() },
+        ]);
+        let (results, done) = search.step(10, &open_buffer);
+        assert!(done);
+        let found = results.iter()
+            .map(|r| r.path.strip_prefix(&root).unwrap().to_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(found, vec!["open.rs", "src/b.rs", "vendor/e.log"]);
+        assert_eq!(results[0].preview, "unsaved view");
+
+        let excludes = vec!["src".to_owned()];
+        let mut search = ProjectSearch::new(SearchId(2), root.clone(), "VIEW", false,
+                                            false, false, false, &excludes).unwrap();
+        let (results, done) = search.step(10, &open_buffer);
+        assert!(done);
+        assert_eq!(results.len(), 5);
+
+        assert!(ProjectSearch::new(SearchId(3), root, "(", true, true,
+                                   false, false, &[]).is_err());
+    }
+}
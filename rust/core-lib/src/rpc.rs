@@ -28,6 +28,9 @@ use serde::ser::{self, Serialize, Serializer};
 use tabs::ViewId;
 use plugins::PlaceholderRpc;
 use config::{Table, ConfigDomainExternal};
+use project_search::SearchId;
+use diff::{ConflictSide, DiffId};
+use file::LineEnding;
 
 // =============================================================================
 //  Command types
@@ -175,8 +178,15 @@ pub enum CoreNotification {
     /// Tells `xi-core` to close the specified view.
     CloseView { view_id: ViewId },
     /// Tells `xi-core` to save the contents of the specified view's
-    /// buffer to the specified path.
-    Save { view_id: ViewId, file_path: String },
+    /// buffer to the specified path. If `encoding` is present, the file
+    /// is saved in that encoding from now on; otherwise it keeps the
+    /// encoding it was opened in.
+    Save {
+        view_id: ViewId,
+        file_path: String,
+        #[serde(default)]
+        encoding: Option<String>,
+    },
     /// Tells `xi-core` to set the theme.
     SetTheme { theme_name: String },
     /// Notifies `xi-core` that the client has started.
@@ -186,6 +196,10 @@ pub enum CoreNotification {
         /// Path to additional plugins, included by the client.
         #[serde(default)]
         client_extras_dir: Option<PathBuf>,
+        /// Path to a directory for state the core keeps between sessions,
+        /// such as undo histories.
+        #[serde(default)]
+        cache_dir: Option<PathBuf>,
     },
     /// Updates the user's config for the given domain. Where keys in
     /// `changes` are `null`, those keys are cleared in the user config
@@ -203,7 +217,28 @@ pub enum CoreNotification {
     TracingConfig {enabled: bool},
     /// Save trace data to the given path.  The core will first send
     /// CoreRequest::CollectTrace to all peers to collect the samples.
-    SaveTrace { destination: PathBuf, frontend_samples: Value }
+    SaveTrace { destination: PathBuf, frontend_samples: Value },
+    /// Cancels a project search started with `CoreRequest::SearchProject`.
+    CancelSearch { search_id: SearchId },
+    /// Stops sharing the buffer of `view_id`, whether it was shared with
+    /// `CoreRequest::ShareBuffer` or joined with
+    /// `CoreRequest::JoinSharedBuffer`.
+    StopSharing { view_id: ViewId },
+    /// Converts the line endings of the buffer of `view_id` to
+    /// `line_ending`, as an edit that can be undone, and uses it for new
+    /// lines from now on.
+    SetLineEnding { view_id: ViewId, line_ending: LineEnding },
+    /// Replaces the buffer of `view_id` with the unsaved changes found in
+    /// its swap file, which were reported with `swap_file_found`.
+    RecoverSwapFile { view_id: ViewId },
+    /// Deletes the swap file reported with `swap_file_found` for `view_id`.
+    DiscardSwapFile { view_id: ViewId },
+    /// Sets the text that the diff markers of `view_id`'s buffer compare
+    /// with, such as the file's version in git's HEAD. With `None`, they
+    /// compare with the last saved text.
+    SetDiffBase { view_id: ViewId, base: Option<String> },
+    /// Stops the side-by-side diff `diff_id`, removing its highlights.
+    CloseDiff { diff_id: DiffId },
 }
 
 /// The requests which make up the base of the protocol.
@@ -247,6 +282,71 @@ pub enum CoreRequest {
     NewView { file_path: Option<String> },
     /// Returns the current collated config object for the given view.
     GetConfig { view_id: ViewId },
+    /// Starts searching for `chars` in the files below the directory
+    /// `root`, skipping files excluded by `.gitignore` files or by the
+    /// additional `.gitignore`-style patterns in `excludes`. The search
+    /// options are the same as for `EditRequest::Find`.
+    ///
+    /// Files that are open are searched as they are in the editor. Results
+    /// are sent incrementally in `project_search_results` notifications,
+    /// followed by a `project_search_finished` notification, unless the
+    /// search is cancelled first.
+    ///
+    /// Returns the identifier of the search.
+    SearchProject {
+        root: PathBuf,
+        chars: String,
+        case_sensitive: bool,
+        #[serde(default)]
+        regex: bool,
+        #[serde(default)]
+        whole_words: bool,
+        #[serde(default)]
+        ignore_accents: bool,
+        #[serde(default)]
+        excludes: Vec<String>,
+    },
+    /// Starts sharing the buffer of `view_id` with other xi-core
+    /// instances, which can join it at `address`: either a TCP
+    /// `host:port`, or `unix:` followed by the path of a Unix domain
+    /// socket. Discards the buffer's undo history.
+    ///
+    /// TCP addresses other than loopback ones are refused unless
+    /// `allow_remote` is set, and Unix domain sockets are only accessible
+    /// to their owner.
+    ///
+    /// Returns the address the buffer is shared at, which differs from
+    /// `address` if it used port `0`, and the token other instances have
+    /// to join with.
+    ShareBuffer {
+        view_id: ViewId,
+        address: String,
+        #[serde(default)]
+        allow_remote: bool,
+    },
+    /// Joins a buffer shared at `address` with the `token` it was shared
+    /// with, opening it in a new view.
+    ///
+    /// Returns the id of the new view.
+    JoinSharedBuffer { address: String, token: String },
+    /// Reads the file open in `view_id` again, decoding it as `encoding`
+    /// instead of the encoding it was guessed to have. Unsaved changes
+    /// are replaced, as an edit that can be undone.
+    ReopenWithEncoding { view_id: ViewId, encoding: String },
+    /// Starts comparing the buffer of `view_id`, on the left, with the
+    /// buffer of `other_view_id` or with the file at `path`, on the right.
+    /// The changed text of each buffer is highlighted with styles, and
+    /// the comparison is updated as either buffer is edited, with
+    /// `diff_changed` notifications, until `close_diff`.
+    ///
+    /// Returns the identifier of the diff and its aligned hunks.
+    DiffBuffers {
+        view_id: ViewId,
+        #[serde(default)]
+        other_view_id: Option<ViewId>,
+        #[serde(default)]
+        path: Option<PathBuf>,
+    },
 }
 
 /// A helper type, which extracts the `view_id` field from edit
@@ -364,6 +464,12 @@ pub enum EditNotification {
     MoveToBeginningOfDocumentAndModifySelection,
     MoveToEndOfDocument,
     MoveToEndOfDocumentAndModifySelection,
+    /// Moves each caret next to a bracket to the other side of the
+    /// bracket matching it.
+    MoveToMatchingBracket,
+    /// Extends each selection past the bracket matching the one next to
+    /// its caret.
+    SelectToMatchingBracket,
     ScrollPageUp,
     PageUpAndModifySelection,
     ScrollPageDown,
@@ -381,8 +487,21 @@ pub enum EditNotification {
     Gesture { line: u64, col: u64, ty: GestureType},
     Undo,
     Redo,
-    FindNext { wrap_around: Option<bool>, allow_same: Option<bool> },
-    FindPrevious { wrap_around: Option<bool> },
+    /// Moves to the state `id` of the undo tree, which may be on a branch
+    /// that was undone. State ids are listed by the `undo_tree` request.
+    GotoUndoState { id: usize },
+    /// Moves to the undo state created `count` states or `seconds` seconds
+    /// before the current one, regardless of branch. Defaults to one state.
+    UndoEarlier { count: Option<usize>, seconds: Option<u64> },
+    /// Moves to the undo state created `count` states or `seconds` seconds
+    /// after the current one, regardless of branch. Defaults to one state.
+    UndoLater { count: Option<usize>, seconds: Option<u64> },
+    /// Selects the next occurrence of the find query named `query`, or of
+    /// any query if `query` is `None`.
+    FindNext { wrap_around: Option<bool>, allow_same: Option<bool>, query: Option<String> },
+    /// Selects the previous occurrence of the find query named `query`, or
+    /// of any query if `query` is `None`.
+    FindPrevious { wrap_around: Option<bool>, query: Option<String> },
     DebugRewrap,
     DebugWrapWidth,
     /// Prints the style spans present in the active selection.
@@ -391,7 +510,41 @@ pub enum EditNotification {
     Uppercase,
     Lowercase,
     Indent,
-    Outdent
+    Outdent,
+    /// Duplicates each selection after itself, or for a caret, its lines
+    /// below them.
+    DuplicateLine,
+    /// Swaps the lines of each selection with the line above them.
+    MoveLinesUp,
+    /// Swaps the lines of each selection with the line below them.
+    MoveLinesDown,
+    /// Joins the lines of each selection, or a caret's line and the next,
+    /// replacing the whitespace around the line breaks with a space.
+    JoinLines,
+    /// Sorts the lines of each selection, or for a single caret, of its
+    /// paragraph. Each option defaults to `false`.
+    SortLines { numeric: Option<bool>, ignore_case: Option<bool>,
+                reverse: Option<bool>, unique: Option<bool> },
+    /// Deletes the lines of each selection.
+    DeleteLine,
+    /// Comments out or uncomments the lines of each caret and multi-line
+    /// selection, and wraps or unwraps each selection within a line in a
+    /// block comment, using the comment tokens of the buffer's syntax.
+    ToggleComment,
+    /// Keeps `side` of the conflict numbered `index`, counting from the
+    /// start of the buffer, or of every conflict if `index` is `None`.
+    ResolveConflict { index: Option<usize>, side: ConflictSide },
+    /// Folds each multi-line selection, and for each caret, the innermost
+    /// fold range reported by plugins that spans its line.
+    Fold,
+    /// Unfolds the folds spanning the lines of the carets.
+    Unfold,
+    UnfoldAll,
+    /// Asks the plugins to format the whole buffer, applying the first
+    /// edit one of them answers with.
+    FormatDocument,
+    /// Asks the plugins to format the lines spanned by the selections.
+    FormatSelection,
 }
 
 /// The edit related requests.
@@ -408,9 +561,67 @@ pub enum EditRequest {
     /// Searches the document for `chars`, if present, falling back on
     /// the last selection region if `chars` is `None`.
     ///
+    /// If `regex` is `true`, the search string is interpreted as a regular
+    /// expression, with `^` and `$` matching at line boundaries. An invalid
+    /// expression results in an error response.
+    ///
+    /// If `whole_words` is `true`, only occurrences that start and end at a
+    /// word boundary are found. Case insensitive searches use full Unicode
+    /// case folding; if `ignore_accents` is also `true`, diacritics are
+    /// ignored as well, regardless of Unicode normalization. `ignore_accents`
+    /// has no effect on case sensitive or regex searches.
+    ///
+    /// A view can have several find queries active at once, each highlighted
+    /// in its own style. `query` names the query to update; if it is `None`,
+    /// the default query is updated. A named query is created the first
+    /// time it is used.
+    ///
     /// If `chars` is `None` and there is an active selection, returns
     /// the string value used for the search, else returns `Null`.
-    Find { chars: Option<String>, case_sensitive: bool },
+    Find {
+        chars: Option<String>,
+        case_sensitive: bool,
+        #[serde(default)]
+        regex: bool,
+        #[serde(default)]
+        whole_words: bool,
+        #[serde(default)]
+        ignore_accents: bool,
+        query: Option<String>,
+    },
+    /// Replaces occurrences of the active search with `chars`. For regex
+    /// searches, `chars` is a template in which `$1`, `${name}` and so on
+    /// refer to capture groups of the match.
+    ///
+    /// If `all` is `true`, every occurrence is replaced. Otherwise, the
+    /// occurrence at the last selection region (if any) is replaced, and the
+    /// next occurrence is selected. Either way the edit is a single undo group.
+    ///
+    /// If `query` is set, only occurrences of the find query with that name
+    /// are replaced; otherwise occurrences of all queries are.
+    ///
+    /// Returns the number of occurrences replaced.
+    Replace {
+        chars: String,
+        #[serde(default)]
+        all: bool,
+        query: Option<String>,
+    },
+    /// Returns the states of the undo tree, as
+    /// `{"current": id, "states": [{"id", "parent", "timestamp"}]}`.
+    /// Each state is an undo group; its text is the result of the edits in
+    /// it and its ancestors. Timestamps are milliseconds since the Unix epoch.
+    UndoTree,
+    /// Returns the delta that transforms the text of undo state `id` into
+    /// the current text.
+    UndoStateDiff { id: usize },
+    /// Returns the blocks of lines that are indented more than the line
+    /// before them, computed with the buffer's `tab_size`. Each is
+    /// `{"start", "end", "start_line", "end_line", "children"}`, where
+    /// `start` and `end` are the offsets of the ends of the line before
+    /// the block and of its last line, and `children` are the blocks
+    /// nested in it. Empty for large files.
+    IndentFoldRanges,
 }
 
 
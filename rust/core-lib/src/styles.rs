@@ -22,9 +22,32 @@ use syntect::highlighting::{Color, Theme, ThemeSet, Highlighter};
 
 pub use syntect::highlighting::ThemeSettings;
 
-const N_RESERVED_STYLES: usize = 2;
+/// Style ids reserved for the client: 0 is the selection, 1 the occurrences
+/// of the first find query, and 2 the currently selected find occurrence.
+const N_RESERVED_STYLES: usize = 3;
 const SYNTAX_PRIORITY_DEFAULT: u16 = 200;
 const SYNTAX_PRIORITY_LOWEST: u16 = 0;
+const FIND_HIGHLIGHT_PRIORITY: u16 = 800;
+const DIFF_HIGHLIGHT_PRIORITY: u16 = 700;
+const BRACKET_HIGHLIGHT_PRIORITY: u16 = 750;
+
+/// Background colors (ARGB) of text removed from and added to the sides
+/// of a side-by-side diff.
+const DIFF_REMOVED_COLOR: u32 = 0x50_FF_40_40;
+const DIFF_ADDED_COLOR: u32 = 0x50_40_D0_40;
+
+/// Background color (ARGB) of a bracket next to a caret and its match.
+const BRACKET_HIGHLIGHT_COLOR: u32 = 0x60_80_80_80;
+
+/// Background colors (ARGB) used to tell apart the occurrences of find
+/// queries beyond the first, which uses a reserved style.
+const FIND_HIGHLIGHT_COLORS: [u32; 5] = [
+    0x60_FF_D7_00,
+    0x60_00_BF_FF,
+    0x60_FF_69_B4,
+    0x60_32_CD_32,
+    0x60_FF_8C_00,
+];
 
 #[derive(Clone, PartialEq, Eq, Default, Hash, Debug, Serialize, Deserialize)]
 /// A mergeable style. All values except priority are optional.
@@ -106,6 +129,12 @@ impl Style {
     /// If both styles define an attribute, the highest priority wins; `other`
     /// wins in the case of a tie.
     ///
+    /// Background colors are merged like the other attributes. Syntax
+    /// styles never have one (see `from_syntect_style_mod`), so the only
+    /// backgrounds are those of the highlights the core adds itself, such
+    /// as find occurrences, which have to survive merging with the syntax
+    /// and default styles underneath them.
+    ///
     /// Note: when merging multiple styles, apply them in increasing priority.
     pub fn merge(&self, other: &Style) -> Style {
         let (p1, p2) = if self.priority > other.priority {
@@ -117,8 +146,7 @@ impl Style {
         Style::new(
             p1.priority,
             p1.fg_color.or(p2.fg_color),
-            //TODO: stop ignoring background color
-            None,
+            p1.bg_color.or(p2.bg_color),
             p1.weight.or(p2.weight),
             p1.underline.or(p2.underline),
             p1.italic.or(p2.italic),
@@ -206,6 +234,28 @@ impl ThemeStyleMap {
         }
     }
 
+    /// Returns the style used to highlight the occurrences of the find query
+    /// at position `ix` in a view. The first query uses a reserved style id
+    /// instead, so `ix` should be at least 1.
+    pub fn find_highlight_style(&self, ix: usize) -> Style {
+        let color = FIND_HIGHLIGHT_COLORS[(ix - 1) % FIND_HIGHLIGHT_COLORS.len()];
+        Style::new(FIND_HIGHLIGHT_PRIORITY, None, Some(color), None, None, None)
+    }
+
+    /// Returns the style highlighting the changes on the left side of a
+    /// side-by-side diff, or on the right side if `added` is true.
+    pub fn diff_style(&self, added: bool) -> Style {
+        let color = if added { DIFF_ADDED_COLOR } else { DIFF_REMOVED_COLOR };
+        Style::new(DIFF_HIGHLIGHT_PRIORITY, None, Some(color), None, None, None)
+    }
+
+    /// Returns the style highlighting a bracket next to a caret, and the
+    /// bracket matching it.
+    pub fn bracket_highlight_style(&self) -> Style {
+        Style::new(BRACKET_HIGHLIGHT_PRIORITY, None, Some(BRACKET_HIGHLIGHT_COLOR),
+                   None, None, None)
+    }
+
     pub fn merge_with_default(&self, style: &Style) -> Style {
         self.default_style.merge(style)
     }
@@ -221,3 +271,29 @@ impl ThemeStyleMap {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syntect::highlighting::{FontStyle, StyleModifier};
+
+    #[test]
+    fn merge_keeps_highlight_background() {
+        let style_map = ThemeStyleMap::new();
+        let syntax = Style::from_syntect_style_mod(&StyleModifier {
+            foreground: Some(Color::BLACK),
+            background: Some(Color::WHITE),
+            font_style: Some(FontStyle::BOLD),
+        });
+        // a theme's backgrounds are still ignored
+        assert_eq!(syntax.bg_color, None);
+
+        let highlight = style_map.find_highlight_style(1);
+        let merged = syntax.merge(&highlight);
+        assert_eq!(merged.fg_color, syntax.fg_color);
+        assert_eq!(merged.weight, Some(700));
+        assert_eq!(merged.bg_color, highlight.bg_color);
+        assert_eq!(highlight.merge(&syntax), merged);
+        assert_eq!(style_map.merge_with_default(&merged).bg_color, highlight.bg_color);
+    }
+}
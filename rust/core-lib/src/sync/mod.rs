@@ -0,0 +1,75 @@
+// Copyright 2018 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sharing a buffer between editing sessions.
+//!
+//! Each session edits its own copy of the buffer's CRDT `Engine`. Sessions
+//! share their engines through a `SyncStore`, and combine what they
+//! receive with their own engine using `Engine::merge`, or
+//! `Engine::merge_revisions` for just the revisions they don't have.
+
+pub mod socket;
+
+use std::collections::BTreeSet;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use xi_rope::engine::{Engine, SessionId};
+
+use file::{fnv_hash, fnv_hash_update};
+
+/// The maximum number of sessions sharing a buffer.
+///
+/// While syncing, each session only creates undo groups that are congruent
+/// to its session slot modulo this number, so that groups created
+/// concurrently by different sessions don't collide.
+pub const MAX_SESSIONS: usize = 64;
+
+/// The session slot of the session that shares a buffer with the others.
+pub const HOST_SLOT: u32 = 0;
+
+/// The selection of a session, as `(start, end)` offsets into the text of
+/// the engine it is sent with.
+pub type SessionSelection = (SessionId, Vec<(usize, usize)>);
+
+/// A place where the state of a buffer is shared with other sessions.
+pub trait SyncStore: Send {
+    /// Called when edits or merges have added revisions to `engine`, or
+    /// when the selections known to this session have changed, so that
+    /// they can be shared.
+    fn state_changed(&mut self, engine: &Engine, selections: &[SessionSelection]);
+
+    /// Called before `state_changed` with the undo groups of this session
+    /// that can no longer be undone. Other sessions merge against their
+    /// revisions, so they are only collected once every session agrees,
+    /// with `Editor::collect_synced`.
+    fn collectible(&mut self, _groups: &BTreeSet<usize>) {}
+
+    /// Called once the groups passed to `Editor::collect_synced` have been
+    /// collected from `engine`.
+    fn collected(&mut self, _engine: &Engine, _selections: &[SessionSelection]) {}
+
+    /// Called by `Editor::transaction_ready`, for stores that can't commit
+    /// a new state as soon as it changes.
+    fn commit_transaction(&mut self, _engine: &Engine) {}
+}
+
+/// Returns a session id for session slot `slot`. The first part of the id
+/// is random, so that it differs between sessions using the same slot.
+pub fn new_session_id(slot: u32) -> SessionId {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let hash = fnv_hash(&elapsed.as_secs().to_le_bytes());
+    let hash = fnv_hash_update(hash, &elapsed.subsec_nanos().to_le_bytes());
+    (fnv_hash_update(hash, &process::id().to_le_bytes()), slot)
+}
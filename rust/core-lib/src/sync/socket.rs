@@ -0,0 +1,910 @@
+// Copyright 2018 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sharing a buffer over TCP or Unix domain sockets.
+//!
+//! One session hosts the buffer, listening on an address; other sessions
+//! join it by connecting to that address and sending the token the host
+//! was given. Messages are JSON objects, one per line. The host welcomes
+//! each guest with its session slot and the revisions of its engine. After
+//! that, whenever its engine changes, each session sends the revisions the
+//! other side doesn't have yet, and the host relays the revisions it
+//! merges from guests to the other guests.
+//!
+//! Selections travel with the revisions, so that they can be transformed
+//! to the merged text. Guests send their own selection; the host sends
+//! those of every session.
+//!
+//! Revisions are sent relative to a union string that garbage collection
+//! changes, so sessions collect undo groups in lockstep. Each session
+//! reports which of its own groups can no longer be undone. Once every
+//! guest has the edits of some of them, the host asks the guests to
+//! collect those groups, and holds back what it would send, and what
+//! guests send after collecting, until all of them are done and it has
+//! collected the groups too.
+//!
+//! Connections are served by background threads, which queue what they
+//! receive and wake the main thread with `SYNC_IDLE_TOKEN`.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem;
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use serde_json;
+use xi_rope::engine::{Engine, RevId, Revisions, Versions};
+
+use client::Client;
+use tabs::SYNC_IDLE_TOKEN;
+use super::{MAX_SESSIONS, SessionSelection, SyncStore};
+
+/// How often the host checks whether it has stopped sharing, while
+/// waiting for connections.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a guest has to send its token after connecting.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The longest message accepted before a guest has sent its token.
+const MAX_HELLO_LEN: usize = 1024;
+
+/// The longest message accepted; a connection sending a longer one is
+/// closed rather than buffered.
+const MAX_MESSAGE_LEN: usize = 256 << 20;
+
+/// Identifies a connection. On the host this is also the session slot of
+/// the guest at the other end; a guest's connection to the host is `0`.
+pub type ConnectionId = usize;
+
+/// The connection of a guest to the host.
+pub const HOST_CONNECTION: ConnectionId = 0;
+
+/// Something that happened on the connections of a shared buffer, to be
+/// handled on the main thread.
+#[derive(Debug)]
+pub enum SyncEvent {
+    /// A guest connected, and should be sent a welcome.
+    Joined(ConnectionId),
+    /// The host welcomed us, assigning us a session slot.
+    Welcome { slot: u32, revisions: Revisions, selections: Vec<SessionSelection> },
+    /// A session sent the revisions we didn't have, its head and its
+    /// collectible undo groups.
+    State {
+        from: ConnectionId,
+        revisions: Revisions,
+        head: RevId,
+        collectible: BTreeSet<usize>,
+        selections: Vec<SessionSelection>,
+    },
+    /// These undo groups should be garbage collected, with
+    /// `Editor::collect_synced`: on a guest because the host asked, on the
+    /// host because every guest has collected them.
+    Collect(BTreeSet<usize>),
+    /// A connection was closed.
+    Closed(ConnectionId),
+}
+
+/// A message between sessions. Generic so that it can be serialized by
+/// reference.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "method", content = "params")]
+enum Message<S> {
+    Hello { token: String },
+    Welcome { slot: u32, revisions: Revisions, selections: S },
+    State { revisions: Revisions, head: RevId, collectible: BTreeSet<usize>, selections: S },
+    Collect { groups: BTreeSet<usize> },
+    Collected,
+}
+
+type Outgoing<'a> = Message<&'a [SessionSelection]>;
+type Incoming = Message<Vec<SessionSelection>>;
+
+/// A connected socket of either kind.
+trait Stream: Read + Write + Send {
+    fn clone_stream(&self) -> io::Result<Box<Stream>>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn close(&self);
+}
+
+impl Stream for TcpStream {
+    fn clone_stream(&self) -> io::Result<Box<Stream>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn close(&self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn clone_stream(&self) -> io::Result<Box<Stream>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn close(&self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+/// Where a buffer is shared: `unix:<path>` for a Unix domain socket,
+/// or a TCP `host:port`.
+enum Address<'a> {
+    Tcp(&'a str),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl<'a> Address<'a> {
+    fn parse(address: &'a str) -> io::Result<Self> {
+        match address.starts_with("unix:") {
+            #[cfg(unix)]
+            true => Ok(Address::Unix(PathBuf::from(&address["unix:".len()..]))),
+            #[cfg(not(unix))]
+            true => Err(io::Error::new(io::ErrorKind::Other,
+                                       "unix sockets are not supported")),
+            false => Ok(Address::Tcp(address)),
+        }
+    }
+
+    fn connect(&self) -> io::Result<Box<Stream>> {
+        match *self {
+            Address::Tcp(addr) => Ok(Box::new(TcpStream::connect(addr)?)),
+            #[cfg(unix)]
+            Address::Unix(ref path) => Ok(Box::new(UnixStream::connect(path)?)),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Binds `address`, returning the listener and the address it can be
+    /// reached at. Unless `allow_remote` is set, TCP addresses must be
+    /// loopback addresses. Unix domain sockets are only accessible to
+    /// their owner.
+    fn bind(address: &Address, allow_remote: bool) -> io::Result<(Self, String)> {
+        let (listener, local) = match *address {
+            Address::Tcp(addr) => {
+                let addrs = addr.to_socket_addrs()?.collect::<Vec<_>>();
+                if !allow_remote && addrs.iter().any(|a| !a.ip().is_loopback()) {
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                              format!("{} is not a loopback address", addr)));
+                }
+                let listener = TcpListener::bind(&addrs[..])?;
+                let local = listener.local_addr()?.to_string();
+                (Listener::Tcp(listener), local)
+            }
+            #[cfg(unix)]
+            Address::Unix(ref path) => {
+                let listener = UnixListener::bind(path)?;
+                fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+                (Listener::Unix(listener), format!("unix:{}", path.display()))
+            }
+        };
+        match listener {
+            Listener::Tcp(ref l) => l.set_nonblocking(true)?,
+            #[cfg(unix)]
+            Listener::Unix(ref l) => l.set_nonblocking(true)?,
+        }
+        Ok((listener, local))
+    }
+
+    /// Accepts a connection, if one is waiting.
+    fn accept(&self) -> io::Result<Option<Box<Stream>>> {
+        let result = match *self {
+            Listener::Tcp(ref l) => l.accept().and_then(|(s, _)| {
+                s.set_nonblocking(false)?;
+                Ok(Box::new(s) as Box<Stream>)
+            }),
+            #[cfg(unix)]
+            Listener::Unix(ref l) => l.accept().and_then(|(s, _)| {
+                s.set_nonblocking(false)?;
+                Ok(Box::new(s) as Box<Stream>)
+            }),
+        };
+        match result {
+            Ok(stream) => Ok(Some(stream)),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// What a session knows about the session at the other end of a
+/// connection.
+#[derive(Default)]
+struct Peer {
+    /// The revisions it has, or has been sent.
+    known: Versions,
+    /// The revisions it last said it has.
+    acked: Versions,
+    /// The head it last reported.
+    head: Option<RevId>,
+    /// The undo groups of its own it last reported as collectible.
+    collectible: BTreeSet<usize>,
+}
+
+/// A garbage collection the host started.
+struct Collecting {
+    groups: BTreeSet<usize>,
+    /// The guests that haven't collected the groups yet.
+    waiting: BTreeSet<ConnectionId>,
+}
+
+/// State shared with the background threads.
+#[derive(Default)]
+struct Shared {
+    events: VecDeque<SyncEvent>,
+    /// The write halves of the open connections.
+    connections: BTreeMap<ConnectionId, Box<Stream>>,
+    /// The sessions revisions are sent to: on the host, the guests that
+    /// were welcomed, and on a guest, the host once it welcomed us.
+    peers: BTreeMap<ConnectionId, Peer>,
+    /// Our own undo groups that can be collected.
+    collectible: BTreeSet<usize>,
+    /// On the host, the garbage collection in progress.
+    collecting: Option<Collecting>,
+    /// On the host, the events held back until it has collected.
+    deferred: VecDeque<SyncEvent>,
+}
+
+/// A buffer shared over a socket, either as its host or as a guest.
+pub struct SocketSync {
+    shared: Arc<Mutex<Shared>>,
+    closed: Arc<AtomicBool>,
+    client: Client,
+    is_host: bool,
+    address: String,
+    token: String,
+    #[cfg(unix)]
+    socket_path: Option<PathBuf>,
+}
+
+/// The `SyncStore` of a `SocketSync`, which sends local changes to every
+/// connected session.
+pub struct SocketStore {
+    shared: Arc<Mutex<Shared>>,
+    client: Client,
+    is_host: bool,
+}
+
+impl SocketSync {
+    /// Starts hosting a buffer at `address`. The address may use port `0`;
+    /// the address actually bound is available from `address`, and the
+    /// token guests have to join with from `token`. TCP addresses other
+    /// than loopback ones are refused unless `allow_remote` is set.
+    pub fn host(address: &str, allow_remote: bool, client: Client) -> io::Result<Self> {
+        let address = Address::parse(address)?;
+        let (listener, local) = Listener::bind(&address, allow_remote)?;
+        let sync = SocketSync {
+            shared: Arc::new(Mutex::new(Shared::default())),
+            closed: Arc::new(AtomicBool::new(false)),
+            client: client.clone(),
+            is_host: true,
+            address: local,
+            token: new_token(),
+            #[cfg(unix)]
+            socket_path: match address {
+                Address::Unix(path) => Some(path),
+                _ => None,
+            },
+        };
+
+        let shared = sync.shared.clone();
+        let closed = sync.closed.clone();
+        let token = sync.token.clone();
+        thread::spawn(move || {
+            while !closed.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok(Some(stream)) => {
+                        let shared = shared.clone();
+                        let token = token.clone();
+                        let client = client.clone();
+                        thread::spawn(move || serve_guest(stream, &token, shared, client));
+                    }
+                    Ok(None) => thread::sleep(ACCEPT_POLL_INTERVAL),
+                    Err(e) => {
+                        eprintln!("error accepting sync connection: {}", e);
+                        thread::sleep(ACCEPT_POLL_INTERVAL);
+                    }
+                }
+            }
+        });
+        Ok(sync)
+    }
+
+    /// Joins a buffer hosted at `address` with the host's `token`. The
+    /// first event will be the host's welcome.
+    pub fn join(address: &str, token: &str, client: Client) -> io::Result<Self> {
+        let mut stream = Address::parse(address)?.connect()?;
+        write_message(&mut stream, &Outgoing::Hello { token: token.to_owned() })?;
+        let sync = SocketSync {
+            shared: Arc::new(Mutex::new(Shared::default())),
+            closed: Arc::new(AtomicBool::new(false)),
+            client: client.clone(),
+            is_host: false,
+            address: address.to_owned(),
+            token: token.to_owned(),
+            #[cfg(unix)]
+            socket_path: None,
+        };
+        sync.shared.lock().unwrap().connections.insert(HOST_CONNECTION, stream.clone_stream()?);
+        let shared = sync.shared.clone();
+        thread::spawn(move || read_messages(HOST_CONNECTION, BufReader::new(stream),
+                                            shared, client));
+        Ok(sync)
+    }
+
+    pub fn is_host(&self) -> bool {
+        self.is_host
+    }
+
+    /// The address of the host.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// The token guests join with.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Returns a `SyncStore` that sends to this buffer's connections.
+    pub fn store(&self) -> SocketStore {
+        SocketStore {
+            shared: self.shared.clone(),
+            client: self.client.clone(),
+            is_host: self.is_host,
+        }
+    }
+
+    /// Returns the events that happened since the last call.
+    pub fn take_events(&self) -> Vec<SyncEvent> {
+        self.shared.lock().unwrap().events.drain(..).collect()
+    }
+
+    /// Sends a guest its session slot and all the revisions of `engine`.
+    pub fn welcome(&self, conn: ConnectionId, engine: &Engine,
+                   selections: &[SessionSelection]) {
+        let mut shared = self.shared.lock().unwrap();
+        let versions = engine.versions();
+        let revisions = engine.revisions_since(&Versions::default());
+        let msg = Message::Welcome { slot: conn as u32, revisions, selections };
+        if shared.send(conn, &msg) {
+            shared.peers.insert(conn, Peer {
+                known: versions.clone(),
+                acked: versions,
+                head: Some(engine.get_head_rev_id()),
+                collectible: BTreeSet::new(),
+            });
+        }
+    }
+
+    /// Records what a session sent, before it is merged: `revisions` and
+    /// the welcome on a guest, or a `SyncEvent::State` on either side.
+    pub fn received(&self, from: ConnectionId, revisions: &Revisions, head: Option<RevId>,
+                    collectible: BTreeSet<usize>) {
+        let mut shared = self.shared.lock().unwrap();
+        if !shared.connections.contains_key(&from) {
+            return;
+        }
+        let versions = revisions.versions();
+        let peer = shared.peers.entry(from).or_insert_with(Peer::default);
+        peer.known = peer.known.union(&versions);
+        peer.acked = versions;
+        peer.head = head;
+        peer.collectible = collectible;
+    }
+
+    /// Closes a connection, for instance because what it sent couldn't be
+    /// merged. Its `SyncEvent::Closed` follows.
+    pub fn disconnect(&self, conn: ConnectionId) {
+        if let Some(stream) = self.shared.lock().unwrap().connections.get(&conn) {
+            stream.close();
+        }
+    }
+
+    /// On the host, starts garbage collecting the undo groups every
+    /// session has the edits of, unless a collection is in progress. The
+    /// guests are asked to collect them; if there are no guests, the groups
+    /// are returned, to be collected right away.
+    pub fn start_collecting(&self, engine: &Engine) -> Option<BTreeSet<usize>> {
+        let mut shared = self.shared.lock().unwrap();
+        if !self.is_host || shared.collecting.is_some() {
+            return None;
+        }
+        let mut groups = shared.collectible.clone();
+        let mut everywhere = engine.versions();
+        let mut keep = BTreeSet::new();
+        for peer in shared.peers.values() {
+            groups.extend(&peer.collectible);
+            everywhere = everywhere.intersection(&peer.acked);
+            keep.extend(peer.head);
+        }
+        let groups = engine.collectible_groups(&groups, &everywhere, &keep);
+        if groups.is_empty() {
+            return None;
+        }
+        let waiting = shared.peers.keys().cloned().collect::<BTreeSet<_>>();
+        for &conn in &waiting {
+            shared.send(conn, &Message::Collect { groups: groups.clone() });
+        }
+        let collect_now = waiting.is_empty();
+        shared.collecting = Some(Collecting { groups: groups.clone(), waiting });
+        if collect_now { Some(groups) } else { None }
+    }
+}
+
+impl Drop for SocketSync {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let mut shared = self.shared.lock().unwrap();
+        for conn in shared.connections.values() {
+            conn.close();
+        }
+        shared.connections.clear();
+        #[cfg(unix)]
+        {
+            if let Some(ref path) = self.socket_path {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+impl SyncStore for SocketStore {
+    fn state_changed(&mut self, engine: &Engine, selections: &[SessionSelection]) {
+        let mut shared = self.shared.lock().unwrap();
+        // guests that already collected couldn't merge what we'd send
+        if shared.collecting.is_none() {
+            shared.send_state(engine, selections);
+        }
+    }
+
+    fn collectible(&mut self, groups: &BTreeSet<usize>) {
+        self.shared.lock().unwrap().collectible = groups.clone();
+    }
+
+    fn collected(&mut self, engine: &Engine, selections: &[SessionSelection]) {
+        let mut shared = self.shared.lock().unwrap();
+        if self.is_host {
+            shared.collecting = None;
+            let deferred = mem::replace(&mut shared.deferred, VecDeque::new());
+            if !deferred.is_empty() {
+                shared.events.extend(deferred);
+                self.client.schedule_idle(SYNC_IDLE_TOKEN);
+            }
+            shared.send_state(engine, selections);
+        } else {
+            shared.send(HOST_CONNECTION, &Message::Collected);
+        }
+    }
+}
+
+impl Shared {
+    /// Writes `msg` to connection `conn`, returning whether it could.
+    /// Failed writes are otherwise ignored; the connection's reader will
+    /// notice that it was closed.
+    fn send(&mut self, conn: ConnectionId, msg: &Outgoing) -> bool {
+        match self.connections.get_mut(&conn) {
+            Some(stream) => write_message(stream, msg).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Sends each peer the revisions of `engine` it doesn't have.
+    fn send_state(&mut self, engine: &Engine, selections: &[SessionSelection]) {
+        let versions = engine.versions();
+        let head = engine.get_head_rev_id();
+        for (conn, peer) in &mut self.peers {
+            let stream = match self.connections.get_mut(conn) {
+                Some(stream) => stream,
+                None => continue,
+            };
+            let msg = Message::State {
+                revisions: engine.revisions_since(&peer.known),
+                head,
+                collectible: self.collectible.clone(),
+                selections,
+            };
+            if write_message(stream, &msg).is_ok() {
+                peer.known = peer.known.union(&versions);
+            }
+        }
+    }
+
+    /// Queues an event from connection `conn`, unless it comes from a
+    /// guest that collected before the host did.
+    fn push_event(&mut self, conn: ConnectionId, event: SyncEvent, client: &Client) {
+        let collected = self.collecting.as_ref()
+            .map_or(false, |c| conn != HOST_CONNECTION && !c.waiting.contains(&conn));
+        if collected {
+            self.deferred.push_back(event);
+        } else {
+            self.events.push_back(event);
+            client.schedule_idle(SYNC_IDLE_TOKEN);
+        }
+    }
+
+    /// Notes that a guest is done collecting, or won't collect because it
+    /// left, and lets the host collect once every guest is done.
+    fn done_collecting(&mut self, conn: ConnectionId, client: &Client) {
+        let groups = match self.collecting {
+            Some(ref mut c) => {
+                if !c.waiting.remove(&conn) || !c.waiting.is_empty() {
+                    return;
+                }
+                c.groups.clone()
+            }
+            None => return,
+        };
+        self.events.push_back(SyncEvent::Collect(groups));
+        client.schedule_idle(SYNC_IDLE_TOKEN);
+    }
+}
+
+/// Returns a token that can't be guessed, to authenticate guests.
+fn new_token() -> String {
+    // std seeds each `RandomState` with randomness from the OS
+    let mut token = String::new();
+    for _ in 0..2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u8(0);
+        token.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    token
+}
+
+fn write_message(stream: &mut Box<Stream>, msg: &Outgoing) -> io::Result<()> {
+    let mut line = serde_json::to_vec(msg)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+    stream.flush()
+}
+
+/// Reads a line of at most `max_len` bytes, without the newline. Returns
+/// `None` at the end of the stream.
+fn read_line<R: BufRead>(reader: &mut R, max_len: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    reader.by_ref().take(max_len as u64 + 1).read_until(b'\n', &mut line)?;
+    if line.last() == Some(&b'\n') {
+        line.pop();
+        Ok(Some(line))
+    } else if line.len() > max_len {
+        Err(io::Error::new(io::ErrorKind::InvalidData, "sync message too long"))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Checks the token a newly connected guest sends, then registers it in
+/// the lowest free session slot and reads its messages.
+fn serve_guest(stream: Box<Stream>, token: &str, shared: Arc<Mutex<Shared>>, client: Client) {
+    let writer = match stream.clone_stream() {
+        Ok(writer) => writer,
+        Err(e) => {
+            eprintln!("error accepting sync connection: {}", e);
+            return;
+        }
+    };
+    let _ = stream.set_read_timeout(Some(HELLO_TIMEOUT));
+    let mut reader = BufReader::new(stream);
+    let hello = read_line(&mut reader, MAX_HELLO_LEN).ok()
+        .and_then(|line| line)
+        .and_then(|line| serde_json::from_slice::<Incoming>(&line).ok());
+    match hello {
+        Some(Message::Hello { token: ref t }) if t == token => (),
+        _ => {
+            eprintln!("refusing sync connection without a valid token");
+            writer.close();
+            return;
+        }
+    }
+    let _ = reader.get_ref().set_read_timeout(None);
+
+    let slot = {
+        let mut locked = shared.lock().unwrap();
+        let slot = (1..MAX_SESSIONS).find(|id| !locked.connections.contains_key(id));
+        let slot = match slot {
+            Some(slot) => slot,
+            None => {
+                eprintln!("too many sync sessions, refusing connection");
+                writer.close();
+                return;
+            }
+        };
+        locked.connections.insert(slot, writer);
+        // guests are only welcomed with collected revisions
+        locked.push_event(slot, SyncEvent::Joined(slot), &client);
+        slot
+    };
+    read_messages(slot, reader, shared, client);
+}
+
+/// Reads messages from connection `conn` until it is closed, or sends
+/// something it shouldn't.
+fn read_messages<R: BufRead>(conn: ConnectionId, mut reader: R,
+                             shared: Arc<Mutex<Shared>>, client: Client) {
+    let from_host = conn == HOST_CONNECTION;
+    loop {
+        let line = match read_line(&mut reader, MAX_MESSAGE_LEN) {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("error reading sync message: {}", e);
+                break;
+            }
+        };
+        let msg = match serde_json::from_slice::<Incoming>(&line) {
+            Ok(msg) => msg,
+            Err(e) => {
+                eprintln!("invalid sync message: {}", e);
+                break;
+            }
+        };
+        let mut locked = shared.lock().unwrap();
+        let event = match msg {
+            Message::Welcome { slot, revisions, selections } if from_host =>
+                SyncEvent::Welcome { slot, revisions, selections },
+            Message::State { revisions, head, collectible, selections } =>
+                SyncEvent::State { from: conn, revisions, head, collectible, selections },
+            Message::Collect { groups } if from_host => SyncEvent::Collect(groups),
+            Message::Collected if !from_host => {
+                locked.done_collecting(conn, &client);
+                continue;
+            }
+            _ => {
+                eprintln!("unexpected sync message");
+                break;
+            }
+        };
+        locked.push_event(conn, event, &client);
+    }
+    let mut locked = shared.lock().unwrap();
+    if let Some(stream) = locked.connections.remove(&conn) {
+        stream.close();
+    }
+    locked.peers.remove(&conn);
+    locked.deferred.retain(|event| match *event {
+        SyncEvent::State { from, .. } => from != conn,
+        _ => true,
+    });
+    locked.done_collecting(conn, &client);
+    locked.events.push_back(SyncEvent::Closed(conn));
+    client.schedule_idle(SYNC_IDLE_TOKEN);
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+    use std::time::Instant;
+    use xi_rope::Rope;
+    use xi_rope::delta::Delta;
+    use xi_rope::interval::Interval;
+    use xi_rpc::test_utils::DummyPeer;
+
+    fn client() -> Client {
+        Client::new(Box::new(DummyPeer))
+    }
+
+    /// Waits for the next event.
+    fn next_event(sync: &SocketSync, pending: &mut VecDeque<SyncEvent>) -> SyncEvent {
+        let start = Instant::now();
+        while pending.is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(5), "no sync event");
+            pending.extend(sync.take_events());
+            thread::sleep(Duration::from_millis(5));
+        }
+        pending.pop_front().unwrap()
+    }
+
+    fn insert(engine: &mut Engine, offset: usize, text: &str, undo_group: usize) {
+        let head = engine.get_head_rev_id().token();
+        let len = engine.get_head().len();
+        let delta = Delta::simple_edit(Interval::new_closed_open(offset, offset),
+                                       Rope::from(text), len);
+        engine.edit_rev(1, undo_group, head, delta);
+    }
+
+    /// Starts a shared buffer with one guest, returning the host's and
+    /// the guest's engines, with the host's text.
+    fn welcome_guest(host: &SocketSync, guest: &SocketSync, host_text: &str)
+        -> (Engine, Engine)
+    {
+        let mut host_engine = Engine::empty();
+        host_engine.set_session_id((1, 0));
+        insert(&mut host_engine, 0, host_text, 0);
+        match next_event(host, &mut VecDeque::new()) {
+            SyncEvent::Joined(1) => host.welcome(1, &host_engine, &[((1, 0), vec![(0, 0)])]),
+            other => panic!("unexpected event {:?}", other),
+        }
+        let mut guest_engine = Engine::empty();
+        guest_engine.set_session_id((2, 1));
+        match next_event(guest, &mut VecDeque::new()) {
+            SyncEvent::Welcome { slot: 1, revisions, selections } => {
+                guest.received(HOST_CONNECTION, &revisions, None, BTreeSet::new());
+                guest_engine.merge_revisions(&revisions).unwrap();
+                assert_eq!(selections, vec![((1, 0), vec![(0, 0)])]);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert_eq!(String::from(guest_engine.get_head()), host_text);
+        (host_engine, guest_engine)
+    }
+
+    /// Merges the state a session sent into `engine`.
+    fn merge_state(sync: &SocketSync, engine: &mut Engine) -> Vec<SessionSelection> {
+        match next_event(sync, &mut VecDeque::new()) {
+            SyncEvent::State { from, revisions, head, collectible, selections } => {
+                sync.received(from, &revisions, Some(head), collectible);
+                engine.merge_revisions(&revisions).unwrap();
+                selections
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tcp_round_trip() {
+        let host = SocketSync::host("127.0.0.1:0", false, client()).unwrap();
+        let guest = SocketSync::join(host.address(), host.token(), client()).unwrap();
+        let (mut host_engine, mut guest_engine) = welcome_guest(&host, &guest, "hello");
+
+        insert(&mut guest_engine, 5, " world", 1);
+        guest.store().state_changed(&guest_engine, &[((2, 1), vec![(11, 11)])]);
+        let selections = merge_state(&host, &mut host_engine);
+        assert_eq!(selections, vec![((2, 1), vec![(11, 11)])]);
+        assert_eq!(String::from(host_engine.get_head()), "hello world");
+
+        // only what the guest doesn't have is sent back
+        insert(&mut host_engine, 0, "> ", 64);
+        host.store().state_changed(&host_engine, &[]);
+        match next_event(&guest, &mut VecDeque::new()) {
+            SyncEvent::State { from: 0, revisions, .. } => {
+                assert_eq!(revisions.versions(), host_engine.versions());
+                guest_engine.merge_revisions(&revisions).unwrap();
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert_eq!(String::from(guest_engine.get_head()), "> hello world");
+
+        drop(host);
+        match next_event(&guest, &mut VecDeque::new()) {
+            SyncEvent::Closed(0) => (),
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn wrong_token() {
+        let host = SocketSync::host("127.0.0.1:0", false, client()).unwrap();
+        let guest = SocketSync::join(host.address(), "wrong", client()).unwrap();
+        match next_event(&guest, &mut VecDeque::new()) {
+            SyncEvent::Closed(0) => (),
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(host.take_events().is_empty());
+    }
+
+    #[test]
+    fn remote_address() {
+        assert!(SocketSync::host("0.0.0.0:0", false, client()).is_err());
+        assert!(SocketSync::host("0.0.0.0:0", true, client()).is_ok());
+    }
+
+    #[test]
+    fn collect_in_lockstep() {
+        let host = SocketSync::host("127.0.0.1:0", false, client()).unwrap();
+        let guest = SocketSync::join(host.address(), host.token(), client()).unwrap();
+        let (mut host_engine, mut guest_engine) = welcome_guest(&host, &guest, "a");
+        let mut host_store = host.store();
+        let mut guest_store = guest.store();
+        insert(&mut host_engine, 1, "b", 64);
+
+        insert(&mut guest_engine, 1, "c", 1);
+        insert(&mut guest_engine, 2, "d", 65);
+        guest_store.collectible(&[1].iter().cloned().collect());
+        guest_store.state_changed(&guest_engine, &[]);
+        merge_state(&host, &mut host_engine);
+        host_store.collectible(&[0].iter().cloned().collect());
+        host_store.state_changed(&host_engine, &[]);
+        merge_state(&guest, &mut guest_engine);
+        // which the guest acknowledges
+        guest_store.state_changed(&guest_engine, &[]);
+        merge_state(&host, &mut host_engine);
+        assert_eq!(String::from(host_engine.get_head()), "abcd");
+
+        // the newest edit of each session is kept
+        assert_eq!(host.start_collecting(&host_engine), None);
+        let groups = match next_event(&guest, &mut VecDeque::new()) {
+            SyncEvent::Collect(groups) => groups,
+            other => panic!("unexpected event {:?}", other),
+        };
+        assert_eq!(groups, [0, 1].iter().cloned().collect());
+        guest_engine.gc(&groups);
+        guest_store.collected(&guest_engine, &[]);
+
+        // nothing is sent until the host has collected too
+        insert(&mut host_engine, 4, "e", 128);
+        host_store.state_changed(&host_engine, &[]);
+        match next_event(&host, &mut VecDeque::new()) {
+            SyncEvent::Collect(ref collect) if *collect == groups => (),
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert_eq!(host.start_collecting(&host_engine), None);
+        host_engine.gc(&groups);
+        host_store.collected(&host_engine, &[]);
+        merge_state(&guest, &mut guest_engine);
+        assert_eq!(String::from(guest_engine.get_head()), "abcde");
+
+        insert(&mut guest_engine, 0, "f", 129);
+        guest_store.state_changed(&guest_engine, &[]);
+        merge_state(&host, &mut host_engine);
+        assert_eq!(String::from(host_engine.get_head()), "fabcde");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket() {
+        let dir = tempdir::TempDir::new("xi-sync").unwrap();
+        let address = format!("unix:{}", dir.path().join("sock").display());
+        let host = SocketSync::host(&address, false, client()).unwrap();
+        assert_eq!(host.address(), address);
+        let mode = fs::metadata(dir.path().join("sock")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let guest = SocketSync::join(&address, host.token(), client()).unwrap();
+        match next_event(&host, &mut VecDeque::new()) {
+            SyncEvent::Joined(1) => (),
+            other => panic!("unexpected event {:?}", other),
+        }
+        drop(guest);
+        match next_event(&host, &mut VecDeque::new()) {
+            SyncEvent::Closed(1) => (),
+            other => panic!("unexpected event {:?}", other),
+        }
+        drop(host);
+        assert!(!dir.path().join("sock").exists());
+    }
+}
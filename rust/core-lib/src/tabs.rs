@@ -19,13 +19,14 @@
 //! This file is called 'tabs' for historical reasons, and should probably
 //! be renamed.
 
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::cell::{Cell, RefCell};
 use std::fmt;
-use std::fs::File;
-use std::io;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
 use std::mem;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use serde::de::{Deserialize, Deserializer};
 use serde::ser::{Serialize, Serializer};
@@ -33,19 +34,24 @@ use serde_json::Value;
 
 use xi_rpc::{self, RpcPeer, RpcCtx, RemoteError};
 use xi_rope::Rope;
+use xi_rope::spans::Spans;
 use xi_trace::{self, trace_block};
 
 use WeakXiCore;
 use client::Client;
-use config::{self, ConfigManager, ConfigDomain, ConfigDomainExternal, Table};
+use config::{self, BufferItems, ConfigManager, ConfigDomain, ConfigDomainExternal, Table};
+use diff::{self, DiffId, DiffSession, DiffSide, SideBySide};
 use editor::Editor;
 use event_context::EventContext;
-use file::FileManager;
+use file::{self, Backup, CharacterEncoding, FileLoader, FileManager, LineEnding};
 use plugins::{PluginCatalog, PluginPid, Plugin, start_plugin_process};
 use plugin_rpc::{PluginNotification, PluginRequest};
+use project_search::{ProjectSearch, SearchHandle, SearchId};
 use rpc::{CoreNotification, CoreRequest, EditNotification, EditRequest,
           PluginNotification as CorePluginNotification};
-use styles::ThemeStyleMap;
+use styles::{Style, ThemeStyleMap};
+use sync;
+use sync::socket::{HOST_CONNECTION, SocketSync, SyncEvent};
 use syntax::SyntaxDefinition;
 use view::View;
 use width_cache::WidthCache;
@@ -76,11 +82,30 @@ pub type ViewIdentifier = ViewId;
 /// Totally arbitrary; we reserve this space for `ViewId`s
 pub(crate) const RENDER_VIEW_IDLE_MASK: usize = 1 << 25;
 
+/// xi_rpc timer Token mask for giving up on format requests, combined
+/// with a `ViewId` like `RENDER_VIEW_IDLE_MASK`.
+pub(crate) const FORMAT_TIMEOUT_MASK: usize = 1 << 26;
+
 const NEW_VIEW_IDLE_TOKEN: usize = 1001;
 
 /// xi_rpc idle Token for watcher related idle scheduling.
 pub(crate) const WATCH_IDLE_TOKEN: usize = 1002;
 
+/// xi_rpc idle Token for handling messages on shared buffers.
+pub(crate) const SYNC_IDLE_TOKEN: usize = 1004;
+
+/// xi_rpc idle Token for continuing to load large files.
+const FILE_LOAD_IDLE_TOKEN: usize = 1005;
+
+/// The number of bytes of a large file that are loaded per idle slice.
+const FILE_LOAD_BYTES_PER_IDLE: usize = 4 * 1024 * 1024;
+
+/// xi_rpc timer Token for writing swap files.
+const SWAP_TIMER_TOKEN: usize = 1006;
+
+/// xi_rpc idle Token for updating side-by-side diffs after edits.
+const DIFF_IDLE_TOKEN: usize = 1007;
+
 #[cfg(feature = "notify")]
 const CONFIG_EVENT_TOKEN: WatchToken = WatchToken(1);
 
@@ -100,6 +125,9 @@ pub struct CoreState {
     width_cache: RefCell<WidthCache>,
     /// User and platform specific settings
     config_manager: ConfigManager,
+    /// The directory for state kept between sessions, such as undo
+    /// histories, if the client gave one.
+    cache_dir: Option<PathBuf>,
     /// A weak reference to the main state container, stashed so that
     /// it can be passed to plugins.
     self_ref: Option<WeakXiCore>,
@@ -110,12 +138,28 @@ pub struct CoreState {
     plugins: PluginCatalog,
     // for the time being we auto-start all plugins we find on launch.
     running_plugins: Vec<Plugin>,
+    /// Project searches that are still running.
+    project_searches: BTreeMap<SearchId, SearchHandle>,
+    /// Buffers shared with other sessions, as host or as guest.
+    shared_buffers: BTreeMap<BufferId, SocketSync>,
+    /// Large files that are still being loaded, and their buffers.
+    file_loads: Vec<(BufferId, FileLoader)>,
+    /// The swap files written for dirty buffers, with the revision each
+    /// one holds.
+    swap_files: HashMap<BufferId, (PathBuf, u64)>,
+    /// Swap files left over from an earlier session, which the client
+    /// has not yet recovered or discarded.
+    recoverable_swaps: HashMap<BufferId, PathBuf>,
+    /// Whether a timer to write swap files is scheduled.
+    swap_timer_pending: bool,
+    /// Side-by-side diffs between buffers, or a buffer and a file.
+    diff_sessions: BTreeMap<DiffId, DiffSession>,
 }
 
 /// Initial setup and bookkeeping
 impl CoreState {
     pub(crate) fn new(peer: &RpcPeer, config_dir: Option<PathBuf>,
-                      extras_dir: Option<PathBuf>) -> Self
+                      extras_dir: Option<PathBuf>, cache_dir: Option<PathBuf>) -> Self
     {
         #[cfg(feature = "notify")]
         let mut watcher = FileWatcher::new(peer.clone());
@@ -148,12 +192,20 @@ impl CoreState {
             style_map: RefCell::new(ThemeStyleMap::new()),
             width_cache: RefCell::new(WidthCache::new()),
             config_manager,
+            cache_dir,
             self_ref: None,
             pending_views: Vec::new(),
             peer: Client::new(peer.clone()),
             id_counter: Counter::default(),
             plugins: PluginCatalog::new(&[]),
             running_plugins: Vec::new(),
+            project_searches: BTreeMap::new(),
+            shared_buffers: BTreeMap::new(),
+            file_loads: Vec::new(),
+            swap_files: HashMap::new(),
+            recoverable_swaps: HashMap::new(),
+            swap_timer_pending: false,
+            diff_sessions: BTreeMap::new(),
         }
     }
 
@@ -169,6 +221,14 @@ impl CoreState {
         PluginPid(self.id_counter.next())
     }
 
+    fn next_search_id(&self) -> SearchId {
+        SearchId(self.id_counter.next())
+    }
+
+    fn next_diff_id(&self) -> DiffId {
+        DiffId(self.id_counter.next())
+    }
+
     pub(crate) fn finish_setup(&mut self, self_ref: WeakXiCore) {
         self.self_ref = Some(self_ref);
 
@@ -226,7 +286,11 @@ impl CoreState {
 
             let editor = self.editors.get(&buffer_id).unwrap();
             let info = self.file_manager.get_info(buffer_id);
-            let plugins = self.running_plugins.iter().collect::<Vec<_>>();
+            let plugins = if editor.borrow().is_large_file() {
+                Vec::new()
+            } else {
+                self.running_plugins.iter().collect::<Vec<_>>()
+            };
 
             EventContext {
                 view,
@@ -260,8 +324,8 @@ impl CoreState {
         match cmd {
             Edit(::rpc::EditCommand { view_id, cmd }) =>
                 self.do_edit(view_id, cmd),
-            Save { view_id, file_path } =>
-                self.do_save(view_id, file_path),
+            Save { view_id, file_path, encoding } =>
+                self.do_save(view_id, file_path, encoding),
             CloseView { view_id } =>
                 self.do_close_view(view_id),
             ModifyUserConfig { domain, changes } =>
@@ -281,6 +345,22 @@ impl CoreState {
                 }
             TracingConfig { enabled } =>
                 self.toggle_tracing(enabled),
+            CancelSearch { search_id } =>
+                if let Some(search) = self.project_searches.remove(&search_id) {
+                    search.cancel();
+                },
+            StopSharing { view_id } =>
+                self.do_stop_sharing(view_id),
+            SetLineEnding { view_id, line_ending } =>
+                self.do_set_line_ending(view_id, line_ending),
+            RecoverSwapFile { view_id } =>
+                self.do_resolve_swap_file(view_id, true),
+            DiscardSwapFile { view_id } =>
+                self.do_resolve_swap_file(view_id, false),
+            SetDiffBase { view_id, base } =>
+                self.do_set_diff_base(view_id, base),
+            CloseDiff { diff_id } =>
+                self.do_close_diff(diff_id),
             // handled at the top level
             ClientStarted { .. } => (),
         }
@@ -300,6 +380,18 @@ impl CoreState {
             //TODO: why is this a request?? make a notification?
             GetConfig { view_id } =>
                 self.do_get_config(view_id).map(|c| json!(c)),
+            SearchProject { root, chars, case_sensitive, regex, whole_words,
+                            ignore_accents, excludes } =>
+                self.do_search_project(root, &chars, case_sensitive, regex,
+                                       whole_words, ignore_accents, &excludes),
+            ShareBuffer { view_id, address, allow_remote } =>
+                self.do_share_buffer(view_id, &address, allow_remote),
+            JoinSharedBuffer { address, token } =>
+                self.do_join_shared_buffer(&address, &token),
+            ReopenWithEncoding { view_id, encoding } =>
+                self.do_reopen_with_encoding(view_id, &encoding),
+            DiffBuffers { view_id, other_view_id, path } =>
+                self.do_diff_buffers(view_id, other_view_id, path),
         }
     }
 
@@ -307,12 +399,17 @@ impl CoreState {
         if let Some(mut edit_ctx) = self.make_context(view_id) {
             edit_ctx.do_edit(cmd);
         }
+        self.schedule_swap_write(view_id);
+        self.schedule_diff_update(view_id);
     }
 
     fn do_edit_sync(&mut self, view_id: ViewId,
                     cmd: EditRequest) -> Result<Value, RemoteError> {
         if let Some(mut edit_ctx) = self.make_context(view_id) {
-            edit_ctx.do_edit_sync(cmd)
+            let result = edit_ctx.do_edit_sync(cmd);
+            self.schedule_swap_write(view_id);
+            self.schedule_diff_update(view_id);
+            result
         } else {
             // TODO: some custom error tpye that can Into<RemoteError>
             Err(RemoteError::custom(404,
@@ -324,25 +421,34 @@ impl CoreState {
     fn do_new_view(&mut self, path: Option<PathBuf>)
         -> Result<Value, RemoteError>
     {
-        let view_id = self.next_view_id();
-        let buffer_id = self.next_buffer_id();
+        self.new_view(path).map(|view_id| json!(view_id))
+    }
 
-        let editor = match path {
-            Some(path) => self.new_with_file(&path, buffer_id)?,
-            None => self.new_empty_buffer(),
-        };
+    fn new_view(&mut self, path: Option<PathBuf>)
+        -> Result<ViewId, RemoteError>
+    {
+        let view_id = self.next_view_id();
+        // a file that is still loading is read once, into the same buffer
+        let loading = path.as_ref().and_then(|path| self.file_loads.iter()
+            .find(|&&(_, ref loader)| loader.path() == path.as_path())
+            .map(|&(buffer_id, _)| buffer_id));
+        let buffer_id = loading.unwrap_or_else(|| self.next_buffer_id());
+
+        if loading.is_none() {
+            let editor = match path {
+                Some(path) => self.new_with_file(&path, buffer_id)?,
+                None => self.new_empty_buffer(),
+            };
+            self.editors.insert(buffer_id, RefCell::new(editor));
+        }
 
         let mut view = View::new(view_id, buffer_id);
-
-        let wrap_width = editor.get_config().items.wrap_width;
-        view.rewrap(editor.get_buffer(), wrap_width);
-        view.set_dirty(editor.get_buffer());
-
-        let editor = RefCell::new(editor);
-        let view = RefCell::new(view);
-
-        self.editors.insert(buffer_id, editor);
-        self.views.insert(view_id, view);
+        {
+            let editor = self.editors[&buffer_id].borrow();
+            view.rewrap(editor.get_buffer(), editor.wrap_width());
+            view.set_dirty(editor.get_buffer());
+        }
+        self.views.insert(view_id, RefCell::new(view));
         //NOTE: because this is a synchronous call, we have to return the
         //view_id before we can send any events to this view. We use mark the
         // viewa s pending and schedule the idle handler so that we can finish
@@ -350,7 +456,7 @@ impl CoreState {
         self.pending_views.push(view_id);
         self.peer.schedule_idle(NEW_VIEW_IDLE_TOKEN);
 
-        Ok(json!(view_id))
+        Ok(view_id)
     }
 
     fn new_empty_buffer(&mut self) -> Editor {
@@ -361,14 +467,141 @@ impl CoreState {
     fn new_with_file(&mut self, path: &Path, buffer_id: BufferId)
         -> Result<Editor, RemoteError>
     {
+        let syntax = SyntaxDefinition::new(path.to_str());
+        let threshold = self.config_manager.get_buffer_config(syntax, buffer_id)
+            .items.large_file_threshold;
+        let len = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        if threshold > 0 && len > threshold {
+            return self.start_loading(path, buffer_id);
+        }
+
         let rope = self.file_manager.open(path, buffer_id)?;
+        // new lines should match the ones already in the file
+        let line_ending = self.file_manager.get_info(buffer_id)
+            .and_then(|info| info.line_ending);
+        if let Some(line_ending) = line_ending {
+            self.set_buffer_line_ending(buffer_id, line_ending);
+        }
         let syntax = SyntaxDefinition::new(path.to_str());
         let config = self.config_manager.get_buffer_config(syntax, buffer_id);
-        let editor = Editor::with_text(rope, config);
+        let mut editor = Editor::with_text(rope, config);
+        if editor.get_config().items.persist_undo_history {
+            self.restore_undo_history(path, &mut editor);
+        }
         Ok(editor)
     }
 
-    fn do_save<P>(&mut self, view_id: ViewId, path: P)
+    /// Starts loading a large file in idle slices, returning the editor it
+    /// will be loaded into, which is empty until then.
+    fn start_loading(&mut self, path: &Path, buffer_id: BufferId)
+        -> Result<Editor, RemoteError>
+    {
+        let loader = self.file_manager.start_loading(path)?;
+        let config = self.config_manager.default_buffer_config();
+        let mut editor = Editor::with_large_text("", config);
+        editor.set_loading(true);
+        self.file_loads.push((buffer_id, loader));
+        self.peer.schedule_idle(FILE_LOAD_IDLE_TOKEN);
+        Ok(editor)
+    }
+
+    fn continue_file_loads(&mut self) {
+        let _t = trace_block("CoreState::continue_file_loads", &["core"]);
+        let loads = mem::replace(&mut self.file_loads, Vec::new());
+        for (buffer_id, mut loader) in loads {
+            // the views may have been closed while loading
+            let view_ids = self.views.values()
+                .filter(|v| v.borrow().buffer_id == buffer_id)
+                .map(|v| v.borrow().view_id)
+                .collect::<Vec<_>>();
+            if view_ids.is_empty() {
+                continue;
+            }
+            let result = loader.step(FILE_LOAD_BYTES_PER_IDLE);
+            let (loaded, total) = loader.progress();
+            match result {
+                Ok(done) => {
+                    for &view_id in &view_ids {
+                        self.peer.load_progress(view_id, loaded, total.max(loaded));
+                    }
+                    if done {
+                        self.finish_loading(&view_ids, buffer_id, loader);
+                    } else {
+                        self.file_loads.push((buffer_id, loader));
+                    }
+                }
+                // the buffer stays empty and read-only, so that it can't be
+                // saved over the file
+                Err(e) => self.peer.alert(format!("failed to load {}: {}",
+                                                  loader.path().display(), e)),
+            }
+        }
+        if !self.file_loads.is_empty() {
+            self.peer.schedule_idle(FILE_LOAD_IDLE_TOKEN);
+        }
+    }
+
+    fn finish_loading(&mut self, view_ids: &[ViewId], buffer_id: BufferId, loader: FileLoader) {
+        let path = loader.path().to_owned();
+        let rope = self.file_manager.finish_loading(buffer_id, loader);
+        let line_ending = self.file_manager.get_info(buffer_id)
+            .and_then(|info| info.line_ending);
+        if let Some(line_ending) = line_ending {
+            self.set_buffer_line_ending(buffer_id, line_ending);
+        }
+        let syntax = SyntaxDefinition::new(path.to_str());
+        let config = self.config_manager.get_buffer_config(syntax, buffer_id);
+        *self.editors[&buffer_id].borrow_mut() = Editor::with_large_text(rope, config);
+        for &view_id in view_ids {
+            self.make_context(view_id).unwrap().finish_loading();
+            self.check_swap_file(view_id);
+        }
+    }
+
+    /// Restores the persisted undo history of the file at `path`, if it
+    /// was saved with the file's current contents.
+    fn restore_undo_history(&self, path: &Path, editor: &mut Editor) {
+        let cache_dir = self.cache_dir.as_ref().map(PathBuf::as_path);
+        let history_path = match file::undo_history_path(path, cache_dir) {
+            Some(p) => p,
+            None => return,
+        };
+        let f = match File::open(&history_path) {
+            Ok(f) => f,
+            Err(_) => return,
+        };
+        if let Err(e) = editor.restore_undo_history(BufReader::new(f)) {
+            eprintln!("failed to restore undo history {:?}: {}", history_path, e);
+        }
+    }
+
+    /// Persists the undo history of a buffer, if its config asks for it.
+    fn save_undo_history(&self, buffer_id: BufferId) {
+        let editor = match self.editors.get(&buffer_id) {
+            Some(ed) => ed.borrow(),
+            None => return,
+        };
+        if !editor.get_config().items.persist_undo_history {
+            return;
+        }
+        let cache_dir = self.cache_dir.as_ref().map(PathBuf::as_path);
+        let history_path = self.file_manager.get_info(buffer_id)
+            .and_then(|info| file::undo_history_path(&info.path, cache_dir));
+        let history_path = match history_path {
+            Some(p) => p,
+            None => return,
+        };
+        let result = file::replace_file(&history_path, None, |f| {
+            let mut writer = BufWriter::new(f);
+            editor.save_undo_history(&mut writer).map_err(io::Error::from)?;
+            writer.flush()
+        });
+        if let Err(e) = result {
+            eprintln!("failed to save undo history {:?}: {}", history_path, e);
+        }
+    }
+
+    fn do_save<P>(&mut self, view_id: ViewId, path: P, encoding: Option<String>)
         where P: AsRef<Path>
     {
         let _t = trace_block("CoreState::do_save", &["core"]);
@@ -378,15 +611,41 @@ impl CoreState {
             Some(id) => id,
             None => return,
         };
+        let encoding = match encoding {
+            Some(name) => match CharacterEncoding::from_name(&name) {
+                Some(encoding) => Some(encoding),
+                None => {
+                    self.peer.alert(format!("unknown encoding {:?}", name));
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        if self.editors[&buffer_id].borrow().is_loading() {
+            self.peer.alert(format!("{} has not been loaded", path.display()));
+            return;
+        }
 
         let ed = self.editors.get(&buffer_id).unwrap();
+        let backup = backup_for_config(&ed.borrow().get_config().items);
+        // files that already mixed line endings are left as they are
+        let mixed = self.file_manager.get_info(buffer_id)
+            .map(|info| info.mixed_line_endings)
+            .unwrap_or(false);
+        let line_ending = if mixed {
+            None
+        } else {
+            ed.borrow().get_config().items.line_ending.parse::<LineEnding>().ok()
+        };
 
-        let result = self.file_manager.save(path, ed.borrow().get_buffer(),
-                                            buffer_id);
+        let result = self.file_manager.save(path, ed.borrow().get_buffer(), buffer_id,
+                                            encoding, line_ending, backup.as_ref());
         if let Err(e) = result {
             self.peer.alert(e.to_string());
             return;
         }
+        self.remove_swap_file(buffer_id);
 
         // hacky, syntax defs per-se are going away soon
         let syntax = SyntaxDefinition::new(path.to_str());
@@ -394,6 +653,182 @@ impl CoreState {
 
         let mut event_ctx = self.make_context(view_id).unwrap();
         event_ctx.after_save(path, config);
+        self.save_undo_history(buffer_id);
+    }
+
+    fn do_set_line_ending(&mut self, view_id: ViewId, line_ending: LineEnding) {
+        let buffer_id = match self.views.get(&view_id) {
+            Some(v) => v.borrow().buffer_id,
+            None => return,
+        };
+        self.make_context(view_id).unwrap().convert_line_endings(line_ending);
+        self.set_buffer_line_ending(buffer_id, line_ending);
+        self.after_config_change();
+    }
+
+    fn do_set_diff_base(&mut self, view_id: ViewId, base: Option<String>) {
+        if let Some(mut context) = self.make_context(view_id) {
+            context.set_diff_base(base.map(Rope::from));
+        }
+    }
+
+    fn do_diff_buffers(&mut self, view_id: ViewId, other_view_id: Option<ViewId>,
+                       path: Option<PathBuf>) -> Result<Value, RemoteError> {
+        let buffer_of = |view_id: ViewId| self.views.get(&view_id)
+            .map(|v| v.borrow().buffer_id)
+            .ok_or_else(|| RemoteError::custom(404, format!("missing {}", view_id), None));
+        let left = buffer_of(view_id)?;
+        let right = match (other_view_id, path) {
+            (Some(other), None) => DiffSide::Buffer(buffer_of(other)?),
+            (None, Some(path)) => file::read_file(&path)
+                .map(DiffSide::File)
+                .map_err(|e| RemoteError::custom(5, format!("failed to read {}: {}",
+                                                            path.display(), e), None))?,
+            _ => return Err(RemoteError::custom(
+                    400, "expected one of other_view_id and path", None)),
+        };
+        let buffers = match right {
+            DiffSide::Buffer(right) => vec![left, right],
+            DiffSide::File(_) => vec![left],
+        };
+        if buffers.iter().any(|id| self.editors[id].borrow().is_large_file()) {
+            return Err(RemoteError::custom(400, "large files can't be compared", None));
+        }
+
+        let diff_id = self.next_diff_id();
+        self.diff_sessions.insert(diff_id, DiffSession { left, right, dirty: true });
+        let diff = self.compare_diff(diff_id).unwrap();
+        Ok(json!({
+            "diff_id": diff_id,
+            "hunks": diff.hunks,
+        }))
+    }
+
+    fn do_close_diff(&mut self, diff_id: DiffId) {
+        if let Some(session) = self.diff_sessions.remove(&diff_id) {
+            self.set_diff_styles(diff_id, session.left, None);
+            if let DiffSide::Buffer(right) = session.right {
+                self.set_diff_styles(diff_id, right, None);
+            }
+        }
+    }
+
+    /// Marks the diffs comparing the buffer of `view_id` as outdated, to
+    /// be compared again when idle, along with its diff gutter.
+    fn schedule_diff_update(&mut self, view_id: ViewId) {
+        let buffer_id = match self.views.get(&view_id) {
+            Some(v) => v.borrow().buffer_id,
+            None => return,
+        };
+        let mut any_dirty = self.editors.get(&buffer_id)
+            .map(|ed| ed.borrow().diff_gutter_is_stale())
+            .unwrap_or(false);
+        for session in self.diff_sessions.values_mut() {
+            if session.has_buffer(buffer_id) {
+                session.dirty = true;
+                any_dirty = true;
+            }
+        }
+        if any_dirty {
+            self.peer.schedule_idle(DIFF_IDLE_TOKEN);
+        }
+    }
+
+    fn update_diffs(&mut self) {
+        let _t = trace_block("CoreState::update_diffs", &["core"]);
+        let stale_gutters = self.views.iter()
+            .map(|(view_id, view)| (*view_id, view.borrow().buffer_id))
+            .filter(|&(_, buffer_id)| self.editors[&buffer_id].borrow().diff_gutter_is_stale())
+            .collect::<Vec<_>>();
+        let mut seen = HashSet::new();
+        for (view_id, buffer_id) in stale_gutters {
+            if seen.insert(buffer_id) {
+                self.make_context(view_id).unwrap().compare_diff_gutter();
+            }
+        }
+
+        let dirty = self.diff_sessions.iter()
+            .filter(|&(_, session)| session.dirty)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for diff_id in dirty {
+            if let Some(diff) = self.compare_diff(diff_id) {
+                self.peer.diff_changed(diff_id, &diff.hunks);
+            }
+        }
+    }
+
+    /// Compares the texts of a diff session, and highlights the changes
+    /// in its buffers.
+    fn compare_diff(&mut self, diff_id: DiffId) -> Option<SideBySide> {
+        let (left_id, right_id, left, right) = {
+            let session = self.diff_sessions.get_mut(&diff_id)?;
+            session.dirty = false;
+            let left = self.editors[&session.left].borrow().get_buffer().clone();
+            match session.right {
+                DiffSide::Buffer(id) => {
+                    let right = self.editors[&id].borrow().get_buffer().clone();
+                    (session.left, Some(id), left, right)
+                }
+                DiffSide::File(ref text) => (session.left, None, left, text.clone()),
+            }
+        };
+        let diff = diff::side_by_side(&left, &right);
+        let (left_style, right_style) = {
+            let style_map = self.style_map.borrow();
+            (style_map.diff_style(false), style_map.diff_style(true))
+        };
+        self.set_diff_styles(diff_id, left_id, Some(diff.styles(&left, false, &left_style)));
+        if let Some(right_id) = right_id {
+            self.set_diff_styles(diff_id, right_id,
+                                 Some(diff.styles(&right, true, &right_style)));
+        }
+        Some(diff)
+    }
+
+    fn set_diff_styles(&self, diff_id: DiffId, buffer_id: BufferId,
+                       styles: Option<Spans<Style>>) {
+        let view_id = self.views.values()
+            .find(|v| v.borrow().buffer_id == buffer_id)
+            .map(|v| v.borrow().view_id);
+        match view_id {
+            Some(view_id) => self.make_context(view_id).unwrap()
+                .set_diff_styles(diff_id, styles),
+            // the buffer is being closed
+            None => if let Some(editor) = self.editors.get(&buffer_id) {
+                editor.borrow_mut().get_layers_mut().set_diff_styles(diff_id, styles);
+            },
+        }
+    }
+
+    /// Makes new lines in `buffer_id` end with `line_ending`, and the file
+    /// be saved with it, unless the user overrides the `line_ending`
+    /// setting for the buffer.
+    fn set_buffer_line_ending(&mut self, buffer_id: BufferId, line_ending: LineEnding) {
+        let mut changes = Table::new();
+        changes.insert("line_ending".into(), json!(line_ending.as_str()));
+        self.config_manager.update_user_config(ConfigDomain::SysOverride(buffer_id), changes)
+            .expect("line_ending is a valid setting");
+    }
+
+    fn do_reopen_with_encoding(&mut self, view_id: ViewId, encoding: &str)
+        -> Result<Value, RemoteError>
+    {
+        let encoding = parse_encoding(encoding)?;
+        let buffer_id = self.views.get(&view_id)
+            .map(|v| v.borrow().buffer_id)
+            .ok_or_else(|| RemoteError::custom(404, format!("missing {}", view_id), None))?;
+        if self.editors[&buffer_id].borrow().is_loading() {
+            return Err(RemoteError::custom(400, "the file has not been loaded", None));
+        }
+        let path = self.file_manager.get_info(buffer_id)
+            .map(|info| info.path.clone())
+            .ok_or_else(|| RemoteError::custom(400, "the buffer has no file", None))?;
+        let text = self.file_manager.open_with_encoding(&path, buffer_id, Some(encoding))
+            .map_err(|e| RemoteError::custom(5, format!("failed to reopen file: {}", e), None))?;
+        self.make_context(view_id).unwrap().reload(text);
+        self.schedule_diff_update(view_id);
+        Ok(Value::Null)
     }
 
     fn do_close_view(&mut self, view_id: ViewId) {
@@ -406,8 +841,25 @@ impl CoreState {
 
         if let Some(buffer_id) = buffer_id {
             if close_buffer {
+                let is_pristine = self.editors.get(&buffer_id)
+                    .map(|ed| ed.borrow().is_pristine())
+                    .unwrap_or(false);
+                // unsaved edits would not match the file on disk
+                if is_pristine {
+                    self.save_undo_history(buffer_id);
+                }
+                // a swap file left over from an earlier session that the
+                // client didn't resolve is kept; ours is no longer needed
+                self.remove_swap_file(buffer_id);
+                self.recoverable_swaps.remove(&buffer_id);
+                let diff_ids = self.diff_sessions.iter()
+                    .filter(|&(_, session)| session.has_buffer(buffer_id))
+                    .map(|(id, _)| *id)
+                    .collect::<Vec<_>>();
+                diff_ids.into_iter().for_each(|id| self.do_close_diff(id));
                 self.editors.remove(&buffer_id);
                 self.file_manager.close(buffer_id);
+                self.shared_buffers.remove(&buffer_id);
             }
         }
     }
@@ -461,6 +913,71 @@ impl CoreState {
             .ok_or(RemoteError::custom(404, format!("missing {}", view_id), None))
     }
 
+    fn do_search_project(&mut self, root: PathBuf, chars: &str, case_sensitive: bool,
+                         regex: bool, whole_words: bool, ignore_accents: bool,
+                         excludes: &[String]) -> Result<Value, RemoteError> {
+        if chars.is_empty() {
+            return Err(RemoteError::custom(400, "empty search string", None));
+        }
+        let root = root.canonicalize()
+            .map_err(|e| RemoteError::custom(404, e.to_string(), None))?;
+        let search_id = self.next_search_id();
+        let search = ProjectSearch::new(search_id, root, chars, case_sensitive, regex,
+                                        whole_words, ignore_accents, excludes)
+            .map_err(|e| RemoteError::custom(400, e.to_string(), None))?;
+
+        // paths in a search are canonical, so open files are looked up that way too
+        let open_buffers = self.file_manager.open_files()
+            .filter_map(|(path, id)| {
+                let text = self.editors.get(&id)?.borrow().get_buffer().clone();
+                path.canonicalize().ok().map(|p| (p, text))
+            })
+            .collect::<HashMap<_, _>>();
+        self.project_searches.retain(|_, search| !search.is_finished());
+        self.project_searches.insert(search_id, search.spawn(open_buffers, self.peer.clone()));
+        Ok(json!(search_id))
+    }
+
+    fn do_share_buffer(&mut self, view_id: ViewId, address: &str, allow_remote: bool)
+        -> Result<Value, RemoteError>
+    {
+        let buffer_id = self.views.get(&view_id)
+            .map(|v| v.borrow().buffer_id)
+            .ok_or_else(|| RemoteError::custom(404, format!("missing {}", view_id), None))?;
+        if self.shared_buffers.contains_key(&buffer_id) {
+            return Err(RemoteError::custom(400, "buffer is already shared", None));
+        }
+        let sync = SocketSync::host(address, allow_remote, self.peer.clone())
+            .map_err(|e| RemoteError::custom(5, format!("failed to share buffer: {}", e), None))?;
+        self.editors[&buffer_id].borrow_mut()
+            .start_sync(sync::new_session_id(sync::HOST_SLOT), Box::new(sync.store()));
+        let result = json!({"address": sync.address(), "token": sync.token()});
+        self.shared_buffers.insert(buffer_id, sync);
+        Ok(result)
+    }
+
+    fn do_join_shared_buffer(&mut self, address: &str, token: &str)
+        -> Result<Value, RemoteError>
+    {
+        let sync = SocketSync::join(address, token, self.peer.clone())
+            .map_err(|e| RemoteError::custom(5, format!("failed to join buffer: {}", e), None))?;
+        let view_id = self.new_view(None)?;
+        let buffer_id = self.views[&view_id].borrow().buffer_id;
+        // the buffer is synced once the host's welcome arrives
+        self.shared_buffers.insert(buffer_id, sync);
+        Ok(json!(view_id))
+    }
+
+    fn do_stop_sharing(&mut self, view_id: ViewId) {
+        let buffer_id = match self.views.get(&view_id) {
+            Some(v) => v.borrow().buffer_id,
+            None => return,
+        };
+        if self.shared_buffers.remove(&buffer_id).is_some() {
+            self.make_context(view_id).unwrap().stop_sync();
+        }
+    }
+
     fn do_start_plugin(&mut self, _view_id: ViewId, plugin: &str) {
         if self.running_plugins.iter().any(|p| p.name == plugin) {
             eprintln!("plugin {} already running", plugin);
@@ -496,6 +1013,12 @@ impl CoreState {
         match token {
             NEW_VIEW_IDLE_TOKEN => self.finalize_new_views(),
             WATCH_IDLE_TOKEN => self.handle_fs_events(),
+            SYNC_IDLE_TOKEN => self.handle_sync_events(),
+            FILE_LOAD_IDLE_TOKEN => self.continue_file_loads(),
+            SWAP_TIMER_TOKEN => self.write_swap_files(),
+            DIFF_IDLE_TOKEN => self.update_diffs(),
+            other if (other & FORMAT_TIMEOUT_MASK) != 0 =>
+                self.handle_format_timer(other ^ FORMAT_TIMEOUT_MASK),
             other if (other & RENDER_VIEW_IDLE_MASK) != 0 =>
                 self.handle_render_timer(other ^ RENDER_VIEW_IDLE_MASK),
             other => panic!("unexpected idle token {}", other),
@@ -507,9 +1030,128 @@ impl CoreState {
         to_start.iter().for_each(|id| {
             let mut edit_ctx = self.make_context(*id).unwrap();
             edit_ctx.finish_init();
+            self.check_swap_file(*id);
         });
     }
 
+    /// Offers the client the unsaved changes left in a swap file for the
+    /// file open in `view_id`, if there are any. A file that is loading is
+    /// checked once it is loaded.
+    fn check_swap_file(&mut self, view_id: ViewId) {
+        let buffer_id = self.views[&view_id].borrow().buffer_id;
+        if self.editors[&buffer_id].borrow().is_loading()
+            || self.recoverable_swaps.contains_key(&buffer_id) {
+            return;
+        }
+        let cache_dir = self.cache_dir.as_ref().map(PathBuf::as_path);
+        let swap_path = match self.file_manager.get_info(buffer_id)
+            .and_then(|info| file::swap_file_path(&info.path, cache_dir)) {
+                Some(p) => p,
+                None => return,
+            };
+        if !swap_path.exists() {
+            return;
+        }
+        let unchanged = file::read_swap_file(&swap_path)
+            .map(|text| String::from(text)
+                 == String::from(self.editors[&buffer_id].borrow().get_buffer()))
+            .unwrap_or(false);
+        if unchanged {
+            let _ = fs::remove_file(&swap_path);
+            return;
+        }
+        self.peer.swap_file_found(view_id, &swap_path);
+        self.recoverable_swaps.insert(buffer_id, swap_path);
+    }
+
+    fn do_resolve_swap_file(&mut self, view_id: ViewId, recover: bool) {
+        let buffer_id = match self.views.get(&view_id) {
+            Some(v) => v.borrow().buffer_id,
+            None => return,
+        };
+        let swap_path = match self.recoverable_swaps.remove(&buffer_id) {
+            Some(p) => p,
+            None => return,
+        };
+        if !recover {
+            let _ = fs::remove_file(&swap_path);
+            return;
+        }
+        match file::read_swap_file(&swap_path) {
+            Ok(text) => {
+                self.make_context(view_id).unwrap().recover(text);
+                // the swap file now holds the current revision
+                let rev = self.editors[&buffer_id].borrow().get_head_rev_token();
+                self.swap_files.insert(buffer_id, (swap_path, rev));
+            }
+            Err(e) => self.peer.alert(format!("failed to recover {}: {}",
+                                              swap_path.display(), e)),
+        }
+    }
+
+    /// Schedules swap files to be written, after the interval configured
+    /// for the buffer of `view_id`, if that isn't already scheduled.
+    fn schedule_swap_write(&mut self, view_id: ViewId) {
+        if self.swap_timer_pending {
+            return;
+        }
+        let interval = self.views.get(&view_id)
+            .and_then(|v| self.editors.get(&v.borrow().buffer_id))
+            .map(|ed| ed.borrow().get_config().items.swap_file_interval)
+            .unwrap_or(0);
+        if interval > 0 {
+            let timeout = Instant::now() + Duration::from_secs(interval);
+            self.peer.schedule_timer(timeout, SWAP_TIMER_TOKEN);
+            self.swap_timer_pending = true;
+        }
+    }
+
+    /// Writes the text of every dirty buffer with a file that changed since
+    /// its swap file was last written, and removes the swap files of buffers
+    /// that are no longer dirty.
+    fn write_swap_files(&mut self) {
+        let _t = trace_block("CoreState::write_swap_files", &["core"]);
+        self.swap_timer_pending = false;
+        let cache_dir = self.cache_dir.as_ref().map(PathBuf::as_path);
+        for (buffer_id, editor) in self.editors.iter() {
+            let ed = editor.borrow();
+            // large files are too costly to copy, and an unresolved swap
+            // file must not be overwritten
+            if ed.is_large_file() || ed.get_config().items.swap_file_interval == 0
+                || self.recoverable_swaps.contains_key(buffer_id) {
+                continue;
+            }
+            if ed.is_pristine() {
+                if let Some((swap_path, _)) = self.swap_files.remove(buffer_id) {
+                    let _ = fs::remove_file(swap_path);
+                }
+                continue;
+            }
+            let rev = ed.get_head_rev_token();
+            if self.swap_files.get(buffer_id).map(|&(_, r)| r) == Some(rev) {
+                continue;
+            }
+            // buffers without a file have nothing to recover their swap
+            // files for
+            let swap_path = match self.file_manager.get_info(*buffer_id)
+                .and_then(|info| file::swap_file_path(&info.path, cache_dir)) {
+                Some(p) => p,
+                None => continue,
+            };
+            match file::write_swap_file(&swap_path, ed.get_buffer()) {
+                Ok(()) => { self.swap_files.insert(*buffer_id, (swap_path, rev)); }
+                Err(e) => eprintln!("failed to write swap file {:?}: {}", swap_path, e),
+            }
+        }
+    }
+
+    /// Removes the swap file of a buffer, if one was written.
+    fn remove_swap_file(&mut self, buffer_id: BufferId) {
+        if let Some((swap_path, _)) = self.swap_files.remove(&buffer_id) {
+            let _ = fs::remove_file(swap_path);
+        }
+    }
+
     fn handle_render_timer(&mut self, token: usize) {
         let id: ViewId = token.into();
         if let Some(mut ctx) = self.make_context(id) {
@@ -517,6 +1159,13 @@ impl CoreState {
         }
     }
 
+    fn handle_format_timer(&mut self, token: usize) {
+        let id: ViewId = token.into();
+        if let Some(mut ctx) = self.make_context(id) {
+            ctx.format_timed_out();
+        }
+    }
+
     #[cfg(feature = "notify")]
     fn handle_fs_events(&mut self) {
         let _t = trace_block("CoreState::handle_fs_events", &["core"]);
@@ -543,6 +1192,78 @@ impl CoreState {
     #[cfg(not(feature = "notify"))]
     fn handle_fs_events(&mut self) { }
 
+    /// Handles what was received on the connections of shared buffers.
+    fn handle_sync_events(&mut self) {
+        let mut closed = Vec::new();
+        for (&buffer_id, sync) in &self.shared_buffers {
+            let view_id = match self.views.values()
+                .find(|v| v.borrow().buffer_id == buffer_id)
+                .map(|v| v.borrow().view_id) {
+                    Some(id) => id,
+                    None => continue,
+                };
+            for event in sync.take_events() {
+                match event {
+                    SyncEvent::Joined(conn) => {
+                        let selections = self.make_context(view_id).unwrap().sync_selections();
+                        let editor = self.editors[&buffer_id].borrow();
+                        sync.welcome(conn, editor.get_engine(), &selections);
+                    }
+                    SyncEvent::Welcome { slot, revisions, selections } => {
+                        self.editors[&buffer_id].borrow_mut()
+                            .start_sync(sync::new_session_id(slot), Box::new(sync.store()));
+                        sync.received(HOST_CONNECTION, &revisions, None, BTreeSet::new());
+                        let merged = self.make_context(view_id).unwrap()
+                            .merge_sync_state(&revisions, &selections, true);
+                        if let Err(e) = merged {
+                            self.peer.alert(format!("failed to join buffer: {}", e));
+                            sync.disconnect(HOST_CONNECTION);
+                        }
+                    }
+                    // the host relays what it merges, and knows best where
+                    // each guest's selection is
+                    SyncEvent::State { from, revisions, head, collectible, mut selections } => {
+                        if sync.is_host() {
+                            selections.retain(|&((_, slot), _)| slot as usize == from);
+                        }
+                        sync.received(from, &revisions, Some(head), collectible);
+                        let merged = self.make_context(view_id).unwrap()
+                            .merge_sync_state(&revisions, &selections, !sync.is_host());
+                        // a session that sends what can't be merged is dropped
+                        if let Err(e) = merged {
+                            eprintln!("failed to merge from sync connection {}: {}", from, e);
+                            sync.disconnect(from);
+                        }
+                    }
+                    SyncEvent::Collect(groups) => {
+                        self.editors[&buffer_id].borrow_mut().collect_synced(groups);
+                    }
+                    SyncEvent::Closed(conn) if sync.is_host() => {
+                        let mut ctx = self.make_context(view_id).unwrap();
+                        let session = ctx.view.borrow().remote_sessions()
+                            .find(|&(_, slot)| slot as usize == conn);
+                        if let Some(session) = session {
+                            ctx.remove_remote_selection(session);
+                        }
+                    }
+                    SyncEvent::Closed(_) => {
+                        closed.push((buffer_id, view_id));
+                        self.peer.alert(format!("{} stopped sharing the buffer",
+                                                sync.address()));
+                    }
+                }
+            }
+            let mut editor = self.editors[&buffer_id].borrow_mut();
+            if let Some(groups) = sync.start_collecting(editor.get_engine()) {
+                editor.collect_synced(groups);
+            }
+        }
+        for (buffer_id, view_id) in closed {
+            self.shared_buffers.remove(&buffer_id);
+            self.make_context(view_id).unwrap().stop_sync();
+        }
+    }
+
     /// Handles a file system event related to a currently open file
     #[cfg(feature = "notify")]
     fn handle_open_file_fs_event(&mut self, event: DebouncedEvent) {
@@ -569,16 +1290,26 @@ impl CoreState {
         // determining if a file has been changed by another process.
         // A more robust solution would also hash the file's contents.
 
-        if has_changes && is_pristine {
-            if let Ok(text) = self.file_manager.open(path, buffer_id) {
-                // this is ugly; we don't map buffer_id -> view_id anywhere
-                // but we know we must have a view.
-                let view_id = self.views.values()
-                    .find(|v| v.borrow().buffer_id == buffer_id)
-                    .map(|v| v.borrow().view_id)
-                    .unwrap();
-                self.make_context(view_id).unwrap().reload(text);
+        // large files are too costly to merge; they can't be saved until
+        // they are reloaded
+        let is_large = self.editors[&buffer_id].borrow().is_large_file();
+        if !has_changes || (is_large && !is_pristine) {
+            return;
+        }
+        if let Ok(text) = self.file_manager.open(path, buffer_id) {
+            // this is ugly; we don't map buffer_id -> view_id anywhere
+            // but we know we must have a view.
+            let view_id = self.views.values()
+                .find(|v| v.borrow().buffer_id == buffer_id)
+                .map(|v| v.borrow().view_id)
+                .unwrap();
+            let mut ctx = self.make_context(view_id).unwrap();
+            if is_pristine {
+                ctx.reload(text);
+            } else {
+                ctx.merge_from_disk(text);
             }
+            self.schedule_diff_update(view_id);
         }
     }
 
@@ -647,11 +1378,15 @@ impl CoreState {
                                   plugin: Result<Plugin, io::Error>) {
         match plugin {
             Ok(plugin) => {
+                // plugins aren't run on large files
                 let init_info = self.iter_groups()
+                    .filter(|ctx| !ctx.editor.borrow().is_large_file())
                     .map(|mut ctx| ctx.plugin_info())
                     .collect::<Vec<_>>();
                 plugin.initialize(init_info);
-                self.iter_groups().for_each(|mut cx| cx.plugin_started(&plugin));
+                self.iter_groups()
+                    .filter(|ctx| !ctx.editor.borrow().is_large_file())
+                    .for_each(|mut cx| cx.plugin_started(&plugin));
                 self.running_plugins.push(plugin);
             }
             Err(e) => eprintln!("failed to start plugin {:?}", e),
@@ -667,12 +1402,24 @@ impl CoreState {
         }
     }
 
+    /// Handles a plugin's answer to a request to format a buffer.
+    pub(crate) fn plugin_format(&mut self, view_id: ViewId, id: usize,
+                                 response: Result<Value, xi_rpc::Error>) {
+        if let Some(mut edit_ctx) = self.make_context(view_id) {
+            edit_ctx.do_plugin_format(id, response);
+        }
+        self.schedule_swap_write(view_id);
+        self.schedule_diff_update(view_id);
+    }
+
     pub(crate) fn plugin_notification(&mut self, _ctx: &RpcCtx,
                                        view_id: ViewId, plugin_id: PluginId,
                                        cmd: PluginNotification) {
         if let Some(mut edit_ctx) = self.make_context(view_id) {
             edit_ctx.do_plugin_cmd(plugin_id, cmd)
         }
+        self.schedule_swap_write(view_id);
+        self.schedule_diff_update(view_id);
     }
 
     pub(crate) fn plugin_request(&mut self, _ctx: &RpcCtx, view_id: ViewId,
@@ -687,6 +1434,21 @@ impl CoreState {
     }
 }
 
+/// Parses the name of a file encoding sent by the client.
+fn parse_encoding(name: &str) -> Result<CharacterEncoding, RemoteError> {
+    CharacterEncoding::from_name(name).ok_or_else(|| RemoteError::custom(
+        400, format!("unknown encoding {:?}", name), None))
+}
+
+/// Returns where backups are kept when saving a buffer with this config.
+fn backup_for_config(items: &BufferItems) -> Option<Backup> {
+    match (items.backup_files, items.backup_directory.as_str()) {
+        (false, _) => None,
+        (true, "") => Some(Backup::Adjacent),
+        (true, dir) => Some(Backup::Directory(PathBuf::from(dir))),
+    }
+}
+
 /// test helpers
 impl CoreState {
     pub fn _test_open_editors(&self) -> Vec<BufferId> {
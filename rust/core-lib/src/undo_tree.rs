@@ -0,0 +1,323 @@
+// Copyright 2018 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The undo tree, which keeps undone edits around after new edits are made.
+//!
+//! Each state in the tree corresponds to an undo group. The text of a state
+//! is obtained by applying the groups of the state and its ancestors, with
+//! every other group undone; the CRDT engine is able to compute this for any
+//! set of undone groups, so moving between states is a single undo.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A state in the undo tree.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UndoState {
+    /// The undo group of this state.
+    pub id: usize,
+    pub parent: Option<usize>,
+    /// When this state was last edited, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The child most recently created or visited; this is where redo goes.
+    last_child: Option<usize>,
+}
+
+/// An amount of history to move through, for stepping earlier or later
+/// in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeStep {
+    /// A number of states, in the order they were created.
+    Count(usize),
+    /// A number of seconds, going by the timestamps of the states.
+    Seconds(u64),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoTree {
+    states: BTreeMap<usize, UndoState>,
+    current: usize,
+}
+
+impl UndoTree {
+    /// Creates a new tree whose only state is `root`.
+    pub fn new(root: usize) -> Self {
+        let mut states = BTreeMap::new();
+        states.insert(root, UndoState::new(root, None));
+        UndoTree { states, current: root }
+    }
+
+    /// Returns the id of the current state.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn get(&self, id: usize) -> Option<&UndoState> {
+        self.states.get(&id)
+    }
+
+    /// Returns all states, in the order they were created.
+    pub fn states<'a>(&'a self) -> impl Iterator<Item=&'a UndoState> + 'a {
+        self.states.values()
+    }
+
+    /// Adds a new state as a child of the current one, and makes it current.
+    pub fn push(&mut self, id: usize) {
+        let parent = self.current;
+        self.states.get_mut(&parent).unwrap().last_child = Some(id);
+        self.states.insert(id, UndoState::new(id, Some(parent)));
+        self.current = id;
+    }
+
+    /// Records that the current state was edited.
+    pub fn touch(&mut self) {
+        self.states.get_mut(&self.current).unwrap().timestamp = now();
+    }
+
+    /// Moves to the parent of the current state, returning the group
+    /// that needs to be undone.
+    pub fn undo(&mut self) -> Option<usize> {
+        let undone = self.current;
+        self.states[&undone].parent.map(|parent| {
+            self.current = parent;
+            undone
+        })
+    }
+
+    /// Moves to the child of the current state that was last visited,
+    /// returning the group that needs to be redone.
+    pub fn redo(&mut self) -> Option<usize> {
+        let child = self.states[&self.current].last_child;
+        if let Some(child) = child {
+            self.current = child;
+        }
+        child
+    }
+
+    /// Makes `id` the current state. Redo from any of its ancestors will
+    /// subsequently lead back to it. Returns `false` if there is no such
+    /// state.
+    pub fn jump(&mut self, id: usize) -> bool {
+        if !self.states.contains_key(&id) {
+            return false;
+        }
+        let mut child = id;
+        while let Some(parent) = self.states[&child].parent {
+            self.states.get_mut(&parent).unwrap().last_child = Some(child);
+            child = parent;
+        }
+        self.current = id;
+        true
+    }
+
+    /// Returns the groups that are undone in the current state: those of
+    /// every state that is not the current state or one of its ancestors.
+    pub fn undone_groups(&self) -> BTreeSet<usize> {
+        let applied = self.path(self.current);
+        self.states.keys().filter(|id| !applied.contains(id)).cloned().collect()
+    }
+
+    /// Returns the state `step` earlier than the current one. States
+    /// are ordered by creation, regardless of the branch they are on.
+    pub fn earlier(&self, step: TimeStep) -> usize {
+        let mut before = self.states.range(..self.current).rev();
+        let target = match step {
+            TimeStep::Count(n) => before.take(n).last(),
+            TimeStep::Seconds(secs) => {
+                let limit = self.states[&self.current].timestamp
+                    .saturating_sub(secs * 1000);
+                before.find(|&(_, state)| state.timestamp <= limit)
+                    .or_else(|| self.states.iter().next())
+            }
+        };
+        target.map(|(id, _)| *id).unwrap_or(self.current)
+    }
+
+    /// Returns the state `step` later than the current one. States are
+    /// ordered by creation, regardless of the branch they are on.
+    pub fn later(&self, step: TimeStep) -> usize {
+        let mut after = self.states.range(self.current + 1..);
+        let target = match step {
+            TimeStep::Count(n) => after.take(n).last(),
+            TimeStep::Seconds(secs) => {
+                let limit = self.states[&self.current].timestamp
+                    .saturating_add(secs * 1000);
+                after.find(|&(_, state)| state.timestamp >= limit)
+                    .or_else(|| self.states.iter().next_back())
+            }
+        };
+        target.map(|(id, _)| *id).unwrap_or(self.current)
+    }
+
+    /// Removes states so that the current state has at most `max_depth`
+    /// ancestors and there are at most `max_states` states in total,
+    /// returning the groups of the removed states.
+    ///
+    /// The root is removed while the current state is too deep, along with
+    /// every branch that doesn't lead to the current state; after that the
+    /// oldest leaves are removed. The groups of removed states should be
+    /// gc'ed: those of former ancestors are applied, all others are undone.
+    pub fn prune(&mut self, max_depth: usize, max_states: usize) -> Vec<usize> {
+        let mut removed = Vec::new();
+        let path = self.path(self.current);
+        if path.len() > max_depth + 1 {
+            let new_root = path[path.len() - max_depth - 1];
+            let keep = self.descendants(new_root);
+            removed.extend(self.states.keys().filter(|id| !keep.contains(id)));
+            for id in &removed {
+                self.states.remove(id);
+            }
+            self.states.get_mut(&new_root).unwrap().parent = None;
+        }
+
+        while self.states.len() > max_states {
+            let leaf = self.states.values()
+                .find(|state| state.id != self.current && state.last_child.is_none())
+                .map(|state| state.id);
+            let leaf = match leaf {
+                Some(leaf) => leaf,
+                None => break,
+            };
+            let parent = self.states.remove(&leaf).unwrap().parent.unwrap();
+            removed.push(leaf);
+            // redo now leads to the most recent remaining child, if any
+            let last_child = self.states.values().rev()
+                .find(|state| state.parent == Some(parent))
+                .map(|state| state.id);
+            self.states.get_mut(&parent).unwrap().last_child = last_child;
+        }
+        removed
+    }
+
+    /// Returns `id` and its ancestors, starting from the root.
+    fn path(&self, id: usize) -> Vec<usize> {
+        let mut path = vec![id];
+        while let Some(parent) = self.states[path.last().unwrap()].parent {
+            path.push(parent);
+        }
+        path.reverse();
+        path
+    }
+
+    /// Returns `id` and all the states below it.
+    fn descendants(&self, id: usize) -> BTreeSet<usize> {
+        let mut result = BTreeSet::new();
+        result.insert(id);
+        // children are always created after their parents
+        for state in self.states.range(id + 1..).map(|(_, state)| state) {
+            if state.parent.map(|p| result.contains(&p)).unwrap_or(false) {
+                result.insert(state.id);
+            }
+        }
+        result
+    }
+}
+
+impl UndoState {
+    fn new(id: usize, parent: Option<usize>) -> Self {
+        UndoState { id, parent, timestamp: now(), last_child: None }
+    }
+}
+
+fn now() -> u64 {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(ids: &[usize]) -> BTreeSet<usize> {
+        ids.iter().cloned().collect()
+    }
+
+    #[test]
+    fn branches() {
+        let mut tree = UndoTree::new(0);
+        tree.push(1);
+        tree.push(2);
+        assert_eq!(tree.undo(), Some(2));
+        tree.push(3);
+        assert_eq!(tree.undone_groups(), set(&[2]));
+        assert_eq!(tree.undo(), Some(3));
+        assert_eq!(tree.redo(), Some(3));
+        assert_eq!(tree.redo(), None);
+
+        assert!(tree.jump(2));
+        assert_eq!(tree.undone_groups(), set(&[3]));
+        assert_eq!(tree.undo(), Some(2));
+        assert_eq!(tree.undo(), Some(1));
+        assert_eq!(tree.undo(), None);
+        assert_eq!(tree.redo(), Some(1));
+        assert_eq!(tree.redo(), Some(2));
+        assert!(!tree.jump(7));
+    }
+
+    #[test]
+    fn earlier_later() {
+        let mut tree = UndoTree::new(0);
+        tree.push(1);
+        tree.push(2);
+        tree.undo();
+        tree.push(3);
+        assert_eq!(tree.earlier(TimeStep::Count(1)), 2);
+        assert_eq!(tree.earlier(TimeStep::Count(2)), 1);
+        assert_eq!(tree.earlier(TimeStep::Count(10)), 0);
+        assert_eq!(tree.later(TimeStep::Count(1)), 3);
+
+        tree.jump(1);
+        assert_eq!(tree.later(TimeStep::Count(1)), 2);
+        assert_eq!(tree.later(TimeStep::Count(2)), 3);
+
+        for (id, timestamp) in vec![(0, 0), (1, 10_000), (2, 20_000), (3, 30_000)] {
+            tree.states.get_mut(&id).unwrap().timestamp = timestamp;
+        }
+        tree.jump(3);
+        assert_eq!(tree.earlier(TimeStep::Seconds(5)), 2);
+        assert_eq!(tree.earlier(TimeStep::Seconds(20)), 1);
+        assert_eq!(tree.earlier(TimeStep::Seconds(100)), 0);
+        tree.jump(0);
+        assert_eq!(tree.later(TimeStep::Seconds(15)), 2);
+        assert_eq!(tree.later(TimeStep::Seconds(100)), 3);
+    }
+
+    #[test]
+    fn prune() {
+        let mut tree = UndoTree::new(0);
+        tree.push(1);
+        tree.push(2);
+        tree.undo();
+        tree.undo();
+        tree.push(3);
+        tree.push(4);
+        assert_eq!(tree.prune(2, 10), Vec::<usize>::new());
+        tree.push(5);
+        // 0 is the root, and 1 and 2 are on another branch
+        assert_eq!(tree.prune(2, 10), vec![0, 1, 2]);
+        assert_eq!(tree.undone_groups(), set(&[]));
+        assert_eq!(tree.get(3).unwrap().parent, None);
+
+        tree.undo();
+        tree.push(6);
+        tree.undo();
+        tree.push(7);
+        assert_eq!(tree.prune(2, 4), vec![5]);
+        assert_eq!(tree.undo(), Some(7));
+        assert_eq!(tree.redo(), Some(7));
+        assert_eq!(tree.prune(2, 3), vec![6]);
+        assert_eq!(tree.states().map(|s| s.id).collect::<Vec<_>>(), vec![3, 4, 7]);
+    }
+}
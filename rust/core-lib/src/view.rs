@@ -14,18 +14,22 @@
 
 use std::cmp::{min,max};
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::ops::Range;
 
+use regex::Error as RegexError;
 use serde_json::Value;
 
 use xi_rope::rope::{Rope, LinesMetric, RopeInfo};
 use xi_rope::delta::Delta;
+use xi_rope::engine::SessionId;
 use xi_rope::tree::Cursor;
 use xi_rope::breaks::{Breaks, BreaksInfo, BreaksMetric, BreaksBaseMetric};
 use xi_rope::interval::Interval;
 use xi_rope::spans::Spans;
 use xi_trace::trace_block;
 use client::Client;
+use diff::DiffGutter;
 use edit_types::ViewEvent;
 use line_cache_shadow::{self, LineCacheShadow, RenderPlan, RenderTactic};
 use movement::{Movement, region_movement, selection_movement};
@@ -35,7 +39,9 @@ use selection::{Affinity, Selection, SelRegion};
 use tabs::{ViewId, BufferId};
 use width_cache::WidthCache;
 use word_boundaries::WordCursor;
+use brackets::BracketMatch;
 use find::Find;
+use fold::Folds;
 use linewrap;
 
 type StyleMap = RefCell<ThemeStyleMap>;
@@ -44,6 +50,11 @@ type StyleMap = RefCell<ThemeStyleMap>;
 /// A flag used to indicate when legacy actions should modify selections
 const FLAG_SELECT: u64 = 2;
 
+/// The reserved style id for occurrences of the first find query.
+const FIND_HIGHLIGHT_STYLE_ID: usize = 1;
+/// The reserved style id for the find occurrence that is currently selected.
+const ACTIVE_FIND_HIGHLIGHT_STYLE_ID: usize = 2;
+
 pub struct View {
     pub view_id: ViewId,
     pub buffer_id: BufferId,
@@ -63,6 +74,8 @@ pub struct View {
     height: usize,
     breaks: Option<Breaks>,
     wrap_col: WrapWidth,
+    /// The folded regions, each shown as a single line.
+    folds: Folds,
 
     /// Front end's line cache state for this view. See the `LineCacheShadow`
     /// description for the invariant.
@@ -74,6 +87,13 @@ pub struct View {
     /// The state for finding text for this view.
     /// Each instance represents a separate search query
     find: Vec<Find>,
+
+    /// The brackets next to the carets, with the brackets matching them,
+    /// which are highlighted.
+    brackets: Vec<BracketMatch>,
+
+    /// The selections of other sessions editing this buffer.
+    remote_selections: BTreeMap<SessionId, Selection>,
 }
 
 /// The visual width of the buffer for the purpose of word wrapping.
@@ -119,8 +139,11 @@ impl View {
             height: 10,
             breaks: None,
             wrap_col: WrapWidth::None,
+            folds: Folds::default(),
             lc_shadow: LineCacheShadow::default(),
             find: Vec::new(),
+            brackets: Vec::new(),
+            remote_selections: BTreeMap::new(),
         }
     }
 
@@ -146,10 +169,12 @@ impl View {
             Gesture { line, col, ty } =>
                 self.do_gesture(text, line, col, ty),
             GotoLine { line } => self.goto_line(text, line),
-            FindNext { wrap_around, allow_same: _ } =>
-                self.find_next(text, false, wrap_around.unwrap_or(false)),
-            FindPrevious { wrap_around } =>
-                self.find_next(text, true, wrap_around.unwrap_or(false)),
+            FindNext { wrap_around, allow_same: _, query } =>
+                self.find_next(text, false, wrap_around.unwrap_or(false),
+                               query.as_ref().map(String::as_str)),
+            FindPrevious { wrap_around, query } =>
+                self.find_next(text, true, wrap_around.unwrap_or(false),
+                               query.as_ref().map(String::as_str)),
             Click(MouseAction { line, column, flags, click_count }) => {
                 // Deprecated (kept for client compatibility):
                 // should be removed in favor of do_gesture
@@ -279,6 +304,7 @@ impl View {
     /// Sets the selection to a new value, without invalidating.
     fn set_selection_for_edit(&mut self, text: &Rope, sel: Selection) {
         self.selection = sel;
+        self.open_folds_at_selection(text);
         self.scroll_to_cursor(text);
     }
 
@@ -288,16 +314,27 @@ impl View {
         self.invalidate_selection(text);
         self.selection = sel;
         self.invalidate_selection(text);
+        self.open_folds_at_selection(text);
     }
 
     /// Invalidate the current selection. Note that we could be even more
     /// fine-grained in the case of multiple cursors, but we also want this
     /// method to be fast even when the selection is large.
     fn invalidate_selection(&mut self, text: &Rope) {
+        let selection = self.selection.clone();
+        self.invalidate_regions(text, &selection);
+    }
+
+    /// Invalidates the lines spanned by `selection`.
+    fn invalidate_regions(&mut self, text: &Rope, selection: &Selection) {
+        let (first, last) = match (selection.first(), selection.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return,
+        };
         // TODO: refine for upstream (caret appears on prev line)
-        let first_line = self.line_of_offset(text, self.selection.first().unwrap().min());
-        let last_line = self.line_of_offset(text, self.selection.last().unwrap().max()) + 1;
-        let all_caret = self.selection.iter().all(|region| region.is_caret());
+        let first_line = self.line_of_offset(text, first.min());
+        let last_line = self.line_of_offset(text, last.max()) + 1;
+        let all_caret = selection.iter().all(|region| region.is_caret());
         let invalid = if all_caret {
             line_cache_shadow::CURSOR_VALID
         } else {
@@ -306,6 +343,42 @@ impl View {
         self.lc_shadow.partial_invalidate(first_line, last_line, invalid);
     }
 
+    /// Sets the selection of another session editing this buffer, or
+    /// removes it if `selection` is `None`.
+    pub(crate) fn set_remote_selection(&mut self, text: &Rope, session: SessionId,
+                                       selection: Option<Selection>) {
+        let old = match selection {
+            Some(selection) => {
+                self.invalidate_regions(text, &selection);
+                self.remote_selections.insert(session, selection)
+            }
+            None => self.remote_selections.remove(&session),
+        };
+        if let Some(old) = old {
+            self.invalidate_regions(text, &old);
+        }
+    }
+
+    /// Returns the sessions whose selections are shown in this view.
+    pub(crate) fn remote_sessions<'a>(&'a self) -> impl Iterator<Item=SessionId> + 'a {
+        self.remote_selections.keys().cloned()
+    }
+
+    /// Returns the selections of other sessions.
+    pub(crate) fn remote_selections<'a>(&'a self)
+        -> impl Iterator<Item=(SessionId, &'a [SelRegion])> + 'a
+    {
+        self.remote_selections.iter().map(|(session, sel)| (*session, &sel[..]))
+    }
+
+    /// Removes the selections of all other sessions.
+    pub(crate) fn clear_remote_selections(&mut self, text: &Rope) {
+        let sessions = self.remote_sessions().collect::<Vec<_>>();
+        for session in sessions {
+            self.set_remote_selection(text, session, None);
+        }
+    }
+
     fn add_selection_by_movement(&mut self, text: &Rope, movement: Movement) {
         let mut sel = Selection::new();
         for &region in self.sel_regions() {
@@ -327,6 +400,39 @@ impl View {
         self.lc_shadow.partial_invalidate(first_line, last_line, line_cache_shadow::STYLES_VALID);
     }
 
+    /// Invalidates the given logical lines, whose annotations changed
+    /// without their text changing.
+    pub fn invalidate_lines(&mut self, text: &Rope, lines: &[usize]) {
+        for &line in lines {
+            let start = text.offset_of_line(line);
+            let end = text.offset_of_line(line + 1);
+            let first_line = self.line_of_offset(text, start);
+            let last_line = self.line_of_offset(text, end).max(first_line + 1);
+            self.lc_shadow.partial_invalidate(first_line, last_line,
+                                              line_cache_shadow::STYLES_VALID);
+        }
+    }
+
+    /// Sets the brackets that match the ones next to the carets,
+    /// invalidating the lines whose highlights changed.
+    pub fn set_brackets(&mut self, text: &Rope, brackets: Vec<BracketMatch>) {
+        if brackets == self.brackets {
+            return;
+        }
+        let lines = self.brackets.iter().chain(brackets.iter())
+            .flat_map(|m| vec![m.bracket, m.matching])
+            .map(|offset| text.line_of_offset(offset.min(text.len())))
+            .collect::<Vec<_>>();
+        self.brackets = brackets;
+        self.invalidate_lines(text, &lines);
+    }
+
+    /// Returns the bracket next to the caret at `offset`, with the one
+    /// matching it, if they were found when the view was last rendered.
+    pub fn matching_bracket(&self, offset: usize) -> Option<BracketMatch> {
+        self.brackets.iter().find(|m| m.caret == offset).cloned()
+    }
+
     /// Select entire buffer.
     ///
     /// Note: unlike movement based selection, this does not scroll.
@@ -439,51 +545,72 @@ impl View {
     // Render a single line, and advance cursors to next line.
     fn render_line(&self, client: &Client, styles: &StyleMap,
                    text: &Rope, start_of_line: &mut Cursor<RopeInfo>,
-                   soft_breaks: Option<&mut Cursor<BreaksInfo>>,
-                   style_spans: &Spans<Style>, line_num: usize) -> Value
+                   mut soft_breaks: Option<&mut Cursor<BreaksInfo>>,
+                   style_spans: &Spans<Style>, gutter: Option<&DiffGutter>,
+                   line_num: usize) -> Value
     {
         let start_pos = start_of_line.pos();
-        let pos = soft_breaks.map_or(start_of_line.next::<LinesMetric>(), |bc| {
-            let pos = bc.next::<BreaksMetric>();
-            // if using breaks update cursor
-            if let Some(pos) = pos { start_of_line.set(pos) }
-            pos
-        }).unwrap_or(text.len());
-
-        let l_str = text.slice_to_string(start_pos, pos);
-        let mut cursors = Vec::new();
-        let mut selections = Vec::new();
-        for region in self.selection.regions_in_range(start_pos, pos) {
-            // cursor
-            let c = region.end;
-            if (c > start_pos && c < pos) ||
-                (!region.is_upstream() && c == start_pos) ||
-                (region.is_upstream() && c == pos) ||
-                (c == pos && c == text.len() && self.line_of_offset(text, c) == line_num)
-            {
-                cursors.push(c - start_pos);
+        let pos = match soft_breaks {
+            Some(ref mut bc) => {
+                let pos = bc.next::<BreaksMetric>();
+                // if using breaks update cursor
+                if let Some(pos) = pos { start_of_line.set(pos) }
+                pos
             }
-
-            // selection with interior
-            let sel_start_ix = clamp(region.min(), start_pos, pos) - start_pos;
-            let sel_end_ix = clamp(region.max(), start_pos, pos) - start_pos;
-            if sel_end_ix > sel_start_ix {
-                selections.push((sel_start_ix, sel_end_ix));
+            None => start_of_line.next::<LinesMetric>(),
+        }.unwrap_or(text.len());
+
+        // a folded line stands for the text hidden after it, which is skipped
+        let folded = self.folds.hidden_at(pos).filter(|_| pos > start_pos);
+        if let Some(hidden) = folded {
+            start_of_line.set(hidden.range.end);
+            if let Some(bc) = soft_breaks {
+                bc.set(hidden.range.end);
             }
         }
 
-        // todo: active highlights different style
+        let l_str = text.slice_to_string(start_pos, pos);
+        let (cursors, selections) = self.regions_in_line(
+            &self.selection, text, start_pos, pos, line_num);
+
         let mut hls = Vec::new();
-        for find in self.find.iter() {
-            for region in find.occurrences().regions_in_range(start_pos, pos) {
+        for (ix, find) in self.find.iter().enumerate() {
+            let occurrences = find.occurrences().regions_in_range(start_pos, pos);
+            if occurrences.is_empty() {
+                continue;
+            }
+            let style_id = match ix {
+                0 => FIND_HIGHLIGHT_STYLE_ID,
+                _ => {
+                    let style = styles.borrow().find_highlight_style(ix);
+                    self.get_or_def_style_id(client, styles, &style)
+                }
+            };
+            for region in occurrences {
                 let sel_start_ix = clamp(region.min(), start_pos, pos) - start_pos;
                 let sel_end_ix = clamp(region.max(), start_pos, pos) - start_pos;
                 if sel_end_ix > sel_start_ix {
-                    hls.push((sel_start_ix, sel_end_ix));
+                    // the occurrence that is currently selected is the active one
+                    let is_active = self.selection.regions_in_range(region.min(), region.max())
+                        .iter().any(|sel| sel.min() == region.min() && sel.max() == region.max());
+                    let style_id = if is_active { ACTIVE_FIND_HIGHLIGHT_STYLE_ID } else { style_id };
+                    hls.push((sel_start_ix, sel_end_ix, style_id));
                 }
             }
         }
 
+        let brackets = self.brackets.iter()
+            .flat_map(|m| vec![m.bracket, m.matching])
+            .filter(|&offset| offset >= start_pos && offset < pos)
+            .collect::<Vec<_>>();
+        if !brackets.is_empty() {
+            let style = styles.borrow().bracket_highlight_style();
+            let style_id = self.get_or_def_style_id(client, styles, &style);
+            for offset in brackets {
+                hls.push((offset - start_pos, offset + 1 - start_pos, style_id));
+            }
+        }
+
         let styles = self.render_styles(client, styles, start_pos, pos,
                                         &selections, &hls, style_spans);
 
@@ -495,12 +622,68 @@ impl View {
         if !cursors.is_empty() {
             result["cursor"] = json!(cursors);
         }
+
+        let mut remote = Vec::new();
+        for (session, selection) in &self.remote_selections {
+            let (cursors, selections) = self.regions_in_line(
+                selection, text, start_pos, pos, line_num);
+            if !cursors.is_empty() || !selections.is_empty() {
+                remote.push(json!({
+                    "session": format!("{:x}-{}", session.0, session.1),
+                    "cursor": cursors,
+                    "selections": selections,
+                }));
+            }
+        }
+        if !remote.is_empty() {
+            result["remote"] = json!(remote);
+        }
+        if let Some(hidden) = folded {
+            result["folded"] = json!(hidden.lines);
+        }
+
+        // the marker goes on the first visual line of a wrapped line
+        if let Some(gutter) = gutter {
+            let line = text.line_of_offset(start_pos);
+            if text.offset_of_line(line) == start_pos {
+                if let Some(change) = gutter.line_change(line) {
+                    result["diff"] = json!(change);
+                }
+            }
+        }
         result
     }
 
+    /// Returns the cursors and the selected ranges of `selection` on the
+    /// line from `start_pos` to `pos`, as offsets into the line.
+    fn regions_in_line(&self, selection: &Selection, text: &Rope, start_pos: usize,
+                       pos: usize, line_num: usize) -> (Vec<usize>, Vec<(usize, usize)>) {
+        let mut cursors = Vec::new();
+        let mut selections = Vec::new();
+        for region in selection.regions_in_range(start_pos, pos) {
+            // cursor
+            let c = region.end;
+            if (c > start_pos && c < pos) ||
+                (!region.is_upstream() && c == start_pos) ||
+                (region.is_upstream() && c == pos) ||
+                (c == pos && c == text.len() && self.line_of_offset(text, c) == line_num)
+            {
+                cursors.push(c - start_pos);
+            }
+
+            // selection with interior
+            let sel_start_ix = clamp(region.min(), start_pos, pos) - start_pos;
+            let sel_end_ix = clamp(region.max(), start_pos, pos) - start_pos;
+            if sel_end_ix > sel_start_ix {
+                selections.push((sel_start_ix, sel_end_ix));
+            }
+        }
+        (cursors, selections)
+    }
+
     pub fn render_styles(&self, client: &Client, styles: &StyleMap,
                          start: usize, end: usize, sel: &[(usize, usize)],
-                         hls: &[(usize, usize)],
+                         hls: &[(usize, usize, usize)],
                          style_spans: &Spans<Style>) -> Vec<isize>
     {
         let mut rendered_styles = Vec::new();
@@ -513,10 +696,10 @@ impl View {
             rendered_styles.push(0);
             ix = sel_end as isize;
         }
-        for &(sel_start, sel_end) in hls {
+        for &(sel_start, sel_end, style_id) in hls {
             rendered_styles.push((sel_start as isize) - ix);
             rendered_styles.push(sel_end as isize - sel_start as isize);
-            rendered_styles.push(1);
+            rendered_styles.push(style_id as isize);
             ix = sel_end as isize;
         }
         for (iv, style) in style_spans.iter() {
@@ -556,7 +739,8 @@ impl View {
 
     fn send_update_for_plan(&mut self, text: &Rope, client: &Client,
                             styles: &StyleMap, style_spans: &Spans<Style>,
-                            plan: &RenderPlan, pristine: bool)
+                            gutter: Option<&DiffGutter>, plan: &RenderPlan,
+                            pristine: bool)
     {
         if !self.lc_shadow.needs_render(plan) { return; }
 
@@ -609,7 +793,7 @@ impl View {
                             let line = self.render_line(client, styles, text,
                                                         &mut line_cursor,
                                                         soft_breaks.as_mut(),
-                                                        style_spans, line_num);
+                                                        style_spans, gutter, line_num);
                             rendered_lines.push(line);
                         }
                         ops.push(self.build_update_op("ins", Some(rendered_lines), seg.n));
@@ -635,12 +819,12 @@ impl View {
     /// unsaved changes.
     pub fn render_if_dirty(&mut self, text: &Rope, client: &Client,
                            styles: &StyleMap, style_spans: &Spans<Style>,
-                           pristine: bool)
+                           gutter: Option<&DiffGutter>, pristine: bool)
     {
         let height = self.line_of_offset(text, text.len()) + 1;
         let plan = RenderPlan::create(height, self.first_line, self.height);
         self.send_update_for_plan(text, client, styles,
-                                  style_spans, &plan, pristine);
+                                  style_spans, gutter, &plan, pristine);
         if let Some(new_scroll_pos) = self.scroll_to.take() {
             let (line, col) = self.offset_to_line_col(text, new_scroll_pos);
             client.scroll_to(self.view_id, line, col);
@@ -650,12 +834,13 @@ impl View {
     // Send the requested lines even if they're outside the current scroll region.
     pub fn request_lines(&mut self, text: &Rope, client: &Client,
                          styles: &StyleMap, style_spans: &Spans<Style>,
-                         first_line: usize, last_line: usize, pristine: bool) {
+                         gutter: Option<&DiffGutter>, first_line: usize,
+                         last_line: usize, pristine: bool) {
         let height = self.line_of_offset(text, text.len()) + 1;
         let mut plan = RenderPlan::create(height, self.first_line, self.height);
         plan.request_lines(first_line, last_line);
         self.send_update_for_plan(text, client, styles,
-                                  style_spans, &plan, pristine);
+                                  style_spans, gutter, &plan, pristine);
     }
 
     /// Invalidates front-end's entire line cache, forcing a full render at the next
@@ -686,9 +871,10 @@ impl View {
 
     pub fn line_col_to_offset(&self, text: &Rope, line: usize, col: usize) -> usize {
         let mut offset = self.offset_of_line(text, line).saturating_add(col);
+        let next_line_offset = self.next_line_offset(text, line);
         if offset >= text.len() {
             offset = text.len();
-            if self.line_of_offset(text, offset) <= line {
+            if next_line_offset.is_none() && self.line_of_offset(text, offset) <= line {
                 return offset;
             }
         } else {
@@ -697,38 +883,65 @@ impl View {
         }
 
         // clamp to end of line
-        let next_line_offset = self.offset_of_line(text, line + 1);
-        if offset >= next_line_offset {
-            if let Some(prev) = text.prev_grapheme_offset(next_line_offset) {
-                offset = prev;
+        if let Some(next_line_offset) = next_line_offset {
+            if offset >= next_line_offset {
+                if let Some(prev) = text.prev_grapheme_offset(next_line_offset) {
+                    offset = prev;
+                }
             }
         }
         offset
     }
 
-    // use own breaks if present, or text if not (no line wrapping)
-
-    /// Returns the visible line number containing the given offset.
+    /// Returns the visible line number containing the given offset. Offsets
+    /// in folded text are on the line that stands for the fold.
     pub fn line_of_offset(&self, text: &Rope, offset: usize) -> usize {
-        match self.breaks {
-            Some(ref breaks) => {
-                breaks.convert_metrics::<BreaksBaseMetric, BreaksMetric>(offset)
+        let breaks = self.breaks.as_ref();
+        let mut hidden_lines = 0;
+        for hidden in self.folds.hidden() {
+            if offset < hidden.range.start {
+                break;
+            }
+            if hidden.contains(offset) {
+                let line = raw_line_of_offset(breaks, text, hidden.range.start);
+                return line - 1 - hidden_lines;
             }
-            None => text.line_of_offset(offset)
+            hidden_lines += hidden.lines;
         }
+        raw_line_of_offset(breaks, text, offset) - hidden_lines
     }
 
     /// Returns the byte offset corresponding to the line `line`.
     pub fn offset_of_line(&self, text: &Rope, line: usize) -> usize {
-        match self.breaks {
-            Some(ref breaks) => {
-                breaks.convert_metrics::<BreaksMetric, BreaksBaseMetric>(line)
-            }
-            None => {
-                // sanitize input
-                let line = line.min(text.measure::<LinesMetric>() + 1);
-                text.offset_of_line(line)
+        let breaks = self.breaks.as_ref();
+        if self.folds.is_empty() {
+            return raw_offset_of_line(breaks, text, line);
+        }
+        let mut raw_line = line;
+        for hidden in self.folds.hidden() {
+            if raw_line < raw_line_of_offset(breaks, text, hidden.range.start) {
+                break;
             }
+            raw_line += hidden.lines;
+        }
+        if raw_line > raw_line_of_offset(breaks, text, text.len()) {
+            return text.len();
+        }
+        raw_offset_of_line(breaks, text, raw_line)
+    }
+
+    /// Returns the offset where the line after `line` starts, or where
+    /// the text folded into `line` starts, or `None` if `line` is the
+    /// last line.
+    pub fn next_line_offset(&self, text: &Rope, line: usize) -> Option<usize> {
+        let start = self.offset_of_line(text, line);
+        let next = self.offset_of_line(text, line + 1);
+        let folded = self.folds.hidden().iter()
+            .find(|hidden| hidden.range.start > start && hidden.range.start <= next);
+        match folded {
+            Some(hidden) => Some(hidden.range.start),
+            None if line < self.line_of_offset(text, text.len()) => Some(next),
+            None => None,
         }
     }
 
@@ -739,6 +952,91 @@ impl View {
         } else {
             self.breaks = None
         }
+        self.update_hidden(text);
+    }
+
+    /// Folds each multi-line selection, and for each caret, the innermost
+    /// of `ranges` that spans its line. Selections in the folded text are
+    /// moved to the line that stands for the fold.
+    pub fn fold(&mut self, text: &Rope, ranges: &[Range<usize>]) {
+        let mut changed = false;
+        for region in self.selection.clone().iter() {
+            let line = text.line_of_offset(region.end);
+            let range = if text.line_of_offset(region.min()) < text.line_of_offset(region.max()) {
+                Some(region.min()..region.max())
+            } else {
+                ranges.iter()
+                    .filter(|range| text.line_of_offset(range.start) <= line
+                            && line <= text.line_of_offset(range.end))
+                    .min_by_key(|range| range.end - range.start)
+                    .cloned()
+            };
+            if let Some(range) = range {
+                changed |= self.folds.add(text, range);
+            }
+        }
+        if !changed {
+            return;
+        }
+        self.update_hidden(text);
+        let mut selection = Selection::new();
+        for region in self.selection.iter() {
+            let hidden = self.folds.hidden_containing(region.start)
+                .or_else(|| self.folds.hidden_containing(region.end));
+            match hidden {
+                Some(hidden) => selection.add_region(SelRegion::caret(hidden.range.start - 1)),
+                None => selection.add_region(*region),
+            }
+        }
+        self.selection = selection;
+        self.set_dirty(text);
+    }
+
+    /// Unfolds the folds on the lines of the carets, or every fold if
+    /// `all` is set.
+    pub fn unfold(&mut self, text: &Rope, all: bool) {
+        let changed = if all {
+            let changed = !self.folds.is_empty();
+            self.folds.clear();
+            changed
+        } else {
+            let lines = self.selection.iter()
+                .map(|region| text.line_of_offset(region.end))
+                .collect::<Vec<_>>();
+            lines.into_iter().fold(false, |changed, line|
+                self.folds.remove_at_line(text, line) || changed)
+        };
+        if changed {
+            self.update_hidden(text);
+            self.set_dirty(text);
+        }
+    }
+
+    /// Opens the folds hiding an end of a selection, such as after a
+    /// selection was moved into one.
+    fn open_folds_at_selection(&mut self, text: &Rope) {
+        if self.folds.is_empty() {
+            return;
+        }
+        let offsets = self.selection.iter()
+            .flat_map(|region| vec![region.start, region.end])
+            .filter(|&offset| self.folds.hidden_containing(offset).is_some())
+            .collect::<Vec<_>>();
+        if offsets.is_empty() {
+            return;
+        }
+        for offset in offsets {
+            self.folds.remove_hiding(text, offset);
+        }
+        self.update_hidden(text);
+        self.set_dirty(text);
+    }
+
+    /// Recomputes the text hidden by folds, after the folds, the text or
+    /// the line breaks changed.
+    fn update_hidden(&mut self, text: &Rope) {
+        let breaks = self.breaks.as_ref();
+        self.folds.update_hidden(text, |offset| raw_line_of_offset(breaks, text, offset));
     }
 
     /// Updates the view after the text has been modified by the given `delta`.
@@ -759,9 +1057,13 @@ impl View {
                                            client, iv, new_len, px),
             }
         }
-        if self.breaks.is_some() {
-            // TODO: finer grain invalidation for the line wrapping, needs info
-            // about what wrapped.
+        if !self.folds.is_empty() {
+            self.folds.apply_delta(delta, text);
+            self.update_hidden(text);
+        }
+        if self.breaks.is_some() || !self.folds.is_empty() {
+            // TODO: finer grain invalidation for the line wrapping and
+            // folds, needs info about what wrapped.
             self.set_dirty(text);
         } else {
             let start = self.line_of_offset(last_text, iv.start());
@@ -782,10 +1084,17 @@ impl View {
         // of the delta so we can set the cursor before or after the edit, as needed.
         let new_sel = self.selection.apply_delta(delta, true, keep_selections);
         self.set_selection_for_edit(text, new_sel);
+
+        for selection in self.remote_selections.values_mut() {
+            *selection = selection.apply_delta(delta, true, false);
+        }
     }
 
-    pub fn do_find(&mut self, text: &Rope, chars: Option<String>,
-                   case_sensitive: bool) -> Value {
+    /// Updates the find query named `query` (or the default query, if it is
+    /// `None`), creating it if it does not exist yet.
+    pub fn do_find(&mut self, text: &Rope, query: Option<&str>, chars: Option<String>,
+                   case_sensitive: bool, is_regex: bool, whole_words: bool,
+                   ignore_accents: bool) -> Result<Value, RegexError> {
         let mut from_sel = false;
         let search_string = if chars.is_some() {
             chars
@@ -802,27 +1111,80 @@ impl View {
 
         self.set_dirty(text);
 
-        // todo: this will be changed once multiple queries are supported
-        // todo: for now only a single search query is supported however in the future
-        // todo: the correct Find instance needs to be updated with the new parameters
-        if self.find.is_empty() {
-            self.find.push(Find::new())
+        let ix = match self.find.iter().position(|find| find.name() == query) {
+            Some(ix) => ix,
+            None => {
+                self.find.push(Find::new(query.map(str::to_owned)));
+                self.find.len() - 1
+            }
+        };
+
+        self.find[ix].do_find(text, search_string, case_sensitive, is_regex,
+                              whole_words, ignore_accents)
+    }
+
+    /// Returns the find query named `query`, or all queries if it is `None`.
+    fn find_queries<'a>(&'a self, query: Option<&'a str>)
+                        -> impl Iterator<Item=&'a Find> + 'a {
+        self.find.iter().filter(move |find| query.is_none() || find.name() == query)
+    }
+
+    /// Computes the replacements for the find occurrences, given a
+    /// replacement `template`. If `all` is set, every occurrence is replaced,
+    /// otherwise only an occurrence matching the last selection region is.
+    /// Only occurrences of the find query named `query` are considered, if
+    /// it is set.
+    ///
+    /// The returned intervals are sorted and non-overlapping.
+    pub fn find_replacements(&self, text: &Rope, template: &str, all: bool,
+                             query: Option<&str>) -> Vec<(Interval, String)> {
+        let mut replacements = Vec::new();
+        if all {
+            for find in self.find_queries(query) {
+                for occurrence in find.occurrences().iter() {
+                    let iv = Interval::new_closed_open(occurrence.min(),
+                                                       occurrence.max());
+                    let replacement = find.replacement(text, occurrence, template);
+                    replacements.push((iv, replacement));
+                }
+            }
+        } else if let Some(&sel) = self.sel_regions().last() {
+            let found = self.find_queries(query).flat_map(|find| {
+                find.occurrences().regions_in_range(sel.min(), sel.max()).iter()
+                    .find(|occ| occ.min() == sel.min() && occ.max() == sel.max())
+                    .map(|occ| (find, occ))
+            }).next();
+            if let Some((find, occurrence)) = found {
+                let iv = Interval::new_closed_open(occurrence.min(), occurrence.max());
+                replacements.push((iv, find.replacement(text, occurrence, template)));
+            }
         }
 
-        self.find.first_mut().unwrap().do_find(text, search_string, case_sensitive)
+        // occurrences from different queries may overlap; the first one wins
+        replacements.sort_by_key(|&(iv, _)| iv.start());
+        let mut last_end = 0;
+        replacements.retain(|&(iv, _)| {
+            let keep = iv.start() >= last_end;
+            if keep { last_end = iv.end(); }
+            keep
+        });
+        replacements
     }
 
-    pub fn find_next(&mut self, text: &Rope, reverse: bool, wrap: bool) {
-        self.select_next_occurrence(text, reverse, false);
+    pub fn find_next(&mut self, text: &Rope, reverse: bool, wrap: bool,
+                     query: Option<&str>) {
+        self.select_next_occurrence(text, reverse, false, query);
         if self.scroll_to.is_none() && wrap {
-            self.select_next_occurrence(text, reverse, true);
+            self.select_next_occurrence(text, reverse, true, query);
         }
     }
 
     /// Select the next occurrence relative to the last cursor. `reverse` determines whether the
     /// next occurrence before (`true`) or after (`false`) the last cursor is selected. `wrapped`
-    /// indicates a search for the next occurrence past the end of the file.
-    pub fn select_next_occurrence(&mut self, text: &Rope, reverse: bool, wrapped: bool)
+    /// indicates a search for the next occurrence past the end of the file. If `query` is set,
+    /// only occurrences of the find query with that name are considered.
+    pub fn select_next_occurrence(&mut self, text: &Rope, reverse: bool, wrapped: bool,
+                                  query: Option<&str>)
     {
         // select occurrence closest to last selection
         let sel = match self.sel_regions().last() {
@@ -831,7 +1193,7 @@ impl View {
         };
 
         // multiple queries; select closest occurrence
-        let closest_occurrence = self.find.iter().flat_map(|x|
+        let closest_occurrence = self.find_queries(query).flat_map(|x|
             x.next_occurrence(text, reverse, wrapped, sel)
         ).min_by_key(|x| {
             match reverse {
@@ -868,6 +1230,30 @@ impl View {
                                                     style_spans, client,
                                                     width_px));
         self.wrap_col = WrapWidth::Width(width_px);
+        self.update_hidden(text);
+    }
+}
+
+// use own breaks if present, or text if not (no line wrapping)
+
+/// Returns the visual line containing `offset`, ignoring folds.
+fn raw_line_of_offset(breaks: Option<&Breaks>, text: &Rope, offset: usize) -> usize {
+    match breaks {
+        Some(breaks) => breaks.convert_metrics::<BreaksBaseMetric, BreaksMetric>(offset),
+        None => text.line_of_offset(offset)
+    }
+}
+
+/// Returns the offset of the start of the visual line `line`, ignoring
+/// folds.
+fn raw_offset_of_line(breaks: Option<&Breaks>, text: &Rope, line: usize) -> usize {
+    match breaks {
+        Some(breaks) => breaks.convert_metrics::<BreaksMetric, BreaksBaseMetric>(line),
+        None => {
+            // sanitize input
+            let line = line.min(text.measure::<LinesMetric>() + 1);
+            text.offset_of_line(line)
+        }
     }
 }
 
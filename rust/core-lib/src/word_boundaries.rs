@@ -68,6 +68,20 @@ impl<'a> WordCursor<'a> {
         None
     }
 
+    /// Returns `true` if the cursor is at the start or the end of a word,
+    /// or at the start or the end of the text. The cursor is not moved.
+    pub fn is_boundary(&mut self) -> bool {
+        let pos = self.inner.pos();
+        let prop_before = self.inner.prev_codepoint().map(get_word_property);
+        self.inner.set(pos);
+        let prop_after = self.inner.next_codepoint().map(get_word_property);
+        self.inner.set(pos);
+        match (prop_before, prop_after) {
+            (Some(pb), Some(pa)) => classify_boundary(pb, pa).is_boundary(),
+            _ => true,
+        }
+    }
+
     /// Return the selection for the word containing the current cursor. The
     /// cursor is moved to the end of that selection.
     pub fn select_word(&mut self) -> (usize, usize) {
@@ -15,10 +15,12 @@
 #[macro_use]
 extern crate serde_json;
 
+extern crate tempdir;
 extern crate xi_rpc;
 extern crate xi_core_lib;
 
-use std::io;
+use std::fs::{self, File};
+use std::io::{self, Write};
 
 use xi_rpc::{RpcLoop, ReadError};
 use xi_rpc::test_utils::{make_reader, test_channel};
@@ -210,6 +212,203 @@ fn test_settings_commands() {
 }
 
 
+#[test]
+fn test_project_search() {
+    let tmp = tempdir::TempDir::new("xi-test-project-search-rpc").unwrap();
+    File::create(tmp.path().join("a.txt")).unwrap()
+        .write_all(b"one\ntwo three two\n").unwrap();
+
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(r#"{"method":"client_started","params":{}}"#);
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    rx.expect_rpc("available_themes");
+
+    let request = json!({
+        "id": 0,
+        "method": "search_project",
+        "params": { "root": tmp.path(), "chars": "two", "case_sensitive": true },
+    });
+    let json = make_reader(request.to_string());
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    let search_id = rx.expect_response().unwrap();
+    let results = rx.expect_rpc("project_search_results");
+    assert_eq!(results.0["params"]["search_id"], search_id);
+    let results = results.0["params"]["results"].as_array().unwrap().clone();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[1]["line"], json!(1));
+    assert_eq!(results[1]["col"], json!(10));
+    assert_eq!(results[1]["preview"], json!("two three two"));
+    rx.expect_rpc("project_search_finished");
+
+    let json = make_reader(r#"{"id":1,"method":"search_project","params":{"root":"/","chars":"(","case_sensitive":true,"regex":true}}
+{"method":"cancel_search","params":{"search_id":42}}"#);
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    assert!(rx.expect_response().is_err());
+    rx.expect_nothing();
+}
+
+#[test]
+fn test_share_buffer() {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(r#"{"method":"client_started","params":{}}
+{"id":0,"method":"new_view","params":{}}"#);
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    rx.expect_rpc("available_themes");
+    assert_eq!(rx.expect_response(), Ok(json!("view-id-1")));
+    rx.expect_rpc("available_plugins");
+    rx.expect_rpc("config_changed");
+    rx.expect_rpc("update");
+    rx.expect_rpc("scroll_to");
+
+    let json = make_reader(r#"{"id":1,"method":"share_buffer","params":{"view_id":"view-id-1","address":"127.0.0.1:0"}}"#);
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    let shared = rx.expect_response().unwrap();
+    assert!(shared["address"].as_str().unwrap().starts_with("127.0.0.1:"));
+    assert!(!shared["token"].as_str().unwrap().is_empty());
+
+    let json = make_reader(r#"{"id":2,"method":"share_buffer","params":{"view_id":"view-id-1","address":"127.0.0.1:0"}}
+{"method":"stop_sharing","params":{"view_id":"view-id-1"}}
+{"id":3,"method":"join_shared_buffer","params":{"address":"unix:/nonexistent/xi-sync","token":""}}"#);
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    assert!(rx.expect_response().is_err());
+    assert!(rx.expect_response().is_err());
+}
+
+#[test]
+fn test_recover_swap_file() {
+    let tmp = tempdir::TempDir::new("xi-test-swap-rpc").unwrap();
+    let path = tmp.path().join("a.txt");
+    File::create(&path).unwrap().write_all(b"saved\n").unwrap();
+    File::create(tmp.path().join(".a.txt.xi-swap")).unwrap()
+        .write_all(b"unsaved\n").unwrap();
+
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let request = json!({"id": 0, "method": "new_view", "params": { "file_path": path }});
+    let json = make_reader(format!("{}\n{}", r#"{"method":"client_started","params":{}}"#,
+                                   request));
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    rx.expect_rpc("available_themes");
+    assert_eq!(rx.expect_response(), Ok(json!("view-id-1")));
+    rx.expect_rpc("available_plugins");
+    rx.expect_rpc("config_changed");
+    rx.expect_rpc("update");
+    rx.expect_rpc("scroll_to");
+    let found = rx.expect_rpc("swap_file_found");
+    assert_eq!(found.0["params"]["view_id"], json!("view-id-1"));
+
+    let json = make_reader(r#"{"method":"recover_swap_file","params":{"view_id":"view-id-1"}}
+{"id":1,"method":"edit","params":{"view_id":"view-id-1","method":"undo_tree","params":[]}}"#);
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    let update = rx.expect_rpc("update");
+    assert_eq!(update.0["params"]["update"]["pristine"], json!(false));
+    rx.expect_rpc("scroll_to");
+    let tree = rx.expect_response().unwrap();
+    assert_eq!(tree["states"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn test_diff_buffers() {
+    let tmp = tempdir::TempDir::new("xi-test-diff-rpc").unwrap();
+    let path = tmp.path().join("a.txt");
+    let other = tmp.path().join("b.txt");
+    File::create(&path).unwrap().write_all(b"one\ntwo\n").unwrap();
+    File::create(&other).unwrap().write_all(b"one\n2\n").unwrap();
+
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let request = json!({"id": 0, "method": "new_view", "params": { "file_path": path }});
+    let json = make_reader(format!("{}\n{}", r#"{"method":"client_started","params":{}}"#,
+                                   request));
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    rx.expect_rpc("available_themes");
+    assert_eq!(rx.expect_response(), Ok(json!("view-id-1")));
+    rx.expect_rpc("available_plugins");
+    rx.expect_rpc("config_changed");
+    rx.expect_rpc("update");
+    rx.expect_rpc("scroll_to");
+
+    let request = json!({"id": 1, "method": "diff_buffers",
+                         "params": { "view_id": "view-id-1", "path": other }});
+    let json = make_reader(request.to_string());
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    // the changed word is highlighted
+    rx.expect_rpc("def_style");
+    let update = rx.expect_rpc("update");
+    let lines = &update.0["params"]["update"]["ops"][0]["lines"];
+    assert_eq!(lines[1]["styles"], json!([0, 3, 3]));
+    let result = rx.expect_response().unwrap();
+    assert_eq!(result["hunks"], json!([{"left": {"start": 1, "end": 2},
+                                        "right": {"start": 1, "end": 2}}]));
+    let diff_id = result["diff_id"].clone();
+
+    let json = make_reader(r#"{"method":"edit","params":{"view_id":"view-id-1","method":"insert","params":{"chars":"zero\n"}}}"#);
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    rx.expect_rpc("update");
+    rx.expect_rpc("scroll_to");
+    // the gutter is compared again, then the diff
+    rx.expect_rpc("update");
+    rx.expect_rpc("update");
+    let changed = rx.expect_rpc("diff_changed");
+    assert_eq!(changed.0["params"]["diff_id"], diff_id);
+    assert_eq!(changed.0["params"]["hunks"][0],
+               json!({"left": {"start": 0, "end": 1}, "right": {"start": 0, "end": 0}}));
+}
+
+#[test]
+fn test_close_one_of_two_diffs() {
+    let tmp = tempdir::TempDir::new("xi-test-diff-rpc").unwrap();
+    let path = tmp.path().join("a.txt");
+    let second = tmp.path().join("b.txt");
+    let first = tmp.path().join("c.txt");
+    File::create(&path).unwrap().write_all(b"one\ntwo\n").unwrap();
+    File::create(&first).unwrap().write_all(b"1\ntwo\n").unwrap();
+    File::create(&second).unwrap().write_all(b"one\n2\n").unwrap();
+
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let request = json!({"id": 0, "method": "new_view", "params": { "file_path": path }});
+    let json = make_reader(format!("{}\n{}", r#"{"method":"client_started","params":{}}"#,
+                                   request));
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    rx.expect_rpc("available_themes");
+    assert_eq!(rx.expect_response(), Ok(json!("view-id-1")));
+    rx.expect_rpc("available_plugins");
+    rx.expect_rpc("config_changed");
+    rx.expect_rpc("update");
+    rx.expect_rpc("scroll_to");
+
+    let json = make_reader(format!("{}\n{}",
+        json!({"id": 1, "method": "diff_buffers",
+               "params": { "view_id": "view-id-1", "path": first }}),
+        json!({"id": 2, "method": "diff_buffers",
+               "params": { "view_id": "view-id-1", "path": second }})));
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    rx.expect_rpc("def_style");
+    rx.expect_rpc("update");
+    let diff_id = rx.expect_response().unwrap()["diff_id"].clone();
+    let update = rx.expect_rpc("update");
+    let lines = &update.0["params"]["update"]["ops"][0]["lines"];
+    assert_eq!(lines[0]["styles"], json!([0, 3, 3]));
+    assert_eq!(lines[1]["styles"], json!([0, 3, 3]));
+    rx.expect_response().unwrap();
+
+    // the highlights of the other diff stay
+    let json = make_reader(json!({"method": "close_diff",
+                                  "params": { "diff_id": diff_id }}).to_string());
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    let update = rx.expect_rpc("update");
+    let lines = &update.0["params"]["update"]["ops"][0]["lines"];
+    assert_eq!(lines[0]["styles"], json!([]));
+    assert_eq!(lines[1]["styles"], json!([0, 3, 3]));
+}
 
 //TODO: test saving rpc
 //TODO: test plugin rpc
@@ -257,6 +456,11 @@ const TEXT_EDIT_RPCS: &str = r#"{"method":"edit","params":{"view_id":"view-id-1"
 {"method":"edit","params":{"view_id":"view-id-1","method":"yank","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"undo","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"redo","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"goto_undo_state","params":{"id":0}}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"undo_earlier","params":{"count":2}}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"undo_later","params":{"seconds":10}}}
+{"id":10,"method":"edit","params":{"view_id":"view-id-1","method":"undo_tree","params":[]}}
+{"id":11,"method":"edit","params":{"view_id":"view-id-1","method":"undo_state_diff","params":{"id":0}}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"transpose","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"uppercase","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"lowercase","params":[]}}
@@ -276,8 +480,79 @@ const OTHER_EDIT_RPCS: &str = r#"{"method":"edit","params":{"view_id":"view-id-1
 {"method":"edit","params":{"view_id":"view-id-1","method":"gesture","params":{"line": 1, "col": 2, "ty": "multi_line_select"}}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"gesture","params":{"line": 1, "col": 2, "ty": "multi_word_select"}}}
 {"id":4,"method":"edit","params":{"view_id":"view-id-1","method":"find","params":{"case_sensitive":false,"chars":"m"}}}
+{"id":5,"method":"edit","params":{"view_id":"view-id-1","method":"find","params":{"case_sensitive":false,"chars":"(m+)","regex":true}}}
+{"id":6,"method":"edit","params":{"view_id":"view-id-1","method":"replace","params":{"chars":"$1$1"}}}
+{"id":7,"method":"edit","params":{"view_id":"view-id-1","method":"replace","params":{"chars":"n","all":true}}}
+{"id":8,"method":"edit","params":{"view_id":"view-id-1","method":"find","params":{"case_sensitive":false,"chars":"a","query":"second"}}}
+{"id":9,"method":"edit","params":{"view_id":"view-id-1","method":"find","params":{"case_sensitive":false,"chars":"a","whole_words":true,"ignore_accents":true}}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"find_next","params":{"wrap_around":true}}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"find_next","params":{"wrap_around":true,"query":"second"}}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"find_previous","params":{"wrap_around":true}}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"debug_rewrap","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"debug_print_spans","params":[]}}
 {"id":3,"method":"edit","params":{"view_id":"view-id-1","method":"copy","params":[]}}"#;
+
+#[test]
+fn test_save_line_endings() {
+    let tmp = tempdir::TempDir::new("xi-test-line-endings-rpc").unwrap();
+    let path = tmp.path().join("a.txt");
+    File::create(&path).unwrap().write_all(b"a\r\nb\r\n").unwrap();
+
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let request = json!({"id": 0, "method": "new_view", "params": { "file_path": path }});
+    let json = make_reader(format!("{}\n{}", r#"{"method":"client_started","params":{}}"#,
+                                   request));
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    rx.expect_rpc("available_themes");
+    assert_eq!(rx.expect_response(), Ok(json!("view-id-1")));
+    rx.expect_rpc("available_plugins");
+    let config = rx.expect_rpc("config_changed");
+    assert_eq!(config.0["params"]["changes"]["line_ending"], json!("\r\n"));
+
+    // only the saved file is converted, so it can be converted back
+    let save = json!({"method": "save", "params": { "view_id": "view-id-1", "file_path": path }});
+    let json = make_reader(format!("{}\n{}", r#"{"method":"modify_user_config","params":{"domain":{"user_override":"view-id-1"},"changes":{"line_ending":"\n"}}}"#,
+                                   save));
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    assert_eq!(fs::read(&path).unwrap(), b"a\nb\n");
+
+    let json = make_reader(format!("{}\n{}", r#"{"method":"modify_user_config","params":{"domain":{"user_override":"view-id-1"},"changes":{"line_ending":null}}}"#,
+                                   save));
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    assert_eq!(fs::read(&path).unwrap(), b"a\r\nb\r\n");
+}
+
+#[test]
+fn test_load_large_file_once() {
+    let tmp = tempdir::TempDir::new("xi-test-large-rpc").unwrap();
+    let path = tmp.path().join("large.txt");
+    File::create(&path).unwrap().write_all(b"large\nfile\n").unwrap();
+
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let first = json!({"id": 0, "method": "new_view", "params": { "file_path": path }});
+    let second = json!({"id": 1, "method": "new_view", "params": { "file_path": path }});
+    let json = make_reader(format!("{}\n{}\n{}\n{}", r#"{"method":"client_started","params":{}}"#,
+                                   r#"{"method":"modify_user_config","params":{"domain":"general","changes":{"large_file_threshold":4}}}"#,
+                                   first, second));
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    rx.expect_rpc("available_themes");
+    assert_eq!(rx.expect_response(), Ok(json!("view-id-1")));
+    assert_eq!(rx.expect_response(), Ok(json!("view-id-3")));
+    // both views show the buffer the file is loaded into once
+    for view_id in &["view-id-1", "view-id-3"] {
+        let progress = rx.expect_rpc("load_progress");
+        assert_eq!(progress.0["params"]["view_id"], json!(view_id));
+        assert_eq!(progress.0["params"]["loaded"], json!(11));
+    }
+    for view_id in &["view-id-1", "view-id-3"] {
+        rx.expect_rpc("config_changed");
+        let update = rx.expect_rpc("update");
+        assert_eq!(update.0["params"]["view_id"], json!(view_id));
+        assert_eq!(update.0["params"]["update"]["ops"][0]["lines"][1]["text"], json!("file\n"));
+        rx.expect_rpc("scroll_to");
+    }
+}
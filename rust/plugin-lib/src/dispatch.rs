@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::collections::HashMap;
+use std::ops::Range;
 use std::path::PathBuf;
 
 use serde_json::{self, Value};
@@ -145,6 +146,19 @@ impl<'a, P: 'a + Plugin> Dispatcher<'a, P> {
         return Ok(Value::from(1));
     }
 
+    fn do_format(&mut self, view_id: ViewIdentifier, rev: u64, ranges: Vec<Range<usize>>)
+        -> Result<Value, RemoteError>
+    {
+        let v = bail_err!(self.views.get_mut(&view_id), "format",
+                          self.pid, view_id);
+        if v.rev != rev {
+            return Ok(Value::Null);
+        }
+        let edit = self.plugin.format(v, &ranges)
+            .map(|delta| v.format_edit(delta));
+        Ok(json!(edit))
+    }
+
     fn do_collect_trace(&self) -> Result<Value, RemoteError> {
         use xi_trace_dump::*;
 
@@ -193,6 +207,8 @@ impl<'a, P: Plugin> RpcHandler for Dispatcher<'a, P> {
                 self.do_update(params),
             CollectTrace ( .. ) =>
                 self.do_collect_trace(),
+            Format { view_id, rev, ranges } =>
+                self.do_format(view_id, rev, ranges),
         }
     }
 
@@ -32,6 +32,7 @@ mod view;
 mod dispatch;
 
 use std::io;
+use std::ops::Range;
 use std::path::Path;
 
 use xi_rpc::{RpcLoop, ReadError};
@@ -134,6 +135,14 @@ pub trait Plugin {
     /// to perform their work incrementally while remaining responsive.
     #[allow(unused_variables)]
     fn idle(&mut self, view: &mut View<Self::Cache>) { }
+
+    /// Called when the user asks for `ranges` of the document to be
+    /// formatted. Plugins that format the view's syntax return a delta
+    /// against the current revision; the core applies the first one it
+    /// receives.
+    #[allow(unused_variables)]
+    fn format(&mut self, view: &mut View<Self::Cache>, ranges: &[Range<usize>])
+        -> Option<RopeDelta> { None }
 }
 
 #[derive(Debug)]
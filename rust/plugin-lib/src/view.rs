@@ -17,7 +17,8 @@ use serde_json::{self, Value};
 use serde::Deserialize;
 
 use xi_core::{ViewIdentifier, PluginPid, BufferConfig, ConfigTable};
-use xi_core::plugin_rpc::{TextUnit, PluginEdit, GetDataResponse, ScopeSpan, PluginBufferInfo};
+use xi_core::plugin_rpc::{TextUnit, PluginEdit, GetDataResponse, ScopeSpan, FoldRange,
+                          PluginBufferInfo};
 use xi_rope::rope::RopeDelta;
 use xi_trace::trace_block;
 
@@ -147,6 +148,18 @@ impl<C: Cache> View<C> {
         self.peer.send_rpc_notification("edit", &params);
     }
 
+    /// Returns the edit answering a request to format this view.
+    pub (crate) fn format_edit(&self, delta: RopeDelta) -> PluginEdit {
+        PluginEdit {
+            rev: self.rev,
+            delta,
+            priority: 0,
+            after_cursor: false,
+            undo_group: None,
+            author: "format".into(),
+        }
+    }
+
     pub fn update_spans(&self, start: usize, len: usize, spans: &[ScopeSpan]) {
         let params = json!({
             "plugin_id": self.plugin_id,
@@ -159,6 +172,31 @@ impl<C: Cache> View<C> {
         self.peer.send_rpc_notification("update_spans", &params);
     }
 
+    /// Reports the ranges of the text that can be folded, replacing any
+    /// reported before.
+    pub fn update_fold_ranges(&self, ranges: &[FoldRange]) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+            "rev": self.rev,
+            "ranges": ranges,
+        });
+        self.peer.send_rpc_notification("update_fold_ranges", &params);
+    }
+
+    /// Sets the indentation of `line` to `indent` columns, overriding the
+    /// indentation the core computed, for instance after a newline.
+    pub fn set_indent(&self, line: usize, indent: usize) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+            "rev": self.rev,
+            "line": line,
+            "indent": indent,
+        });
+        self.peer.send_rpc_notification("set_indent", &params);
+    }
+
     pub fn schedule_idle(&self) {
         let token: usize = self.view_id.into();
         self.peer.schedule_idle(token);
@@ -114,6 +114,18 @@ impl<N: NodeInfo> Delta<N> {
         }
     }
 
+    /// Returns `true` if applying this delta leaves the text unchanged.
+    pub fn is_identity(&self) -> bool {
+        let mut pos = 0;
+        for el in &self.els {
+            match *el {
+                DeltaElement::Copy(beg, end) if beg == pos => pos = end,
+                _ => return false,
+            }
+        }
+        pos == self.base_len
+    }
+
     /// Apply the delta to the given rope. May not work well if the length of the rope
     /// is not compatible with the construction of the delta.
     pub fn apply(&self, base: &Node<N>) -> Node<N> {
@@ -706,6 +718,16 @@ mod tests {
         assert_eq!(6, d.new_document_len());
     }
 
+    #[test]
+    fn is_identity() {
+        let d = Delta::simple_edit(Interval::new_closed_open(1, 9), Rope::from("era"), 11);
+        assert!(!d.is_identity());
+        let d = Delta::simple_edit(Interval::new_closed_open(1, 1), Rope::from(""), 11);
+        assert!(d.is_identity());
+        let d = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from(""), 0);
+        assert!(d.is_identity());
+    }
+
     #[test]
     fn factor() {
         let d = Delta::simple_edit(Interval::new_closed_open(1, 9), Rope::from("era"), 11);
@@ -31,12 +31,13 @@
 use std::borrow::Cow;
 use std::collections::BTreeSet;
 use std::collections::hash_map::DefaultHasher;
+use std::fmt;
 use std;
 
 use rope::{Rope, RopeInfo};
 use multiset::{Subset, CountMatcher};
 use interval::Interval;
-use delta::{Delta, InsertDelta};
+use delta::{Delta, DeltaElement, InsertDelta};
 
 /// Represents the current state of a document and all of its history
 #[derive(Serialize, Deserialize, Debug)]
@@ -107,6 +108,44 @@ pub type RevToken = u64;
 /// the session ID component of a `RevId`
 pub type SessionId = (u64, u32);
 
+/// A set of revisions, as the number of the newest revision of each
+/// session. A session numbers its revisions in the order it creates them,
+/// and merges keep that order, so an engine that has one revision of a
+/// session also has (or has garbage collected) all the earlier ones.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Versions(Vec<(SessionId, u32)>);
+
+/// The revisions of an engine that another engine doesn't have, in a form
+/// that can be sent to it. See `Engine::revisions_since`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Revisions {
+    /// The revisions both engines have.
+    base: Versions,
+    /// The other revisions, in order, rebased on `base`.
+    ops: Vec<RevisionOp>,
+}
+
+/// The serialized form of a `DeltaOp`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct RevisionOp {
+    rev_id: RevId,
+    priority: usize,
+    undo_group: usize,
+    inserts: Delta<RopeInfo>,
+    deletes: Subset,
+}
+
+/// Why `Engine::merge_revisions` refused a set of revisions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeError {
+    /// They are based on revisions this engine doesn't have.
+    MissingBase,
+    /// They include a revision this engine already has.
+    Duplicate,
+    /// An operation doesn't fit the union string it applies to.
+    Malformed,
+}
+
 #[derive(Clone, Copy, PartialOrd, Ord, PartialEq, Eq)]
 struct FullPriority {
     priority: usize,
@@ -152,6 +191,74 @@ fn initial_revision_counter() -> u32 {
     1
 }
 
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match *self {
+            MergeError::MissingBase => "revisions are based on unknown revisions",
+            MergeError::Duplicate => "revisions were already merged",
+            MergeError::Malformed => "revisions are malformed",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl Versions {
+    /// Whether the revision `rev_id` is in the set.
+    pub fn contains(&self, rev_id: RevId) -> bool {
+        self.get(rev_id.session_id()).map_or(false, |num| rev_id.num <= num)
+    }
+
+    /// Whether every revision in `other` is also in the set.
+    pub fn includes(&self, other: &Versions) -> bool {
+        other.0.iter().all(|&(session, num)| self.get(session).map_or(false, |n| num <= n))
+    }
+
+    /// Adds `rev_id`, and the earlier revisions of its session.
+    pub fn insert(&mut self, rev_id: RevId) {
+        let session = rev_id.session_id();
+        match self.0.binary_search_by_key(&session, |&(s, _)| s) {
+            Ok(ix) => self.0[ix].1 = self.0[ix].1.max(rev_id.num),
+            Err(ix) => self.0.insert(ix, (session, rev_id.num)),
+        }
+    }
+
+    /// The revisions in either set.
+    pub fn union(&self, other: &Versions) -> Versions {
+        let mut result = self.clone();
+        for &(session, num) in &other.0 {
+            result.insert(RevId { session1: session.0, session2: session.1, num });
+        }
+        result
+    }
+
+    /// The revisions in both sets.
+    pub fn intersection(&self, other: &Versions) -> Versions {
+        Versions(self.0.iter()
+            .filter_map(|&(session, num)| other.get(session).map(|n| (session, num.min(n))))
+            .collect())
+    }
+
+    fn get(&self, session: SessionId) -> Option<u32> {
+        self.0.binary_search_by_key(&session, |&(s, _)| s).ok().map(|ix| self.0[ix].1)
+    }
+}
+
+impl Revisions {
+    /// The revisions of the engine these were taken from.
+    pub fn versions(&self) -> Versions {
+        let mut versions = self.base.clone();
+        for op in &self.ops {
+            versions.insert(op.rev_id);
+        }
+        versions
+    }
+
+    /// Whether there are no revisions to merge.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
 impl RevId {
     /// Returns a u64 that will be equal for equivalent revision IDs and
     /// should be as unlikely to collide as two random u64s.
@@ -304,9 +411,31 @@ impl Engine {
     pub fn delta_rev_head(&self, base_rev: RevToken) -> Delta<RopeInfo> {
         let ix = self.find_rev_token(base_rev).expect("base revision not found");
         let prev_from_union = self.deletes_from_cur_union_for_index(ix);
+        self.delta_from_union_head(&prev_from_union)
+    }
+
+    /// A delta that, when applied to the text the head would have if exactly
+    /// `groups` were undone, results in the current head. Unlike revisions,
+    /// this is not affected by gc dropping undo history.
+    pub fn delta_undo_head(&self, groups: &BTreeSet<usize>) -> Delta<RopeInfo> {
+        let (_, prev_from_union) = self.compute_undo(groups);
+        self.delta_from_union_head(&prev_from_union)
+    }
+
+    /// A delta that, when applied to the current head, results in the text
+    /// the head would have with exactly `groups` undone. Applying it as an
+    /// edit has the effect of an undo without adding an undo revision.
+    pub fn delta_head_undo(&self, groups: &BTreeSet<usize>) -> Delta<RopeInfo> {
+        let (_, new_from_union) = self.compute_undo(groups);
+        Delta::synthesize(&self.tombstones, &self.deletes_from_union, &new_from_union)
+    }
+
+    /// A delta from the text obtained by deleting `prev_from_union` from the
+    /// current union string to the current head.
+    fn delta_from_union_head(&self, prev_from_union: &Subset) -> Delta<RopeInfo> {
         // TODO: this does 2 calls to Delta::synthesize and 1 to apply, this probably could be better.
-        let old_tombstones = shuffle_tombstones(&self.text, &self.tombstones, &self.deletes_from_union, &prev_from_union);
-        Delta::synthesize(&old_tombstones, &prev_from_union, &self.deletes_from_union)
+        let old_tombstones = shuffle_tombstones(&self.text, &self.tombstones, &self.deletes_from_union, prev_from_union);
+        Delta::synthesize(&old_tombstones, prev_from_union, &self.deletes_from_union)
     }
 
     // TODO: don't construct transform if subsets are empty
@@ -592,6 +721,158 @@ impl Engine {
         self.revs.append(&mut new_revs);
     }
 
+    /// The revisions this engine has.
+    pub fn versions(&self) -> Versions {
+        let mut versions = Versions::default();
+        for rev in &self.revs {
+            versions.insert(rev.rev_id);
+        }
+        versions
+    }
+
+    /// Returns the revisions of this engine that are not in `known`, which
+    /// can be merged with `merge_revisions` into an engine that has the
+    /// revisions in `known`. This is much less to send than the whole
+    /// engine when `known` is most of it.
+    pub fn revisions_since(&self, known: &Versions) -> Revisions {
+        let common = self.revs[1..].iter()
+            .map(|rev| rev.rev_id)
+            .filter(|&rev_id| known.contains(rev_id))
+            .collect::<BTreeSet<_>>();
+        let new = rearrange(&self.revs[1..], &common, self.deletes_from_union.len());
+        let ops = compute_deltas(&new, &self.text, &self.tombstones, &self.deletes_from_union)
+            .into_iter()
+            .map(|op| RevisionOp {
+                rev_id: op.rev_id,
+                priority: op.priority,
+                undo_group: op.undo_group,
+                inserts: (*op.inserts).clone(),
+                deletes: op.deletes,
+            })
+            .collect();
+        Revisions { base: known.intersection(&self.versions()), ops }
+    }
+
+    /// Merges revisions taken from another engine with `revisions_since`,
+    /// and returns the delta from that engine's text to the merged text.
+    ///
+    /// Unlike `merge`, the revisions are checked against this engine
+    /// first, and it is left unchanged if they don't fit.
+    pub fn merge_revisions(&mut self, revisions: &Revisions) -> Result<Delta<RopeInfo>, MergeError> {
+        let versions = self.versions();
+        if !versions.includes(&revisions.base) {
+            return Err(MergeError::MissingBase);
+        }
+        let mut seen = versions.clone();
+        for op in &revisions.ops {
+            if seen.contains(op.rev_id) {
+                return Err(MergeError::Duplicate);
+            }
+            seen.insert(op.rev_id);
+        }
+
+        let common = self.revs[1..].iter()
+            .map(|rev| rev.rev_id)
+            .filter(|&rev_id| revisions.base.contains(rev_id))
+            .collect::<BTreeSet<_>>();
+        let ours = rearrange(&self.revs[1..], &common, self.deletes_from_union.len());
+
+        // Work out which characters of the union string the other engine's
+        // text leaves out, starting from the revisions both have.
+        let mut from_union = self.deletes_from_union.clone();
+        for rev in ours.iter().rev() {
+            if let Edit { ref inserts, ref deletes, ref undo_group, .. } = rev.edit {
+                if !self.undone_groups.contains(undo_group) {
+                    from_union = from_union.subtract(deletes);
+                }
+                from_union = from_union.transform_shrink(inserts);
+            }
+        }
+        let mut ours_inserted = Subset::new(from_union.len());
+        for rev in &ours {
+            if let Edit { ref inserts, .. } = rev.edit {
+                ours_inserted = ours_inserted.transform_union(inserts);
+            }
+        }
+
+        let mut ops = Vec::with_capacity(revisions.ops.len());
+        for op in &revisions.ops {
+            if op.inserts.base_len != from_union.len() || !is_insert_only(&op.inserts) {
+                return Err(MergeError::Malformed);
+            }
+            let (inserts, _) = op.inserts.clone().factor();
+            let inserted = inserts.inserted_subset();
+            if op.deletes.len() != inserted.len() {
+                return Err(MergeError::Malformed);
+            }
+            from_union = from_union.transform_expand(&inserted).union(&op.deletes);
+            ops.push(DeltaOp {
+                rev_id: op.rev_id,
+                priority: op.priority,
+                undo_group: op.undo_group,
+                inserts,
+                deletes: op.deletes.clone(),
+            });
+        }
+
+        let expand_by = compute_transforms(ours);
+        let max_undo = self.max_undo_group_id();
+        let (mut new_revs, text, tombstones, deletes_from_union) =
+            rebase(expand_by, ops, self.text.clone(), self.tombstones.clone(),
+                   self.deletes_from_union.clone(), max_undo);
+
+        // the other text also leaves out what our own revisions inserted
+        let mut theirs_inserted = Subset::new(self.deletes_from_union.len());
+        for rev in &new_revs {
+            if let Edit { ref inserts, .. } = rev.edit {
+                theirs_inserted = theirs_inserted.transform_union(inserts);
+            }
+        }
+        let from_union = from_union.transform_union(&ours_inserted.transform_expand(&theirs_inserted));
+
+        self.text = text;
+        self.tombstones = tombstones;
+        self.deletes_from_union = deletes_from_union;
+        self.revs.append(&mut new_revs);
+        Ok(self.delta_from_union_head(&from_union))
+    }
+
+    /// Returns those of `groups` that engines having at least the revisions
+    /// in `everywhere` can all garbage collect, leaving them with the same
+    /// union string. These are the groups all of whose edits are in
+    /// `everywhere`, except for undone groups and the groups of the edits
+    /// in `keep`, of the head, and of the newest edit of each session in
+    /// `everywhere`, which `gc` and `versions` can't do without.
+    pub fn collectible_groups(&self, groups: &BTreeSet<usize>, everywhere: &Versions,
+                              keep: &BTreeSet<RevId>) -> BTreeSet<usize> {
+        let head = self.get_head_rev_id();
+        let mut result = groups - &self.undone_groups;
+        for rev in &self.revs {
+            if let Edit { undo_group, .. } = rev.edit {
+                let rev_id = rev.rev_id;
+                if !everywhere.contains(rev_id) || rev_id == head || keep.contains(&rev_id)
+                    || everywhere.get(rev_id.session_id()) == Some(rev_id.num) {
+                    result.remove(&undo_group);
+                }
+            }
+        }
+        result
+    }
+
+    /// Prepares an `Engine` that was deserialized for further edits. The
+    /// revision counter is not serialized, so without this new revisions
+    /// could reuse the ids of deserialized revisions from the same session.
+    pub fn continue_session(&mut self) {
+        let session = self.session;
+        let max_num = self.revs.iter()
+            .filter(|rev| rev.rev_id.session_id() == session)
+            .map(|rev| rev.rev_id.num)
+            .max();
+        if let Some(num) = max_num {
+            self.rev_id_counter = num + 1;
+        }
+    }
+
     /// When merging between multiple concurrently-editing sessions, each session should have a unique ID
     /// set with this function, which will make the revisions they create not have colliding IDs.
     /// For safety, this will panic if any revisions have already been added to the Engine.
@@ -630,6 +911,20 @@ fn shuffle(text: &Rope, tombstones: &Rope,
 
 // ======== Merge helpers
 
+/// Whether `delta` only inserts, copying all of its base in order.
+fn is_insert_only(delta: &Delta<RopeInfo>) -> bool {
+    let mut copied = 0;
+    for el in &delta.els {
+        if let DeltaElement::Copy(start, end) = *el {
+            if start != copied || end < start {
+                return false;
+            }
+            copied = end;
+        }
+    }
+    copied == delta.base_len
+}
+
 /// Find an index before which everything is the same
 fn find_base_index(a: &[Revision], b: &[Revision]) -> usize {
     assert!(!a.is_empty() && !b.is_empty());
@@ -825,6 +1120,7 @@ mod tests {
     use multiset::Subset;
     use interval::Interval;
     use std::collections::BTreeSet;
+    use serde_json;
     use test_helpers::{parse_subset_list, parse_subset, parse_delta, debug_subsets};
 
     const TEST_STR: &'static str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
@@ -956,6 +1252,78 @@ mod tests {
         assert_eq!("a0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz", String::from(engine.get_head()));
     }
 
+    #[test]
+    fn serialize_and_continue() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let d1 = Delta::simple_edit(Interval::new_closed_open(0,10), Rope::from(""), TEST_STR.len());
+        let first_rev = engine.get_head_rev_id().token();
+        engine.edit_rev(1, 1, first_rev, d1);
+        engine.undo([1].iter().cloned().collect());
+
+        let json = serde_json::to_string(&engine).unwrap();
+        let mut engine: Engine = serde_json::from_str(&json).unwrap();
+        engine.continue_session();
+        assert_eq!(TEST_STR, String::from(engine.get_head()));
+        let head = engine.get_head_rev_id();
+        let d2 = Delta::simple_edit(Interval::new_closed_open(0,0), Rope::from("a"), TEST_STR.len());
+        engine.edit_rev(1, 2, head.token(), d2);
+        assert!(engine.get_head_rev_id() != head);
+        assert!(!engine.is_equivalent_revision(head, engine.get_head_rev_id()));
+        engine.undo([].iter().cloned().collect());
+        assert_eq!("aABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz", String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn delta_undo_head() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let d1 = Delta::simple_edit(Interval::new_closed_open(0,10), Rope::from(""), TEST_STR.len());
+        let first_rev = engine.get_head_rev_id().token();
+        engine.edit_rev(1, 1, first_rev, d1);
+        let new_head = engine.get_head_rev_id().token();
+        let d2 = Delta::simple_edit(Interval::new_closed_open(0,0), Rope::from("a"), TEST_STR.len() - 10);
+        engine.edit_rev(1, 2, new_head, d2);
+        engine.undo([2].iter().cloned().collect());
+        assert_eq!("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz", String::from(engine.get_head()));
+
+        let groups = [1].iter().cloned().collect();
+        let delta = engine.delta_undo_head(&groups);
+        let undone_text = Rope::from("a0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz");
+        assert_eq!(String::from(engine.get_head()), String::from(delta.apply(&undone_text)));
+        let delta = engine.delta_undo_head(&engine.undone_groups.clone());
+        assert_eq!(String::from(engine.get_head()), String::from(delta.apply(engine.get_head())));
+    }
+
+    #[test]
+    fn delta_head_undo() {
+        let mut a = Engine::empty();
+        let mut b = Engine::empty();
+        a.set_session_id((1, 0));
+        b.set_session_id((2, 0));
+        let head = a.get_head_rev_id().token();
+        a.edit_rev(0, 0, head, Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("abc"), 0));
+        b.merge(&a);
+        let d1 = Delta::simple_edit(Interval::new_closed_open(3, 3), Rope::from("de"), 3);
+        let head = a.get_head_rev_id().token();
+        a.edit_rev(1, 1, head, d1);
+        let d2 = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("X"), 3);
+        let head = b.get_head_rev_id().token();
+        b.edit_rev(1, 2, head, d2);
+
+        // undo group 1 as an edit in group 3, which leaves a mergeable history
+        let delta = a.delta_head_undo(&[1].iter().cloned().collect());
+        let head = a.get_head_rev_id().token();
+        a.edit_rev(1, 3, head, delta);
+        assert_eq!("abc", String::from(a.get_head()));
+        a.merge(&b);
+        b.merge(&a);
+        assert_eq!("Xabc", String::from(a.get_head()));
+        assert_eq!("Xabc", String::from(b.get_head()));
+
+        // and redo it by undoing the compensating group
+        let delta = a.delta_head_undo(&[3].iter().cloned().collect());
+        assert_eq!("Xabcde", String::from(delta.apply(a.get_head())));
+    }
+
     #[test]
     fn undo_5() {
         let mut engine = Engine::new(Rope::from(TEST_STR));
@@ -1588,4 +1956,76 @@ mod tests {
         ];
         MergeTestState::new(3).run_script(&script[..]);
     }
+
+    fn edit(engine: &mut Engine, undo_group: usize, delta: &str) {
+        let head = engine.get_head_rev_id().token();
+        engine.edit_rev(1, undo_group, head, parse_delta(delta));
+    }
+
+    /// Sends `to` the revisions of `from` it doesn't have, and returns the
+    /// delta from the text of `from` to the merged text.
+    fn send_revisions(from: &Engine, to: &mut Engine) -> Delta<RopeInfo> {
+        let revisions = from.revisions_since(&to.versions());
+        let json = serde_json::to_string(&revisions).unwrap();
+        to.merge_revisions(&serde_json::from_str(&json).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn merge_revisions() {
+        let mut a = Engine::new(Rope::from(""));
+        a.set_session_id((1, 0));
+        let mut b = Engine::new(Rope::from(""));
+        b.set_session_id((2, 0));
+        edit(&mut a, 1, "ab");
+        send_revisions(&a, &mut b);
+        assert_eq!("ab", String::from(b.get_head()));
+
+        edit(&mut a, 2, "-c-");
+        edit(&mut b, 3, "!-");
+        edit(&mut b, 3, "-p");
+        let delta = send_revisions(&a, &mut b);
+        assert_eq!("cbp", String::from(b.get_head()));
+        assert_eq!("cbp", String::from(delta.apply(a.get_head())));
+        let delta = send_revisions(&b, &mut a);
+        assert_eq!("cbp", String::from(a.get_head()));
+        assert!(delta.is_identity());
+
+        let again = a.revisions_since(&Versions::default());
+        assert_eq!(Err(MergeError::Duplicate), b.merge_revisions(&again).map(|_| ()));
+        let mut c = Engine::new(Rope::from(""));
+        c.set_session_id((3, 0));
+        let unknown_base = a.revisions_since(&b.versions());
+        assert_eq!(Err(MergeError::MissingBase), c.merge_revisions(&unknown_base).map(|_| ()));
+        assert_eq!("", String::from(c.get_head()));
+    }
+
+    #[test]
+    fn gc_collectible_groups() {
+        let mut a = Engine::new(Rope::from(""));
+        a.set_session_id((1, 0));
+        let mut b = Engine::new(Rope::from(""));
+        b.set_session_id((2, 0));
+        edit(&mut a, 1, "abc");
+        edit(&mut a, 2, "-!-");
+        edit(&mut a, 3, "--d");
+        send_revisions(&a, &mut b);
+        let b_versions = b.versions();
+        let b_head = b.get_head_rev_id();
+        edit(&mut b, 4, "x---");
+        edit(&mut a, 5, "---e");
+
+        // the newest edit of each session that everyone has is kept
+        let everywhere = a.versions().intersection(&b_versions);
+        let keep = [b_head].iter().cloned().collect();
+        let groups = a.collectible_groups(&[1, 2, 3, 5].iter().cloned().collect(),
+                                          &everywhere, &keep);
+        assert_eq!(vec![1, 2], groups.iter().cloned().collect::<Vec<_>>());
+        a.gc(&groups);
+        b.gc(&groups);
+
+        send_revisions(&a, &mut b);
+        send_revisions(&b, &mut a);
+        assert_eq!("xacde", String::from(a.get_head()));
+        assert_eq!("xacde", String::from(b.get_head()));
+    }
 }
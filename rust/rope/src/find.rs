@@ -17,7 +17,11 @@
 use std::cmp::min;
 
 use memchr::{memchr, memchr2, memchr3};
+use regex::{Match, Regex};
+use regex_syntax::ParserBuilder;
+use regex_syntax::hir::{Class, Hir, HirKind, Literal};
 
+use fold::{Folded, accent_fold, case_fold, fold_str};
 use rope::{BaseMetric, RopeInfo};
 use tree::Cursor;
 
@@ -34,9 +38,7 @@ pub enum FindResult {
     TryAgain,
 }
 
-/// A policy for case matching. There may be more choices in the future (for
-/// example, an even more forgiving mode that ignores accents, or possibly
-/// handling Unicode normalization).
+/// A policy for case matching.
 #[derive(Clone, Copy, PartialEq)]
 pub enum CaseMatching {
     /// Require an exact codepoint-for-codepoint match (implies case sensitivity).
@@ -45,6 +47,14 @@ pub enum CaseMatching {
     /// reasonably well otherwise (it is currently defined in terms of the
     /// `to_lowercase` methods in the Rust standard library).
     CaseInsensitive,
+    /// Case insensitive match using full Unicode case folding, so that for
+    /// example "strasse" matches "Straße". Matches always span whole
+    /// codepoints of the rope.
+    CaseFolding,
+    /// Like `CaseFolding`, but also ignoring diacritics, so that for example
+    /// "resume" matches "résumé" whether or not the accents are precomposed.
+    /// Combining marks following a match are included in it.
+    AccentInsensitive,
 }
 
 /// Finds a pattern string in the rope referenced by the cursor, starting at
@@ -109,9 +119,29 @@ pub fn find_progress(cursor: &mut Cursor<RopeInfo>, cm: CaseMatching, pat: &str,
                 find_progress_iter(cursor, &pat_lower, &scanner, &matcher, num_steps)
             }
         }
+        CaseMatching::CaseFolding =>
+            find_progress_folded(cursor, pat, case_fold, num_steps),
+        CaseMatching::AccentInsensitive =>
+            find_progress_folded(cursor, pat, accent_fold, num_steps),
     }
 }
 
+// Find, comparing the rope and the pattern after applying `fold` to both.
+fn find_progress_folded(cursor: &mut Cursor<RopeInfo>, pat: &str,
+        fold: fn(char) -> Folded, num_steps: usize) -> FindResult
+{
+    let pat_folded = fold_str(pat, fold);
+    let c = match pat_folded.chars().next() {
+        Some(c) => c,
+        // e.g. a pattern of only combining marks, when ignoring accents
+        None => return FindResult::NotFound,
+    };
+    let scanner = |s: &str| scan_folded(c, fold, s);
+    let matcher = |cursor: &mut Cursor<RopeInfo>, pat: &str|
+        compare_cursor_str_folded(cursor, pat, fold);
+    find_progress_iter(cursor, &pat_folded, &scanner, &matcher, num_steps)
+}
+
 // Run the core repeatedly until there is a result, up to a certain number of steps.
 fn find_progress_iter(cursor: &mut Cursor<RopeInfo>, pat: &str,
         scanner: &Fn(&str) -> Option<usize>,
@@ -207,6 +237,172 @@ fn compare_cursor_str_casei(cursor: &mut Cursor<RopeInfo>, pat: &str) -> bool {
     }
 }
 
+/// Like `compare_cursor_str` but comparing the rope after applying `fold`
+/// to it; `pat` is expected to be folded already. A match can't end in the
+/// middle of the folding of a codepoint, and includes any codepoints that
+/// fold to nothing immediately after it.
+fn compare_cursor_str_folded(cursor: &mut Cursor<RopeInfo>, pat: &str,
+                             fold: fn(char) -> Folded) -> bool {
+    let mut pat_iter = pat.chars().peekable();
+    while pat_iter.peek().is_some() {
+        match cursor.next_codepoint() {
+            Some(rope_c) => {
+                for fc in fold(rope_c) {
+                    if pat_iter.next() != Some(fc) {
+                        return false;
+                    }
+                }
+            }
+            // end of string before pattern is complete
+            None => return false,
+        }
+    }
+    loop {
+        let pos = cursor.pos();
+        match cursor.next_codepoint() {
+            Some(rope_c) if fold(rope_c).is_empty() => (),
+            _ => {
+                cursor.set(pos);
+                return true;
+            }
+        }
+    }
+}
+
+/// Finds the first match of `regex` in the rope referenced by the cursor,
+/// starting at the current location of the cursor. Empty matches are skipped.
+///
+/// Matching is done a line at a time, with the whole line available as
+/// context so that anchors and word boundaries behave as expected. If the
+/// pattern can match across line breaks (see [`is_multiline_regex`]), the
+/// remainder of the rope is matched as a single string instead, which is
+/// considerably more expensive; use a [`RegexSearcher`] to find several
+/// matches without copying the text out of the rope each time.
+///
+/// As with [`find`][find], on success the cursor is updated to immediately
+/// follow the match, and the start of the match is returned.
+///
+/// [find]: fn.find.html
+/// [`is_multiline_regex`]: fn.is_multiline_regex.html
+/// [`RegexSearcher`]: struct.RegexSearcher.html
+pub fn find_regex(cursor: &mut Cursor<RopeInfo>, regex: &Regex) -> Option<usize> {
+    RegexSearcher::new(regex).find(cursor)
+}
+
+/// Finds successive matches of a regex in a single rope, as
+/// [`find_regex`][find_regex] does.
+///
+/// For a pattern that can match across line breaks, the text from the line
+/// of the first search to the end of the rope is copied out once and reused
+/// by later searches, as long as they don't start before it.
+///
+/// [find_regex]: fn.find_regex.html
+pub struct RegexSearcher<'a> {
+    regex: &'a Regex,
+    multiline: bool,
+    /// The offset and contents of the copied text, for a multi-line pattern.
+    hay: Option<(usize, String)>,
+}
+
+impl<'a> RegexSearcher<'a> {
+    pub fn new(regex: &'a Regex) -> RegexSearcher<'a> {
+        RegexSearcher {
+            regex,
+            multiline: is_multiline_regex(regex.as_str()),
+            hay: None,
+        }
+    }
+
+    /// Finds the first match at or after the cursor, updating the cursor
+    /// to follow it. All calls must be made with cursors on the same rope.
+    pub fn find(&mut self, cursor: &mut Cursor<RopeInfo>) -> Option<usize> {
+        let text = cursor.root();
+        let pos = cursor.pos();
+
+        if self.multiline {
+            let is_valid = match self.hay {
+                Some((start, ref hay)) => start <= pos && start + hay.len() == text.len(),
+                None => false,
+            };
+            if !is_valid {
+                let line_start = text.offset_of_line(text.line_of_offset(pos));
+                self.hay = Some((line_start, text.slice_to_string(line_start, text.len())));
+            }
+            let (start, ref hay) = *self.hay.as_ref().unwrap();
+            return find_non_empty(self.regex, hay, pos - start).map(|mat| {
+                cursor.set(start + mat.end());
+                start + mat.start()
+            });
+        }
+
+        let mut line_offset = text.offset_of_line(text.line_of_offset(pos));
+        let mut from = pos - line_offset;
+        for line in text.lines_raw(line_offset, text.len()) {
+            if let Some(mat) = find_non_empty(self.regex, &line, from) {
+                cursor.set(line_offset + mat.end());
+                return Some(line_offset + mat.start());
+            }
+            line_offset += line.len();
+            from = 0;
+        }
+        None
+    }
+}
+
+/// Returns `true` if the regular expression `pat` may match a line break,
+/// and therefore can't be matched one line at a time.
+///
+/// This checks whether any literal or character class in the parsed pattern
+/// includes a newline, so it accounts for escapes such as `\s`, negated
+/// classes and the `s` flag. Patterns that fail to parse are assumed to be
+/// multi-line.
+pub fn is_multiline_regex(pat: &str) -> bool {
+    match ParserBuilder::new().multi_line(true).build().parse(pat) {
+        Ok(hir) => hir_matches_newline(&hir),
+        Err(_) => true,
+    }
+}
+
+fn hir_matches_newline(hir: &Hir) -> bool {
+    match *hir.kind() {
+        HirKind::Literal(Literal::Unicode(c)) => c == '\n',
+        HirKind::Literal(Literal::Byte(b)) => b == b'\n',
+        HirKind::Class(Class::Unicode(ref class)) =>
+            class.iter().any(|r| r.start() <= '\n' && '\n' <= r.end()),
+        HirKind::Class(Class::Bytes(ref class)) =>
+            class.iter().any(|r| r.start() <= b'\n' && b'\n' <= r.end()),
+        HirKind::Repetition(ref rep) => hir_matches_newline(&rep.hir),
+        HirKind::Group(ref group) => hir_matches_newline(&group.hir),
+        HirKind::Concat(ref hirs) | HirKind::Alternation(ref hirs) =>
+            hirs.iter().any(hir_matches_newline),
+        HirKind::Empty | HirKind::Anchor(_) | HirKind::WordBoundary(_) => false,
+    }
+}
+
+// Finds the first non-empty match at or after `from`.
+fn find_non_empty<'t>(regex: &Regex, hay: &'t str, mut from: usize) -> Option<Match<'t>> {
+    while from <= hay.len() {
+        match regex.find_at(hay, from) {
+            Some(mat) if mat.start() == mat.end() => {
+                // step over the empty match, staying on a char boundary
+                match hay[mat.end()..].chars().next() {
+                    Some(c) => from = mat.end() + c.len_utf8(),
+                    None => return None,
+                }
+            }
+            result => return result,
+        }
+    }
+    None
+}
+
+/// Scan for a codepoint whose folding starts with the probe.
+fn scan_folded(probe: char, fold: fn(char) -> Folded, s: &str) -> Option<usize> {
+    s.char_indices()
+        .find(|&(_, c)| fold(c).next() == Some(probe))
+        .map(|(i, _)| i)
+}
+
 /// Scan for a codepoint that, after conversion to lowercase, matches the probe.
 fn scan_lowercase(probe: char, s: &str) -> Option<usize> {
     for (i, c) in s.char_indices() {
@@ -220,7 +416,8 @@ fn scan_lowercase(probe: char, s: &str) -> Option<usize> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use super::CaseMatching::{Exact, CaseInsensitive};
+    use super::CaseMatching::{Exact, CaseInsensitive, CaseFolding, AccentInsensitive};
+    use regex::Regex;
     use tree::Cursor;
     use rope::Rope;
 
@@ -325,6 +522,115 @@ mod tests {
         assert_eq!(find(&mut c, CaseInsensitive, "i"), Some(2));
     }
 
+    #[test]
+    fn find_case_folding() {
+        let a = Rope::from("Straße STRASSE ﬁnal ΣΑΣ");
+        let mut c = Cursor::new(&a, 0);
+        assert_eq!(find(&mut c, CaseFolding, "strasse"), Some(0));
+        assert_eq!(c.pos(), 7);
+        assert_eq!(find(&mut c, CaseFolding, "strasse"), Some(8));
+        assert_eq!(find(&mut c, CaseFolding, "FINAL"), Some(16));
+        assert_eq!(find(&mut c, CaseFolding, "σας"), Some(23));
+
+        // matches can't end in the middle of a folded codepoint
+        let mut c = Cursor::new(&a, 0);
+        assert_eq!(find(&mut c, CaseFolding, "stras"), Some(8));
+        let mut c = Cursor::new(&a, 0);
+        assert_eq!(find(&mut c, CaseFolding, "fi"), Some(16));
+        assert_eq!(find(&mut c, CaseFolding, "f"), None);
+    }
+
+    #[test]
+    fn find_accent_insensitive() {
+        let a = Rope::from("r\u{e9}sum\u{e9} Re\u{301}sume\u{301} resume");
+        let mut c = Cursor::new(&a, 0);
+        assert_eq!(find(&mut c, AccentInsensitive, "resume"), Some(0));
+        assert_eq!(c.pos(), 8);
+        // trailing combining marks are part of the match
+        assert_eq!(find(&mut c, AccentInsensitive, "RESUME"), Some(9));
+        assert_eq!(c.pos(), 19);
+        assert_eq!(find(&mut c, AccentInsensitive, "r\u{e9}sum\u{e9}"), Some(20));
+        assert_eq!(find(&mut c, AccentInsensitive, "\u{301}"), None);
+
+        let mut c = Cursor::new(&a, 0);
+        assert_eq!(find(&mut c, CaseFolding, "resume"), Some(20));
+    }
+
+    #[test]
+    fn find_regex_small() {
+        let a = Rope::from("Löwe 老虎 Léopard\nlion tiger leopard");
+        let mut c = Cursor::new(&a, 0);
+        let re = Regex::new(r"L\w+").unwrap();
+        assert_eq!(find_regex(&mut c, &re), Some(0));
+        assert_eq!(c.pos(), 5);
+        assert_eq!(find_regex(&mut c, &re), Some(13));
+        assert_eq!(c.pos(), 21);
+        assert_eq!(find_regex(&mut c, &re), None);
+        c.set(0);
+        let re = Regex::new(r"(?i)l\w+").unwrap();
+        assert_eq!(find_regex(&mut c, &re), Some(0));
+        assert_eq!(find_regex(&mut c, &re), Some(13));
+        assert_eq!(find_regex(&mut c, &re), Some(22));
+        assert_eq!(find_regex(&mut c, &re), Some(33));
+        assert_eq!(find_regex(&mut c, &re), None);
+    }
+
+    #[test]
+    fn find_regex_anchors() {
+        let a = Rope::from("foo bar\nbar foo\nbar");
+        let mut c = Cursor::new(&a, 0);
+        let re = Regex::new(r"(?m)^bar").unwrap();
+        assert_eq!(find_regex(&mut c, &re), Some(8));
+        assert_eq!(find_regex(&mut c, &re), Some(16));
+        assert_eq!(find_regex(&mut c, &re), None);
+        // starting mid-line must not make `^` match at the cursor
+        c.set(4);
+        assert_eq!(find_regex(&mut c, &re), Some(8));
+        c.set(0);
+        let re = Regex::new(r"(?m)foo$").unwrap();
+        assert_eq!(find_regex(&mut c, &re), Some(12));
+        assert_eq!(find_regex(&mut c, &re), None);
+    }
+
+    #[test]
+    fn find_regex_multiline() {
+        let a = Rope::from("one\ntwo\nthree");
+        let mut c = Cursor::new(&a, 0);
+        let re = Regex::new(r"o\nt").unwrap();
+        assert_eq!(find_regex(&mut c, &re), Some(6));
+        assert_eq!(c.pos(), 9);
+        assert_eq!(find_regex(&mut c, &re), None);
+        assert!(is_multiline_regex(r"o\nt"));
+        assert!(is_multiline_regex(r"o\st"));
+        assert!(is_multiline_regex(r"o[^x]t"));
+        assert!(is_multiline_regex(r"(?s)o.t"));
+        assert!(is_multiline_regex(r"o\Wt"));
+        assert!(!is_multiline_regex(r"o.t"));
+        assert!(!is_multiline_regex(r"(?m)^o\S+t$"));
+
+        // the searcher copies the text once and keeps finding matches in it
+        let a = Rope::from("a\nb a\nb\na");
+        let re = Regex::new(r"a\s").unwrap();
+        let mut searcher = RegexSearcher::new(&re);
+        let mut c = Cursor::new(&a, 0);
+        assert_eq!(searcher.find(&mut c), Some(0));
+        assert_eq!(searcher.find(&mut c), Some(4));
+        assert_eq!(searcher.find(&mut c), None);
+        c.set(2);
+        assert_eq!(searcher.find(&mut c), Some(4));
+    }
+
+    #[test]
+    fn find_regex_skips_empty() {
+        let a = Rope::from("xaxxaa");
+        let mut c = Cursor::new(&a, 0);
+        let re = Regex::new(r"a*").unwrap();
+        assert_eq!(find_regex(&mut c, &re), Some(1));
+        assert_eq!(find_regex(&mut c, &re), Some(4));
+        assert_eq!(c.pos(), 6);
+        assert_eq!(find_regex(&mut c, &re), None);
+    }
+
     #[test]
     fn compare_cursor_str_small() {
         let a = Rope::from("Löwe 老虎 Léopard");
@@ -0,0 +1,493 @@
+// Copyright 2018 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unicode case folding and removal of diacritics, used for the more
+//! forgiving [`CaseMatching`] policies of [`find`].
+//!
+//! [`CaseMatching`]: ../find/enum.CaseMatching.html
+//! [`find`]: ../find/fn.find.html
+
+/// The result of folding a single codepoint, which can expand to up to three
+/// codepoints, or (for combining marks, when removing diacritics) to none.
+pub struct Folded {
+    chars: [char; 3],
+    len: usize,
+    ix: usize,
+}
+
+impl Folded {
+    fn empty() -> Folded {
+        Folded { chars: ['\0'; 3], len: 0, ix: 0 }
+    }
+
+    fn push(&mut self, c: char) {
+        self.chars[self.len] = c;
+        self.len += 1;
+    }
+
+    fn from_str(s: &str) -> Folded {
+        let mut result = Folded::empty();
+        for c in s.chars() {
+            result.push(c);
+        }
+        result
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ix == self.len
+    }
+}
+
+impl Iterator for Folded {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if self.ix < self.len {
+            self.ix += 1;
+            Some(self.chars[self.ix - 1])
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the full case folding of `c`, as defined by the `C` and `F`
+/// mappings of the Unicode `CaseFolding.txt`. For example, `'ß'` folds to
+/// `"ss"` and the final sigma `'ς'` to `'σ'`.
+pub fn case_fold(c: char) -> Folded {
+    if c.is_ascii() {
+        let mut result = Folded::empty();
+        result.push(c.to_ascii_lowercase());
+        return result;
+    }
+    match CASE_FOLDING.binary_search_by_key(&c, |&(k, _)| k) {
+        Ok(ix) => Folded::from_str(CASE_FOLDING[ix].1),
+        // for everything else, folding agrees with lowercasing
+        Err(_) => {
+            let mut result = Folded::empty();
+            for lc in c.to_lowercase() {
+                result.push(lc);
+            }
+            result
+        }
+    }
+}
+
+/// Returns the case folding of `c` with any diacritics removed. Combining
+/// marks fold to nothing, and precomposed letters to the case folding of
+/// their base letter, so that for example both `"\u{e9}"` and
+/// `"e\u{301}"` fold to `"e"`.
+pub fn accent_fold(c: char) -> Folded {
+    if c.is_ascii() {
+        return case_fold(c);
+    }
+    let base = match BASE_LETTERS.binary_search_by_key(&c, |&(k, _)| k) {
+        Ok(ix) => BASE_LETTERS[ix].1,
+        Err(_) => c,
+    };
+    // some foldings (such as that of 'ǰ') introduce combining marks
+    let mut result = Folded::empty();
+    for fc in case_fold(base).filter(|&fc| !is_combining_mark(fc)) {
+        result.push(fc);
+    }
+    result
+}
+
+/// Folds every codepoint in `s` using `fold`.
+pub fn fold_str(s: &str, fold: fn(char) -> Folded) -> String {
+    s.chars().flat_map(fold).collect()
+}
+
+fn is_combining_mark(c: char) -> bool {
+    COMBINING_MARKS.iter().any(|&(start, end)| start <= c && c <= end)
+}
+
+// The tables below are maintained by hand, following the Unicode character
+// database. They only cover the scripts people commonly search in: the
+// combining marks are the blocks of combining diacritical marks,
+// `CASE_FOLDING` has the entries of `CaseFolding.txt` where full folding
+// differs from `char::to_lowercase`, and `BASE_LETTERS` has the precomposed
+// Latin, Greek and Cyrillic letters between U+00C0 and U+1FFC. Other
+// precomposed letters only match themselves.
+
+/// Blocks of combining diacritical marks.
+const COMBINING_MARKS: &[(char, char)] = &[
+    ('\u{300}', '\u{36f}'),
+    ('\u{1ab0}', '\u{1aff}'),
+    ('\u{1dc0}', '\u{1dff}'),
+    ('\u{20d0}', '\u{20ff}'),
+    ('\u{fe20}', '\u{fe2f}'),
+];
+
+/// Codepoints whose full case folding differs from their lowercase mapping
+/// (in `char::to_lowercase`), with their folding.
+const CASE_FOLDING: &[(char, &str)] = &[
+    ('\u{b5}', "\u{3bc}"), ('\u{df}', "ss"), ('\u{149}', "\u{2bc}n"),
+    ('\u{17f}', "s"), ('\u{1f0}', "j\u{30c}"), ('\u{345}', "\u{3b9}"),
+    ('\u{390}', "\u{3b9}\u{308}\u{301}"), ('\u{3b0}', "\u{3c5}\u{308}\u{301}"),
+    ('\u{3c2}', "\u{3c3}"), ('\u{3d0}', "\u{3b2}"), ('\u{3d1}', "\u{3b8}"), ('\u{3d5}', "\u{3c6}"),
+    ('\u{3d6}', "\u{3c0}"), ('\u{3f0}', "\u{3ba}"), ('\u{3f1}', "\u{3c1}"), ('\u{3f5}', "\u{3b5}"),
+    ('\u{587}', "\u{565}\u{582}"), ('\u{13a0}', "\u{13a0}"), ('\u{13a1}', "\u{13a1}"),
+    ('\u{13a2}', "\u{13a2}"), ('\u{13a3}', "\u{13a3}"), ('\u{13a4}', "\u{13a4}"),
+    ('\u{13a5}', "\u{13a5}"), ('\u{13a6}', "\u{13a6}"), ('\u{13a7}', "\u{13a7}"),
+    ('\u{13a8}', "\u{13a8}"), ('\u{13a9}', "\u{13a9}"), ('\u{13aa}', "\u{13aa}"),
+    ('\u{13ab}', "\u{13ab}"), ('\u{13ac}', "\u{13ac}"), ('\u{13ad}', "\u{13ad}"),
+    ('\u{13ae}', "\u{13ae}"), ('\u{13af}', "\u{13af}"), ('\u{13b0}', "\u{13b0}"),
+    ('\u{13b1}', "\u{13b1}"), ('\u{13b2}', "\u{13b2}"), ('\u{13b3}', "\u{13b3}"),
+    ('\u{13b4}', "\u{13b4}"), ('\u{13b5}', "\u{13b5}"), ('\u{13b6}', "\u{13b6}"),
+    ('\u{13b7}', "\u{13b7}"), ('\u{13b8}', "\u{13b8}"), ('\u{13b9}', "\u{13b9}"),
+    ('\u{13ba}', "\u{13ba}"), ('\u{13bb}', "\u{13bb}"), ('\u{13bc}', "\u{13bc}"),
+    ('\u{13bd}', "\u{13bd}"), ('\u{13be}', "\u{13be}"), ('\u{13bf}', "\u{13bf}"),
+    ('\u{13c0}', "\u{13c0}"), ('\u{13c1}', "\u{13c1}"), ('\u{13c2}', "\u{13c2}"),
+    ('\u{13c3}', "\u{13c3}"), ('\u{13c4}', "\u{13c4}"), ('\u{13c5}', "\u{13c5}"),
+    ('\u{13c6}', "\u{13c6}"), ('\u{13c7}', "\u{13c7}"), ('\u{13c8}', "\u{13c8}"),
+    ('\u{13c9}', "\u{13c9}"), ('\u{13ca}', "\u{13ca}"), ('\u{13cb}', "\u{13cb}"),
+    ('\u{13cc}', "\u{13cc}"), ('\u{13cd}', "\u{13cd}"), ('\u{13ce}', "\u{13ce}"),
+    ('\u{13cf}', "\u{13cf}"), ('\u{13d0}', "\u{13d0}"), ('\u{13d1}', "\u{13d1}"),
+    ('\u{13d2}', "\u{13d2}"), ('\u{13d3}', "\u{13d3}"), ('\u{13d4}', "\u{13d4}"),
+    ('\u{13d5}', "\u{13d5}"), ('\u{13d6}', "\u{13d6}"), ('\u{13d7}', "\u{13d7}"),
+    ('\u{13d8}', "\u{13d8}"), ('\u{13d9}', "\u{13d9}"), ('\u{13da}', "\u{13da}"),
+    ('\u{13db}', "\u{13db}"), ('\u{13dc}', "\u{13dc}"), ('\u{13dd}', "\u{13dd}"),
+    ('\u{13de}', "\u{13de}"), ('\u{13df}', "\u{13df}"), ('\u{13e0}', "\u{13e0}"),
+    ('\u{13e1}', "\u{13e1}"), ('\u{13e2}', "\u{13e2}"), ('\u{13e3}', "\u{13e3}"),
+    ('\u{13e4}', "\u{13e4}"), ('\u{13e5}', "\u{13e5}"), ('\u{13e6}', "\u{13e6}"),
+    ('\u{13e7}', "\u{13e7}"), ('\u{13e8}', "\u{13e8}"), ('\u{13e9}', "\u{13e9}"),
+    ('\u{13ea}', "\u{13ea}"), ('\u{13eb}', "\u{13eb}"), ('\u{13ec}', "\u{13ec}"),
+    ('\u{13ed}', "\u{13ed}"), ('\u{13ee}', "\u{13ee}"), ('\u{13ef}', "\u{13ef}"),
+    ('\u{13f0}', "\u{13f0}"), ('\u{13f1}', "\u{13f1}"), ('\u{13f2}', "\u{13f2}"),
+    ('\u{13f3}', "\u{13f3}"), ('\u{13f4}', "\u{13f4}"), ('\u{13f5}', "\u{13f5}"),
+    ('\u{13f8}', "\u{13f0}"), ('\u{13f9}', "\u{13f1}"), ('\u{13fa}', "\u{13f2}"),
+    ('\u{13fb}', "\u{13f3}"), ('\u{13fc}', "\u{13f4}"), ('\u{13fd}', "\u{13f5}"),
+    ('\u{1c80}', "\u{432}"), ('\u{1c81}', "\u{434}"), ('\u{1c82}', "\u{43e}"),
+    ('\u{1c83}', "\u{441}"), ('\u{1c84}', "\u{442}"), ('\u{1c85}', "\u{442}"),
+    ('\u{1c86}', "\u{44a}"), ('\u{1c87}', "\u{463}"), ('\u{1c88}', "\u{a64b}"),
+    ('\u{1e96}', "h\u{331}"), ('\u{1e97}', "t\u{308}"), ('\u{1e98}', "w\u{30a}"),
+    ('\u{1e99}', "y\u{30a}"), ('\u{1e9a}', "a\u{2be}"), ('\u{1e9b}', "\u{1e61}"),
+    ('\u{1e9e}', "ss"), ('\u{1f50}', "\u{3c5}\u{313}"),
+    ('\u{1f52}', "\u{3c5}\u{313}\u{300}"), ('\u{1f54}', "\u{3c5}\u{313}\u{301}"),
+    ('\u{1f56}', "\u{3c5}\u{313}\u{342}"), ('\u{1f80}', "\u{1f00}\u{3b9}"),
+    ('\u{1f81}', "\u{1f01}\u{3b9}"), ('\u{1f82}', "\u{1f02}\u{3b9}"),
+    ('\u{1f83}', "\u{1f03}\u{3b9}"), ('\u{1f84}', "\u{1f04}\u{3b9}"),
+    ('\u{1f85}', "\u{1f05}\u{3b9}"), ('\u{1f86}', "\u{1f06}\u{3b9}"),
+    ('\u{1f87}', "\u{1f07}\u{3b9}"), ('\u{1f88}', "\u{1f00}\u{3b9}"),
+    ('\u{1f89}', "\u{1f01}\u{3b9}"), ('\u{1f8a}', "\u{1f02}\u{3b9}"),
+    ('\u{1f8b}', "\u{1f03}\u{3b9}"), ('\u{1f8c}', "\u{1f04}\u{3b9}"),
+    ('\u{1f8d}', "\u{1f05}\u{3b9}"), ('\u{1f8e}', "\u{1f06}\u{3b9}"),
+    ('\u{1f8f}', "\u{1f07}\u{3b9}"), ('\u{1f90}', "\u{1f20}\u{3b9}"),
+    ('\u{1f91}', "\u{1f21}\u{3b9}"), ('\u{1f92}', "\u{1f22}\u{3b9}"),
+    ('\u{1f93}', "\u{1f23}\u{3b9}"), ('\u{1f94}', "\u{1f24}\u{3b9}"),
+    ('\u{1f95}', "\u{1f25}\u{3b9}"), ('\u{1f96}', "\u{1f26}\u{3b9}"),
+    ('\u{1f97}', "\u{1f27}\u{3b9}"), ('\u{1f98}', "\u{1f20}\u{3b9}"),
+    ('\u{1f99}', "\u{1f21}\u{3b9}"), ('\u{1f9a}', "\u{1f22}\u{3b9}"),
+    ('\u{1f9b}', "\u{1f23}\u{3b9}"), ('\u{1f9c}', "\u{1f24}\u{3b9}"),
+    ('\u{1f9d}', "\u{1f25}\u{3b9}"), ('\u{1f9e}', "\u{1f26}\u{3b9}"),
+    ('\u{1f9f}', "\u{1f27}\u{3b9}"), ('\u{1fa0}', "\u{1f60}\u{3b9}"),
+    ('\u{1fa1}', "\u{1f61}\u{3b9}"), ('\u{1fa2}', "\u{1f62}\u{3b9}"),
+    ('\u{1fa3}', "\u{1f63}\u{3b9}"), ('\u{1fa4}', "\u{1f64}\u{3b9}"),
+    ('\u{1fa5}', "\u{1f65}\u{3b9}"), ('\u{1fa6}', "\u{1f66}\u{3b9}"),
+    ('\u{1fa7}', "\u{1f67}\u{3b9}"), ('\u{1fa8}', "\u{1f60}\u{3b9}"),
+    ('\u{1fa9}', "\u{1f61}\u{3b9}"), ('\u{1faa}', "\u{1f62}\u{3b9}"),
+    ('\u{1fab}', "\u{1f63}\u{3b9}"), ('\u{1fac}', "\u{1f64}\u{3b9}"),
+    ('\u{1fad}', "\u{1f65}\u{3b9}"), ('\u{1fae}', "\u{1f66}\u{3b9}"),
+    ('\u{1faf}', "\u{1f67}\u{3b9}"), ('\u{1fb2}', "\u{1f70}\u{3b9}"),
+    ('\u{1fb3}', "\u{3b1}\u{3b9}"), ('\u{1fb4}', "\u{3ac}\u{3b9}"), ('\u{1fb6}', "\u{3b1}\u{342}"),
+    ('\u{1fb7}', "\u{3b1}\u{342}\u{3b9}"), ('\u{1fbc}', "\u{3b1}\u{3b9}"), ('\u{1fbe}', "\u{3b9}"),
+    ('\u{1fc2}', "\u{1f74}\u{3b9}"), ('\u{1fc3}', "\u{3b7}\u{3b9}"),
+    ('\u{1fc4}', "\u{3ae}\u{3b9}"), ('\u{1fc6}', "\u{3b7}\u{342}"),
+    ('\u{1fc7}', "\u{3b7}\u{342}\u{3b9}"), ('\u{1fcc}', "\u{3b7}\u{3b9}"),
+    ('\u{1fd2}', "\u{3b9}\u{308}\u{300}"), ('\u{1fd3}', "\u{3b9}\u{308}\u{301}"),
+    ('\u{1fd6}', "\u{3b9}\u{342}"), ('\u{1fd7}', "\u{3b9}\u{308}\u{342}"),
+    ('\u{1fe2}', "\u{3c5}\u{308}\u{300}"), ('\u{1fe3}', "\u{3c5}\u{308}\u{301}"),
+    ('\u{1fe4}', "\u{3c1}\u{313}"), ('\u{1fe6}', "\u{3c5}\u{342}"),
+    ('\u{1fe7}', "\u{3c5}\u{308}\u{342}"), ('\u{1ff2}', "\u{1f7c}\u{3b9}"),
+    ('\u{1ff3}', "\u{3c9}\u{3b9}"), ('\u{1ff4}', "\u{3ce}\u{3b9}"), ('\u{1ff6}', "\u{3c9}\u{342}"),
+    ('\u{1ff7}', "\u{3c9}\u{342}\u{3b9}"), ('\u{1ffc}', "\u{3c9}\u{3b9}"),
+    ('\u{ab70}', "\u{13a0}"), ('\u{ab71}', "\u{13a1}"), ('\u{ab72}', "\u{13a2}"),
+    ('\u{ab73}', "\u{13a3}"), ('\u{ab74}', "\u{13a4}"), ('\u{ab75}', "\u{13a5}"),
+    ('\u{ab76}', "\u{13a6}"), ('\u{ab77}', "\u{13a7}"), ('\u{ab78}', "\u{13a8}"),
+    ('\u{ab79}', "\u{13a9}"), ('\u{ab7a}', "\u{13aa}"), ('\u{ab7b}', "\u{13ab}"),
+    ('\u{ab7c}', "\u{13ac}"), ('\u{ab7d}', "\u{13ad}"), ('\u{ab7e}', "\u{13ae}"),
+    ('\u{ab7f}', "\u{13af}"), ('\u{ab80}', "\u{13b0}"), ('\u{ab81}', "\u{13b1}"),
+    ('\u{ab82}', "\u{13b2}"), ('\u{ab83}', "\u{13b3}"), ('\u{ab84}', "\u{13b4}"),
+    ('\u{ab85}', "\u{13b5}"), ('\u{ab86}', "\u{13b6}"), ('\u{ab87}', "\u{13b7}"),
+    ('\u{ab88}', "\u{13b8}"), ('\u{ab89}', "\u{13b9}"), ('\u{ab8a}', "\u{13ba}"),
+    ('\u{ab8b}', "\u{13bb}"), ('\u{ab8c}', "\u{13bc}"), ('\u{ab8d}', "\u{13bd}"),
+    ('\u{ab8e}', "\u{13be}"), ('\u{ab8f}', "\u{13bf}"), ('\u{ab90}', "\u{13c0}"),
+    ('\u{ab91}', "\u{13c1}"), ('\u{ab92}', "\u{13c2}"), ('\u{ab93}', "\u{13c3}"),
+    ('\u{ab94}', "\u{13c4}"), ('\u{ab95}', "\u{13c5}"), ('\u{ab96}', "\u{13c6}"),
+    ('\u{ab97}', "\u{13c7}"), ('\u{ab98}', "\u{13c8}"), ('\u{ab99}', "\u{13c9}"),
+    ('\u{ab9a}', "\u{13ca}"), ('\u{ab9b}', "\u{13cb}"), ('\u{ab9c}', "\u{13cc}"),
+    ('\u{ab9d}', "\u{13cd}"), ('\u{ab9e}', "\u{13ce}"), ('\u{ab9f}', "\u{13cf}"),
+    ('\u{aba0}', "\u{13d0}"), ('\u{aba1}', "\u{13d1}"), ('\u{aba2}', "\u{13d2}"),
+    ('\u{aba3}', "\u{13d3}"), ('\u{aba4}', "\u{13d4}"), ('\u{aba5}', "\u{13d5}"),
+    ('\u{aba6}', "\u{13d6}"), ('\u{aba7}', "\u{13d7}"), ('\u{aba8}', "\u{13d8}"),
+    ('\u{aba9}', "\u{13d9}"), ('\u{abaa}', "\u{13da}"), ('\u{abab}', "\u{13db}"),
+    ('\u{abac}', "\u{13dc}"), ('\u{abad}', "\u{13dd}"), ('\u{abae}', "\u{13de}"),
+    ('\u{abaf}', "\u{13df}"), ('\u{abb0}', "\u{13e0}"), ('\u{abb1}', "\u{13e1}"),
+    ('\u{abb2}', "\u{13e2}"), ('\u{abb3}', "\u{13e3}"), ('\u{abb4}', "\u{13e4}"),
+    ('\u{abb5}', "\u{13e5}"), ('\u{abb6}', "\u{13e6}"), ('\u{abb7}', "\u{13e7}"),
+    ('\u{abb8}', "\u{13e8}"), ('\u{abb9}', "\u{13e9}"), ('\u{abba}', "\u{13ea}"),
+    ('\u{abbb}', "\u{13eb}"), ('\u{abbc}', "\u{13ec}"), ('\u{abbd}', "\u{13ed}"),
+    ('\u{abbe}', "\u{13ee}"), ('\u{abbf}', "\u{13ef}"), ('\u{fb00}', "ff"),
+    ('\u{fb01}', "fi"), ('\u{fb02}', "fl"), ('\u{fb03}', "ffi"),
+    ('\u{fb04}', "ffl"), ('\u{fb05}', "st"), ('\u{fb06}', "st"),
+    ('\u{fb13}', "\u{574}\u{576}"), ('\u{fb14}', "\u{574}\u{565}"), ('\u{fb15}', "\u{574}\u{56b}"),
+    ('\u{fb16}', "\u{57e}\u{576}"), ('\u{fb17}', "\u{574}\u{56d}"),
+];
+
+/// Precomposed letters whose canonical decomposition consists of a base
+/// letter followed by combining marks, with that base letter.
+const BASE_LETTERS: &[(char, char)] = &[
+    ('\u{c0}', '\u{41}'), ('\u{c1}', '\u{41}'), ('\u{c2}', '\u{41}'), ('\u{c3}', '\u{41}'),
+    ('\u{c4}', '\u{41}'), ('\u{c5}', '\u{41}'), ('\u{c7}', '\u{43}'), ('\u{c8}', '\u{45}'),
+    ('\u{c9}', '\u{45}'), ('\u{ca}', '\u{45}'), ('\u{cb}', '\u{45}'), ('\u{cc}', '\u{49}'),
+    ('\u{cd}', '\u{49}'), ('\u{ce}', '\u{49}'), ('\u{cf}', '\u{49}'), ('\u{d1}', '\u{4e}'),
+    ('\u{d2}', '\u{4f}'), ('\u{d3}', '\u{4f}'), ('\u{d4}', '\u{4f}'), ('\u{d5}', '\u{4f}'),
+    ('\u{d6}', '\u{4f}'), ('\u{d9}', '\u{55}'), ('\u{da}', '\u{55}'), ('\u{db}', '\u{55}'),
+    ('\u{dc}', '\u{55}'), ('\u{dd}', '\u{59}'), ('\u{e0}', '\u{61}'), ('\u{e1}', '\u{61}'),
+    ('\u{e2}', '\u{61}'), ('\u{e3}', '\u{61}'), ('\u{e4}', '\u{61}'), ('\u{e5}', '\u{61}'),
+    ('\u{e7}', '\u{63}'), ('\u{e8}', '\u{65}'), ('\u{e9}', '\u{65}'), ('\u{ea}', '\u{65}'),
+    ('\u{eb}', '\u{65}'), ('\u{ec}', '\u{69}'), ('\u{ed}', '\u{69}'), ('\u{ee}', '\u{69}'),
+    ('\u{ef}', '\u{69}'), ('\u{f1}', '\u{6e}'), ('\u{f2}', '\u{6f}'), ('\u{f3}', '\u{6f}'),
+    ('\u{f4}', '\u{6f}'), ('\u{f5}', '\u{6f}'), ('\u{f6}', '\u{6f}'), ('\u{f9}', '\u{75}'),
+    ('\u{fa}', '\u{75}'), ('\u{fb}', '\u{75}'), ('\u{fc}', '\u{75}'), ('\u{fd}', '\u{79}'),
+    ('\u{ff}', '\u{79}'), ('\u{100}', '\u{41}'), ('\u{101}', '\u{61}'), ('\u{102}', '\u{41}'),
+    ('\u{103}', '\u{61}'), ('\u{104}', '\u{41}'), ('\u{105}', '\u{61}'), ('\u{106}', '\u{43}'),
+    ('\u{107}', '\u{63}'), ('\u{108}', '\u{43}'), ('\u{109}', '\u{63}'), ('\u{10a}', '\u{43}'),
+    ('\u{10b}', '\u{63}'), ('\u{10c}', '\u{43}'), ('\u{10d}', '\u{63}'), ('\u{10e}', '\u{44}'),
+    ('\u{10f}', '\u{64}'), ('\u{112}', '\u{45}'), ('\u{113}', '\u{65}'), ('\u{114}', '\u{45}'),
+    ('\u{115}', '\u{65}'), ('\u{116}', '\u{45}'), ('\u{117}', '\u{65}'), ('\u{118}', '\u{45}'),
+    ('\u{119}', '\u{65}'), ('\u{11a}', '\u{45}'), ('\u{11b}', '\u{65}'), ('\u{11c}', '\u{47}'),
+    ('\u{11d}', '\u{67}'), ('\u{11e}', '\u{47}'), ('\u{11f}', '\u{67}'), ('\u{120}', '\u{47}'),
+    ('\u{121}', '\u{67}'), ('\u{122}', '\u{47}'), ('\u{123}', '\u{67}'), ('\u{124}', '\u{48}'),
+    ('\u{125}', '\u{68}'), ('\u{128}', '\u{49}'), ('\u{129}', '\u{69}'), ('\u{12a}', '\u{49}'),
+    ('\u{12b}', '\u{69}'), ('\u{12c}', '\u{49}'), ('\u{12d}', '\u{69}'), ('\u{12e}', '\u{49}'),
+    ('\u{12f}', '\u{69}'), ('\u{130}', '\u{49}'), ('\u{134}', '\u{4a}'), ('\u{135}', '\u{6a}'),
+    ('\u{136}', '\u{4b}'), ('\u{137}', '\u{6b}'), ('\u{139}', '\u{4c}'), ('\u{13a}', '\u{6c}'),
+    ('\u{13b}', '\u{4c}'), ('\u{13c}', '\u{6c}'), ('\u{13d}', '\u{4c}'), ('\u{13e}', '\u{6c}'),
+    ('\u{143}', '\u{4e}'), ('\u{144}', '\u{6e}'), ('\u{145}', '\u{4e}'), ('\u{146}', '\u{6e}'),
+    ('\u{147}', '\u{4e}'), ('\u{148}', '\u{6e}'), ('\u{14c}', '\u{4f}'), ('\u{14d}', '\u{6f}'),
+    ('\u{14e}', '\u{4f}'), ('\u{14f}', '\u{6f}'), ('\u{150}', '\u{4f}'), ('\u{151}', '\u{6f}'),
+    ('\u{154}', '\u{52}'), ('\u{155}', '\u{72}'), ('\u{156}', '\u{52}'), ('\u{157}', '\u{72}'),
+    ('\u{158}', '\u{52}'), ('\u{159}', '\u{72}'), ('\u{15a}', '\u{53}'), ('\u{15b}', '\u{73}'),
+    ('\u{15c}', '\u{53}'), ('\u{15d}', '\u{73}'), ('\u{15e}', '\u{53}'), ('\u{15f}', '\u{73}'),
+    ('\u{160}', '\u{53}'), ('\u{161}', '\u{73}'), ('\u{162}', '\u{54}'), ('\u{163}', '\u{74}'),
+    ('\u{164}', '\u{54}'), ('\u{165}', '\u{74}'), ('\u{168}', '\u{55}'), ('\u{169}', '\u{75}'),
+    ('\u{16a}', '\u{55}'), ('\u{16b}', '\u{75}'), ('\u{16c}', '\u{55}'), ('\u{16d}', '\u{75}'),
+    ('\u{16e}', '\u{55}'), ('\u{16f}', '\u{75}'), ('\u{170}', '\u{55}'), ('\u{171}', '\u{75}'),
+    ('\u{172}', '\u{55}'), ('\u{173}', '\u{75}'), ('\u{174}', '\u{57}'), ('\u{175}', '\u{77}'),
+    ('\u{176}', '\u{59}'), ('\u{177}', '\u{79}'), ('\u{178}', '\u{59}'), ('\u{179}', '\u{5a}'),
+    ('\u{17a}', '\u{7a}'), ('\u{17b}', '\u{5a}'), ('\u{17c}', '\u{7a}'), ('\u{17d}', '\u{5a}'),
+    ('\u{17e}', '\u{7a}'), ('\u{1a0}', '\u{4f}'), ('\u{1a1}', '\u{6f}'), ('\u{1af}', '\u{55}'),
+    ('\u{1b0}', '\u{75}'), ('\u{1cd}', '\u{41}'), ('\u{1ce}', '\u{61}'), ('\u{1cf}', '\u{49}'),
+    ('\u{1d0}', '\u{69}'), ('\u{1d1}', '\u{4f}'), ('\u{1d2}', '\u{6f}'), ('\u{1d3}', '\u{55}'),
+    ('\u{1d4}', '\u{75}'), ('\u{1d5}', '\u{55}'), ('\u{1d6}', '\u{75}'), ('\u{1d7}', '\u{55}'),
+    ('\u{1d8}', '\u{75}'), ('\u{1d9}', '\u{55}'), ('\u{1da}', '\u{75}'), ('\u{1db}', '\u{55}'),
+    ('\u{1dc}', '\u{75}'), ('\u{1de}', '\u{41}'), ('\u{1df}', '\u{61}'), ('\u{1e0}', '\u{41}'),
+    ('\u{1e1}', '\u{61}'), ('\u{1e2}', '\u{c6}'), ('\u{1e3}', '\u{e6}'), ('\u{1e6}', '\u{47}'),
+    ('\u{1e7}', '\u{67}'), ('\u{1e8}', '\u{4b}'), ('\u{1e9}', '\u{6b}'), ('\u{1ea}', '\u{4f}'),
+    ('\u{1eb}', '\u{6f}'), ('\u{1ec}', '\u{4f}'), ('\u{1ed}', '\u{6f}'), ('\u{1ee}', '\u{1b7}'),
+    ('\u{1ef}', '\u{292}'), ('\u{1f0}', '\u{6a}'), ('\u{1f4}', '\u{47}'), ('\u{1f5}', '\u{67}'),
+    ('\u{1f8}', '\u{4e}'), ('\u{1f9}', '\u{6e}'), ('\u{1fa}', '\u{41}'), ('\u{1fb}', '\u{61}'),
+    ('\u{1fc}', '\u{c6}'), ('\u{1fd}', '\u{e6}'), ('\u{1fe}', '\u{d8}'), ('\u{1ff}', '\u{f8}'),
+    ('\u{200}', '\u{41}'), ('\u{201}', '\u{61}'), ('\u{202}', '\u{41}'), ('\u{203}', '\u{61}'),
+    ('\u{204}', '\u{45}'), ('\u{205}', '\u{65}'), ('\u{206}', '\u{45}'), ('\u{207}', '\u{65}'),
+    ('\u{208}', '\u{49}'), ('\u{209}', '\u{69}'), ('\u{20a}', '\u{49}'), ('\u{20b}', '\u{69}'),
+    ('\u{20c}', '\u{4f}'), ('\u{20d}', '\u{6f}'), ('\u{20e}', '\u{4f}'), ('\u{20f}', '\u{6f}'),
+    ('\u{210}', '\u{52}'), ('\u{211}', '\u{72}'), ('\u{212}', '\u{52}'), ('\u{213}', '\u{72}'),
+    ('\u{214}', '\u{55}'), ('\u{215}', '\u{75}'), ('\u{216}', '\u{55}'), ('\u{217}', '\u{75}'),
+    ('\u{218}', '\u{53}'), ('\u{219}', '\u{73}'), ('\u{21a}', '\u{54}'), ('\u{21b}', '\u{74}'),
+    ('\u{21e}', '\u{48}'), ('\u{21f}', '\u{68}'), ('\u{226}', '\u{41}'), ('\u{227}', '\u{61}'),
+    ('\u{228}', '\u{45}'), ('\u{229}', '\u{65}'), ('\u{22a}', '\u{4f}'), ('\u{22b}', '\u{6f}'),
+    ('\u{22c}', '\u{4f}'), ('\u{22d}', '\u{6f}'), ('\u{22e}', '\u{4f}'), ('\u{22f}', '\u{6f}'),
+    ('\u{230}', '\u{4f}'), ('\u{231}', '\u{6f}'), ('\u{232}', '\u{59}'), ('\u{233}', '\u{79}'),
+    ('\u{385}', '\u{a8}'), ('\u{386}', '\u{391}'), ('\u{388}', '\u{395}'), ('\u{389}', '\u{397}'),
+    ('\u{38a}', '\u{399}'), ('\u{38c}', '\u{39f}'), ('\u{38e}', '\u{3a5}'), ('\u{38f}', '\u{3a9}'),
+    ('\u{390}', '\u{3b9}'), ('\u{3aa}', '\u{399}'), ('\u{3ab}', '\u{3a5}'), ('\u{3ac}', '\u{3b1}'),
+    ('\u{3ad}', '\u{3b5}'), ('\u{3ae}', '\u{3b7}'), ('\u{3af}', '\u{3b9}'), ('\u{3b0}', '\u{3c5}'),
+    ('\u{3ca}', '\u{3b9}'), ('\u{3cb}', '\u{3c5}'), ('\u{3cc}', '\u{3bf}'), ('\u{3cd}', '\u{3c5}'),
+    ('\u{3ce}', '\u{3c9}'), ('\u{3d3}', '\u{3d2}'), ('\u{3d4}', '\u{3d2}'), ('\u{400}', '\u{415}'),
+    ('\u{401}', '\u{415}'), ('\u{403}', '\u{413}'), ('\u{407}', '\u{406}'), ('\u{40c}', '\u{41a}'),
+    ('\u{40d}', '\u{418}'), ('\u{40e}', '\u{423}'), ('\u{419}', '\u{418}'), ('\u{439}', '\u{438}'),
+    ('\u{450}', '\u{435}'), ('\u{451}', '\u{435}'), ('\u{453}', '\u{433}'), ('\u{457}', '\u{456}'),
+    ('\u{45c}', '\u{43a}'), ('\u{45d}', '\u{438}'), ('\u{45e}', '\u{443}'), ('\u{476}', '\u{474}'),
+    ('\u{477}', '\u{475}'), ('\u{4c1}', '\u{416}'), ('\u{4c2}', '\u{436}'), ('\u{4d0}', '\u{410}'),
+    ('\u{4d1}', '\u{430}'), ('\u{4d2}', '\u{410}'), ('\u{4d3}', '\u{430}'), ('\u{4d6}', '\u{415}'),
+    ('\u{4d7}', '\u{435}'), ('\u{4da}', '\u{4d8}'), ('\u{4db}', '\u{4d9}'), ('\u{4dc}', '\u{416}'),
+    ('\u{4dd}', '\u{436}'), ('\u{4de}', '\u{417}'), ('\u{4df}', '\u{437}'), ('\u{4e2}', '\u{418}'),
+    ('\u{4e3}', '\u{438}'), ('\u{4e4}', '\u{418}'), ('\u{4e5}', '\u{438}'), ('\u{4e6}', '\u{41e}'),
+    ('\u{4e7}', '\u{43e}'), ('\u{4ea}', '\u{4e8}'), ('\u{4eb}', '\u{4e9}'), ('\u{4ec}', '\u{42d}'),
+    ('\u{4ed}', '\u{44d}'), ('\u{4ee}', '\u{423}'), ('\u{4ef}', '\u{443}'), ('\u{4f0}', '\u{423}'),
+    ('\u{4f1}', '\u{443}'), ('\u{4f2}', '\u{423}'), ('\u{4f3}', '\u{443}'), ('\u{4f4}', '\u{427}'),
+    ('\u{4f5}', '\u{447}'), ('\u{4f8}', '\u{42b}'), ('\u{4f9}', '\u{44b}'), ('\u{1e00}', '\u{41}'),
+    ('\u{1e01}', '\u{61}'), ('\u{1e02}', '\u{42}'), ('\u{1e03}', '\u{62}'), ('\u{1e04}', '\u{42}'),
+    ('\u{1e05}', '\u{62}'), ('\u{1e06}', '\u{42}'), ('\u{1e07}', '\u{62}'), ('\u{1e08}', '\u{43}'),
+    ('\u{1e09}', '\u{63}'), ('\u{1e0a}', '\u{44}'), ('\u{1e0b}', '\u{64}'), ('\u{1e0c}', '\u{44}'),
+    ('\u{1e0d}', '\u{64}'), ('\u{1e0e}', '\u{44}'), ('\u{1e0f}', '\u{64}'), ('\u{1e10}', '\u{44}'),
+    ('\u{1e11}', '\u{64}'), ('\u{1e12}', '\u{44}'), ('\u{1e13}', '\u{64}'), ('\u{1e14}', '\u{45}'),
+    ('\u{1e15}', '\u{65}'), ('\u{1e16}', '\u{45}'), ('\u{1e17}', '\u{65}'), ('\u{1e18}', '\u{45}'),
+    ('\u{1e19}', '\u{65}'), ('\u{1e1a}', '\u{45}'), ('\u{1e1b}', '\u{65}'), ('\u{1e1c}', '\u{45}'),
+    ('\u{1e1d}', '\u{65}'), ('\u{1e1e}', '\u{46}'), ('\u{1e1f}', '\u{66}'), ('\u{1e20}', '\u{47}'),
+    ('\u{1e21}', '\u{67}'), ('\u{1e22}', '\u{48}'), ('\u{1e23}', '\u{68}'), ('\u{1e24}', '\u{48}'),
+    ('\u{1e25}', '\u{68}'), ('\u{1e26}', '\u{48}'), ('\u{1e27}', '\u{68}'), ('\u{1e28}', '\u{48}'),
+    ('\u{1e29}', '\u{68}'), ('\u{1e2a}', '\u{48}'), ('\u{1e2b}', '\u{68}'), ('\u{1e2c}', '\u{49}'),
+    ('\u{1e2d}', '\u{69}'), ('\u{1e2e}', '\u{49}'), ('\u{1e2f}', '\u{69}'), ('\u{1e30}', '\u{4b}'),
+    ('\u{1e31}', '\u{6b}'), ('\u{1e32}', '\u{4b}'), ('\u{1e33}', '\u{6b}'), ('\u{1e34}', '\u{4b}'),
+    ('\u{1e35}', '\u{6b}'), ('\u{1e36}', '\u{4c}'), ('\u{1e37}', '\u{6c}'), ('\u{1e38}', '\u{4c}'),
+    ('\u{1e39}', '\u{6c}'), ('\u{1e3a}', '\u{4c}'), ('\u{1e3b}', '\u{6c}'), ('\u{1e3c}', '\u{4c}'),
+    ('\u{1e3d}', '\u{6c}'), ('\u{1e3e}', '\u{4d}'), ('\u{1e3f}', '\u{6d}'), ('\u{1e40}', '\u{4d}'),
+    ('\u{1e41}', '\u{6d}'), ('\u{1e42}', '\u{4d}'), ('\u{1e43}', '\u{6d}'), ('\u{1e44}', '\u{4e}'),
+    ('\u{1e45}', '\u{6e}'), ('\u{1e46}', '\u{4e}'), ('\u{1e47}', '\u{6e}'), ('\u{1e48}', '\u{4e}'),
+    ('\u{1e49}', '\u{6e}'), ('\u{1e4a}', '\u{4e}'), ('\u{1e4b}', '\u{6e}'), ('\u{1e4c}', '\u{4f}'),
+    ('\u{1e4d}', '\u{6f}'), ('\u{1e4e}', '\u{4f}'), ('\u{1e4f}', '\u{6f}'), ('\u{1e50}', '\u{4f}'),
+    ('\u{1e51}', '\u{6f}'), ('\u{1e52}', '\u{4f}'), ('\u{1e53}', '\u{6f}'), ('\u{1e54}', '\u{50}'),
+    ('\u{1e55}', '\u{70}'), ('\u{1e56}', '\u{50}'), ('\u{1e57}', '\u{70}'), ('\u{1e58}', '\u{52}'),
+    ('\u{1e59}', '\u{72}'), ('\u{1e5a}', '\u{52}'), ('\u{1e5b}', '\u{72}'), ('\u{1e5c}', '\u{52}'),
+    ('\u{1e5d}', '\u{72}'), ('\u{1e5e}', '\u{52}'), ('\u{1e5f}', '\u{72}'), ('\u{1e60}', '\u{53}'),
+    ('\u{1e61}', '\u{73}'), ('\u{1e62}', '\u{53}'), ('\u{1e63}', '\u{73}'), ('\u{1e64}', '\u{53}'),
+    ('\u{1e65}', '\u{73}'), ('\u{1e66}', '\u{53}'), ('\u{1e67}', '\u{73}'), ('\u{1e68}', '\u{53}'),
+    ('\u{1e69}', '\u{73}'), ('\u{1e6a}', '\u{54}'), ('\u{1e6b}', '\u{74}'), ('\u{1e6c}', '\u{54}'),
+    ('\u{1e6d}', '\u{74}'), ('\u{1e6e}', '\u{54}'), ('\u{1e6f}', '\u{74}'), ('\u{1e70}', '\u{54}'),
+    ('\u{1e71}', '\u{74}'), ('\u{1e72}', '\u{55}'), ('\u{1e73}', '\u{75}'), ('\u{1e74}', '\u{55}'),
+    ('\u{1e75}', '\u{75}'), ('\u{1e76}', '\u{55}'), ('\u{1e77}', '\u{75}'), ('\u{1e78}', '\u{55}'),
+    ('\u{1e79}', '\u{75}'), ('\u{1e7a}', '\u{55}'), ('\u{1e7b}', '\u{75}'), ('\u{1e7c}', '\u{56}'),
+    ('\u{1e7d}', '\u{76}'), ('\u{1e7e}', '\u{56}'), ('\u{1e7f}', '\u{76}'), ('\u{1e80}', '\u{57}'),
+    ('\u{1e81}', '\u{77}'), ('\u{1e82}', '\u{57}'), ('\u{1e83}', '\u{77}'), ('\u{1e84}', '\u{57}'),
+    ('\u{1e85}', '\u{77}'), ('\u{1e86}', '\u{57}'), ('\u{1e87}', '\u{77}'), ('\u{1e88}', '\u{57}'),
+    ('\u{1e89}', '\u{77}'), ('\u{1e8a}', '\u{58}'), ('\u{1e8b}', '\u{78}'), ('\u{1e8c}', '\u{58}'),
+    ('\u{1e8d}', '\u{78}'), ('\u{1e8e}', '\u{59}'), ('\u{1e8f}', '\u{79}'), ('\u{1e90}', '\u{5a}'),
+    ('\u{1e91}', '\u{7a}'), ('\u{1e92}', '\u{5a}'), ('\u{1e93}', '\u{7a}'), ('\u{1e94}', '\u{5a}'),
+    ('\u{1e95}', '\u{7a}'), ('\u{1e96}', '\u{68}'), ('\u{1e97}', '\u{74}'), ('\u{1e98}', '\u{77}'),
+    ('\u{1e99}', '\u{79}'), ('\u{1e9b}', '\u{17f}'), ('\u{1ea0}', '\u{41}'),
+    ('\u{1ea1}', '\u{61}'), ('\u{1ea2}', '\u{41}'), ('\u{1ea3}', '\u{61}'), ('\u{1ea4}', '\u{41}'),
+    ('\u{1ea5}', '\u{61}'), ('\u{1ea6}', '\u{41}'), ('\u{1ea7}', '\u{61}'), ('\u{1ea8}', '\u{41}'),
+    ('\u{1ea9}', '\u{61}'), ('\u{1eaa}', '\u{41}'), ('\u{1eab}', '\u{61}'), ('\u{1eac}', '\u{41}'),
+    ('\u{1ead}', '\u{61}'), ('\u{1eae}', '\u{41}'), ('\u{1eaf}', '\u{61}'), ('\u{1eb0}', '\u{41}'),
+    ('\u{1eb1}', '\u{61}'), ('\u{1eb2}', '\u{41}'), ('\u{1eb3}', '\u{61}'), ('\u{1eb4}', '\u{41}'),
+    ('\u{1eb5}', '\u{61}'), ('\u{1eb6}', '\u{41}'), ('\u{1eb7}', '\u{61}'), ('\u{1eb8}', '\u{45}'),
+    ('\u{1eb9}', '\u{65}'), ('\u{1eba}', '\u{45}'), ('\u{1ebb}', '\u{65}'), ('\u{1ebc}', '\u{45}'),
+    ('\u{1ebd}', '\u{65}'), ('\u{1ebe}', '\u{45}'), ('\u{1ebf}', '\u{65}'), ('\u{1ec0}', '\u{45}'),
+    ('\u{1ec1}', '\u{65}'), ('\u{1ec2}', '\u{45}'), ('\u{1ec3}', '\u{65}'), ('\u{1ec4}', '\u{45}'),
+    ('\u{1ec5}', '\u{65}'), ('\u{1ec6}', '\u{45}'), ('\u{1ec7}', '\u{65}'), ('\u{1ec8}', '\u{49}'),
+    ('\u{1ec9}', '\u{69}'), ('\u{1eca}', '\u{49}'), ('\u{1ecb}', '\u{69}'), ('\u{1ecc}', '\u{4f}'),
+    ('\u{1ecd}', '\u{6f}'), ('\u{1ece}', '\u{4f}'), ('\u{1ecf}', '\u{6f}'), ('\u{1ed0}', '\u{4f}'),
+    ('\u{1ed1}', '\u{6f}'), ('\u{1ed2}', '\u{4f}'), ('\u{1ed3}', '\u{6f}'), ('\u{1ed4}', '\u{4f}'),
+    ('\u{1ed5}', '\u{6f}'), ('\u{1ed6}', '\u{4f}'), ('\u{1ed7}', '\u{6f}'), ('\u{1ed8}', '\u{4f}'),
+    ('\u{1ed9}', '\u{6f}'), ('\u{1eda}', '\u{4f}'), ('\u{1edb}', '\u{6f}'), ('\u{1edc}', '\u{4f}'),
+    ('\u{1edd}', '\u{6f}'), ('\u{1ede}', '\u{4f}'), ('\u{1edf}', '\u{6f}'), ('\u{1ee0}', '\u{4f}'),
+    ('\u{1ee1}', '\u{6f}'), ('\u{1ee2}', '\u{4f}'), ('\u{1ee3}', '\u{6f}'), ('\u{1ee4}', '\u{55}'),
+    ('\u{1ee5}', '\u{75}'), ('\u{1ee6}', '\u{55}'), ('\u{1ee7}', '\u{75}'), ('\u{1ee8}', '\u{55}'),
+    ('\u{1ee9}', '\u{75}'), ('\u{1eea}', '\u{55}'), ('\u{1eeb}', '\u{75}'), ('\u{1eec}', '\u{55}'),
+    ('\u{1eed}', '\u{75}'), ('\u{1eee}', '\u{55}'), ('\u{1eef}', '\u{75}'), ('\u{1ef0}', '\u{55}'),
+    ('\u{1ef1}', '\u{75}'), ('\u{1ef2}', '\u{59}'), ('\u{1ef3}', '\u{79}'), ('\u{1ef4}', '\u{59}'),
+    ('\u{1ef5}', '\u{79}'), ('\u{1ef6}', '\u{59}'), ('\u{1ef7}', '\u{79}'), ('\u{1ef8}', '\u{59}'),
+    ('\u{1ef9}', '\u{79}'), ('\u{1f00}', '\u{3b1}'), ('\u{1f01}', '\u{3b1}'),
+    ('\u{1f02}', '\u{3b1}'), ('\u{1f03}', '\u{3b1}'), ('\u{1f04}', '\u{3b1}'),
+    ('\u{1f05}', '\u{3b1}'), ('\u{1f06}', '\u{3b1}'), ('\u{1f07}', '\u{3b1}'),
+    ('\u{1f08}', '\u{391}'), ('\u{1f09}', '\u{391}'), ('\u{1f0a}', '\u{391}'),
+    ('\u{1f0b}', '\u{391}'), ('\u{1f0c}', '\u{391}'), ('\u{1f0d}', '\u{391}'),
+    ('\u{1f0e}', '\u{391}'), ('\u{1f0f}', '\u{391}'), ('\u{1f10}', '\u{3b5}'),
+    ('\u{1f11}', '\u{3b5}'), ('\u{1f12}', '\u{3b5}'), ('\u{1f13}', '\u{3b5}'),
+    ('\u{1f14}', '\u{3b5}'), ('\u{1f15}', '\u{3b5}'), ('\u{1f18}', '\u{395}'),
+    ('\u{1f19}', '\u{395}'), ('\u{1f1a}', '\u{395}'), ('\u{1f1b}', '\u{395}'),
+    ('\u{1f1c}', '\u{395}'), ('\u{1f1d}', '\u{395}'), ('\u{1f20}', '\u{3b7}'),
+    ('\u{1f21}', '\u{3b7}'), ('\u{1f22}', '\u{3b7}'), ('\u{1f23}', '\u{3b7}'),
+    ('\u{1f24}', '\u{3b7}'), ('\u{1f25}', '\u{3b7}'), ('\u{1f26}', '\u{3b7}'),
+    ('\u{1f27}', '\u{3b7}'), ('\u{1f28}', '\u{397}'), ('\u{1f29}', '\u{397}'),
+    ('\u{1f2a}', '\u{397}'), ('\u{1f2b}', '\u{397}'), ('\u{1f2c}', '\u{397}'),
+    ('\u{1f2d}', '\u{397}'), ('\u{1f2e}', '\u{397}'), ('\u{1f2f}', '\u{397}'),
+    ('\u{1f30}', '\u{3b9}'), ('\u{1f31}', '\u{3b9}'), ('\u{1f32}', '\u{3b9}'),
+    ('\u{1f33}', '\u{3b9}'), ('\u{1f34}', '\u{3b9}'), ('\u{1f35}', '\u{3b9}'),
+    ('\u{1f36}', '\u{3b9}'), ('\u{1f37}', '\u{3b9}'), ('\u{1f38}', '\u{399}'),
+    ('\u{1f39}', '\u{399}'), ('\u{1f3a}', '\u{399}'), ('\u{1f3b}', '\u{399}'),
+    ('\u{1f3c}', '\u{399}'), ('\u{1f3d}', '\u{399}'), ('\u{1f3e}', '\u{399}'),
+    ('\u{1f3f}', '\u{399}'), ('\u{1f40}', '\u{3bf}'), ('\u{1f41}', '\u{3bf}'),
+    ('\u{1f42}', '\u{3bf}'), ('\u{1f43}', '\u{3bf}'), ('\u{1f44}', '\u{3bf}'),
+    ('\u{1f45}', '\u{3bf}'), ('\u{1f48}', '\u{39f}'), ('\u{1f49}', '\u{39f}'),
+    ('\u{1f4a}', '\u{39f}'), ('\u{1f4b}', '\u{39f}'), ('\u{1f4c}', '\u{39f}'),
+    ('\u{1f4d}', '\u{39f}'), ('\u{1f50}', '\u{3c5}'), ('\u{1f51}', '\u{3c5}'),
+    ('\u{1f52}', '\u{3c5}'), ('\u{1f53}', '\u{3c5}'), ('\u{1f54}', '\u{3c5}'),
+    ('\u{1f55}', '\u{3c5}'), ('\u{1f56}', '\u{3c5}'), ('\u{1f57}', '\u{3c5}'),
+    ('\u{1f59}', '\u{3a5}'), ('\u{1f5b}', '\u{3a5}'), ('\u{1f5d}', '\u{3a5}'),
+    ('\u{1f5f}', '\u{3a5}'), ('\u{1f60}', '\u{3c9}'), ('\u{1f61}', '\u{3c9}'),
+    ('\u{1f62}', '\u{3c9}'), ('\u{1f63}', '\u{3c9}'), ('\u{1f64}', '\u{3c9}'),
+    ('\u{1f65}', '\u{3c9}'), ('\u{1f66}', '\u{3c9}'), ('\u{1f67}', '\u{3c9}'),
+    ('\u{1f68}', '\u{3a9}'), ('\u{1f69}', '\u{3a9}'), ('\u{1f6a}', '\u{3a9}'),
+    ('\u{1f6b}', '\u{3a9}'), ('\u{1f6c}', '\u{3a9}'), ('\u{1f6d}', '\u{3a9}'),
+    ('\u{1f6e}', '\u{3a9}'), ('\u{1f6f}', '\u{3a9}'), ('\u{1f70}', '\u{3b1}'),
+    ('\u{1f71}', '\u{3b1}'), ('\u{1f72}', '\u{3b5}'), ('\u{1f73}', '\u{3b5}'),
+    ('\u{1f74}', '\u{3b7}'), ('\u{1f75}', '\u{3b7}'), ('\u{1f76}', '\u{3b9}'),
+    ('\u{1f77}', '\u{3b9}'), ('\u{1f78}', '\u{3bf}'), ('\u{1f79}', '\u{3bf}'),
+    ('\u{1f7a}', '\u{3c5}'), ('\u{1f7b}', '\u{3c5}'), ('\u{1f7c}', '\u{3c9}'),
+    ('\u{1f7d}', '\u{3c9}'), ('\u{1f80}', '\u{3b1}'), ('\u{1f81}', '\u{3b1}'),
+    ('\u{1f82}', '\u{3b1}'), ('\u{1f83}', '\u{3b1}'), ('\u{1f84}', '\u{3b1}'),
+    ('\u{1f85}', '\u{3b1}'), ('\u{1f86}', '\u{3b1}'), ('\u{1f87}', '\u{3b1}'),
+    ('\u{1f88}', '\u{391}'), ('\u{1f89}', '\u{391}'), ('\u{1f8a}', '\u{391}'),
+    ('\u{1f8b}', '\u{391}'), ('\u{1f8c}', '\u{391}'), ('\u{1f8d}', '\u{391}'),
+    ('\u{1f8e}', '\u{391}'), ('\u{1f8f}', '\u{391}'), ('\u{1f90}', '\u{3b7}'),
+    ('\u{1f91}', '\u{3b7}'), ('\u{1f92}', '\u{3b7}'), ('\u{1f93}', '\u{3b7}'),
+    ('\u{1f94}', '\u{3b7}'), ('\u{1f95}', '\u{3b7}'), ('\u{1f96}', '\u{3b7}'),
+    ('\u{1f97}', '\u{3b7}'), ('\u{1f98}', '\u{397}'), ('\u{1f99}', '\u{397}'),
+    ('\u{1f9a}', '\u{397}'), ('\u{1f9b}', '\u{397}'), ('\u{1f9c}', '\u{397}'),
+    ('\u{1f9d}', '\u{397}'), ('\u{1f9e}', '\u{397}'), ('\u{1f9f}', '\u{397}'),
+    ('\u{1fa0}', '\u{3c9}'), ('\u{1fa1}', '\u{3c9}'), ('\u{1fa2}', '\u{3c9}'),
+    ('\u{1fa3}', '\u{3c9}'), ('\u{1fa4}', '\u{3c9}'), ('\u{1fa5}', '\u{3c9}'),
+    ('\u{1fa6}', '\u{3c9}'), ('\u{1fa7}', '\u{3c9}'), ('\u{1fa8}', '\u{3a9}'),
+    ('\u{1fa9}', '\u{3a9}'), ('\u{1faa}', '\u{3a9}'), ('\u{1fab}', '\u{3a9}'),
+    ('\u{1fac}', '\u{3a9}'), ('\u{1fad}', '\u{3a9}'), ('\u{1fae}', '\u{3a9}'),
+    ('\u{1faf}', '\u{3a9}'), ('\u{1fb0}', '\u{3b1}'), ('\u{1fb1}', '\u{3b1}'),
+    ('\u{1fb2}', '\u{3b1}'), ('\u{1fb3}', '\u{3b1}'), ('\u{1fb4}', '\u{3b1}'),
+    ('\u{1fb6}', '\u{3b1}'), ('\u{1fb7}', '\u{3b1}'), ('\u{1fb8}', '\u{391}'),
+    ('\u{1fb9}', '\u{391}'), ('\u{1fba}', '\u{391}'), ('\u{1fbb}', '\u{391}'),
+    ('\u{1fbc}', '\u{391}'), ('\u{1fc1}', '\u{a8}'), ('\u{1fc2}', '\u{3b7}'),
+    ('\u{1fc3}', '\u{3b7}'), ('\u{1fc4}', '\u{3b7}'), ('\u{1fc6}', '\u{3b7}'),
+    ('\u{1fc7}', '\u{3b7}'), ('\u{1fc8}', '\u{395}'), ('\u{1fc9}', '\u{395}'),
+    ('\u{1fca}', '\u{397}'), ('\u{1fcb}', '\u{397}'), ('\u{1fcc}', '\u{397}'),
+    ('\u{1fcd}', '\u{1fbf}'), ('\u{1fce}', '\u{1fbf}'), ('\u{1fcf}', '\u{1fbf}'),
+    ('\u{1fd0}', '\u{3b9}'), ('\u{1fd1}', '\u{3b9}'), ('\u{1fd2}', '\u{3b9}'),
+    ('\u{1fd3}', '\u{3b9}'), ('\u{1fd6}', '\u{3b9}'), ('\u{1fd7}', '\u{3b9}'),
+    ('\u{1fd8}', '\u{399}'), ('\u{1fd9}', '\u{399}'), ('\u{1fda}', '\u{399}'),
+    ('\u{1fdb}', '\u{399}'), ('\u{1fdd}', '\u{1ffe}'), ('\u{1fde}', '\u{1ffe}'),
+    ('\u{1fdf}', '\u{1ffe}'), ('\u{1fe0}', '\u{3c5}'), ('\u{1fe1}', '\u{3c5}'),
+    ('\u{1fe2}', '\u{3c5}'), ('\u{1fe3}', '\u{3c5}'), ('\u{1fe4}', '\u{3c1}'),
+    ('\u{1fe5}', '\u{3c1}'), ('\u{1fe6}', '\u{3c5}'), ('\u{1fe7}', '\u{3c5}'),
+    ('\u{1fe8}', '\u{3a5}'), ('\u{1fe9}', '\u{3a5}'), ('\u{1fea}', '\u{3a5}'),
+    ('\u{1feb}', '\u{3a5}'), ('\u{1fec}', '\u{3a1}'), ('\u{1fed}', '\u{a8}'),
+    ('\u{1fee}', '\u{a8}'), ('\u{1ff2}', '\u{3c9}'), ('\u{1ff3}', '\u{3c9}'),
+    ('\u{1ff4}', '\u{3c9}'), ('\u{1ff6}', '\u{3c9}'), ('\u{1ff7}', '\u{3c9}'),
+    ('\u{1ff8}', '\u{39f}'), ('\u{1ff9}', '\u{39f}'), ('\u{1ffa}', '\u{3a9}'),
+    ('\u{1ffb}', '\u{3a9}'), ('\u{1ffc}', '\u{3a9}'),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_folding() {
+        assert_eq!(fold_str("Hello", case_fold), "hello");
+        assert_eq!(fold_str("Stra\u{df}e", case_fold), "strasse");
+        assert_eq!(fold_str("\u{3a3}\u{3c3}\u{3c2}", case_fold), "\u{3c3}\u{3c3}\u{3c3}");
+        assert_eq!(fold_str("\u{fb03}", case_fold), "ffi");
+    }
+
+    #[test]
+    fn accent_folding() {
+        assert_eq!(fold_str("R\u{e9}sum\u{e9}", accent_fold), "resume");
+        assert_eq!(fold_str("Re\u{301}sume\u{301}", accent_fold), "resume");
+        assert_eq!(fold_str("\u{c5}ngstr\u{f6}m", accent_fold), "angstrom");
+        assert_eq!(fold_str("\u{1e9e}", accent_fold), "ss");
+        assert_eq!(fold_str("\u{1f0}", accent_fold), "j");
+    }
+}
@@ -26,6 +26,8 @@
 
 extern crate bytecount;
 extern crate memchr;
+extern crate regex;
+extern crate regex_syntax;
 extern crate serde;
 extern crate unicode_segmentation;
 #[macro_use] extern crate serde_derive;
@@ -41,6 +43,7 @@ pub mod spans;
 pub mod multiset;
 pub mod engine;
 pub mod find;
+mod fold;
 #[cfg(test)]
 mod test_helpers;
 